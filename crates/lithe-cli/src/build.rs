@@ -12,9 +12,9 @@ pub fn handle_build(static_site: bool, out_dir: &str) -> Result<()> {
     let project_dir = std::env::current_dir()?;
     info!("Generating routes...");
 
-    generate::generate_all(&project_dir, 3000)?;
+    let items = generate::generate_all(&project_dir, 3000)?;
     sync_public_assets(&project_dir)?;
-    build_wasm_unified(&project_dir)?;
+    build_wasm_unified(&project_dir, &items)?;
 
     if static_site {
         build_static_site(&project_dir, out_dir)?;
@@ -25,17 +25,10 @@ pub fn handle_build(static_site: bool, out_dir: &str) -> Result<()> {
     Ok(())
 }
 
-pub fn build_wasm_unified(project_dir: &Path) -> Result<()> {
-    let cargo_toml_path = project_dir.join("Cargo.toml");
-    let cargo_toml_content = fs::read_to_string(&cargo_toml_path)?;
-    let project_name = cargo_toml_content
-        .lines()
-        .find(|l| l.starts_with("name = "))
-        .map(|l| l.split('=').nth(1).unwrap().trim().trim_matches('"'))
-        .unwrap_or("app");
-    info!("Scanning for client and server functions...");
-
-    let (client_fns, server_fns) = server::discover_functions(project_dir, project_name)?;
+pub fn build_wasm_unified(project_dir: &Path, items: &server::ProjectItems) -> Result<()> {
+    let project_name = &generate::read_project_name(project_dir)?;
+    let client_fns = &items.client_fns;
+    let server_fns = &items.server_fns;
     if client_fns.is_empty() && server_fns.is_empty() {
         info!("No client or server functions found, skipping WASM build");
         return Ok(());
@@ -46,7 +39,7 @@ pub fn build_wasm_unified(project_dir: &Path) -> Result<()> {
         client_fns.len(),
         server_fns.len()
     );
-    server::generate_wasm_exports(project_dir, project_name, &client_fns, &server_fns)?;
+    server::generate_wasm_exports(project_dir, project_name, client_fns, server_fns)?;
 
     info!("Building unified WASM bundle for {}...", project_name);
 
@@ -113,12 +106,10 @@ fn build_binary(project_dir: &Path, out_dir: &str) -> Result<()> {
         let mut target_binary = project_dir.join("target/release/lithe-app");
         if !target_binary.exists() {
             // Try looking up for workspace target
-            if let Some(parent) = project_dir.parent() {
-                if let Some(ws_root) = parent.parent() {
-                    let workspace_target = ws_root.join("target/release/lithe-app");
-                    if workspace_target.exists() {
-                        target_binary = workspace_target;
-                    }
+            if let Some(ws_root) = project_dir.parent().and_then(|p| p.parent()) {
+                let workspace_target = ws_root.join("target/release/lithe-app");
+                if workspace_target.exists() {
+                    target_binary = workspace_target;
                 }
             }
         }
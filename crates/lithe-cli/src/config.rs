@@ -0,0 +1,150 @@
+use anyhow::{Context, Result};
+use lithe_core::rpc::Encoding;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Project settings read from `[package.metadata.lithe]` in the project's Cargo.toml.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProjectConfig {
+    pub default_locale: Option<String>,
+    /// Prefix the app is served under behind a reverse proxy, e.g. `/app`.
+    pub base_path: Option<String>,
+    /// Maps a hostname to the `src/pages` subdirectory that serves as its page tree.
+    pub hosts: BTreeMap<String, String>,
+    /// Guard run before every server function that declares neither `guard` nor `public`,
+    /// e.g. `"crate::auth::require_user"`.
+    pub default_guard: Option<String>,
+    /// Encoding server functions use unless they set their own: `json` (the default),
+    /// `msgpack`, `cbor` or `postcard`.
+    pub encoding: Option<String>,
+    /// Largest request body the RPC endpoints accept, and largest socket message, e.g. `"10MB"`.
+    /// Defaults to 2MB.
+    pub rpc_body_limit: Option<String>,
+    /// `text` (the default) or `json` for one JSON object per log line.
+    pub log_format: Option<String>,
+    /// Path of the Prometheus metrics endpoint, `/metrics` by default; empty turns it off.
+    pub metrics_path: Option<String>,
+    pub csrf: CsrfConfig,
+}
+
+/// How the RPC endpoint tells the app's own calls from forged ones, set in
+/// `[package.metadata.lithe.csrf]`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct CsrfConfig {
+    /// Sites whose pages may call server functions, e.g. `"https://admin.example.com"`.
+    pub trusted_origins: Vec<String>,
+    /// Server functions any site may call, by path (`"crate::hooks::stripe"`) or by `name`.
+    pub exempt: Vec<String>,
+}
+
+impl ProjectConfig {
+    pub fn load(project_dir: &Path) -> Result<Self> {
+        let cargo_toml_path = project_dir.join("Cargo.toml");
+        let content = fs::read_to_string(&cargo_toml_path).context("Failed to read Cargo.toml")?;
+        let manifest: toml::Value =
+            toml::from_str(&content).context("Failed to parse Cargo.toml")?;
+
+        match manifest
+            .get("package")
+            .and_then(|p| p.get("metadata"))
+            .and_then(|m| m.get("lithe"))
+        {
+            Some(section) => section
+                .clone()
+                .try_into()
+                .context("Invalid [package.metadata.lithe] section"),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// The base path normalised to `/segment` form, or empty for root-mounted apps.
+    pub fn base_path(&self) -> String {
+        let trimmed = self.base_path.as_deref().unwrap_or("").trim_matches('/');
+        if trimmed.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", trimmed)
+        }
+    }
+
+    /// The default guard as a path usable from the generated server, e.g. `app::auth::require_user`.
+    pub fn default_guard_path(&self, crate_name: &str) -> Option<String> {
+        let guard = self.default_guard.as_deref()?;
+        let guard = guard.strip_prefix("crate::").unwrap_or(guard);
+        Some(format!("{}::{}", crate_name.replace('-', "_"), guard))
+    }
+
+    pub fn encoding(&self) -> Result<Encoding> {
+        match self.encoding.as_deref() {
+            None => Ok(Encoding::default()),
+            Some(name) => Encoding::from_name(name).with_context(|| {
+                format!(
+                    "Invalid encoding {:?} in [package.metadata.lithe], expected json, msgpack, cbor or postcard",
+                    name
+                )
+            }),
+        }
+    }
+
+    /// The RPC body limit in bytes.
+    pub fn rpc_body_limit(&self) -> Result<usize> {
+        match self.rpc_body_limit.as_deref() {
+            None => Ok(2 * 1024 * 1024),
+            Some(size) => parse_size(size).with_context(|| {
+                format!(
+                    "Invalid rpc_body_limit {:?} in [package.metadata.lithe], expected a size such as \"512KB\" or \"10MB\"",
+                    size
+                )
+            }),
+        }
+    }
+
+    /// Whether the server logs JSON lines rather than text.
+    pub fn json_logs(&self) -> Result<bool> {
+        match self.log_format.as_deref() {
+            None | Some("text") => Ok(false),
+            Some("json") => Ok(true),
+            Some(format) => anyhow::bail!(
+                "Invalid log_format {:?} in [package.metadata.lithe], expected text or json",
+                format
+            ),
+        }
+    }
+
+    /// The metrics endpoint's path, or `None` when it is turned off.
+    pub fn metrics_path(&self) -> Option<String> {
+        match self.metrics_path.as_deref().map(|p| p.trim_matches('/')) {
+            None => Some("/metrics".to_string()),
+            Some("") => None,
+            Some(path) => Some(format!("/{}", path)),
+        }
+    }
+
+    /// `(host, route prefix)` pairs, e.g. `("docs.example.com", "/docs")`.
+    pub fn host_prefixes(&self) -> Vec<(String, String)> {
+        self.hosts
+            .iter()
+            .map(|(host, dir)| (host.clone(), format!("/{}", dir.trim_matches('/'))))
+            .collect()
+    }
+}
+
+/// Parses a size such as `"512KB"`, `"10MB"` or `"1048576"`; units are powers of 1024.
+fn parse_size(size: &str) -> Option<usize> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let unit: usize = match size[split..].trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "KB" => 1024,
+        "MB" => 1024 * 1024,
+        "GB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    size[..split].parse::<usize>().ok()?.checked_mul(unit)
+}
@@ -9,11 +9,11 @@ pub fn handle_dev(port: u16) -> Result<()> {
     let project_dir = std::env::current_dir()?;
     info!("Generating routes and server code...");
 
-    generate::generate_all(&project_dir, port)?;
+    let items = generate::generate_all(&project_dir, port)?;
     ensure_cargo_bin_config(&project_dir)?;
     build::sync_public_assets(&project_dir)?;
 
-    if let Err(e) = build::build_wasm_unified(&project_dir) {
+    if let Err(e) = build::build_wasm_unified(&project_dir, &items) {
         warn!("WASM build failed: {}", e);
     }
 
@@ -114,6 +114,24 @@ path = ".lithe/main.rs"
         modified = true;
     }
 
+    // `#[server]` expands to code behind `cfg(lithe_native_client)`, which rustc warns about
+    // unless the crate declares it.
+    if !content.contains("lithe_native_client") {
+        let check_cfg =
+            "unexpected_cfgs = { level = \"warn\", check-cfg = [\"cfg(lithe_native_client)\"] }";
+        if content.contains("unexpected_cfgs") || content.contains("[lints]") {
+            warn!(
+                "Add `cfg(lithe_native_client)` to the check-cfg list of the `unexpected_cfgs` lint in Cargo.toml"
+            );
+        } else if content.contains("[lints.rust]") {
+            content = content.replace("[lints.rust]", &format!("[lints.rust]\n{}", check_cfg));
+            modified = true;
+        } else {
+            content = format!("{}\n[lints.rust]\n{}\n", content, check_cfg);
+            modified = true;
+        }
+    }
+
     if modified {
         std::fs::write(&cargo_path, content).context("Failed to update Cargo.toml")?;
     }
@@ -1,8 +1,13 @@
+use crate::config::ProjectConfig;
+use crate::i18n::{self, Locales};
+use crate::manifest::Manifest;
+use crate::openapi;
 use crate::server::{self, ClientFunction, ServerFunction};
 use anyhow::{Context, Result};
-use regex::Regex;
+use lithe_core::rpc::{handler_id, stable_hash};
+use serde::Serialize;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 pub struct PageRoute {
@@ -11,6 +16,77 @@ pub struct PageRoute {
     pub file_path: String,
     pub name: String,
     pub full_mod_path: String,
+    pub attrs: PageAttrs,
+}
+
+/// Arguments of a page's `#[page(...)]` attribute, mirroring `lithe::PageMeta`.
+#[derive(Debug, Clone, Serialize)]
+pub struct PageAttrs {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub cache: Option<String>,
+    pub layout: bool,
+    pub mode: String,
+    pub draft: bool,
+}
+
+impl Default for PageAttrs {
+    fn default() -> Self {
+        Self {
+            title: None,
+            description: None,
+            cache: None,
+            layout: true,
+            mode: "ssr".to_string(),
+            draft: false,
+        }
+    }
+}
+
+/// Finds the `#[page]` function in a source file and reads its attribute arguments.
+fn parse_page_attrs(path: &Path, content: &str) -> Result<Option<PageAttrs>> {
+    let file = syn::parse_file(content).context(format!("Failed to parse {:?}", path))?;
+    for item in &file.items {
+        let syn::Item::Fn(item_fn) = item else {
+            continue;
+        };
+        if !server::cfg_enabled(&item_fn.attrs, server::Target::Server) {
+            continue;
+        }
+        let Some(attr) = item_fn
+            .attrs
+            .iter()
+            .find(|a| a.path().segments.last().is_some_and(|s| s.ident == "page"))
+        else {
+            continue;
+        };
+
+        let mut attrs = PageAttrs::default();
+        if matches!(attr.meta, syn::Meta::List(_)) {
+            attr.parse_nested_meta(|meta| {
+                let key = meta
+                    .path
+                    .get_ident()
+                    .map(|i| i.to_string())
+                    .unwrap_or_default();
+                match key.as_str() {
+                    "title" => attrs.title = Some(meta.value()?.parse::<syn::LitStr>()?.value()),
+                    "description" => {
+                        attrs.description = Some(meta.value()?.parse::<syn::LitStr>()?.value())
+                    }
+                    "cache" => attrs.cache = Some(meta.value()?.parse::<syn::LitStr>()?.value()),
+                    "layout" => attrs.layout = meta.value()?.parse::<syn::LitBool>()?.value,
+                    "mode" => attrs.mode = meta.value()?.parse::<syn::LitStr>()?.value(),
+                    "draft" => attrs.draft = meta.value()?.parse::<syn::LitBool>()?.value,
+                    _ => return Err(meta.error("unsupported page attribute")),
+                }
+                Ok(())
+            })
+            .context(format!("Invalid #[page] attribute in {:?}", path))?;
+        }
+        return Ok(Some(attrs));
+    }
+    Ok(None)
 }
 
 pub fn discover_pages(project_dir: &Path, crate_name: &str) -> Result<Vec<PageRoute>> {
@@ -41,7 +117,6 @@ fn scan_pages_recursive(
     crate_name: &str,
     pages: &mut Vec<PageRoute>,
 ) -> Result<()> {
-    let page_re = Regex::new(r#"#\[(?:[\w:]+::)?page\]"#).unwrap();
     let crate_name_clean = crate_name.replace('-', "_");
 
     for entry in fs::read_dir(dir).context(format!("Failed to read directory: {:?}", dir))? {
@@ -49,20 +124,20 @@ fn scan_pages_recursive(
         let path = entry.path();
         if path.is_dir() {
             scan_pages_recursive(&path, pages_root, project_root, crate_name, pages)?;
-        } else if path.extension().map_or(false, |ext| ext == "rs") {
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
             let file_stem = path.file_stem().unwrap().to_str().unwrap().to_string();
             if file_stem == "mod" {
                 continue;
             }
 
             let content = fs::read_to_string(&path).unwrap_or_default();
-            if !page_re.is_match(&content) {
+            let Some(attrs) = parse_page_attrs(&path, &content)? else {
                 continue;
-            }
+            };
 
             let relative_from_src = path.strip_prefix(project_root.join("src"))?;
 
-            // Logic must match server::scan_file and generate_lib_rs_content
+            // Logic must match discover_modules and generate_lib_rs_content
             let mod_name = relative_from_src
                 .with_extension("")
                 .to_string_lossy()
@@ -89,6 +164,7 @@ fn scan_pages_recursive(
                 file_path: relative_path,
                 name: file_stem,
                 full_mod_path,
+                attrs,
             });
         }
     }
@@ -100,98 +176,409 @@ pub fn generate_routes_content(
     project_name: &str,
     client_fns: &[ClientFunction],
     server_fns: &[ServerFunction],
-) -> String {
+    locales: &Locales,
+    config: &ProjectConfig,
+    build_id: &str,
+) -> Result<String> {
     let mut output = String::new();
     output.push_str("// Auto-generated by lithe-cli - do not edit manually\n");
-    output.push_str("use lithe::render_page;\n\n");
+    output.push_str(
+        "use lithe::{PageMeta, PageRequest, PageResponse, RenderContext, i18n, preview, render_page, with_render_context};\n\n",
+    );
     let crate_name = project_name.replace('-', "_");
 
     for page in pages {
         output.push_str(&format!("#[path = \"{}\"]\n", page.file_path));
         output.push_str(&format!("mod {};\n", page.module_name));
     }
-    output.push_str("\npub fn dispatch(path: &str) -> String {\n");
-    output.push_str("    match path {\n");
-
-    for page in pages {
-        let mut map = std::collections::HashMap::new();
-
-        for f in client_fns {
-            let path_key = f.full_path.replace("::", "_");
-            map.insert(
-                format!("h_{}", server::hash_id_raw(&path_key)),
-                f.hashed_id.clone(),
-            );
 
-            if f.full_path.starts_with(&page.full_mod_path)
-                && f.full_path.len() > page.full_mod_path.len()
-            {
-                let local_name = &f.full_path[page.full_mod_path.len() + 2..];
-                map.insert(
-                    format!("h_{}", server::hash_id_raw(local_name)),
-                    f.hashed_id.clone(),
-                );
-            }
-        }
+    let locale_list: Vec<String> = locales.codes.iter().map(|c| format!("{:?}", c)).collect();
+    let default_locale = if locales.is_empty() {
+        "en"
+    } else {
+        &locales.default_locale
+    };
+    output.push_str(&format!(
+        "\nconst LOCALES: &[&str] = &[{}];\n",
+        locale_list.join(", ")
+    ));
+    output.push_str(&format!(
+        "const DEFAULT_LOCALE: &str = {:?};\n",
+        default_locale
+    ));
+    output.push_str("const CATALOGS: &[(&str, &str)] = &[");
+    let catalogs: Vec<String> = locales
+        .catalogs
+        .iter()
+        .map(|(code, path)| format!("({:?}, include_str!({:?}))", code, path))
+        .collect();
+    output.push_str(&catalogs.join(", "));
+    output.push_str("];\n");
+    output.push_str(&format!(
+        "const BASE_PATH: &str = {:?};\n",
+        config.base_path()
+    ));
+    output.push_str("const HOSTS: &[(&str, &str)] = &[");
+    let hosts: Vec<String> = config
+        .host_prefixes()
+        .iter()
+        .map(|(host, prefix)| format!("({:?}, {:?})", host, prefix))
+        .collect();
+    output.push_str(&hosts.join(", "));
+    output.push_str("];\n");
+    output.push_str(
+        "/// Id of this build; RPC calls from pages rendered by another build are rejected.\n",
+    );
+    output.push_str(&format!("pub const BUILD_ID: &str = {:?};\n", build_id));
+    output.push_str(&format!(
+        "const ENCODING: lithe::rpc::Encoding = lithe::rpc::Encoding::{:?};\n",
+        config.encoding()?
+    ));
+
+    output.push_str(
+        r#"
+#[allow(dead_code)]
+pub fn dispatch(path: &str) -> String {
+    dispatch_request(&PageRequest::new(path)).body
+}
 
-        for f in server_fns {
-            let path_key = f.full_path.replace("::", "_");
-            map.insert(
-                format!("h_{}", server::hash_id_raw(&path_key)),
-                f.hashed_id.clone(),
-            );
+pub fn dispatch_request(req: &PageRequest) -> PageResponse {
+    let (locale, path) = match i18n::split_locale_prefix(&req.path, LOCALES) {
+        Some((locale, path)) => (locale, path),
+        None => (
+            i18n::negotiate_locale(
+                LOCALES,
+                DEFAULT_LOCALE,
+                req.cookie(i18n::LOCALE_COOKIE),
+                req.accept_language.as_deref(),
+            ),
+            req.path.as_str(),
+        ),
+    };
+    let page_path = req.host_path(path, HOSTS);
+    let preview = preview::is_preview_request(req);
+    i18n::install_catalogs(CATALOGS);
+    let mut ctx = RenderContext {
+        path: path.to_string(),
+        base_path: BASE_PATH.to_string(),
+        locale: locale.to_string(),
+        default_locale: DEFAULT_LOCALE.to_string(),
+        alternates: i18n::alternates(BASE_PATH, path, LOCALES),
+        preview,
+        build_id: BUILD_ID.to_string(),
+        encoding: ENCODING,
+        ..RenderContext::default()
+    };
+    // Metrics are labelled with the matched route, not the path, so SPA sub-paths share one.
+    let (meta, route, render): (PageMeta, &str, fn() -> String) = match page_path.as_str() {
+"#,
+    );
 
-            if f.full_path.starts_with(&page.full_mod_path)
-                && f.full_path.len() > page.full_mod_path.len()
+    let mut spa_arms = Vec::new();
+    for page in pages {
+        // Handler ids the on_click rewrite may emit, each mapped to the function it calls.
+        let mut handlers = Vec::new();
+        let functions = client_fns
+            .iter()
+            .map(|f| (&f.full_path, &f.id))
+            .chain(server_fns.iter().map(|f| (&f.full_path, &f.id)));
+        for (full_path, id) in functions {
+            handlers.push((handler_id(&full_path.replace("::", "_")), id, full_path));
+
+            if full_path.starts_with(&page.full_mod_path)
+                && full_path.len() > page.full_mod_path.len()
             {
-                let local_name = &f.full_path[page.full_mod_path.len() + 2..];
-                map.insert(
-                    format!("h_{}", server::hash_id_raw(local_name)),
-                    f.hashed_id.clone(),
-                );
+                let local_name = &full_path[page.full_mod_path.len() + 2..];
+                handlers.push((handler_id(local_name), id, full_path));
             }
         }
+        server::check_unique_ids(
+            "handler",
+            handlers
+                .iter()
+                .map(|(h, _, path)| (h.as_str(), path.as_str())),
+        )?;
+        let map: std::collections::BTreeMap<_, _> =
+            handlers.into_iter().map(|(h, id, _)| (h, id)).collect();
 
         let map_json = serde_json::to_string(&map).unwrap();
 
-        output.push_str(&format!(
-            "        \"{}\" => render_page({}::page(), \"{}\", {:?}),\n",
-            page.route, page.module_name, crate_name, map_json
-        ));
+        let pattern = if page.attrs.mode != "spa" {
+            format!("\"{}\"", page.route)
+        } else if page.route == "/" {
+            "_".to_string()
+        } else {
+            format!("p if p == \"{0}\" || p.starts_with(\"{0}/\")", page.route)
+        };
+        let arm = format!(
+            "        {} => ({}::PAGE_META, {:?}, || render_page({}::page(), \"{}\", {:?})),\n",
+            pattern, page.module_name, page.route, page.module_name, crate_name, map_json
+        );
+        if page.attrs.mode == "spa" {
+            spa_arms.push((page.route.len(), arm));
+        } else {
+            output.push_str(&arm);
+        }
     }
-    output.push_str("        _ => \"404 Not Found\".to_string(),\n");
-    output.push_str("    }\n");
-    output.push_str("}\n");
+    // SPA pages also match their sub-paths, so they go after exact routes, most specific first.
+    spa_arms.sort_by_key(|(len, _)| std::cmp::Reverse(*len));
+    for (_, arm) in &spa_arms {
+        output.push_str(arm);
+    }
+    if !spa_arms.iter().any(|(len, _)| *len == 1) {
+        output.push_str("        _ => return PageResponse::not_found(),\n");
+    }
+    output.push_str("    };\n");
+    output.push_str(
+        r#"    if meta.draft && !preview {
+        return PageResponse::not_found();
+    }
+    ctx.meta = meta;
+    lithe::telemetry::set_route(route);
+    if lithe::csrf::page_may_carry_token(meta.cache) {
+        ctx.csrf_token = req.csrf_token.clone().unwrap_or_default();
+    }
+    let page = PageResponse::ok(with_render_context(ctx, render), &meta);
+    if preview { page.preview() } else { page }
+}
+"#,
+    );
 
-    output.push_str("\npub fn routes() -> Vec<&'static str> {\n");
+    output.push_str("\n#[allow(dead_code)]\npub fn routes() -> Vec<&'static str> {\n");
     output.push_str("    vec![");
-    let routes: Vec<String> = pages.iter().map(|p| format!("\"{}\"", p.route)).collect();
+    // Drafts are only reachable through a preview session, so static builds leave them out.
+    let published: Vec<&PageRoute> = pages.iter().filter(|p| !p.attrs.draft).collect();
+    let mut routes: Vec<String> = published
+        .iter()
+        .map(|p| format!("\"{}\"", p.route))
+        .collect();
+    for code in &locales.codes {
+        for page in &published {
+            if page.route == "/" {
+                routes.push(format!("\"/{}\"", code));
+            } else {
+                routes.push(format!("\"/{}{}\"", code, page.route));
+            }
+        }
+    }
     output.push_str(&routes.join(", "));
     output.push_str("]\n");
     output.push_str("}\n");
-    output
+    Ok(output)
 }
 
-pub fn generate_main_content(port: u16) -> String {
-    format!(
+/// Derives the build id from every file under `src`, so any source change yields a new one.
+pub fn compute_build_id(project_dir: &Path) -> Result<String> {
+    fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+        for entry in fs::read_dir(dir).context(format!("Failed to read directory: {:?}", dir))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect(&path, files)?;
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let src_dir = project_dir.join("src");
+    let mut files = Vec::new();
+    if src_dir.exists() {
+        collect(&src_dir, &mut files)?;
+    }
+    files.sort();
+
+    let mut fingerprint = String::new();
+    for file in files {
+        let content = fs::read(&file).context(format!("Failed to read {:?}", file))?;
+        fingerprint.push_str(&file.strip_prefix(&src_dir)?.to_string_lossy());
+        fingerprint.push('\0');
+        fingerprint.push_str(&stable_hash(&String::from_utf8_lossy(&content)));
+        fingerprint.push('\0');
+    }
+    Ok(stable_hash(&fingerprint))
+}
+
+/// Finds the `AppState` type declared in `src/state.rs`, returning its path from `main.rs`.
+pub fn discover_state(project_dir: &Path, crate_name: &str) -> Result<Option<String>> {
+    let path = project_dir.join("src/state.rs");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path).context("Failed to read src/state.rs")?;
+    let file = syn::parse_file(&content).context("Failed to parse src/state.rs")?;
+
+    let declares_state = file.items.iter().any(|item| match item {
+        syn::Item::Struct(s) => s.ident == "AppState",
+        syn::Item::Type(t) => t.ident == "AppState",
+        _ => false,
+    });
+    let has_constructor = file.items.iter().any(|item| {
+        let syn::Item::Impl(item_impl) = item else {
+            return false;
+        };
+        let syn::Type::Path(self_ty) = &*item_impl.self_ty else {
+            return false;
+        };
+        self_ty.path.is_ident("AppState")
+            && item_impl.items.iter().any(|i| {
+                matches!(i, syn::ImplItem::Fn(f)
+                    if f.sig.ident == "new" && f.sig.asyncness.is_some() && f.sig.inputs.is_empty())
+            })
+    });
+    if !declares_state || !has_constructor {
+        anyhow::bail!("src/state.rs must declare `AppState` with `pub async fn new() -> AppState`");
+    }
+    Ok(Some(format!(
+        "{}::state::AppState",
+        crate_name.replace('-', "_")
+    )))
+}
+
+pub fn generate_main_content(
+    port: u16,
+    config: &ProjectConfig,
+    state: Option<&str>,
+    jobs: bool,
+    sockets: bool,
+) -> Result<String> {
+    let base_path = &config.base_path();
+    let rpc_body_limit = config.rpc_body_limit()?;
+    let metrics_path = config.metrics_path();
+    let mount = if base_path.is_empty() {
+        String::new()
+    } else {
+        format!(
+            "    let app = Router::new()\n        .nest({base:?}, app)\n        .route(\"{base}/\", get(|| async {{ axum::response::Redirect::permanent({base:?}) }}));\n",
+            base = base_path
+        )
+    };
+
+    let (init_state, with_state) = match state {
+        Some(state) => (
+            format!(
+                "    let state = lithe::server::provide_state({}::new().await);\n",
+                state
+            ),
+            "\n        .with_state(state)",
+        ),
+        None => (String::new(), ""),
+    };
+
+    // Projects with `#[job]` or `#[scheduled]` functions start them once their state is set up.
+    let (jobs_mod, start_jobs) = if jobs {
+        (
+            "#[path = \"jobs.rs\"]\nmod jobs;\n",
+            "    lithe::jobs::start(jobs::JOBS, jobs::SCHEDULED);\n",
+        )
+    } else {
+        ("", "")
+    };
+
+    // Projects with `#[socket]` functions serve them on one WebSocket route.
+    let (sockets_mod, socket_route) = if sockets {
+        (
+            "#[path = \"sockets.rs\"]\nmod sockets;\n",
+            "        .route(&format!(\"{}/*id\", lithe::SOCKET_ENDPOINT), get(sockets::handle_socket))\n",
+        )
+    } else {
+        ("", "")
+    };
+
+    let log_format = if config.json_logs()? { "Json" } else { "Text" };
+    let (metrics_route, metrics_handler) = match metrics_path {
+        Some(path) => (
+            format!("        .route({:?}, get(metrics))\n", path),
+            r#"
+/// Serves the metrics collected by `lithe::telemetry` to Prometheus.
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, lithe::telemetry::METRICS_CONTENT_TYPE)],
+        lithe::telemetry::render_metrics(),
+    )
+}
+"#,
+        ),
+        None => (String::new(), ""),
+    };
+
+    Ok(format!(
         r#"// Auto-generated by lithe-cli - do not edit manually
-use axum::{{Router, extract::Path, response::{{Html, IntoResponse, Response}}, routing::{{get, post}}, body::Body, http::{{header, StatusCode}}}};
+use axum::{{Router, extract::{{MatchedPath, Path, Query, Request}}, middleware::{{self, Next}}, response::{{Html, IntoResponse, Redirect, Response}}, routing::{{get, post}}, body::Body, http::{{header, HeaderMap, StatusCode}}}};
 use rust_embed::RustEmbed;
+use std::collections::HashMap;
 #[path = "routes.rs"]
 mod routes;
 #[path = "rpc.rs"]
 mod rpc;
-
+{jobs_mod}{sockets_mod}
 #[derive(RustEmbed)]
 #[folder = ".lithe/public/"]
 struct Assets;
 
-async fn handle_root() -> Html<String> {{
-    Html(routes::dispatch("/"))
+fn render(path: String, headers: &HeaderMap) -> Response {{
+    let header_value = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let mut req = lithe::PageRequest {{
+        path,
+        host: header_value(header::HOST),
+        accept_language: header_value(header::ACCEPT_LANGUAGE),
+        cookie: header_value(header::COOKIE),
+        csrf_token: None,
+    }};
+    let csrf_cookie = lithe::server::start_csrf_session(&mut req);
+    let page = routes::dispatch_request(&req);
+    let mut response = (
+        StatusCode::from_u16(page.status).unwrap_or(StatusCode::OK),
+        [(header::VARY, "Accept-Language, Cookie")],
+        Html(page.body),
+    )
+        .into_response();
+    // Pages in shared caches carry no token, and must not set one for everyone.
+    if let Some(cookie) = csrf_cookie.filter(|_| lithe::csrf::page_may_carry_token(page.cache_control.as_deref()))
+        && let Ok(cookie) = cookie.parse()
+    {{
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }}
+    if let Some(cache) = page.cache_control.and_then(|c| c.parse().ok()) {{
+        response.headers_mut().insert(header::CACHE_CONTROL, cache);
+    }}
+    response
+}}
+
+async fn handle_root(headers: HeaderMap) -> Response {{
+    render("/".to_string(), &headers)
+}}
+
+async fn handle_path(Path(path): Path<String>, headers: HeaderMap) -> Response {{
+    render(format!("/{{}}", path), &headers)
+}}
+
+/// Starts a preview session: `/api/lithe-preview?secret=...&redirect=/drafts/post`.
+async fn enter_preview(Query(params): Query<HashMap<String, String>>) -> Response {{
+    let secret = params.get("secret").map(String::as_str).unwrap_or("");
+    if !lithe::preview::check_secret(secret) {{
+        return (StatusCode::UNAUTHORIZED, "Invalid preview secret").into_response();
+    }}
+    let Some(token) = lithe::preview::issue_token() else {{
+        return (StatusCode::UNAUTHORIZED, "Preview mode is not configured").into_response();
+    }};
+    (
+        [(header::SET_COOKIE, lithe::preview::enable_cookie(&token))],
+        Redirect::to(&preview_redirect(&params)),
+    )
+        .into_response()
 }}
 
-async fn handle_path(Path(path): Path<String>) -> Html<String> {{
-    Html(routes::dispatch(&format!("/{{}}", path)))
+async fn exit_preview(Query(params): Query<HashMap<String, String>>) -> Response {{
+    (
+        [(header::SET_COOKIE, lithe::preview::disable_cookie())],
+        Redirect::to(&preview_redirect(&params)),
+    )
+        .into_response()
+}}
+
+fn preview_redirect(params: &HashMap<String, String>) -> String {{
+    lithe::server::preview_redirect(params.get("redirect").map(String::as_str), {base_path:?})
 }}
 
 async fn static_handler(Path(path): Path<String>) -> impl IntoResponse {{
@@ -210,30 +597,85 @@ async fn static_handler(Path(path): Path<String>) -> impl IntoResponse {{
     }}
 }}
 
+/// Runs every request in a span, and counts it for the metrics.
+async fn observe(request: Request, next: Next) -> Response {{
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let request_id = request
+        .headers()
+        .get(lithe::telemetry::REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let matched = request.extensions().get::<MatchedPath>().map(|m| m.as_str().to_string());
+    lithe::telemetry::observe(&method, &path, request_id.as_deref(), matched.as_deref(), next.run(request)).await
+}}
+{metrics_handler}
 #[tokio::main]
 async fn main() {{
-    let app = Router::new()
-        .route("/api/lithe-rpc", post(rpc::handle_rpc))
+    lithe::telemetry::init(lithe::telemetry::LogFormat::{log_format});
+{init_state}{start_jobs}    let app = Router::new()
+        .route(lithe::RPC_ENDPOINT, get(rpc::handle_rpc_get).post(rpc::handle_rpc))
+        .route(lithe::RPC_STREAM_ENDPOINT, post(rpc::handle_rpc_stream))
+        .route(lithe::RPC_BATCH_ENDPOINT, post(rpc::handle_rpc_batch))
+        .route(
+            &format!("{{}}/*function", lithe::RPC_FUNCTION_ENDPOINT),
+            get(rpc::handle_rpc_function_get).post(rpc::handle_rpc_function),
+        )
+        // Applies to the routes above, i.e. server function calls.
+        .layer(axum::extract::DefaultBodyLimit::max({rpc_body_limit}))
+{socket_route}{metrics_route}        .route(lithe::PREVIEW_ENDPOINT, get(enter_preview))
+        .route(lithe::PREVIEW_EXIT_ENDPOINT, get(exit_preview))
         .route("/public/*path", get(static_handler))
         .route("/", get(handle_root))
-        .route("/*path", get(handle_path));
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:{port}").await.unwrap();
-    println!("Listening on http://localhost:{port}");
-    axum::serve(listener, app).await.unwrap();
+        .route("/*path", get(handle_path))
+        .layer(middleware::from_fn(observe)){with_state};
+{mount}    let listener = tokio::net::TcpListener::bind("0.0.0.0:{port}").await.unwrap();
+    lithe::tracing::info!("Listening on http://localhost:{port}");
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .await
+        .unwrap();
 }}
 "#,
-        port = port
-    )
+        port = port,
+        base_path = base_path,
+        init_state = init_state,
+        with_state = with_state,
+        mount = mount,
+        rpc_body_limit = rpc_body_limit,
+        log_format = log_format,
+        metrics_route = metrics_route,
+        metrics_handler = metrics_handler,
+        jobs_mod = jobs_mod,
+        start_jobs = start_jobs,
+        sockets_mod = sockets_mod,
+        socket_route = socket_route
+    ))
 }
 
-pub fn discover_modules(project_dir: &Path, crate_name: &str) -> Result<Vec<(String, String)>> {
+pub fn discover_modules(project_dir: &Path) -> Result<Vec<(String, String)>> {
     let src_dir = project_dir.join("src");
     let mut modules = Vec::new();
     if !src_dir.exists() {
         return Ok(modules);
     }
 
-    scan_modules_recursive(&src_dir, &src_dir, crate_name, &mut modules)?;
+    scan_modules_recursive(&src_dir, &src_dir, &mut modules)?;
+
+    // Files declared with `mod name;` are compiled as part of their parent module.
+    let nested: Vec<PathBuf> = modules
+        .iter()
+        .flat_map(|(_, rel_path)| {
+            server::nested_module_files(&src_dir.join(rel_path.trim_start_matches("../src/")))
+        })
+        .filter_map(|p| p.canonicalize().ok())
+        .collect();
+    modules.retain(|(_, rel_path)| {
+        src_dir
+            .join(rel_path.trim_start_matches("../src/"))
+            .canonicalize()
+            .is_ok_and(|p| !nested.contains(&p))
+    });
+    modules.sort();
 
     Ok(modules)
 }
@@ -241,7 +683,6 @@ pub fn discover_modules(project_dir: &Path, crate_name: &str) -> Result<Vec<(Str
 fn scan_modules_recursive(
     dir: &Path,
     src_root: &Path,
-    crate_name: &str,
     modules: &mut Vec<(String, String)>,
 ) -> Result<()> {
     for entry in fs::read_dir(dir).context("Failed to read src directory")? {
@@ -252,8 +693,8 @@ fn scan_modules_recursive(
             if dir_name == "public" {
                 continue;
             }
-            scan_modules_recursive(&path, src_root, crate_name, modules)?;
-        } else if path.extension().map_or(false, |ext| ext == "rs") {
+            scan_modules_recursive(&path, src_root, modules)?;
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
             let file_stem = path.file_stem().unwrap().to_str().unwrap().to_string();
             if file_stem == "lib" || file_stem == "main" || file_stem == "mod" {
                 continue;
@@ -277,6 +718,10 @@ pub fn generate_lib_rs_content(pages: &[PageRoute], modules: &[(String, String)]
     output.push_str("// Auto-generated by lithe-cli - do not edit manually\n\n");
 
     for (name, path) in modules {
+        // App state holds server resources such as connection pools, so it stays off WASM.
+        if name == "state" {
+            output.push_str("#[cfg(not(target_arch = \"wasm32\"))]\n");
+        }
         output.push_str(&format!("#[path = \"{}\"]\n", path));
         output.push_str(&format!("pub mod {};\n\n", name));
     }
@@ -306,33 +751,106 @@ pub fn generate_lib_rs_content(pages: &[PageRoute], modules: &[(String, String)]
     output
 }
 
-pub fn generate_all(project_dir: &Path, port: u16) -> Result<()> {
-    let lithe_dir = project_dir.join(".lithe");
-    fs::create_dir_all(&lithe_dir).context("Failed to create .lithe directory")?;
+pub fn read_project_name(project_dir: &Path) -> Result<String> {
     let cargo_toml_path = project_dir.join("Cargo.toml");
-    let cargo_toml_content = fs::read_to_string(&cargo_toml_path)?;
-    let project_name = cargo_toml_content
+    let cargo_toml_content =
+        fs::read_to_string(&cargo_toml_path).context("Failed to read Cargo.toml")?;
+    Ok(cargo_toml_content
         .lines()
         .find(|l| l.starts_with("name = "))
         .map(|l| l.split('=').nth(1).unwrap().trim().trim_matches('"'))
-        .unwrap_or("app");
+        .unwrap_or("app")
+        .to_string())
+}
 
-    let pages = discover_pages(project_dir, project_name)?;
-    let modules = discover_modules(project_dir, project_name)?;
-    let (client_fns, server_fns) = server::discover_functions(project_dir, project_name)?;
+/// Writes everything under `.lithe/`, returning what the scan of the project found so later
+/// build steps needn't scan it again.
+pub fn generate_all(project_dir: &Path, port: u16) -> Result<server::ProjectItems> {
+    let lithe_dir = project_dir.join(".lithe");
+    fs::create_dir_all(&lithe_dir).context("Failed to create .lithe directory")?;
+    let project_name = &read_project_name(project_dir)?;
 
-    server::generate_rpc_dispatcher(project_dir, project_name, &server_fns)?;
+    let pages = discover_pages(project_dir, project_name)?;
+    let modules = discover_modules(project_dir)?;
+    let server::ProjectItems {
+        client_fns,
+        server_fns,
+        background_fns,
+        socket_fns,
+        types,
+    } = server::discover_items(project_dir, project_name)?;
+    let config = ProjectConfig::load(project_dir)?;
+    let locales = i18n::discover_locales(project_dir, config.default_locale.as_deref())?;
+    i18n::check_catalogs(project_dir, &locales)?;
+
+    server::generate_rpc_dispatcher(
+        project_dir,
+        project_name,
+        &server_fns,
+        config.default_guard_path(project_name).as_deref(),
+        &config.csrf,
+    )?;
     server::generate_wasm_exports(project_dir, project_name, &client_fns, &server_fns)?;
-
-    let routes_content = generate_routes_content(&pages, project_name, &client_fns, &server_fns);
+    server::generate_jobs(project_dir, &background_fns)?;
+    server::generate_sockets(
+        project_dir,
+        &socket_fns,
+        config.default_guard_path(project_name).as_deref(),
+        &config.csrf,
+        config.rpc_body_limit()?,
+    )?;
+    openapi::generate_api_clients(
+        project_dir,
+        project_name,
+        &server_fns,
+        &types,
+        &config.base_path(),
+    )?;
+
+    let build_id = compute_build_id(project_dir)?;
+    let routes_content = generate_routes_content(
+        &pages,
+        project_name,
+        &client_fns,
+        &server_fns,
+        &locales,
+        &config,
+        &build_id,
+    )?;
     fs::write(lithe_dir.join("routes.rs"), routes_content)
         .context("Failed to write .lithe/routes.rs")?;
 
-    let main_content = generate_main_content(port);
+    let state = discover_state(project_dir, project_name)?;
+    let main_content = generate_main_content(
+        port,
+        &config,
+        state.as_deref(),
+        !background_fns.is_empty(),
+        !socket_fns.is_empty(),
+    )?;
     fs::write(lithe_dir.join("main.rs"), main_content).context("Failed to write .lithe/main.rs")?;
 
     let lib_content = generate_lib_rs_content(&pages, &modules);
     fs::write(lithe_dir.join("lib.rs"), lib_content).context("Failed to write .lithe/lib.rs")?;
 
-    Ok(())
+    Manifest::new(
+        project_dir,
+        project_name,
+        &pages,
+        &client_fns,
+        &server_fns,
+        &locales,
+        &config,
+    )?
+    .with_jobs(&background_fns)
+    .with_sockets(&socket_fns)
+    .write(project_dir)?;
+
+    Ok(server::ProjectItems {
+        client_fns,
+        server_fns,
+        background_fns,
+        socket_fns,
+        types,
+    })
 }
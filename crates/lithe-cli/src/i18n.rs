@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use lithe_core::i18n::flatten_catalog;
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default)]
+pub struct Locales {
+    /// Locale codes, default locale first.
+    pub codes: Vec<String>,
+    pub default_locale: String,
+    /// `(locale, path relative to .lithe)` for each catalog.
+    pub catalogs: Vec<(String, String)>,
+}
+
+impl Locales {
+    pub fn is_empty(&self) -> bool {
+        self.codes.is_empty()
+    }
+}
+
+pub fn discover_locales(project_dir: &Path, default_locale: Option<&str>) -> Result<Locales> {
+    let locales_dir = project_dir.join("src/locales");
+    if !locales_dir.exists() {
+        return Ok(Locales::default());
+    }
+
+    let mut codes = Vec::new();
+    for entry in fs::read_dir(&locales_dir).context("Failed to read src/locales")? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            codes.push(path.file_stem().unwrap().to_string_lossy().to_string());
+        }
+    }
+    codes.sort();
+    if codes.is_empty() {
+        return Ok(Locales::default());
+    }
+
+    let default_locale = match default_locale {
+        Some(l) if codes.iter().any(|c| c == l) => l.to_string(),
+        Some(l) => anyhow::bail!("Default locale '{}' has no catalog in src/locales", l),
+        None if codes.iter().any(|c| c == "en") => "en".to_string(),
+        None => codes[0].clone(),
+    };
+    codes.retain(|c| c != &default_locale);
+    codes.insert(0, default_locale.clone());
+
+    let catalogs = codes
+        .iter()
+        .map(|c| (c.clone(), format!("../src/locales/{}.json", c)))
+        .collect();
+
+    Ok(Locales {
+        codes,
+        default_locale,
+        catalogs,
+    })
+}
+
+/// Fails the build if any `t!` key or default-locale key is missing from a catalog.
+pub fn check_catalogs(project_dir: &Path, locales: &Locales) -> Result<()> {
+    if locales.is_empty() {
+        return Ok(());
+    }
+
+    let mut catalogs = BTreeMap::new();
+    for code in &locales.codes {
+        let path = project_dir.join(format!("src/locales/{}.json", code));
+        let content = fs::read_to_string(&path).context(format!("Failed to read {:?}", path))?;
+        let value: serde_json::Value =
+            serde_json::from_str(&content).context(format!("Invalid JSON in {:?}", path))?;
+        let mut messages = HashMap::new();
+        flatten_catalog("", &value, &mut messages);
+        catalogs.insert(code.clone(), messages);
+    }
+
+    let mut used = BTreeMap::new();
+    scan_used_keys(&project_dir.join("src"), project_dir, &mut used)?;
+    for key in catalogs[&locales.default_locale].keys() {
+        used.entry(key.clone())
+            .or_insert_with(|| format!("src/locales/{}.json", locales.default_locale));
+    }
+
+    let mut missing = Vec::new();
+    for (code, messages) in &catalogs {
+        for (key, source) in &used {
+            if !messages.contains_key(key) {
+                missing.push(format!("  {}: \"{}\" (used in {})", code, key, source));
+            }
+        }
+    }
+
+    if !missing.is_empty() {
+        anyhow::bail!("Missing translation keys:\n{}", missing.join("\n"));
+    }
+    Ok(())
+}
+
+fn scan_used_keys(
+    dir: &Path,
+    project_dir: &Path,
+    used: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    let key_re = Regex::new(r#"\bt!\(\s*"([^"]+)""#).unwrap();
+    for entry in fs::read_dir(dir).context(format!("Failed to read directory: {:?}", dir))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            scan_used_keys(&path, project_dir, used)?;
+        } else if path.extension().is_some_and(|ext| ext == "rs") {
+            let content = fs::read_to_string(&path)?;
+            let source = path
+                .strip_prefix(project_dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            for cap in key_re.captures_iter(&content) {
+                used.entry(cap[1].to_string())
+                    .or_insert_with(|| source.clone());
+            }
+        }
+    }
+    Ok(())
+}
@@ -3,9 +3,14 @@ use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 
 mod build;
+mod config;
 mod dev;
 mod generate;
+mod i18n;
 mod init;
+mod manifest;
+mod openapi;
+mod routes;
 mod server;
 
 #[derive(Serialize, Deserialize)]
@@ -54,6 +59,11 @@ enum Commands {
         #[arg(long, help = "Build as a static site only")]
         static_site: bool,
     },
+    #[command(about = "Lists the pages, server functions and assets the project serves")]
+    Routes {
+        #[arg(long, help = "Print the route manifest as JSON")]
+        json: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -86,6 +96,9 @@ fn main() -> Result<()> {
         } => {
             build::handle_build(static_site, &out_dir)?;
         }
+        Commands::Routes { json } => {
+            routes::handle_routes(json)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,209 @@
+use crate::config::ProjectConfig;
+use crate::generate::{self, PageAttrs, PageRoute};
+use crate::i18n::Locales;
+use crate::server::{BackgroundFunction, ClientFunction, ServerFunction, SocketFunction};
+use anyhow::{Context, Result};
+use lithe_core::context::join_base_path;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+pub const RPC_ENDPOINT: &str = "/api/lithe-rpc";
+pub const SOCKET_ENDPOINT: &str = "/api/lithe-socket";
+
+/// Everything a Lithe project serves, as derived by `generate_all`.
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    pub crate_name: String,
+    /// Id RPC calls must carry to be accepted by this build.
+    pub build_id: String,
+    /// Prefix every route below is served under; empty for root-mounted apps.
+    pub base_path: String,
+    pub rpc_endpoint: String,
+    /// Prefix of the WebSocket paths of `sockets`, `{socket_endpoint}/{id}`.
+    pub socket_endpoint: String,
+    /// Locale codes with catalogs in `src/locales`, default first.
+    pub locales: Vec<String>,
+    /// Hostnames served from a `src/pages` subtree, mapped to that subtree's route prefix.
+    pub hosts: BTreeMap<String, String>,
+    pub pages: Vec<PageEntry>,
+    pub server_functions: Vec<FunctionEntry>,
+    pub client_functions: Vec<FunctionEntry>,
+    /// `#[job]` and `#[scheduled]` functions.
+    pub jobs: Vec<JobEntry>,
+    /// `#[socket]` functions.
+    pub sockets: Vec<FunctionEntry>,
+    pub assets: Vec<AssetEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageEntry {
+    pub route: String,
+    pub source: String,
+    pub module: String,
+    #[serde(flatten)]
+    pub attrs: PageAttrs,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FunctionEntry {
+    pub name: String,
+    pub path: String,
+    pub id: String,
+    pub source: String,
+    /// HTTP method a server function is called with; absent for client functions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobEntry {
+    pub name: String,
+    pub path: String,
+    pub source: String,
+    /// Cron expression of a scheduled function; absent for jobs, which run when enqueued.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssetEntry {
+    pub route: String,
+    pub source: String,
+}
+
+impl Manifest {
+    pub fn new(
+        project_dir: &Path,
+        crate_name: &str,
+        pages: &[PageRoute],
+        client_fns: &[ClientFunction],
+        server_fns: &[ServerFunction],
+        locales: &Locales,
+        config: &ProjectConfig,
+    ) -> Result<Self> {
+        let base_path = config.base_path();
+
+        let pages = pages
+            .iter()
+            .map(|p| PageEntry {
+                route: join_base_path(&base_path, &p.route),
+                source: p.file_path.trim_start_matches("../").to_string(),
+                module: p.full_mod_path.clone(),
+                attrs: p.attrs.clone(),
+            })
+            .collect();
+
+        let server_functions = server_fns
+            .iter()
+            .map(|f| FunctionEntry {
+                name: f.fn_name.clone(),
+                path: f.full_path.clone(),
+                id: f.id.clone(),
+                source: f.source.clone(),
+                method: Some(if f.get { "GET" } else { "POST" }.to_string()),
+                cache: f.cache.clone(),
+            })
+            .collect();
+
+        let client_functions = client_fns
+            .iter()
+            .map(|f| FunctionEntry {
+                name: f.fn_name.clone(),
+                path: f.full_path.clone(),
+                id: f.id.clone(),
+                source: f.source.clone(),
+                method: None,
+                cache: None,
+            })
+            .collect();
+
+        let mut assets = Vec::new();
+        let public_dir = project_dir.join("src/public");
+        if public_dir.exists() {
+            scan_assets(&public_dir, &public_dir, &base_path, &mut assets)?;
+        }
+        assets.sort_by(|a, b| a.route.cmp(&b.route));
+
+        Ok(Self {
+            crate_name: crate_name.to_string(),
+            build_id: generate::compute_build_id(project_dir)?,
+            rpc_endpoint: format!("{}{}", base_path, RPC_ENDPOINT),
+            socket_endpoint: format!("{}{}", base_path, SOCKET_ENDPOINT),
+            base_path,
+            locales: locales.codes.clone(),
+            hosts: config.host_prefixes().into_iter().collect(),
+            pages,
+            server_functions,
+            client_functions,
+            jobs: Vec::new(),
+            sockets: Vec::new(),
+            assets,
+        })
+    }
+
+    /// Adds the project's `#[job]` and `#[scheduled]` functions.
+    pub fn with_jobs(mut self, background_fns: &[BackgroundFunction]) -> Self {
+        self.jobs = background_fns
+            .iter()
+            .map(|f| JobEntry {
+                name: f.name.clone(),
+                path: f.full_path.clone(),
+                source: f.source.clone(),
+                schedule: f.cron.clone(),
+            })
+            .collect();
+        self
+    }
+
+    /// Adds the project's `#[socket]` functions.
+    pub fn with_sockets(mut self, socket_fns: &[SocketFunction]) -> Self {
+        self.sockets = socket_fns
+            .iter()
+            .map(|f| FunctionEntry {
+                name: f.fn_name.clone(),
+                path: f.full_path.clone(),
+                id: f.id.clone(),
+                source: f.source.clone(),
+                method: None,
+                cache: None,
+            })
+            .collect();
+        self
+    }
+
+    pub fn write(&self, project_dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(project_dir.join(".lithe/manifest.json"), json)
+            .context("Failed to write .lithe/manifest.json")?;
+        Ok(())
+    }
+}
+
+fn scan_assets(
+    dir: &Path,
+    public_root: &Path,
+    base_path: &str,
+    assets: &mut Vec<AssetEntry>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).context(format!("Failed to read directory: {:?}", dir))? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            scan_assets(&path, public_root, base_path, assets)?;
+        } else {
+            let relative = path
+                .strip_prefix(public_root)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            assets.push(AssetEntry {
+                route: format!("{}/public/{}", base_path, relative),
+                source: format!("src/public/{}", relative),
+            });
+        }
+    }
+    Ok(())
+}
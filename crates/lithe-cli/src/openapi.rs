@@ -0,0 +1,1393 @@
+//! `.lithe/openapi.json` and `.lithe/client.ts`: an OpenAPI document and a typed TypeScript
+//! client for the project's server functions, regenerated with everything else so callers
+//! outside the Rust app (mobile apps, plain JS pages) stay in sync with it.
+//!
+//! Types are read from the source, like the rest of discovery. Argument and return types are
+//! mapped to the JSON serde gives them, following the project's own
+//! `#[derive(Serialize, Deserialize)]` types and their `#[serde(...)]` attributes. Anything
+//! that can't be resolved that way, e.g. types from other crates or with hand-written impls,
+//! is left open (`{}` in the schema, `unknown` in TypeScript).
+
+use crate::server::{ServerFunction, TypeDef};
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Path of the per-function RPC endpoints, mirroring `lithe::RPC_FUNCTION_ENDPOINT`.
+const RPC_FUNCTION_ENDPOINT: &str = "/api/lithe-rpc/fn";
+
+/// Schema name of `ServerFnError` with a string detail, shared by most functions.
+const SERVER_FN_ERROR: &str = "ServerFnError";
+
+/// Schema name of the reference an `UploadedFile` leaves among the arguments, mirroring
+/// `lithe::files::FilePart`.
+const UPLOADED_FILE: &str = "UploadedFile";
+
+/// `kind`s of `ServerFnError` other than `app` and `invalid`, whose detail is always a string.
+const ERROR_KINDS: &[&str] = &[
+    "transport",
+    "decode",
+    "args",
+    "not_found",
+    "unauthorized",
+    "forbidden",
+    "outdated",
+    "server",
+    "timeout",
+    "cancelled",
+    "rate_limited",
+];
+
+/// The JSON a Rust type serializes to, as far as its source tells.
+#[derive(Debug, Clone, PartialEq)]
+enum Shape {
+    Any,
+    Null,
+    Bool,
+    Integer,
+    Number,
+    String,
+    /// A fixed string, e.g. a unit variant or an enum tag.
+    Const(String),
+    Array(Box<Shape>),
+    Tuple(Vec<Shape>),
+    Optional(Box<Shape>),
+    /// An object with arbitrary keys and values of one shape.
+    Map(Box<Shape>),
+    Object(Vec<Field>),
+    OneOf(Vec<Shape>),
+    AllOf(Vec<Shape>),
+    /// A project type, defined once by name.
+    Ref(String),
+    /// An `UploadedFile` argument or a `Download` result, sent as raw bytes.
+    File,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    name: String,
+    shape: Shape,
+    required: bool,
+}
+
+/// The `#[serde(...)]` attributes that change a type's JSON.
+#[derive(Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: Option<String>,
+    tag: Option<String>,
+    content: Option<String>,
+    untagged: bool,
+    transparent: bool,
+    skip: bool,
+    default: bool,
+    flatten: bool,
+    /// Serialized by a `with`/`serialize_with` function, so its shape is unknown.
+    custom: bool,
+}
+
+fn serde_attrs(attrs: &[syn::Attribute]) -> SerdeAttrs {
+    let mut out = SerdeAttrs::default();
+    for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+        // Attributes this can't parse are left to serde to report.
+        let _ = attr.parse_nested_meta(|meta| {
+            let key = meta
+                .path
+                .get_ident()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            match key.as_str() {
+                "rename" | "rename_all" | "tag" | "content" => {
+                    let value = string_value(&meta)?;
+                    match key.as_str() {
+                        "rename" => out.rename = value,
+                        "rename_all" => out.rename_all = value,
+                        "tag" => out.tag = value,
+                        _ => out.content = value,
+                    }
+                    return Ok(());
+                }
+                "untagged" => out.untagged = true,
+                "transparent" => out.transparent = true,
+                "skip" | "skip_serializing" | "skip_deserializing" => out.skip = true,
+                "default" | "skip_serializing_if" => out.default = true,
+                "flatten" => out.flatten = true,
+                "with" | "serialize_with" => out.custom = true,
+                _ => {}
+            }
+            skip_value(&meta)
+        });
+    }
+    out
+}
+
+/// The string value of `key = "..."`, or the `serialize` one of `key(serialize = "...")`.
+fn string_value(meta: &syn::meta::ParseNestedMeta) -> syn::Result<Option<String>> {
+    if meta.input.peek(syn::Token![=]) {
+        return match meta.value()?.parse::<syn::Lit>()? {
+            syn::Lit::Str(s) => Ok(Some(s.value())),
+            _ => Ok(None),
+        };
+    }
+    let mut value = None;
+    if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|inner| {
+            if inner.path.is_ident("serialize") {
+                value = string_value(&inner)?;
+                Ok(())
+            } else {
+                skip_value(&inner)
+            }
+        })?;
+    }
+    Ok(value)
+}
+
+fn skip_value(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        meta.value()?.parse::<syn::Expr>()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|inner| skip_value(&inner))?;
+    }
+    Ok(())
+}
+
+/// Applies a `rename_all` rule the way serde does. Fields are snake_case and variants
+/// PascalCase to begin with.
+fn rename(name: &str, rule: &str, variant: bool) -> String {
+    let mut words: Vec<String> = Vec::new();
+    for part in name.split('_').filter(|p| !p.is_empty()) {
+        let mut word = String::new();
+        for c in part.chars() {
+            if c.is_uppercase() && !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            word.extend(c.to_lowercase());
+        }
+        words.push(word);
+    }
+    let capitalized = || {
+        words
+            .iter()
+            .map(|w| {
+                let mut chars = w.chars();
+                chars
+                    .next()
+                    .map(|c| c.to_uppercase().chain(chars).collect::<String>())
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>()
+    };
+    let separator = if variant { "" } else { "_" };
+    match rule {
+        "lowercase" => words.join(separator),
+        "UPPERCASE" => words.join(separator).to_uppercase(),
+        "PascalCase" => capitalized().concat(),
+        "camelCase" => {
+            let pascal = capitalized().concat();
+            let mut chars = pascal.chars();
+            chars
+                .next()
+                .map(|c| c.to_lowercase().chain(chars).collect())
+                .unwrap_or_default()
+        }
+        "snake_case" => words.join("_"),
+        "SCREAMING_SNAKE_CASE" => words.join("_").to_uppercase(),
+        "kebab-case" => words.join("-"),
+        "SCREAMING-KEBAB-CASE" => words.join("-").to_uppercase(),
+        _ => name.to_string(),
+    }
+}
+
+type Params = BTreeMap<String, Shape>;
+
+/// Maps Rust types to shapes, collecting the project types they refer to.
+struct Resolver<'a> {
+    types: &'a [TypeDef],
+    /// Names given to the types resolved so far, by index into `types`.
+    names: BTreeMap<usize, String>,
+    /// Definitions by name.
+    defs: BTreeMap<String, Shape>,
+    /// How deeply generic types are being inlined, to stop on recursive ones.
+    depth: usize,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(types: &'a [TypeDef]) -> Self {
+        Self {
+            types,
+            names: BTreeMap::new(),
+            defs: BTreeMap::new(),
+            depth: 0,
+        }
+    }
+
+    fn shape(&mut self, ty: &syn::Type, module: &str, params: &Params) -> Shape {
+        match ty {
+            syn::Type::Reference(r) => self.shape(&r.elem, module, params),
+            syn::Type::Paren(p) => self.shape(&p.elem, module, params),
+            syn::Type::Group(g) => self.shape(&g.elem, module, params),
+            syn::Type::Slice(s) => Shape::Array(Box::new(self.shape(&s.elem, module, params))),
+            syn::Type::Array(a) => Shape::Array(Box::new(self.shape(&a.elem, module, params))),
+            syn::Type::Tuple(t) if t.elems.is_empty() => Shape::Null,
+            syn::Type::Tuple(t) => Shape::Tuple(
+                t.elems
+                    .iter()
+                    .map(|ty| self.shape(ty, module, params))
+                    .collect(),
+            ),
+            syn::Type::Path(p) if p.qself.is_none() => self.path_shape(&p.path, module, params),
+            _ => Shape::Any,
+        }
+    }
+
+    fn path_shape(&mut self, path: &syn::Path, module: &str, params: &Params) -> Shape {
+        let Some(last) = path.segments.last() else {
+            return Shape::Any;
+        };
+        let name = last.ident.to_string();
+        if path.segments.len() == 1
+            && let Some(shape) = params.get(&name)
+        {
+            return shape.clone();
+        }
+        let args = type_args(last);
+        let mut inner = |i: usize| {
+            args.get(i)
+                .map_or(Shape::Any, |ty| self.shape(ty, module, params))
+        };
+        match name.as_str() {
+            "bool" => Shape::Bool,
+            "i8" | "i16" | "i32" | "i64" | "i128" | "isize" | "u8" | "u16" | "u32" | "u64"
+            | "u128" | "usize" | "NonZeroU32" | "NonZeroU64" | "NonZeroUsize" => Shape::Integer,
+            "f32" | "f64" => Shape::Number,
+            "String" | "str" | "char" | "PathBuf" | "Path" | "Uuid" | "Url" | "DateTime"
+            | "NaiveDate" | "NaiveDateTime" | "NaiveTime" | "IpAddr" | "Ipv4Addr" | "Ipv6Addr"
+            | "SocketAddr" => Shape::String,
+            "Value" => Shape::Any,
+            "UploadedFile" | "Download" => Shape::File,
+            "Option" => Shape::Optional(Box::new(inner(0))),
+            "Box" | "Rc" | "Arc" | "Cow" | "Cell" | "RefCell" | "Mutex" | "RwLock" => inner(0),
+            "Vec" | "VecDeque" | "LinkedList" | "HashSet" | "BTreeSet" | "BinaryHeap"
+            | "IndexSet" => Shape::Array(Box::new(inner(0))),
+            "HashMap" | "BTreeMap" | "IndexMap" => Shape::Map(Box::new(inner(1))),
+            _ => self.named(&name, &args, module, params),
+        }
+    }
+
+    /// A project type, by reference, or inlined with its arguments when it is generic.
+    fn named(&mut self, name: &str, args: &[&syn::Type], module: &str, params: &Params) -> Shape {
+        let types = self.types;
+        let Some(index) = find_type(types, name, module) else {
+            return Shape::Any;
+        };
+        let def = &types[index];
+        let generics = match &def.item {
+            syn::Item::Struct(s) => &s.generics,
+            syn::Item::Enum(e) => &e.generics,
+            _ => return Shape::Any,
+        };
+        if generics.type_params().next().is_some() {
+            if self.depth >= 8 {
+                return Shape::Any;
+            }
+            let inner: Params = generics
+                .type_params()
+                .enumerate()
+                .map(|(i, param)| {
+                    let shape = args
+                        .get(i)
+                        .map_or(Shape::Any, |ty| self.shape(ty, module, params));
+                    (param.ident.to_string(), shape)
+                })
+                .collect();
+            self.depth += 1;
+            let shape = self.definition(def, &inner);
+            self.depth -= 1;
+            return shape;
+        }
+
+        if let Some(name) = self.names.get(&index) {
+            return Shape::Ref(name.clone());
+        }
+        let name = if self.defs.contains_key(name) {
+            // Same name in another module: qualify it with the module path.
+            let path: String = def
+                .module_path
+                .split("::")
+                .skip(1)
+                .map(|segment| rename(segment, "PascalCase", false))
+                .collect();
+            format!("{}{}", path, name)
+        } else {
+            name.to_string()
+        };
+        self.names.insert(index, name.clone());
+        // Recursive types refer back to the name while it is being defined.
+        self.defs.insert(name.clone(), Shape::Any);
+        let shape = self.definition(def, &Params::new());
+        self.defs.insert(name.clone(), shape);
+        Shape::Ref(name)
+    }
+
+    fn definition(&mut self, def: &TypeDef, params: &Params) -> Shape {
+        let module = def.module_path.as_str();
+        match &def.item {
+            syn::Item::Struct(item) => {
+                let attrs = serde_attrs(&item.attrs);
+                let fields: Vec<&syn::Field> = item
+                    .fields
+                    .iter()
+                    .filter(|f| !serde_attrs(&f.attrs).skip)
+                    .collect();
+                match &item.fields {
+                    _ if attrs.transparent => fields
+                        .first()
+                        .map_or(Shape::Any, |f| self.shape(&f.ty, module, params)),
+                    syn::Fields::Named(named) => self.object(named, &attrs, module, params),
+                    syn::Fields::Unnamed(_) if fields.len() == 1 => {
+                        self.shape(&fields[0].ty, module, params)
+                    }
+                    syn::Fields::Unnamed(_) => Shape::Tuple(
+                        fields
+                            .iter()
+                            .map(|f| self.shape(&f.ty, module, params))
+                            .collect(),
+                    ),
+                    syn::Fields::Unit => Shape::Null,
+                }
+            }
+            syn::Item::Enum(item) => self.enumeration(item, module, params),
+            _ => Shape::Any,
+        }
+    }
+
+    fn object(
+        &mut self,
+        fields: &syn::FieldsNamed,
+        container: &SerdeAttrs,
+        module: &str,
+        params: &Params,
+    ) -> Shape {
+        let mut properties = Vec::new();
+        let mut flattened = Vec::new();
+        for field in &fields.named {
+            let attrs = serde_attrs(&field.attrs);
+            if attrs.skip {
+                continue;
+            }
+            let shape = if attrs.custom {
+                Shape::Any
+            } else {
+                self.shape(&field.ty, module, params)
+            };
+            if attrs.flatten {
+                flattened.push(shape);
+                continue;
+            }
+            let ident = field.ident.as_ref().map(ToString::to_string);
+            let ident = ident.as_deref().unwrap_or_default();
+            let ident = ident.strip_prefix("r#").unwrap_or(ident);
+            let name = attrs.rename.unwrap_or_else(|| match &container.rename_all {
+                Some(rule) => rename(ident, rule, false),
+                None => ident.to_string(),
+            });
+            // Missing `Option` fields deserialize as `None`.
+            let required =
+                !attrs.default && !container.default && !matches!(shape, Shape::Optional(_));
+            properties.push(Field {
+                name,
+                shape,
+                required,
+            });
+        }
+        if flattened.is_empty() {
+            Shape::Object(properties)
+        } else {
+            flattened.insert(0, Shape::Object(properties));
+            Shape::AllOf(flattened)
+        }
+    }
+
+    fn enumeration(&mut self, item: &syn::ItemEnum, module: &str, params: &Params) -> Shape {
+        let attrs = serde_attrs(&item.attrs);
+        let mut variants = Vec::new();
+        for variant in &item.variants {
+            let variant_attrs = serde_attrs(&variant.attrs);
+            if variant_attrs.skip {
+                continue;
+            }
+            let ident = variant.ident.to_string();
+            let name = variant_attrs
+                .rename
+                .clone()
+                .unwrap_or_else(|| match &attrs.rename_all {
+                    Some(rule) => rename(&ident, rule, true),
+                    None => ident,
+                });
+            let content = match &variant.fields {
+                syn::Fields::Unit => None,
+                syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                    Some(self.shape(&fields.unnamed[0].ty, module, params))
+                }
+                syn::Fields::Unnamed(fields) => Some(Shape::Tuple(
+                    fields
+                        .unnamed
+                        .iter()
+                        .map(|f| self.shape(&f.ty, module, params))
+                        .collect(),
+                )),
+                syn::Fields::Named(fields) => {
+                    Some(self.object(fields, &variant_attrs, module, params))
+                }
+            };
+            let tag = |tag: &String| Field {
+                name: tag.clone(),
+                shape: Shape::Const(name.clone()),
+                required: true,
+            };
+            variants.push(match (&attrs.tag, &attrs.content, content) {
+                (_, _, content) if attrs.untagged => content.unwrap_or(Shape::Null),
+                (Some(t), Some(c), content) => {
+                    let mut fields = vec![tag(t)];
+                    fields.extend(content.map(|shape| Field {
+                        name: c.clone(),
+                        shape,
+                        required: true,
+                    }));
+                    Shape::Object(fields)
+                }
+                (Some(t), None, None) => Shape::Object(vec![tag(t)]),
+                (Some(t), None, Some(Shape::Object(fields))) => {
+                    Shape::Object(std::iter::once(tag(t)).chain(fields).collect())
+                }
+                (Some(t), None, Some(content)) => {
+                    Shape::AllOf(vec![Shape::Object(vec![tag(t)]), content])
+                }
+                (None, _, None) => Shape::Const(name),
+                (None, _, Some(shape)) => Shape::Object(vec![Field {
+                    name,
+                    shape,
+                    required: true,
+                }]),
+            });
+        }
+        if variants.len() == 1 {
+            variants.remove(0)
+        } else {
+            Shape::OneOf(variants)
+        }
+    }
+}
+
+/// The type named `name`, preferring one defined in `module`.
+fn find_type(types: &[TypeDef], name: &str, module: &str) -> Option<usize> {
+    let matches = |def: &TypeDef| match &def.item {
+        syn::Item::Struct(s) => s.ident == name,
+        syn::Item::Enum(e) => e.ident == name,
+        _ => false,
+    };
+    types
+        .iter()
+        .position(|def| matches(def) && def.module_path == module)
+        .or_else(|| types.iter().position(matches))
+}
+
+/// The type arguments of a path segment, e.g. `T` and `E` of `Result<T, E>`.
+fn type_args(segment: &syn::PathSegment) -> Vec<&syn::Type> {
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(a) => a
+            .args
+            .iter()
+            .filter_map(|arg| match arg {
+                syn::GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The item type a server function replies with and the detail type of its `App` errors.
+fn output_types(func: &ServerFunction) -> (Option<&syn::Type>, Option<&syn::Type>) {
+    match &func.output {
+        syn::Type::ImplTrait(impl_trait) => {
+            let item = impl_trait.bounds.iter().find_map(|bound| {
+                let syn::TypeParamBound::Trait(t) = bound else {
+                    return None;
+                };
+                let syn::PathArguments::AngleBracketed(a) = &t.path.segments.last()?.arguments
+                else {
+                    return None;
+                };
+                a.args.iter().find_map(|arg| match arg {
+                    syn::GenericArgument::AssocType(assoc) if assoc.ident == "Item" => {
+                        Some(&assoc.ty)
+                    }
+                    _ => None,
+                })
+            });
+            (item, None)
+        }
+        syn::Type::Path(p) => {
+            let Some(last) = p.path.segments.last() else {
+                return (None, None);
+            };
+            let args = type_args(last);
+            let error = if last.ident == "ServerFnResult" {
+                args.get(1).copied()
+            } else {
+                // `Result<T, ServerFnError<E>>`
+                match args.get(1) {
+                    Some(syn::Type::Path(e)) => e
+                        .path
+                        .segments
+                        .last()
+                        .and_then(|s| type_args(s).first().copied()),
+                    _ => None,
+                }
+            };
+            (args.first().copied(), error)
+        }
+        _ => (None, None),
+    }
+}
+
+/// One server function, with the shapes of what it takes and returns.
+struct Operation<'a> {
+    func: &'a ServerFunction,
+    /// Name of the TypeScript function calling it.
+    name: String,
+    params: Vec<(String, Shape)>,
+    args: Shape,
+    output: Shape,
+    error: Shape,
+    /// Whether the arguments hold files.
+    uploads: bool,
+}
+
+impl Operation<'_> {
+    fn module(&self) -> &str {
+        self.func
+            .full_path
+            .strip_suffix(&self.func.fn_name)
+            .unwrap_or(&self.func.full_path)
+            .trim_end_matches("::")
+    }
+}
+
+/// Whether `shape` holds a file anywhere, so its call goes out as `multipart/form-data`.
+fn contains_file(
+    shape: &Shape,
+    defs: &BTreeMap<String, Shape>,
+    seen: &mut BTreeSet<String>,
+) -> bool {
+    match shape {
+        Shape::File => true,
+        Shape::Array(inner) | Shape::Optional(inner) | Shape::Map(inner) => {
+            contains_file(inner, defs, seen)
+        }
+        Shape::Tuple(shapes) | Shape::OneOf(shapes) | Shape::AllOf(shapes) => {
+            shapes.iter().any(|s| contains_file(s, defs, seen))
+        }
+        Shape::Object(fields) => fields.iter().any(|f| contains_file(&f.shape, defs, seen)),
+        Shape::Ref(name) => {
+            seen.insert(name.clone())
+                && defs
+                    .get(name)
+                    .is_some_and(|def| contains_file(def, defs, seen))
+        }
+        _ => false,
+    }
+}
+
+/// The reference an `UploadedFile` leaves among the arguments: the part holding its bytes.
+fn uploaded_file_shape() -> Shape {
+    let field = |name: &str, shape: Shape| Field {
+        name: name.to_string(),
+        shape,
+        required: true,
+    };
+    Shape::Object(vec![
+        field("part", Shape::String),
+        field("name", Shape::String),
+        field("content_type", Shape::String),
+        field("size", Shape::Integer),
+    ])
+}
+
+/// `{"ok": output}` or `{"error": error}`, as sent for every call.
+fn envelope(ok: Option<Shape>, error: Option<Shape>) -> Shape {
+    let (name, shape) = match (ok, error) {
+        (Some(ok), _) => ("ok", ok),
+        (None, Some(error)) => ("error", error),
+        (None, None) => return Shape::Any,
+    };
+    Shape::Object(vec![Field {
+        name: name.to_string(),
+        shape,
+        required: true,
+    }])
+}
+
+/// `ServerFnError<E>` as it appears on the wire.
+fn error_shape(detail: Shape) -> Shape {
+    let object = |kind: Shape, detail: Shape| {
+        Shape::Object(vec![
+            Field {
+                name: "kind".to_string(),
+                shape: kind,
+                required: true,
+            },
+            Field {
+                name: "detail".to_string(),
+                shape: detail,
+                required: true,
+            },
+        ])
+    };
+    Shape::OneOf(vec![
+        object(
+            Shape::OneOf(
+                ERROR_KINDS
+                    .iter()
+                    .map(|k| Shape::Const(k.to_string()))
+                    .collect(),
+            ),
+            Shape::String,
+        ),
+        // Mirrors `lithe::validate::FieldError`.
+        object(
+            Shape::Const("invalid".to_string()),
+            Shape::Array(Box::new(Shape::Object(
+                ["field", "code", "message"]
+                    .into_iter()
+                    .map(|name| Field {
+                        name: name.to_string(),
+                        shape: Shape::String,
+                        required: true,
+                    })
+                    .collect(),
+            ))),
+        ),
+        object(Shape::Const("app".to_string()), detail),
+    ])
+}
+
+fn schema(shape: &Shape) -> Value {
+    match shape {
+        Shape::Any => json!({}),
+        Shape::Null => json!({ "type": "null" }),
+        Shape::Bool => json!({ "type": "boolean" }),
+        Shape::Integer => json!({ "type": "integer" }),
+        Shape::Number => json!({ "type": "number" }),
+        Shape::String => json!({ "type": "string" }),
+        Shape::Const(value) => json!({ "const": value }),
+        Shape::Array(items) => json!({ "type": "array", "items": schema(items) }),
+        Shape::Tuple(items) => json!({
+            "type": "array",
+            "prefixItems": items.iter().map(schema).collect::<Vec<_>>(),
+            "minItems": items.len(),
+            "maxItems": items.len(),
+        }),
+        Shape::Optional(inner) => json!({ "anyOf": [schema(inner), { "type": "null" }] }),
+        Shape::Map(values) => json!({ "type": "object", "additionalProperties": schema(values) }),
+        Shape::Object(fields) => {
+            let properties: serde_json::Map<String, Value> = fields
+                .iter()
+                .map(|f| (f.name.clone(), schema(&f.shape)))
+                .collect();
+            let required: Vec<&str> = fields
+                .iter()
+                .filter(|f| f.required)
+                .map(|f| f.name.as_str())
+                .collect();
+            let mut object = json!({ "type": "object", "properties": properties });
+            if !required.is_empty() {
+                object["required"] = json!(required);
+            }
+            object
+        }
+        Shape::OneOf(variants) => {
+            let consts: Option<Vec<&String>> = variants
+                .iter()
+                .map(|v| match v {
+                    Shape::Const(value) => Some(value),
+                    _ => None,
+                })
+                .collect();
+            match consts {
+                Some(values) => json!({ "type": "string", "enum": values }),
+                None => json!({ "oneOf": variants.iter().map(schema).collect::<Vec<_>>() }),
+            }
+        }
+        Shape::AllOf(parts) => json!({ "allOf": parts.iter().map(schema).collect::<Vec<_>>() }),
+        Shape::Ref(name) => json!({ "$ref": format!("#/components/schemas/{}", name) }),
+        Shape::File => json!({ "$ref": format!("#/components/schemas/{}", UPLOADED_FILE) }),
+    }
+}
+
+fn typescript(shape: &Shape) -> String {
+    // Unions and intersections need parentheses inside arrays and intersections.
+    let operand = |shape: &Shape| match shape {
+        Shape::OneOf(_) | Shape::AllOf(_) | Shape::Optional(_) => {
+            format!("({})", typescript(shape))
+        }
+        _ => typescript(shape),
+    };
+    match shape {
+        Shape::Any => "unknown".to_string(),
+        Shape::Null => "null".to_string(),
+        Shape::Bool => "boolean".to_string(),
+        Shape::Integer | Shape::Number => "number".to_string(),
+        Shape::String => "string".to_string(),
+        Shape::Const(value) => json!(value).to_string(),
+        Shape::Array(items) => format!("{}[]", operand(items)),
+        Shape::Tuple(items) => format!(
+            "[{}]",
+            items.iter().map(typescript).collect::<Vec<_>>().join(", ")
+        ),
+        Shape::Optional(inner) => format!("{} | null", typescript(inner)),
+        Shape::Map(values) => format!("Record<string, {}>", typescript(values)),
+        Shape::Object(fields) if fields.is_empty() => "Record<string, never>".to_string(),
+        Shape::Object(fields) => format!(
+            "{{ {} }}",
+            fields
+                .iter()
+                .map(|f| format!("{}: {}", property_name(f), typescript(&f.shape)))
+                .collect::<Vec<_>>()
+                .join("; ")
+        ),
+        Shape::OneOf(variants) => variants
+            .iter()
+            .map(typescript)
+            .collect::<Vec<_>>()
+            .join(" | "),
+        Shape::AllOf(parts) => parts.iter().map(operand).collect::<Vec<_>>().join(" & "),
+        Shape::Ref(name) => name.clone(),
+        Shape::File => "Blob".to_string(),
+    }
+}
+
+/// A property key, quoted unless it is a plain identifier, with `?` when it may be missing.
+fn property_name(field: &Field) -> String {
+    let plain = field
+        .name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && field
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$');
+    let name = if plain {
+        field.name.clone()
+    } else {
+        json!(field.name).to_string()
+    };
+    if field.required {
+        name
+    } else {
+        format!("{}?", name)
+    }
+}
+
+/// A doc comment for TypeScript, or nothing when `doc` is empty.
+fn js_doc(doc: &str, indent: &str) -> String {
+    if doc.is_empty() {
+        return String::new();
+    }
+    let doc = doc.replace("*/", "*\\/");
+    let lines: Vec<&str> = doc.lines().collect();
+    if lines.len() == 1 {
+        return format!("{}/** {} */\n", indent, lines[0]);
+    }
+    let mut out = format!("{}/**\n", indent);
+    for line in lines {
+        out.push_str(&format!(
+            "{} *{}{}\n",
+            indent,
+            if line.is_empty() { "" } else { " " },
+            line
+        ));
+    }
+    out.push_str(&format!("{} */\n", indent));
+    out
+}
+
+/// `snake_case` to `camelCase`, avoiding the words TypeScript reserves.
+fn camel_case(name: &str) -> String {
+    const RESERVED: &[&str] = &[
+        "break",
+        "case",
+        "catch",
+        "class",
+        "const",
+        "continue",
+        "debugger",
+        "default",
+        "delete",
+        "do",
+        "else",
+        "enum",
+        "export",
+        "extends",
+        "false",
+        "finally",
+        "for",
+        "function",
+        "if",
+        "import",
+        "in",
+        "instanceof",
+        "new",
+        "null",
+        "return",
+        "super",
+        "switch",
+        "this",
+        "throw",
+        "true",
+        "try",
+        "typeof",
+        "var",
+        "void",
+        "while",
+        "with",
+        "yield",
+        "let",
+        "static",
+        "implements",
+        "interface",
+        "package",
+        "private",
+        "protected",
+        "public",
+        "await",
+    ];
+    let name = rename(name.trim_start_matches("r#"), "camelCase", false);
+    if RESERVED.contains(&name.as_str()) {
+        format!("{}_", name)
+    } else {
+        name
+    }
+}
+
+fn operations<'a>(functions: &'a [ServerFunction], resolver: &mut Resolver) -> Vec<Operation<'a>> {
+    let mut operations: Vec<Operation> = functions
+        .iter()
+        .map(|func| {
+            let module = func
+                .full_path
+                .strip_suffix(&func.fn_name)
+                .unwrap_or(&func.full_path)
+                .trim_end_matches("::");
+            let params: Vec<(String, Shape)> = func
+                .args
+                .iter()
+                .map(|(name, ty)| (camel_case(name), resolver.shape(ty, module, &Params::new())))
+                .collect();
+            let args = match params.as_slice() {
+                [] => Shape::Null,
+                [(_, shape)] => shape.clone(),
+                params => Shape::Tuple(params.iter().map(|(_, s)| s.clone()).collect()),
+            };
+            let (output, error) = output_types(func);
+            let output = output.map_or(Shape::Any, |ty| resolver.shape(ty, module, &Params::new()));
+            let error = error.map_or(Shape::String, |ty| {
+                resolver.shape(ty, module, &Params::new())
+            });
+            Operation {
+                func,
+                name: camel_case(&func.fn_name),
+                params,
+                args,
+                output,
+                error,
+                uploads: false,
+            }
+        })
+        .collect();
+    for op in &mut operations {
+        op.uploads = contains_file(&op.args, &resolver.defs, &mut BTreeSet::new());
+    }
+
+    // Functions with the same name in different modules are told apart by their module.
+    let mut seen = BTreeMap::new();
+    for op in &operations {
+        *seen.entry(op.name.clone()).or_insert(0) += 1;
+    }
+    for op in &mut operations {
+        if seen[&op.name] > 1 {
+            let path = op
+                .func
+                .full_path
+                .split("::")
+                .skip(1)
+                .collect::<Vec<_>>()
+                .join("_");
+            op.name = camel_case(&path);
+        }
+    }
+    operations
+}
+
+/// Writes `.lithe/openapi.json` and `.lithe/client.ts` for `functions`.
+pub fn generate_api_clients(
+    project_dir: &Path,
+    project_name: &str,
+    functions: &[ServerFunction],
+    types: &[TypeDef],
+    base_path: &str,
+) -> Result<()> {
+    let lithe_dir = project_dir.join(".lithe");
+    let mut resolver = Resolver::new(types);
+    let operations = operations(functions, &mut resolver);
+    let mut defs = resolver.defs;
+    if operations.iter().any(|op| op.error == Shape::String) {
+        defs.insert(SERVER_FN_ERROR.to_string(), error_shape(Shape::String));
+    }
+    if operations.iter().any(|op| op.uploads) {
+        defs.insert(UPLOADED_FILE.to_string(), uploaded_file_shape());
+    }
+
+    let openapi = openapi_document(project_dir, project_name, &operations, &defs, base_path)?;
+    let json = serde_json::to_string_pretty(&openapi)?;
+    fs::write(lithe_dir.join("openapi.json"), json + "\n")
+        .context("Failed to write .lithe/openapi.json")?;
+
+    let client = typescript_client(project_name, &operations, &defs, base_path);
+    fs::write(lithe_dir.join("client.ts"), client).context("Failed to write .lithe/client.ts")?;
+    Ok(())
+}
+
+fn openapi_document(
+    project_dir: &Path,
+    project_name: &str,
+    operations: &[Operation],
+    defs: &BTreeMap<String, Shape>,
+    base_path: &str,
+) -> Result<Value> {
+    let cargo_toml =
+        fs::read_to_string(project_dir.join("Cargo.toml")).context("Failed to read Cargo.toml")?;
+    let version = cargo_toml
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|t| {
+            t.get("package")?
+                .get("version")?
+                .as_str()
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| "0.0.0".to_string());
+
+    let mut paths = serde_json::Map::new();
+    for op in operations {
+        let func = op.func;
+        let ok = envelope(Some(op.output.clone()), None);
+        let error = match op.error {
+            Shape::String => Shape::Ref(SERVER_FN_ERROR.to_string()),
+            ref detail => error_shape(detail.clone()),
+        };
+        let failed = envelope(None, Some(error));
+        let mut operation = json!({
+            "operationId": op.name,
+            "summary": func.doc.lines().next().unwrap_or(&func.full_path),
+            "tags": [op.module().split("::").skip(1).collect::<Vec<_>>().join("::")],
+            "responses": {
+                "default": {
+                    "description": "The call failed.",
+                    "content": { "application/json": { "schema": schema(&failed) } },
+                },
+            },
+        });
+        if func.doc.lines().count() > 1 {
+            operation["description"] = json!(func.doc);
+        }
+        operation["x-lithe-function"] = json!(func.full_path);
+
+        let method = if func.get {
+            let required = !matches!(op.args, Shape::Null | Shape::Optional(_));
+            operation["parameters"] = json!([{
+                "name": "args",
+                "in": "query",
+                "description": "The arguments, as JSON.",
+                "required": required,
+                "content": { "application/json": { "schema": schema(&op.args) } },
+            }]);
+            operation["responses"]["200"] = success(op, &ok);
+            operation["responses"]["304"] =
+                json!({ "description": "The cached result is still current." });
+            "get"
+        } else {
+            let description =
+                "The arguments: `null` for none, the value for one, an array for several.";
+            operation["requestBody"] = if op.uploads {
+                json!({
+                    "description": "The arguments, with each file replaced by a reference to the part holding it.",
+                    "required": true,
+                    "content": {
+                        "multipart/form-data": {
+                            "schema": {
+                                "type": "object",
+                                "properties": { "args": schema(&op.args) },
+                                "required": ["args"],
+                                "additionalProperties": { "type": "string", "format": "binary" },
+                            },
+                            "encoding": { "args": { "contentType": "application/json" } },
+                        },
+                    },
+                })
+            } else {
+                json!({
+                    "description": description,
+                    "required": true,
+                    "content": { "application/json": { "schema": schema(&op.args) } },
+                })
+            };
+            operation["responses"]["200"] = if func.streaming {
+                let item = Shape::OneOf(vec![ok, failed]);
+                json!({
+                    "description": "One envelope per item, as NDJSON or as server-sent events when asked for `text/event-stream`.",
+                    "content": {
+                        "application/x-ndjson": { "schema": schema(&item) },
+                        "text/event-stream": { "schema": { "type": "string" } },
+                    },
+                })
+            } else {
+                success(op, &ok)
+            };
+            "post"
+        };
+        paths.insert(
+            format!("{}/{}", RPC_FUNCTION_ENDPOINT, func.id),
+            json!({ method: operation }),
+        );
+    }
+
+    let schemas: serde_json::Map<String, Value> = defs
+        .iter()
+        .map(|(name, shape)| (name.clone(), schema(shape)))
+        .collect();
+    Ok(json!({
+        "openapi": "3.1.0",
+        "info": { "title": project_name, "version": version },
+        "servers": [{ "url": if base_path.is_empty() { "/" } else { base_path } }],
+        "paths": paths,
+        "components": { "schemas": schemas },
+    }))
+}
+
+/// The `200` response of a function that doesn't stream.
+fn success(op: &Operation, ok: &Shape) -> Value {
+    if op.output == Shape::File {
+        json!({
+            "description": "The file, with its own content type.",
+            "content": { "*/*": { "schema": { "type": "string", "format": "binary" } } },
+        })
+    } else {
+        json!({
+            "description": "The function's result.",
+            "content": { "application/json": { "schema": schema(ok) } },
+        })
+    }
+}
+
+const CLIENT_RUNTIME: &str = r#"export interface ClientOptions {
+  /** URL of the app including its base path, e.g. `https://example.com/app`. */
+  baseUrl?: string;
+  /** Headers sent with every call, e.g. `Authorization`. */
+  headers?: Record<string, string>;
+  /** The `fetch` calls are made with. */
+  fetch?: typeof fetch;
+}
+
+const options: ClientOptions = {};
+
+/** Changes how calls are made. Defaults to the page's own origin. */
+export function configure(next: ClientOptions): void {
+  Object.assign(options, next);
+}
+
+/** A rule an argument field broke, see `#[derive(Validate)]`. */
+export type FieldError = { field: string; code: string; message: string };
+
+export type ServerFnError<E = string> =
+  | { kind: ErrorKind; detail: string }
+  | { kind: "invalid"; detail: FieldError[] }
+  | { kind: "app"; detail: E };
+
+export type RpcResponse<T, E = string> = { ok: T } | { error: ServerFnError<E> };
+
+/** Thrown by calls that fail, with the server's error. */
+export class LitheError<E = string> extends Error {
+  constructor(
+    readonly error: ServerFnError<E>,
+    readonly status: number,
+  ) {
+    super(`${error.kind}: ${typeof error.detail === "string" ? error.detail : JSON.stringify(error.detail)}`);
+  }
+}
+
+/**
+ * The session's CSRF token on a page of the app, which the server expects from browsers:
+ * the page's, else the cookie's, else a fresh one stored in the cookie.
+ */
+function csrfToken(): string | undefined {
+  if (typeof document === "undefined" || options.baseUrl) {
+    return undefined;
+  }
+  const isToken = (t?: string) => t !== undefined && /^[0-9a-f]{32}$/.test(t);
+  const page = (globalThis as { Lithe?: { csrf_token?: string } }).Lithe?.csrf_token;
+  if (isToken(page)) {
+    return page;
+  }
+  const cookie = document.cookie.match(/(?:^|;\s*)lithe_csrf=([^;]*)/)?.[1];
+  if (isToken(cookie)) {
+    return cookie;
+  }
+  const token = Array.from(crypto.getRandomValues(new Uint8Array(16)), (b) => b.toString(16).padStart(2, "0")).join("");
+  document.cookie = `lithe_csrf=${token}; Path=/; SameSite=Lax`;
+  return token;
+}
+
+function endpoint(id: string): string {
+  return `${options.baseUrl ?? BASE_PATH}${FUNCTION_ENDPOINT}/${id}`;
+}
+
+/** Arguments as JSON, with each `Blob` replaced by a reference to the part it is sent in. */
+function encodeArgs(args: unknown): { json: string; files: [string, Blob, string][] } {
+  const files: [string, Blob, string][] = [];
+  const json = JSON.stringify(args, (_key, value) => {
+    if (typeof Blob === "undefined" || !(value instanceof Blob)) {
+      return value;
+    }
+    const part = `file-${files.length}`;
+    const name = typeof File !== "undefined" && value instanceof File ? value.name : part;
+    files.push([part, value, name]);
+    return { part, name, content_type: value.type || "application/octet-stream", size: value.size };
+  });
+  return { json, files };
+}
+
+async function send(id: string, args: unknown, method: "GET" | "POST", accept: string): Promise<Response> {
+  const doFetch = options.fetch ?? fetch;
+  const headers: Record<string, string> = { accept, ...options.headers };
+  const { json, files } = encodeArgs(args);
+  if (files.length > 0 && method === "GET") {
+    throw new LitheError({ kind: "args", detail: "files cannot be sent with GET" }, 0);
+  }
+  try {
+    if (method === "GET") {
+      return await doFetch(`${endpoint(id)}?args=${encodeURIComponent(json)}`, { headers });
+    }
+    const token = csrfToken();
+    if (token) {
+      headers["x-lithe-csrf"] = token;
+    }
+    if (files.length === 0) {
+      headers["content-type"] = "application/json";
+      return await doFetch(endpoint(id), { method, headers, body: json });
+    }
+    // `fetch` sets the multipart content type itself.
+    const form = new FormData();
+    form.append("args", new Blob([json], { type: "application/json" }));
+    for (const [part, blob, name] of files) {
+      form.append(part, blob, name);
+    }
+    return await doFetch(endpoint(id), { method, headers, body: form });
+  } catch (e) {
+    throw new LitheError({ kind: "transport", detail: String(e) }, 0);
+  }
+}
+
+async function failure<E>(response: Response): Promise<LitheError<E>> {
+  try {
+    const envelope = (await response.json()) as RpcResponse<unknown, E>;
+    if ("error" in envelope) {
+      return new LitheError(envelope.error, response.status);
+    }
+  } catch {
+    // Not a Lithe reply, e.g. from a proxy.
+  }
+  return new LitheError({ kind: "transport", detail: `HTTP ${response.status}` }, response.status);
+}
+
+async function call<T, E>(id: string, args: unknown, method: "GET" | "POST"): Promise<T> {
+  const response = await send(id, args, method, "application/json");
+  if (!response.ok) {
+    throw await failure<E>(response);
+  }
+  let envelope: RpcResponse<T, E>;
+  try {
+    envelope = await response.json();
+  } catch (e) {
+    throw new LitheError({ kind: "decode", detail: String(e) }, response.status);
+  }
+  if ("error" in envelope) {
+    throw new LitheError(envelope.error, response.status);
+  }
+  return envelope.ok;
+}
+
+async function download<E>(id: string, args: unknown, method: "GET" | "POST"): Promise<Blob> {
+  const response = await send(id, args, method, "application/json");
+  if (!response.ok) {
+    throw await failure<E>(response);
+  }
+  return await response.blob();
+}
+
+async function* stream<T, E>(id: string, args: unknown): AsyncGenerator<T> {
+  const response = await send(id, args, "POST", "application/x-ndjson");
+  if (!response.ok || !response.body) {
+    throw await failure<E>(response);
+  }
+  const reader = response.body.pipeThrough(new TextDecoderStream()).getReader();
+  let buffer = "";
+  for (;;) {
+    const { done, value } = await reader.read();
+    buffer += value ?? "";
+    let newline: number;
+    while ((newline = buffer.indexOf("\n")) >= 0) {
+      const line = buffer.slice(0, newline).trim();
+      buffer = buffer.slice(newline + 1);
+      if (!line) {
+        continue;
+      }
+      const envelope = JSON.parse(line) as RpcResponse<T, E>;
+      if ("error" in envelope) {
+        throw new LitheError(envelope.error, response.status);
+      }
+      yield envelope.ok;
+    }
+    if (done) {
+      return;
+    }
+  }
+}
+"#;
+
+fn typescript_client(
+    project_name: &str,
+    operations: &[Operation],
+    defs: &BTreeMap<String, Shape>,
+    base_path: &str,
+) -> String {
+    let mut out = String::new();
+    out.push_str("// Auto-generated by lithe-cli - do not edit manually\n");
+    out.push_str(&format!(
+        "// Typed client for the server functions of `{}`, described by `openapi.json` too.\n\n",
+        project_name
+    ));
+    out.push_str(&format!(
+        "/** Prefix the app is served under. */\nconst BASE_PATH = {};\nconst FUNCTION_ENDPOINT = {};\n\n",
+        json!(base_path),
+        json!(RPC_FUNCTION_ENDPOINT)
+    ));
+    out.push_str(&format!(
+        "export type ErrorKind = {};\n\n",
+        ERROR_KINDS
+            .iter()
+            .map(|k| json!(k).to_string())
+            .collect::<Vec<_>>()
+            .join(" | ")
+    ));
+    out.push_str(CLIENT_RUNTIME);
+
+    // `ServerFnError` is generic over its detail in TypeScript, and defined above. Files are
+    // passed as `Blob`s, so `UploadedFile` needs no type of its own.
+    let declared = |name: &str| name != SERVER_FN_ERROR && name != UPLOADED_FILE;
+    if defs.keys().any(|name| declared(name)) {
+        out.push_str("\n// Types\n");
+    }
+    for (name, shape) in defs.iter().filter(|(name, _)| declared(name)) {
+        out.push('\n');
+        match shape {
+            Shape::Object(fields) => {
+                out.push_str(&format!("export interface {} {{\n", name));
+                for field in fields {
+                    out.push_str(&format!(
+                        "  {}: {};\n",
+                        property_name(field),
+                        typescript(&field.shape)
+                    ));
+                }
+                out.push_str("}\n");
+            }
+            shape => out.push_str(&format!("export type {} = {};\n", name, typescript(shape))),
+        }
+    }
+
+    out.push_str("\n// Server functions\n");
+    for op in operations {
+        let func = op.func;
+        let params = op
+            .params
+            .iter()
+            .map(|(name, shape)| format!("{}: {}", name, typescript(shape)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let args = match op.params.as_slice() {
+            [] => "null".to_string(),
+            [(name, _)] => name.clone(),
+            params => format!(
+                "[{}]",
+                params
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let doc = if func.doc.is_empty() {
+            format!("Calls `{}`.", func.full_path)
+        } else {
+            format!("{}\n\nCalls `{}`.", func.doc, func.full_path)
+        };
+        out.push('\n');
+        out.push_str(&js_doc(&doc, ""));
+        let output = typescript(&op.output);
+        let error = typescript(&op.error);
+        if func.streaming {
+            out.push_str(&format!(
+                "export function {}({}): AsyncGenerator<{}> {{\n  return stream<{}, {}>({}, {});\n}}\n",
+                op.name,
+                params,
+                output,
+                output,
+                error,
+                json!(func.id),
+                args
+            ));
+        } else if op.output == Shape::File {
+            out.push_str(&format!(
+                "export function {}({}): Promise<Blob> {{\n  return download<{}>({}, {}, {});\n}}\n",
+                op.name,
+                params,
+                error,
+                json!(func.id),
+                args,
+                json!(if func.get { "GET" } else { "POST" })
+            ));
+        } else {
+            out.push_str(&format!(
+                "export function {}({}): Promise<{}> {{\n  return call<{}, {}>({}, {}, {});\n}}\n",
+                op.name,
+                params,
+                output,
+                output,
+                error,
+                json!(func.id),
+                args,
+                json!(if func.get { "GET" } else { "POST" })
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,185 @@
+use super::*;
+use serde::Serialize;
+
+/// Checks `rename` against what serde itself does with a `rename_all` rule, on fields and on
+/// variants.
+macro_rules! assert_renames_like_serde {
+    ($rule:literal) => {{
+        #[derive(Serialize, Default)]
+        #[serde(rename_all = $rule)]
+        struct Fields {
+            name: u8,
+            user_id: u8,
+            field2_name: u8,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = $rule)]
+        enum Variants {
+            Name,
+            UserId,
+            V2Beta,
+            HTTPRequest,
+        }
+
+        let serialized = serde_json::to_value(Fields::default()).unwrap();
+        let expected: BTreeSet<&String> = serialized.as_object().unwrap().keys().collect();
+        let renamed: BTreeSet<String> = ["name", "user_id", "field2_name"]
+            .iter()
+            .map(|field| rename(field, $rule, false))
+            .collect();
+        assert_eq!(
+            renamed.iter().collect::<BTreeSet<_>>(),
+            expected,
+            "fields, {}",
+            $rule
+        );
+
+        for (variant, ident) in [
+            (Variants::Name, "Name"),
+            (Variants::UserId, "UserId"),
+            (Variants::V2Beta, "V2Beta"),
+            (Variants::HTTPRequest, "HTTPRequest"),
+        ] {
+            assert_eq!(
+                json!(rename(ident, $rule, true)),
+                serde_json::to_value(variant).unwrap(),
+                "variant {}, {}",
+                ident,
+                $rule
+            );
+        }
+    }};
+}
+
+#[test]
+fn test_rename_matches_serde() {
+    assert_renames_like_serde!("lowercase");
+    assert_renames_like_serde!("UPPERCASE");
+    assert_renames_like_serde!("PascalCase");
+    assert_renames_like_serde!("camelCase");
+    assert_renames_like_serde!("snake_case");
+    assert_renames_like_serde!("SCREAMING_SNAKE_CASE");
+    assert_renames_like_serde!("kebab-case");
+    assert_renames_like_serde!("SCREAMING-KEBAB-CASE");
+}
+
+/// The project types defined in `source`, as if it were the crate root of `app`.
+fn type_defs(source: &str) -> Vec<TypeDef> {
+    syn::parse_file(source)
+        .unwrap()
+        .items
+        .into_iter()
+        .filter(|item| matches!(item, syn::Item::Struct(_) | syn::Item::Enum(_)))
+        .map(|item| TypeDef {
+            module_path: "app".to_string(),
+            item,
+        })
+        .collect()
+}
+
+/// The shape of `ty` and the definitions it refers to.
+fn resolve(types: &[TypeDef], ty: &str) -> (Shape, BTreeMap<String, Shape>) {
+    let mut resolver = Resolver::new(types);
+    let shape = resolver.shape(&syn::parse_str(ty).unwrap(), "app", &Params::new());
+    (shape, resolver.defs)
+}
+
+#[test]
+fn test_resolve_tagged_enum() {
+    #[derive(Serialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[derive(Serialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Event {
+        Started { at: u64 },
+        Stopped,
+        Moved(Point),
+    }
+
+    let types = type_defs(
+        r#"
+        #[derive(Serialize)]
+        struct Point { x: i32, y: i32 }
+
+        #[derive(Serialize)]
+        #[serde(tag = "type", rename_all = "snake_case")]
+        enum Event {
+            Started { at: u64 },
+            Stopped,
+            Moved(Point),
+        }
+        "#,
+    );
+    let (shape, defs) = resolve(&types, "Event");
+    assert_eq!(shape, Shape::Ref("Event".to_string()));
+    assert_eq!(
+        typescript(&defs["Event"]),
+        r#"{ type: "started"; at: number } | { type: "stopped" } | { type: "moved" } & Point"#
+    );
+    assert_eq!(typescript(&defs["Point"]), "{ x: number; y: number }");
+
+    // The tags are the ones serde writes.
+    let events = [
+        Event::Started { at: 1 },
+        Event::Stopped,
+        Event::Moved(Point { x: 1, y: 2 }),
+    ];
+    let tags: Vec<Value> = events
+        .iter()
+        .map(|event| serde_json::to_value(event).unwrap()["type"].clone())
+        .collect();
+    assert_eq!(tags, [json!("started"), json!("stopped"), json!("moved")]);
+}
+
+#[test]
+fn test_resolve_options_and_vecs() {
+    let types = type_defs(
+        r#"
+        struct Order {
+            id: u64,
+            note: Option<String>,
+            #[serde(default)]
+            tags: Vec<String>,
+            lines: Vec<Option<f64>>,
+        }
+        "#,
+    );
+    let (shape, defs) = resolve(&types, "Option<Vec<Order>>");
+    assert_eq!(typescript(&shape), "Order[] | null");
+    assert_eq!(
+        schema(&shape),
+        json!({
+            "anyOf": [
+                { "type": "array", "items": { "$ref": "#/components/schemas/Order" } },
+                { "type": "null" },
+            ]
+        })
+    );
+    assert_eq!(
+        typescript(&defs["Order"]),
+        "{ id: number; note?: string | null; tags?: string[]; lines: (number | null)[] }"
+    );
+    assert_eq!(schema(&defs["Order"])["required"], json!(["id", "lines"]));
+}
+
+#[test]
+fn test_resolve_unknown_types() {
+    let types = type_defs("struct Wrapper { inner: chrono::Duration }");
+    for ty in [
+        "Duration",
+        "other_crate::Thing",
+        "<T as Trait>::Output",
+        "dyn Fn()",
+    ] {
+        let (shape, _) = resolve(&types, ty);
+        assert_eq!(schema(&shape), json!({}), "{}", ty);
+        assert_eq!(typescript(&shape), "unknown", "{}", ty);
+    }
+    let (_, defs) = resolve(&types, "Wrapper");
+    assert_eq!(typescript(&defs["Wrapper"]), "{ inner: unknown }");
+}
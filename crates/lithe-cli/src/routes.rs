@@ -0,0 +1,165 @@
+use crate::config::ProjectConfig;
+use crate::generate;
+use crate::i18n;
+use crate::manifest::Manifest;
+use crate::server;
+use anyhow::Result;
+
+pub fn handle_routes(json: bool) -> Result<()> {
+    let project_dir = std::env::current_dir()?;
+    let project_name = generate::read_project_name(&project_dir)?;
+
+    let pages = generate::discover_pages(&project_dir, &project_name)?;
+    let items = server::discover_items(&project_dir, &project_name)?;
+    let config = ProjectConfig::load(&project_dir)?;
+    let locales = i18n::discover_locales(&project_dir, config.default_locale.as_deref())?;
+    let manifest = Manifest::new(
+        &project_dir,
+        &project_name,
+        &pages,
+        &items.client_fns,
+        &items.server_fns,
+        &locales,
+        &config,
+    )?
+    .with_jobs(&items.background_fns)
+    .with_sockets(&items.socket_fns);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&manifest)?);
+        return Ok(());
+    }
+
+    println!("Build: {}\n", manifest.build_id);
+    if !manifest.base_path.is_empty() {
+        println!("Base path: {}\n", manifest.base_path);
+    }
+    for (host, prefix) in &manifest.hosts {
+        println!("Host {} serves pages under {}", host, prefix);
+    }
+    if !manifest.hosts.is_empty() {
+        println!();
+    }
+    if let Some((default_locale, others)) = manifest.locales.split_first() {
+        let mut locales = vec![format!("{} (default)", default_locale)];
+        locales.extend(others.iter().cloned());
+        println!(
+            "Locales: {}; pages are also served under /<locale>/\n",
+            locales.join(", ")
+        );
+    }
+
+    print_table(
+        "Pages",
+        &[
+            "ROUTE", "SOURCE", "MODE", "LAYOUT", "CACHE", "DRAFT", "TITLE",
+        ],
+        manifest
+            .pages
+            .iter()
+            .map(|p| {
+                vec![
+                    p.route.clone(),
+                    p.source.clone(),
+                    p.attrs.mode.clone(),
+                    if p.attrs.layout { "default" } else { "none" }.to_string(),
+                    p.attrs.cache.clone().unwrap_or_else(|| "-".to_string()),
+                    if p.attrs.draft { "yes" } else { "-" }.to_string(),
+                    p.attrs.title.clone().unwrap_or_else(|| "-".to_string()),
+                ]
+            })
+            .collect(),
+    );
+    print_table(
+        &format!("Server functions ({})", manifest.rpc_endpoint),
+        &["ID", "METHOD", "FUNCTION", "CACHE", "SOURCE"],
+        manifest
+            .server_functions
+            .iter()
+            .map(|f| {
+                vec![
+                    f.id.clone(),
+                    f.method.clone().unwrap_or_default(),
+                    f.path.clone(),
+                    f.cache.clone().unwrap_or_else(|| "-".to_string()),
+                    f.source.clone(),
+                ]
+            })
+            .collect(),
+    );
+    print_table(
+        "Client functions",
+        &["ID", "FUNCTION", "SOURCE"],
+        manifest
+            .client_functions
+            .iter()
+            .map(|f| vec![f.id.clone(), f.path.clone(), f.source.clone()])
+            .collect(),
+    );
+    print_table(
+        "Jobs",
+        &["NAME", "SCHEDULE", "FUNCTION", "SOURCE"],
+        manifest
+            .jobs
+            .iter()
+            .map(|j| {
+                vec![
+                    j.name.clone(),
+                    j.schedule.clone().unwrap_or_else(|| "-".to_string()),
+                    j.path.clone(),
+                    j.source.clone(),
+                ]
+            })
+            .collect(),
+    );
+    print_table(
+        &format!("Sockets ({})", manifest.socket_endpoint),
+        &["ID", "FUNCTION", "SOURCE"],
+        manifest
+            .sockets
+            .iter()
+            .map(|f| vec![f.id.clone(), f.path.clone(), f.source.clone()])
+            .collect(),
+    );
+    print_table(
+        "Assets",
+        &["ROUTE", "SOURCE"],
+        manifest
+            .assets
+            .iter()
+            .map(|a| vec![a.route.clone(), a.source.clone()])
+            .collect(),
+    );
+
+    Ok(())
+}
+
+fn print_table(title: &str, headers: &[&str], rows: Vec<Vec<String>>) {
+    println!("{}: {}", title, rows.len());
+    if rows.is_empty() {
+        println!("  (none)\n");
+        return;
+    }
+
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        format!("  {}", padded.join("  ").trim_end())
+    };
+
+    println!("{}", format_row(headers.to_vec()));
+    for row in &rows {
+        println!("{}", format_row(row.iter().map(String::as_str).collect()));
+    }
+    println!();
+}
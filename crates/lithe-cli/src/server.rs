@@ -1,205 +1,1787 @@
+use crate::config::CsrfConfig;
+use crate::generate;
 use anyhow::{Context, Result};
-use regex::Regex;
+use lithe_core::cron::Schedule;
+use lithe_core::rpc::{RateLimit, RateLimitKey, function_id, is_valid_function_name, parse_rate};
+use log::warn;
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use syn::Token;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
 
 #[derive(Debug, Clone)]
 pub struct ServerFunction {
     pub full_path: String,
     pub fn_name: String,
-    pub args_type: String,
-    pub hashed_id: String,
+    pub id: String,
+    pub source: String,
+    /// Whether the function returns `impl Stream<Item = T>`.
+    pub streaming: bool,
+    /// Whether the function is declared with `method = "GET"`.
+    pub get: bool,
+    /// `Cache-Control` sent with successful GET replies.
+    pub cache: Option<String>,
+    /// Whether the function declares `guard`, or `public` to skip the default guard.
+    pub guards: bool,
+    pub public: bool,
+    /// Declared with `rate_limit` and `rate_limit_by`.
+    pub rate_limit: Option<RateLimit>,
+    /// Argument names and types, in declaration order.
+    pub args: Vec<(String, syn::Type)>,
+    /// The declared return type.
+    pub output: syn::Type,
+    /// The function's doc comment.
+    pub doc: String,
 }
 
+/// A struct or enum deriving `Serialize` or `Deserialize`, which server functions may take or
+/// return.
 #[derive(Debug, Clone)]
-pub struct ClientFunction {
+pub struct TypeDef {
+    pub module_path: String,
+    pub item: syn::Item,
+}
+
+/// A `#[job]` or `#[scheduled]` function, which the server runs in the background.
+#[derive(Debug, Clone)]
+pub struct BackgroundFunction {
     pub full_path: String,
     pub fn_name: String,
-    pub hashed_id: String,
+    /// The name its runs are stored under, see `lithe::jobs::Job::name`.
+    pub name: String,
+    /// The cron expression of a `#[scheduled]` function; `None` for jobs.
+    pub cron: Option<String>,
+    pub source: String,
 }
 
-pub fn hash_id_raw(path: &str) -> String {
-    let mut hash: u64 = 5381;
-    for c in path.bytes() {
-        hash = ((hash << 5).wrapping_add(hash)).wrapping_add(c as u64);
+impl BackgroundFunction {
+    /// Path of the `lithe::jobs::Job` or `Scheduled` the macro declares next to the function.
+    pub fn const_path(&self) -> String {
+        let module = &self.full_path[..self.full_path.len() - self.fn_name.len()];
+        match self.cron {
+            Some(_) => format!("{}__LITHE_SCHEDULED_{}", module, self.fn_name),
+            None => format!("{}__LITHE_JOB_{}", module, self.fn_name),
+        }
     }
-    format!("{:x}", hash)
 }
 
-pub fn hash_id(path: &str) -> String {
-    format!("f_{}", hash_id_raw(path))
+/// A `#[socket]` function, which the server runs for each WebSocket connection to it.
+#[derive(Debug, Clone)]
+pub struct SocketFunction {
+    pub full_path: String,
+    pub fn_name: String,
+    pub id: String,
+    pub source: String,
 }
 
-pub fn discover_functions(
-    project_dir: &Path,
-    crate_name: &str,
-) -> Result<(Vec<ClientFunction>, Vec<ServerFunction>)> {
-    let src_dir = project_dir.join("src");
-    let mut client_fns = Vec::new();
-    let mut server_fns = Vec::new();
-    if !src_dir.exists() {
-        return Ok((client_fns, server_fns));
-    }
-    scan_directory(
-        &src_dir,
-        &src_dir,
-        crate_name,
-        &mut client_fns,
-        &mut server_fns,
-    )?;
-    Ok((client_fns, server_fns))
+impl SocketFunction {
+    /// Path of an item the macro declares next to the function, e.g. `__lithe_socket_guard_`.
+    fn sibling(&self, prefix: &str) -> String {
+        let module = &self.full_path[..self.full_path.len() - self.fn_name.len()];
+        format!("{}{}{}", module, prefix, self.fn_name)
+    }
 }
 
-fn scan_directory(
-    dir: &Path,
-    src_root: &Path,
-    crate_name: &str,
-    client_fns: &mut Vec<ClientFunction>,
-    server_fns: &mut Vec<ServerFunction>,
-) -> Result<()> {
-    for entry in fs::read_dir(dir).context(format!("Failed to read directory: {:?}", dir))? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            let dir_name = path.file_name().unwrap().to_str().unwrap();
-            if dir_name == "public" {
-                continue;
+#[derive(Debug, Clone)]
+pub struct ClientFunction {
+    pub full_path: String,
+    pub fn_name: String,
+    pub id: String,
+    pub source: String,
+}
+
+/// What a piece of code is compiled for, used when evaluating `#[cfg(...)]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Server,
+    Wasm,
+}
+
+/// Evaluates the `#[cfg(...)]` attributes in `attrs` for `target`.
+///
+/// `test` is off, `debug_assertions` is on and target predicates follow `target`; anything
+/// else (features, custom cfgs) is assumed to be enabled.
+pub fn cfg_enabled(attrs: &[syn::Attribute], target: Target) -> bool {
+    attrs.iter().filter(|a| a.path().is_ident("cfg")).all(|a| {
+        a.parse_args::<syn::Meta>()
+            .is_ok_and(|m| eval_cfg(&m, target))
+    })
+}
+
+fn eval_cfg(meta: &syn::Meta, target: Target) -> bool {
+    let nested = |list: &syn::MetaList| {
+        list.parse_args_with(Punctuated::<syn::Meta, Token![,]>::parse_terminated)
+            .map(|p| p.into_iter().collect::<Vec<_>>())
+            .unwrap_or_default()
+    };
+    match meta {
+        syn::Meta::List(list) if list.path.is_ident("all") => {
+            nested(list).iter().all(|m| eval_cfg(m, target))
+        }
+        syn::Meta::List(list) if list.path.is_ident("any") => {
+            nested(list).iter().any(|m| eval_cfg(m, target))
+        }
+        syn::Meta::List(list) if list.path.is_ident("not") => {
+            nested(list).first().is_none_or(|m| !eval_cfg(m, target))
+        }
+        syn::Meta::Path(path) if path.is_ident("test") => false,
+        syn::Meta::NameValue(nv) => {
+            let syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(value),
+                ..
+            }) = &nv.value
+            else {
+                return true;
+            };
+            let value = value.value();
+            let wasm = target == Target::Wasm;
+            if nv.path.is_ident("target_arch") {
+                if wasm {
+                    value == "wasm32"
+                } else {
+                    value == std::env::consts::ARCH
+                }
+            } else if nv.path.is_ident("target_family") {
+                (value == "wasm") == wasm
+            } else if nv.path.is_ident("target_os") {
+                if wasm {
+                    value == "unknown"
+                } else {
+                    value == std::env::consts::OS
+                }
+            } else {
+                true
             }
-            scan_directory(&path, src_root, crate_name, client_fns, server_fns)?;
-        } else if path.extension().map_or(false, |ext| ext == "rs") {
-            let file_stem = path.file_stem().unwrap().to_str().unwrap();
-            if file_stem == "lib" || file_stem == "main" || file_stem == "mod" {
+        }
+        _ => true,
+    }
+}
+
+/// Resolves the file a `mod name;` declaration refers to.
+///
+/// `children_dir` is where the declaring module looks for its children: its own directory
+/// for `mod.rs` files and files loaded through `#[path]` (as every module in the generated
+/// `lib.rs` is), and `<dir>/<stem>/` otherwise.
+fn resolve_mod_file(item: &syn::ItemMod, children_dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    let explicit = item.attrs.iter().find_map(|a| match &a.meta {
+        syn::Meta::NameValue(nv) if nv.path.is_ident("path") => match &nv.value {
+            syn::Expr::Lit(syn::ExprLit {
+                lit: syn::Lit::Str(s),
+                ..
+            }) => Some(s.value()),
+            _ => None,
+        },
+        _ => None,
+    });
+    if let Some(explicit) = explicit {
+        let file = children_dir.join(explicit);
+        let dir = file.parent()?.to_path_buf();
+        return file.exists().then_some((file, dir));
+    }
+
+    let name = item.ident.to_string();
+    let flat = children_dir.join(format!("{}.rs", name));
+    if flat.exists() {
+        return Some((flat, children_dir.join(&name)));
+    }
+    let nested = children_dir.join(&name).join("mod.rs");
+    nested.exists().then(|| (nested, children_dir.join(&name)))
+}
+
+/// Returns every file pulled in through `mod name;` declarations from `file`, recursively.
+pub fn nested_module_files(file: &Path) -> Vec<PathBuf> {
+    fn collect(items: &[syn::Item], children_dir: &Path, out: &mut Vec<PathBuf>) {
+        for item in items {
+            let syn::Item::Mod(item_mod) = item else {
                 continue;
+            };
+            match &item_mod.content {
+                Some((_, items)) => {
+                    collect(items, &children_dir.join(item_mod.ident.to_string()), out)
+                }
+                None => {
+                    let Some((path, dir)) = resolve_mod_file(item_mod, children_dir) else {
+                        continue;
+                    };
+                    if let Ok(file) = fs::read_to_string(&path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|c| syn::parse_file(&c).map_err(anyhow::Error::from))
+                    {
+                        collect(&file.items, &dir, out);
+                    }
+                    out.push(path);
+                }
             }
-            scan_file(&path, src_root, crate_name, client_fns, server_fns)?;
         }
     }
-    Ok(())
+
+    let mut out = Vec::new();
+    if let (Some(dir), Ok(content)) = (file.parent(), fs::read_to_string(file))
+        && let Ok(parsed) = syn::parse_file(&content)
+    {
+        collect(&parsed.items, dir, &mut out);
+    }
+    out
 }
 
-fn scan_file(
-    file_path: &Path,
-    src_root: &Path,
-    crate_name: &str,
-    client_fns: &mut Vec<ClientFunction>,
-    server_fns: &mut Vec<ServerFunction>,
-) -> Result<()> {
-    let content =
-        fs::read_to_string(file_path).context(format!("Failed to read file: {:?}", file_path))?;
+/// Where a function's generics start: their `<`, or the `where` of a bare `where` clause, whose
+/// empty generics would otherwise point at the start of the file.
+fn generics_span(generics: &syn::Generics) -> proc_macro2::Span {
+    match (&generics.lt_token, &generics.where_clause) {
+        (None, Some(where_clause)) => where_clause.where_token.span,
+        _ => generics.span(),
+    }
+}
 
-    let relative = file_path
-        .strip_prefix(src_root)
-        .context("File not under src root")?;
+fn find_attr<'a>(attrs: &'a [syn::Attribute], name: &str) -> Option<&'a syn::Attribute> {
+    attrs
+        .iter()
+        .find(|a| a.path().segments.last().is_some_and(|s| s.ident == name))
+}
 
-    let mod_name = relative
-        .with_extension("")
-        .to_string_lossy()
-        .replace(['/', '\\'], "_");
+/// The options of a `#[server(...)]` attribute that shape the generated dispatcher.
+#[derive(Default)]
+struct ServerAttrs {
+    name: Option<syn::LitStr>,
+    get: bool,
+    cache: Option<String>,
+    guards: bool,
+    public: bool,
+    rate_limit: Option<RateLimit>,
+}
 
-    let crate_name_clean = crate_name.replace('-', "_");
-    let module_path = format!("{}::{}", crate_name_clean, mod_name);
-
-    // Scan for #[client]
-    let client_re =
-        Regex::new(r#"#\[(?:[\w:]+::)?client\]\s*(?:pub(?:\([^)]+\))?\s+)?fn\s+(\w+)"#).unwrap();
-    for cap in client_re.captures_iter(&content) {
-        let fn_name = cap[1].to_string();
-        let full_path = format!("{}::{}", module_path, fn_name);
-        client_fns.push(ClientFunction {
-            hashed_id: hash_id(&full_path),
+/// `cache` made safe for replies a guard decides on: shared caches would hand them to callers
+/// the guard never saw, so `public` and `s-maxage` give way to `private`.
+fn private_cache(cache: &str) -> String {
+    let mut directives: Vec<&str> = cache
+        .split(',')
+        .map(str::trim)
+        .filter(|d| {
+            let name = d.split('=').next().unwrap_or_default().trim();
+            !name.eq_ignore_ascii_case("public") && !name.eq_ignore_ascii_case("s-maxage")
+        })
+        .collect();
+    let restricted = directives
+        .iter()
+        .any(|d| d.eq_ignore_ascii_case("private") || d.eq_ignore_ascii_case("no-store"));
+    if !restricted {
+        directives.insert(0, "private");
+    }
+    directives.join(", ")
+}
+
+/// Reads `name`, `method`, `cache`, the guards and the rate limit from a `#[server(...)]`
+/// attribute. The macro validates the rest.
+fn server_attrs(attr: &syn::Attribute) -> syn::Result<ServerAttrs> {
+    let mut attrs = ServerAttrs::default();
+    let (mut rate, mut key) = (None, RateLimitKey::default());
+    if matches!(attr.meta, syn::Meta::List(_)) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                attrs.name = Some(meta.value()?.parse::<syn::LitStr>()?);
+            } else if meta.path.is_ident("method") {
+                let method = meta.value()?.parse::<syn::LitStr>()?;
+                attrs.get = method.value().eq_ignore_ascii_case("GET");
+            } else if meta.path.is_ident("cache") {
+                attrs.cache = Some(meta.value()?.parse::<syn::LitStr>()?.value());
+            } else if meta.path.is_ident("guard") {
+                attrs.guards = true;
+                meta.value()?.parse::<syn::Expr>()?;
+            } else if meta.path.is_ident("public") {
+                attrs.public = true;
+            } else if meta.path.is_ident("rate_limit") {
+                rate = parse_rate(&meta.value()?.parse::<syn::LitStr>()?.value());
+            } else if meta.path.is_ident("rate_limit_by") {
+                let by = meta.value()?.parse::<syn::LitStr>()?.value();
+                key = RateLimitKey::from_name(&by).unwrap_or_default();
+            } else if meta.input.peek(Token![=]) {
+                meta.value()?.parse::<syn::Expr>()?;
+            }
+            Ok(())
+        })?;
+    }
+    attrs.rate_limit = rate.map(|(max, window)| RateLimit { max, window, key });
+    Ok(attrs)
+}
+
+/// Reads the `name` of a `#[job(...)]`, or the cron expression and `name` of a
+/// `#[scheduled("...")]`. The macros validate the rest.
+fn background_attrs(
+    attr: &syn::Attribute,
+    scheduled: bool,
+) -> syn::Result<(Option<syn::LitStr>, Option<syn::LitStr>)> {
+    let (mut name, mut cron) = (None, None);
+    if !matches!(attr.meta, syn::Meta::List(_)) {
+        return Ok((name, cron));
+    }
+    attr.parse_args_with(|input: syn::parse::ParseStream| {
+        if scheduled {
+            cron = Some(input.parse::<syn::LitStr>()?);
+            if input.parse::<Option<Token![,]>>()?.is_none() {
+                return Ok(());
+            }
+        }
+        let options = Punctuated::<syn::MetaNameValue, Token![,]>::parse_terminated(input)?;
+        for option in options {
+            if option.path.is_ident("name")
+                && let syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(value),
+                    ..
+                }) = option.value
+            {
+                name = Some(value);
+            }
+        }
+        Ok(())
+    })?;
+    Ok((name, cron))
+}
+
+struct Scanner<'a> {
+    project_dir: &'a Path,
+    client_fns: Vec<ClientFunction>,
+    server_fns: Vec<ServerFunction>,
+    background_fns: Vec<BackgroundFunction>,
+    socket_fns: Vec<SocketFunction>,
+    types: Vec<TypeDef>,
+    diagnostics: Vec<String>,
+}
+
+/// Where the items being scanned come from.
+#[derive(Clone)]
+struct Scope<'a> {
+    source: &'a str,
+    module_path: String,
+    children_dir: PathBuf,
+    server: bool,
+    wasm: bool,
+    /// The first module on the path that isn't `pub`, which hides the functions below it from
+    /// the generated code.
+    private_mod: Option<String>,
+}
+
+impl Scanner<'_> {
+    fn diagnostic(&mut self, source: &str, span: proc_macro2::Span, message: &str) {
+        let start = span.start();
+        self.diagnostics.push(format!(
+            "{}:{}:{}: {}",
+            source,
+            start.line,
+            start.column + 1,
+            message
+        ));
+    }
+
+    fn scan_file(
+        &mut self,
+        file: &Path,
+        module_path: String,
+        children_dir: PathBuf,
+        (server, wasm): (bool, bool),
+        private_mod: Option<String>,
+    ) -> Result<()> {
+        let content =
+            fs::read_to_string(file).context(format!("Failed to read file: {:?}", file))?;
+        let source = file
+            .strip_prefix(self.project_dir)
+            .unwrap_or(file)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let parsed = match syn::parse_file(&content) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.diagnostic(&source, e.span(), &e.to_string());
+                return Ok(());
+            }
+        };
+        let scope = Scope {
+            source: &source,
+            module_path,
+            children_dir,
+            server: server && cfg_enabled(&parsed.attrs, Target::Server),
+            wasm: wasm && cfg_enabled(&parsed.attrs, Target::Wasm),
+            private_mod,
+        };
+        self.scan_items(&parsed.items, &scope)
+    }
+
+    /// Reports functions with a Lithe attribute that the generated code can't reach because a
+    /// module on their path isn't `pub`.
+    fn check_reachable(&mut self, item_fn: &syn::ItemFn, scope: &Scope) -> bool {
+        let Some(private_mod) = &scope.private_mod else {
+            return true;
+        };
+        let Some(attr) = ["server", "client", "job", "scheduled", "socket"]
+            .into_iter()
+            .find(|name| find_attr(&item_fn.attrs, name).is_some())
+        else {
+            return true;
+        };
+        let message = format!(
+            "`{}`: #[{}] functions must be reachable from the generated code, but module `{}` is private; declare it `pub mod`",
+            item_fn.sig.ident, attr, private_mod
+        );
+        self.diagnostic(scope.source, item_fn.sig.ident.span(), &message);
+        false
+    }
+
+    fn scan_items(&mut self, items: &[syn::Item], scope: &Scope) -> Result<()> {
+        for item in items {
+            match item {
+                syn::Item::Fn(item_fn) => {
+                    let server = scope.server && cfg_enabled(&item_fn.attrs, Target::Server);
+                    let wasm = scope.wasm && cfg_enabled(&item_fn.attrs, Target::Wasm);
+                    if (server || wasm) && !self.check_reachable(item_fn, scope) {
+                        continue;
+                    }
+                    if server && let Some(attr) = find_attr(&item_fn.attrs, "server") {
+                        self.add_server_fn(item_fn, attr, scope);
+                    }
+                    if wasm && find_attr(&item_fn.attrs, "client").is_some() {
+                        self.add_client_fn(item_fn, scope);
+                    }
+                    if server && let Some(attr) = find_attr(&item_fn.attrs, "job") {
+                        self.add_background_fn(item_fn, attr, false, scope);
+                    }
+                    if server && let Some(attr) = find_attr(&item_fn.attrs, "scheduled") {
+                        self.add_background_fn(item_fn, attr, true, scope);
+                    }
+                    if server && let Some(attr) = find_attr(&item_fn.attrs, "socket") {
+                        self.add_socket_fn(item_fn, attr, scope);
+                    }
+                }
+                syn::Item::Struct(syn::ItemStruct { attrs, .. })
+                | syn::Item::Enum(syn::ItemEnum { attrs, .. })
+                    if scope.server
+                        && cfg_enabled(attrs, Target::Server)
+                        && derives_serde(attrs) =>
+                {
+                    self.types.push(TypeDef {
+                        module_path: scope.module_path.clone(),
+                        item: item.clone(),
+                    });
+                }
+                syn::Item::Mod(item_mod) => {
+                    let server = scope.server && cfg_enabled(&item_mod.attrs, Target::Server);
+                    let wasm = scope.wasm && cfg_enabled(&item_mod.attrs, Target::Wasm);
+                    if !server && !wasm {
+                        continue;
+                    }
+                    let module_path = format!("{}::{}", scope.module_path, item_mod.ident);
+                    let private_mod = scope.private_mod.clone().or_else(|| {
+                        (!matches!(item_mod.vis, syn::Visibility::Public(_)))
+                            .then(|| module_path.clone())
+                    });
+                    match &item_mod.content {
+                        Some((_, items)) => {
+                            let inner = Scope {
+                                module_path,
+                                children_dir: scope.children_dir.join(item_mod.ident.to_string()),
+                                server,
+                                wasm,
+                                private_mod,
+                                ..scope.clone()
+                            };
+                            self.scan_items(items, &inner)?;
+                        }
+                        None => match resolve_mod_file(item_mod, &scope.children_dir) {
+                            Some((path, dir)) => self.scan_file(
+                                &path,
+                                module_path,
+                                dir,
+                                (server, wasm),
+                                private_mod,
+                            )?,
+                            None => self.diagnostic(
+                                scope.source,
+                                item_mod.ident.span(),
+                                &format!(
+                                    "file not found for module `{0}`, expected {1}/{0}.rs or {1}/{0}/mod.rs",
+                                    item_mod.ident,
+                                    scope
+                                        .children_dir
+                                        .strip_prefix(self.project_dir)
+                                        .unwrap_or(&scope.children_dir)
+                                        .display()
+                                ),
+                            ),
+                        },
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add_server_fn(&mut self, item_fn: &syn::ItemFn, attr: &syn::Attribute, scope: &Scope) {
+        let sig = &item_fn.sig;
+        let mut problems = Vec::new();
+        let attrs = match server_attrs(attr) {
+            Ok(attrs) => attrs,
+            Err(e) => {
+                problems.push((e.span(), e.to_string()));
+                ServerAttrs::default()
+            }
+        };
+        if let Some(name) = &attrs.name
+            && !is_valid_function_name(&name.value())
+        {
+            problems.push((
+                name.span(),
+                "server function names may only contain letters, digits, `_`, `-`, `.` and `/`"
+                    .to_string(),
+            ));
+        }
+        if sig.asyncness.is_none() {
+            problems.push((
+                sig.fn_token.span,
+                "#[server] functions must be async".to_string(),
+            ));
+        }
+        if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+            problems.push((
+                generics_span(&sig.generics),
+                "#[server] functions cannot be generic".to_string(),
+            ));
+        }
+        for input in &sig.inputs {
+            match input {
+                syn::FnArg::Receiver(r) => problems.push((
+                    r.self_token.span,
+                    "#[server] functions cannot take `self`".to_string(),
+                )),
+                syn::FnArg::Typed(pat_type) => {
+                    if !matches!(&*pat_type.pat, syn::Pat::Ident(_)) {
+                        problems.push((
+                            pat_type.pat.span(),
+                            "#[server] arguments must be plain identifiers".to_string(),
+                        ));
+                    }
+                    if matches!(&*pat_type.ty, syn::Type::ImplTrait(_)) {
+                        problems.push((
+                            pat_type.ty.span(),
+                            "#[server] arguments cannot use `impl Trait`".to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        if matches!(sig.output, syn::ReturnType::Default) {
+            problems.push((
+                sig.ident.span(),
+                "#[server] functions must return `Result<T, ServerFnError<E>>` or `impl Stream<Item = T>`"
+                    .to_string(),
+            ));
+        }
+        if !problems.is_empty() {
+            for (span, message) in problems {
+                self.diagnostic(scope.source, span, &format!("`{}`: {}", sig.ident, message));
+            }
+            return;
+        }
+
+        let args = sig
+            .inputs
+            .iter()
+            .filter_map(|input| match input {
+                syn::FnArg::Typed(pat_type) => match &*pat_type.pat {
+                    syn::Pat::Ident(pat) => Some((pat.ident.to_string(), (*pat_type.ty).clone())),
+                    _ => None,
+                },
+                syn::FnArg::Receiver(_) => None,
+            })
+            .collect();
+        let syn::ReturnType::Type(_, output) = &sig.output else {
+            unreachable!("checked above");
+        };
+
+        let fn_name = sig.ident.to_string();
+        let full_path = format!("{}::{}", scope.module_path, fn_name);
+        self.server_fns.push(ServerFunction {
+            id: attrs
+                .name
+                .map_or_else(|| function_id(&full_path), |n| n.value()),
             full_path,
             fn_name,
+            source: scope.source.to_string(),
+            streaming: returns_stream(&sig.output),
+            get: attrs.get,
+            cache: attrs.cache,
+            guards: attrs.guards,
+            public: attrs.public,
+            rate_limit: attrs.rate_limit,
+            args,
+            output: (**output).clone(),
+            doc: doc_comment(&item_fn.attrs),
         });
     }
 
-    // Scan for #[server]
-    let server_re = Regex::new(
-        r#"#\[(?:[\w:]+::)?server\]\s*(?:pub(?:\([^)]+\))?\s+)?async\s+fn\s+(\w+)\s*\(([^)]*)\)"#,
-    )
-    .unwrap();
-    for cap in server_re.captures_iter(&content) {
-        let fn_name = cap[1].to_string();
-        let args_raw = &cap[2];
-
-        let mut arg_types = Vec::new();
-        for arg in args_raw.split(',') {
-            let arg = arg.trim();
-            if arg.is_empty() {
-                continue;
-            }
-            if let Some(pos) = arg.find(':') {
-                arg_types.push(arg[pos + 1..].trim().to_string());
+    fn add_background_fn(
+        &mut self,
+        item_fn: &syn::ItemFn,
+        attr: &syn::Attribute,
+        scheduled: bool,
+        scope: &Scope,
+    ) {
+        let sig = &item_fn.sig;
+        let kind = if scheduled { "#[scheduled]" } else { "#[job]" };
+        let mut problems = Vec::new();
+        let (name, cron) = background_attrs(attr, scheduled).unwrap_or_else(|e| {
+            problems.push((e.span(), e.to_string()));
+            (None, None)
+        });
+        if let Some(cron) = &cron
+            && let Err(e) = Schedule::parse(&cron.value())
+        {
+            problems.push((cron.span(), e));
+        }
+        if scheduled && cron.is_none() && problems.is_empty() {
+            problems.push((
+                attr.span(),
+                "expected a cron expression such as #[scheduled(\"0 3 * * *\")]".to_string(),
+            ));
+        }
+        if let Some(name) = &name
+            && !is_valid_function_name(&name.value())
+        {
+            problems.push((
+                name.span(),
+                format!(
+                    "{} names may only contain letters, digits, `_`, `-`, `.` and `/`",
+                    kind
+                ),
+            ));
+        }
+        if sig.asyncness.is_none() {
+            problems.push((
+                sig.fn_token.span,
+                format!("{} functions must be async", kind),
+            ));
+        }
+        if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+            problems.push((
+                generics_span(&sig.generics),
+                format!("{} functions cannot be generic", kind),
+            ));
+        }
+        if scheduled && !sig.inputs.is_empty() {
+            problems.push((
+                sig.inputs.span(),
+                "#[scheduled] functions cannot take arguments".to_string(),
+            ));
+        }
+        if !problems.is_empty() {
+            for (span, message) in problems {
+                self.diagnostic(scope.source, span, &format!("`{}`: {}", sig.ident, message));
             }
+            return;
         }
 
-        let args_type = if arg_types.is_empty() {
-            "()".to_string()
-        } else if arg_types.len() == 1 {
-            arg_types[0].clone()
-        } else {
-            format!("({})", arg_types.join(", "))
+        let fn_name = sig.ident.to_string();
+        let full_path = format!("{}::{}", scope.module_path, fn_name);
+        self.background_fns.push(BackgroundFunction {
+            name: name.map_or_else(|| full_path.clone(), |n| n.value()),
+            full_path,
+            fn_name,
+            cron: cron.map(|c| c.value()),
+            source: scope.source.to_string(),
+        });
+    }
+
+    fn add_socket_fn(&mut self, item_fn: &syn::ItemFn, attr: &syn::Attribute, scope: &Scope) {
+        let sig = &item_fn.sig;
+        let mut problems = Vec::new();
+        let name = match server_attrs(attr) {
+            Ok(attrs) => attrs.name,
+            Err(e) => {
+                problems.push((e.span(), e.to_string()));
+                None
+            }
         };
+        if let Some(name) = &name
+            && !is_valid_function_name(&name.value())
+        {
+            problems.push((
+                name.span(),
+                "socket names may only contain letters, digits, `_`, `-`, `.` and `/`".to_string(),
+            ));
+        }
+        if sig.asyncness.is_none() {
+            problems.push((
+                sig.fn_token.span,
+                "#[socket] functions must be async".to_string(),
+            ));
+        }
+        if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+            problems.push((
+                generics_span(&sig.generics),
+                "#[socket] functions cannot be generic".to_string(),
+            ));
+        }
+        if sig.inputs.len() != 1 || matches!(sig.inputs.first(), Some(syn::FnArg::Receiver(_))) {
+            problems.push((
+                sig.inputs.span(),
+                "#[socket] functions take a single `lithe::socket::Socket<In, Out>` argument"
+                    .to_string(),
+            ));
+        }
+        if !problems.is_empty() {
+            for (span, message) in problems {
+                self.diagnostic(scope.source, span, &format!("`{}`: {}", sig.ident, message));
+            }
+            return;
+        }
+
+        let fn_name = sig.ident.to_string();
+        let full_path = format!("{}::{}", scope.module_path, fn_name);
+        self.socket_fns.push(SocketFunction {
+            id: name.map_or_else(|| function_id(&full_path), |n| n.value()),
+            full_path,
+            fn_name,
+            source: scope.source.to_string(),
+        });
+    }
 
-        let full_path = format!("{}::{}", module_path, fn_name);
-        server_fns.push(ServerFunction {
-            hashed_id: hash_id(&full_path),
+    fn add_client_fn(&mut self, item_fn: &syn::ItemFn, scope: &Scope) {
+        let sig = &item_fn.sig;
+        if !sig.inputs.is_empty() {
+            self.diagnostic(
+                scope.source,
+                sig.inputs.span(),
+                &format!("`{}`: #[client] functions cannot take arguments", sig.ident),
+            );
+            return;
+        }
+        if !sig.generics.params.is_empty() {
+            self.diagnostic(
+                scope.source,
+                generics_span(&sig.generics),
+                &format!("`{}`: #[client] functions cannot be generic", sig.ident),
+            );
+            return;
+        }
+
+        let fn_name = sig.ident.to_string();
+        let full_path = format!("{}::{}", scope.module_path, fn_name);
+        self.client_fns.push(ClientFunction {
+            id: function_id(&full_path),
             full_path,
             fn_name,
-            args_type,
+            source: scope.source.to_string(),
         });
     }
+}
+
+/// Parses every module of the project, failing on unsupported server or client functions.
+fn scan<'a>(project_dir: &'a Path, crate_name: &str) -> Result<Scanner<'a>> {
+    let crate_name_clean = crate_name.replace('-', "_");
+    let mut scanner = Scanner {
+        project_dir,
+        client_fns: Vec::new(),
+        server_fns: Vec::new(),
+        background_fns: Vec::new(),
+        socket_fns: Vec::new(),
+        types: Vec::new(),
+        diagnostics: Vec::new(),
+    };
+    let src_dir = project_dir.join("src");
+    for (mod_name, rel_path) in generate::discover_modules(project_dir)? {
+        let file = src_dir.join(rel_path.trim_start_matches("../src/"));
+        // Files loaded through `#[path]` look for their children in their own directory.
+        let children_dir = file.parent().unwrap_or(&src_dir).to_path_buf();
+        scanner.scan_file(
+            &file,
+            format!("{}::{}", crate_name_clean, mod_name),
+            children_dir,
+            (true, true),
+            None,
+        )?;
+    }
+
+    if !scanner.diagnostics.is_empty() {
+        anyhow::bail!(
+            "Unsupported server or client functions:\n  {}",
+            scanner.diagnostics.join("\n  ")
+        );
+    }
+    Ok(scanner)
+}
+
+/// What the project's modules declare, found in a single pass over them, see
+/// [`discover_items`].
+pub struct ProjectItems {
+    pub client_fns: Vec<ClientFunction>,
+    pub server_fns: Vec<ServerFunction>,
+    /// `#[job]` and `#[scheduled]` functions.
+    pub background_fns: Vec<BackgroundFunction>,
+    pub socket_fns: Vec<SocketFunction>,
+    /// The serde types defined in the project's server code, in module order.
+    pub types: Vec<TypeDef>,
+}
+
+/// Scans the project once for everything the generated code needs, failing on functions that
+/// share an id. Functions are sorted by path.
+pub fn discover_items(project_dir: &Path, crate_name: &str) -> Result<ProjectItems> {
+    let Scanner {
+        mut client_fns,
+        mut server_fns,
+        mut background_fns,
+        mut socket_fns,
+        types,
+        ..
+    } = scan(project_dir, crate_name)?;
+    check_unique_ids(
+        "server function",
+        server_fns
+            .iter()
+            .map(|f| (f.id.as_str(), f.full_path.as_str())),
+    )?;
+    check_unique_ids(
+        "client function",
+        client_fns
+            .iter()
+            .map(|f| (f.id.as_str(), f.full_path.as_str())),
+    )?;
+    check_unique_ids(
+        "job",
+        background_fns
+            .iter()
+            .filter(|f| f.cron.is_none())
+            .map(|f| (f.name.as_str(), f.full_path.as_str())),
+    )?;
+    check_unique_ids(
+        "scheduled task",
+        background_fns
+            .iter()
+            .filter(|f| f.cron.is_some())
+            .map(|f| (f.name.as_str(), f.full_path.as_str())),
+    )?;
+    check_unique_ids(
+        "socket",
+        socket_fns
+            .iter()
+            .map(|f| (f.id.as_str(), f.full_path.as_str())),
+    )?;
+    client_fns.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+    server_fns.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+    background_fns.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+    socket_fns.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+    Ok(ProjectItems {
+        client_fns,
+        server_fns,
+        background_fns,
+        socket_fns,
+        types,
+    })
+}
+
+/// Writes `.lithe/jobs.rs`, listing the background functions for `lithe::jobs::start`. Projects
+/// without any get no file, and their server doesn't start the runner.
+pub fn generate_jobs(project_dir: &Path, background_fns: &[BackgroundFunction]) -> Result<()> {
+    let path = project_dir.join(".lithe/jobs.rs");
+    if background_fns.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).context("Failed to remove .lithe/jobs.rs")?;
+        }
+        return Ok(());
+    }
+    let list = |scheduled: bool| -> String {
+        background_fns
+            .iter()
+            .filter(|f| f.cron.is_some() == scheduled)
+            .map(|f| format!("    {},\n", f.const_path()))
+            .collect()
+    };
+    let output = format!(
+        r#"// Auto-generated by lithe-cli - do not edit manually
+use lithe::jobs::{{Job, Scheduled}};
 
+/// The project's `#[job]` functions.
+pub static JOBS: &[Job] = &[
+{}];
+
+/// The project's `#[scheduled]` functions.
+pub static SCHEDULED: &[Scheduled] = &[
+{}];
+"#,
+        list(false),
+        list(true)
+    );
+    fs::write(path, output).context("Failed to write .lithe/jobs.rs")
+}
+
+/// Writes `.lithe/sockets.rs`, the WebSocket route of the `#[socket]` functions. Projects
+/// without any get no file, and their server no route.
+pub fn generate_sockets(
+    project_dir: &Path,
+    socket_fns: &[SocketFunction],
+    default_guard: Option<&str>,
+    csrf: &CsrfConfig,
+    max_message_size: usize,
+) -> Result<()> {
+    let path = project_dir.join(".lithe/sockets.rs");
+    if socket_fns.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path).context("Failed to remove .lithe/sockets.rs")?;
+        }
+        return Ok(());
+    }
+    if !axum_has_ws(project_dir)? {
+        anyhow::bail!(
+            "#[socket] functions need axum's `ws` feature; in Cargo.toml, use axum = {{ version = \"0.7\", features = [\"ws\"] }}"
+        );
+    }
+
+    let mut guards = String::new();
+    let mut handlers = String::new();
+    for func in socket_fns {
+        let default = match default_guard {
+            Some(_) => format!(
+                "            default_guard(encoding, {}).await?;\n",
+                func.sibling("__LITHE_SOCKET_GUARDED_")
+            ),
+            None => String::new(),
+        };
+        guards.push_str(&format!(
+            "        {:?} => {{\n{}            {}(encoding).await?;\n            Ok(true)\n        }}\n",
+            func.id,
+            default,
+            func.sibling("__lithe_socket_guard_")
+        ));
+        handlers.push_str(&format!(
+            "        {:?} => {}(encoding, io).await,\n",
+            func.id,
+            func.sibling("__lithe_socket_wrapper_")
+        ));
+    }
+    let default_guard = match default_guard {
+        Some(guard) => format!(
+            r#"
+/// Runs the project's default guard for sockets that declare neither `guard` nor `public`.
+async fn default_guard(encoding: Encoding, guarded: bool) -> Result<(), RpcReply> {{
+    if guarded {{
+        return Ok(());
+    }}
+    {}()
+        .await
+        .map_err(|e| RpcReply::error(encoding, e))
+}}
+"#,
+            guard
+        ),
+        None => String::new(),
+    };
+
+    let output = format!(
+        r#"// Auto-generated by lithe-cli - do not edit manually
+// `#[socket]` functions need axum's `ws` feature: axum = {{ version = "0.7", features = ["ws"] }}
+use axum::{{
+    extract::ws::{{Message, WebSocket, WebSocketUpgrade}},
+    extract::{{ConnectInfo, Path, Query}},
+    http::{{HeaderMap, Method, StatusCode, Uri, header}},
+    response::{{IntoResponse, Response}},
+}};
+use lithe::rpc::{{Encoding, RpcReply, ServerFnError}};
+use lithe::server::{{RequestContext, ResponseParts}};
+use lithe::socket::SocketRemote;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+
+/// Origins from `[package.metadata.lithe.csrf]` allowed to open sockets.
+const TRUSTED_ORIGINS: &[&str] = &{trusted_origins:?};
+
+/// Opens a WebSocket to the `#[socket]` function named by the path, e.g.
+/// `GET /api/lithe-socket/{{id}}?encoding=json`, once its guards accept the handshake.
+pub async fn handle_socket(
+    upgrade: WebSocketUpgrade,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {{
+    let request = RequestContext {{
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    }};
+    let encoding = params
+        .get("encoding")
+        .and_then(|name| Encoding::from_name(name))
+        .unwrap_or_default();
+    if let Err(e) = lithe::server::check_socket_origin(&request, TRUSTED_ORIGINS) {{
+        let reply = RpcReply::error(encoding, ServerFnError::<()>::Forbidden(e));
+        return reply_response(reply, ResponseParts::default());
+    }}
+    let (accepted, parts) = lithe::server::scope(request.clone(), guard(&id, encoding)).await;
+    match accepted {{
+        Ok(true) => {{}}
+        Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+        Err(reply) => return reply_response(reply, parts),
+    }}
+    upgrade
+        .max_message_size({max_message_size})
+        .on_upgrade(move |socket| async move {{
+            let (io, remote) = lithe::socket::channel();
+            let handler = async move {{
+                lithe::telemetry::observe_socket(&id, async {{
+                    match id.as_str() {{
+{handlers}                        _ => Ok(()),
+                    }}
+                }})
+                .await
+            }};
+            lithe::server::scope(request, pump(socket, encoding, remote, handler)).await;
+        }})
+}}
+
+/// Runs the guards of socket `id`, returning whether there is such a socket.
+async fn guard(id: &str, encoding: Encoding) -> Result<bool, RpcReply> {{
+    match id {{
+{guards}        _ => Ok(false),
+    }}
+}}
+{default_guard}
+/// Carries messages between the WebSocket and the function until either side is done. Once
+/// the client is gone the function's `recv` returns `None` and its sends fail, so it can
+/// finish on its own.
+async fn pump(
+    mut socket: WebSocket,
+    encoding: Encoding,
+    remote: SocketRemote,
+    handler: impl Future<Output = ()>,
+) {{
+    let SocketRemote {{ incoming, mut outgoing }} = remote;
+    let mut handler = std::pin::pin!(handler);
+    // A message the function has no room for yet; reading waits until it has.
+    let mut pending: Option<Vec<u8>> = None;
+    loop {{
+        tokio::select! {{
+            () = &mut handler => {{
+                while let Ok(bytes) = outgoing.try_recv() {{
+                    if socket.send(frame(encoding, bytes)).await.is_err() {{
+                        break;
+                    }}
+                }}
+                let _ = socket.close().await;
+                return;
+            }}
+            permit = incoming.reserve(), if pending.is_some() => match permit {{
+                Ok(permit) => permit.send(pending.take().unwrap_or_default()),
+                // The function dropped its socket; what the client sends goes nowhere.
+                Err(_) => pending = None,
+            }},
+            message = socket.recv(), if pending.is_none() => match message {{
+                Some(Ok(Message::Text(text))) => pending = Some(text.into_bytes()),
+                Some(Ok(Message::Binary(bytes))) => pending = Some(bytes),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {{}}
+            }},
+            Some(bytes) = outgoing.recv() => {{
+                if socket.send(frame(encoding, bytes)).await.is_err() {{
+                    break;
+                }}
+            }}
+        }}
+    }}
+    // Reading on answers a client's close frame and finishes the closing handshake.
+    while let Some(Ok(_)) = socket.recv().await {{}}
+    drop((incoming, outgoing));
+    handler.await;
+}}
+
+/// JSON goes out in text frames, the binary encodings in binary ones.
+fn frame(encoding: Encoding, bytes: Vec<u8>) -> Message {{
+    match encoding {{
+        Encoding::Json => Message::Text(String::from_utf8_lossy(&bytes).into_owned()),
+        _ => Message::Binary(bytes),
+    }}
+}}
+
+fn reply_response(reply: RpcReply, parts: ResponseParts) -> Response {{
+    let status = parts
+        .status
+        .unwrap_or(StatusCode::from_u16(reply.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+    let mut response = (
+        status,
+        [(header::CONTENT_TYPE, reply.encoding.content_type())],
+        reply.body,
+    )
+        .into_response();
+    response.headers_mut().extend(parts.headers);
+    response
+}}
+"#,
+        trusted_origins = csrf.trusted_origins,
+        max_message_size = max_message_size,
+        handlers = handlers
+            .lines()
+            .map(|line| format!("                {}\n", line))
+            .collect::<String>(),
+        guards = guards,
+        default_guard = default_guard,
+    );
+    fs::write(path, output).context("Failed to write .lithe/sockets.rs")
+}
+
+/// Whether the project's `axum` dependency, in `[dependencies]` or a target's, enables `ws`.
+fn axum_has_ws(project_dir: &Path) -> Result<bool> {
+    let content =
+        fs::read_to_string(project_dir.join("Cargo.toml")).context("Failed to read Cargo.toml")?;
+    let manifest: toml::Value = toml::from_str(&content).context("Failed to parse Cargo.toml")?;
+    let targets = manifest.get("target").and_then(|t| t.as_table());
+    let has_ws = std::iter::once(&manifest)
+        .chain(targets.into_iter().flat_map(|t| t.values()))
+        .filter_map(|table| {
+            table
+                .get("dependencies")?
+                .get("axum")?
+                .get("features")?
+                .as_array()
+        })
+        .flatten()
+        .any(|feature| feature.as_str() == Some("ws"));
+    Ok(has_ws)
+}
+
+/// Whether `attrs` include `#[derive(Serialize)]` or `#[derive(Deserialize)]`.
+fn derives_serde(attrs: &[syn::Attribute]) -> bool {
+    attrs
+        .iter()
+        .filter(|a| a.path().is_ident("derive"))
+        .any(|a| {
+            a.parse_args_with(
+                syn::punctuated::Punctuated::<syn::Path, syn::Token![,]>::parse_terminated,
+            )
+            .is_ok_and(|paths| {
+                paths.iter().any(|path| {
+                    path.segments
+                        .last()
+                        .is_some_and(|s| s.ident == "Serialize" || s.ident == "Deserialize")
+                })
+            })
+        })
+}
+
+/// The text of the `///` comments in `attrs`.
+pub fn doc_comment(attrs: &[syn::Attribute]) -> String {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter_map(|a| match &a.meta {
+            syn::Meta::NameValue(nv) if nv.path.is_ident("doc") => match &nv.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(s),
+                    ..
+                }) => Some(s.value()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    lines
+        .iter()
+        .map(|line| line.strip_prefix(' ').unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Fails when two different items were given the same id.
+pub fn check_unique_ids<'a>(
+    kind: &str,
+    entries: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<()> {
+    let mut seen: BTreeMap<&str, &str> = BTreeMap::new();
+    let mut collisions = Vec::new();
+    for (id, path) in entries {
+        match seen.get(id) {
+            Some(existing) if *existing != path => {
+                collisions.push(format!("{}: {} and {}", id, existing, path))
+            }
+            Some(_) => {}
+            None => {
+                seen.insert(id, path);
+            }
+        }
+    }
+    if !collisions.is_empty() {
+        anyhow::bail!(
+            "Conflicting {} ids (rename one, or give it an explicit `name = \"...\"`):\n  {}",
+            kind,
+            collisions.join("\n  ")
+        );
+    }
     Ok(())
 }
 
+/// Whether a server function returns `impl Stream<...>`, mirroring the `#[server]` macro.
+fn returns_stream(output: &syn::ReturnType) -> bool {
+    let syn::ReturnType::Type(_, ty) = output else {
+        return false;
+    };
+    let syn::Type::ImplTrait(impl_trait) = &**ty else {
+        return false;
+    };
+    impl_trait.bounds.iter().any(|bound| {
+        matches!(bound, syn::TypeParamBound::Trait(t)
+            if t.path.segments.last().is_some_and(|s| s.ident == "Stream"))
+    })
+}
+
 pub fn generate_rpc_dispatcher(
     project_dir: &Path,
-    _project_name: &str,
+    project_name: &str,
     functions: &[ServerFunction],
+    default_guard: Option<&str>,
+    csrf: &CsrfConfig,
 ) -> Result<()> {
+    let exempt = csrf_exempt(functions, project_name, &csrf.exempt)?;
     let lithe_dir = project_dir.join(".lithe");
     let mut output = String::new();
 
     output.push_str("// Auto-generated by lithe-cli - do not edit manually\n");
-    output.push_str("use axum::{Json, response::IntoResponse, http::StatusCode};\n");
-    output.push_str("use lithe::rpc::{RpcRequest, RpcResponse};\n\n");
+    output.push_str(
+        r#"use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Path, Query},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use lithe::rpc::{
+    ARGS_PARAM, BUILD_HEADER, Encoding, Encodings, FUNCTION_HEADER, FUNCTION_PARAM, RpcReply,
+    RpcRequest, ServerFnError, StreamFormat,
+};
+use lithe::server::{RequestContext, ResponseParts, RpcStream};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 
-    output
-        .push_str("pub async fn handle_rpc(Json(req): Json<RpcRequest>) -> impl IntoResponse {\n");
-    output.push_str("    let result = match req.function.as_str() {\n");
+pub async fn handle_rpc(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let function = request.header(FUNCTION_HEADER).map(str::to_string);
+    call(request, function, body).await
+}
 
-    for func in functions {
-        output.push_str(&format!("        \"{}\" => {{\n", func.hashed_id));
-        output.push_str(&format!(
-            "            let args: {} = serde_json::from_value(req.args).unwrap();\n",
-            func.args_type
-        ));
+/// Calls a `method = "GET"` server function with its JSON arguments in the query string.
+/// Successful replies carry an `ETag`, and conditional requests that still match get
+/// `304 Not Modified` without a body.
+pub async fn handle_rpc_get(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let function = params.get(FUNCTION_PARAM).cloned().unwrap_or_default();
+    call_get(request, function, &params).await
+}
 
-        let internal_fn = format!("__lithe_rpc_wrapper_{}", func.fn_name);
+/// Calls the server function named by the path, e.g. `POST /api/lithe-rpc/fn/{id}`, so each
+/// function has its own URL for clients generated from `.lithe/openapi.json`.
+pub async fn handle_rpc_function(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(function): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    if is_streaming(&function) {
+        call_stream(request, function, body).await
+    } else {
+        call(request, Some(function), body).await
+    }
+}
 
-        output.push_str(&format!(
-            "            {}::{}(args).await\n",
-            func.full_path
-                .strip_suffix(&func.fn_name)
-                .unwrap()
-                .trim_end_matches("::"),
-            internal_fn
+/// The `GET` form of [`handle_rpc_function`], for `method = "GET"` functions.
+pub async fn handle_rpc_function_get(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(function): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    call_get(request, function, &params).await
+}
+
+/// Runs a batch of calls concurrently and replies with one result per call.
+pub async fn handle_rpc_batch(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let (reply, parts) = match preflight_post(&request, None) {
+        Err(reply) => (reply, ResponseParts::default()),
+        Ok(encodings) => {
+            lithe::server::batch(request, encodings, &body, |call| async move {
+                dispatch(&call.function, encodings, &call.args.0).await
+            })
+            .await
+        }
+    };
+    reply_response(reply, parts)
+}
+
+/// Calls a streaming server function and sends its items as NDJSON, or as SSE when asked.
+pub async fn handle_rpc_stream(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let function = request
+        .header(FUNCTION_HEADER)
+        .unwrap_or_default()
+        .to_string();
+    call_stream(request, function, body).await
+}
+
+async fn call(request: RequestContext, function: Option<String>, body: Bytes) -> Response {
+    let encodings = match preflight_post(&request, function.as_deref()) {
+        Ok(encodings) => encodings,
+        Err(reply) => return reply_response(reply, ResponseParts::default()),
+    };
+    // Calls with files are `multipart/form-data`, with the encoded arguments in one part.
+    let (body, encodings, uploads) =
+        match lithe::server::read_uploads(&request, encodings, body.into()).await {
+            Ok(split) => split,
+            Err(e) => {
+                let reply = RpcReply::error(encodings.response, ServerFnError::<()>::Args(e));
+                return reply_response(reply, ResponseParts::default());
+            }
+        };
+    let (reply, parts) = lithe::server::scope(
+        request,
+        lithe::server::with_uploads(uploads, async move {
+            if let Some(function) = function {
+                return dispatch(&function, encodings, &body).await;
+            }
+            // Calls without the function header carry a JSON `{"function": ..., "args": ...}` body.
+            match lithe::serde_json::from_slice::<RpcRequest>(&body) {
+                Ok(req) => {
+                    let encodings = Encodings {
+                        request: Encoding::Json,
+                        ..encodings
+                    };
+                    dispatch(&req.function, encodings, req.args.to_string().as_bytes()).await
+                }
+                Err(e) => RpcReply::error(
+                    encodings.response,
+                    ServerFnError::<()>::Args(e.to_string()),
+                ),
+            }
+        }),
+    )
+    .await;
+    reply_response(reply, parts)
+}
+
+async fn call_get(
+    request: RequestContext,
+    function: String,
+    params: &HashMap<String, String>,
+) -> Response {
+    // Functions without arguments take `()`, which is `null` in JSON.
+    let args = params.get(ARGS_PARAM).map_or("null", String::as_str).to_string();
+    let if_none_match = request
+        .header(header::IF_NONE_MATCH.as_str())
+        .map(str::to_string);
+    let (reply, mut parts) = match preflight(&request) {
+        Err(reply) => (reply, ResponseParts::default()),
+        Ok(encodings) => {
+            let encodings = Encodings {
+                request: Encoding::Json,
+                ..encodings
+            };
+            let function = function.clone();
+            lithe::server::scope(request, async move {
+                dispatch_get(&function, encodings, args.as_bytes()).await
+            })
+            .await
+        }
+    };
+    if reply.status != 200 || parts.status.is_some() {
+        return reply_response(reply, parts);
+    }
+
+    let etag = reply.etag();
+    // The reply depends on the encoding the caller accepts.
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    if let Some((cache, guarded)) = cache_control(&function) {
+        parts
+            .headers
+            .entry(header::CACHE_CONTROL)
+            .or_insert(HeaderValue::from_static(cache));
+        if guarded {
+            // Who may see the reply depends on the caller's credentials.
+            parts
+                .headers
+                .append(header::VARY, HeaderValue::from_static("cookie, authorization"));
+        }
+    }
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        parts.headers.insert(header::ETAG, value);
+    }
+    if if_none_match.is_some_and(|tags| lithe::rpc::etag_matches(&tags, &etag)) {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        response.headers_mut().extend(parts.headers);
+        return response;
+    }
+    reply_response(reply, parts)
+}
+
+async fn call_stream(request: RequestContext, function: String, body: Bytes) -> Response {
+    let format = StreamFormat::from_accept(request.header(header::ACCEPT.as_str()));
+    let (result, parts) = match preflight_post(&request, Some(&function)) {
+        Err(reply) => (Err(reply), ResponseParts::default()),
+        Ok(encodings) => {
+            lithe::server::scope(request, async move {
+                dispatch_stream(&function, encodings, &body).await
+            })
+            .await
+        }
+    };
+    match result {
+        Ok(stream) => {
+            let mut response = (
+                parts.status.unwrap_or(StatusCode::OK),
+                [(header::CONTENT_TYPE, format.content_type())],
+                Body::from_stream(stream.into_frames(format)),
+            )
+                .into_response();
+            response.headers_mut().extend(parts.headers);
+            response
+        }
+        Err(reply) => reply_response(reply, parts),
+    }
+}
+
+/// Negotiates the encodings of a call and rejects calls from outdated builds. Multipart calls
+/// declare their arguments' encoding in a part, see `lithe::server::read_uploads`.
+fn preflight(request: &RequestContext) -> Result<Encodings, RpcReply> {
+    let Some(encodings) = Encodings::negotiate(
+        request
+            .header(header::CONTENT_TYPE.as_str())
+            .filter(|ct| !lithe::server::is_multipart(ct)),
+        request.header(header::ACCEPT.as_str()),
+    ) else {
+        let mut reply = RpcReply::error(
+            Encoding::Json,
+            ServerFnError::<()>::Args("unsupported content type".to_string()),
+        );
+        reply.status = StatusCode::UNSUPPORTED_MEDIA_TYPE.as_u16();
+        return Err(reply);
+    };
+    if request
+        .header(BUILD_HEADER)
+        .is_some_and(|build| build != crate::routes::BUILD_ID)
+    {
+        return Err(RpcReply::error(
+            encodings.response,
+            ServerFnError::<()>::Outdated(
+                "the app was updated after this page was loaded".to_string(),
+            ),
         ));
-        output.push_str("        }\n");
     }
+    Ok(encodings)
+}
 
+/// [`preflight`] for calls that may change state, which also rejects calls made by other
+/// sites unless the function is exempt.
+fn preflight_post(request: &RequestContext, function: Option<&str>) -> Result<Encodings, RpcReply> {
+    let encodings = preflight(request)?;
+    if function.is_some_and(csrf_exempt) {
+        return Ok(encodings);
+    }
+    lithe::server::check_csrf(request, TRUSTED_ORIGINS)
+        .map(|()| encodings)
+        .map_err(|e| RpcReply::error(encodings.response, ServerFnError::<()>::Forbidden(e)))
+}
+
+fn reply_response(reply: RpcReply, parts: ResponseParts) -> Response {
+    let status = parts
+        .status
+        .unwrap_or(StatusCode::from_u16(reply.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+    let mut response = (
+        status,
+        [(header::CONTENT_TYPE, reply.encoding.content_type())],
+        reply.body,
+    )
+        .into_response();
+    response.headers_mut().extend(parts.headers);
+    response
+}
+"#,
+    );
+
+    let (streaming, plain): (Vec<&ServerFunction>, Vec<&ServerFunction>) =
+        functions.iter().partition(|f| f.streaming);
+    output.push_str(
+        "\nasync fn dispatch(function: &str, encodings: Encodings, args: &[u8]) -> RpcReply {\n",
+    );
+    output.push_str(&observed(dispatch_body(&plain, default_guard, false)));
+    output.push_str("}\n");
     output.push_str(
-        "        _ => return (StatusCode::NOT_FOUND, \"Function not found\").into_response(),\n",
+        "\nasync fn dispatch_stream(\n    function: &str,\n    encodings: Encodings,\n    args: &[u8],\n) -> Result<RpcStream, RpcReply> {\n",
     );
-    output.push_str("    };\n\n");
+    output.push_str(&observed(dispatch_body(&streaming, default_guard, true)));
+    output.push_str("}\n");
+
+    output.push_str("\nfn is_streaming(function: &str) -> bool {\n");
+    if streaming.is_empty() {
+        output.push_str("    let _ = function;\n    false\n");
+    } else {
+        let ids: Vec<String> = streaming.iter().map(|f| format!("{:?}", f.id)).collect();
+        output.push_str(&format!("    matches!(function, {})\n", ids.join(" | ")));
+    }
+    output.push_str("}\n");
 
-    output.push_str("    Json(RpcResponse { result }).into_response()\n");
+    // Only generated when used, see `dispatch_body`.
+    let limited: Vec<&ServerFunction> = functions
+        .iter()
+        .filter(|f| f.rate_limit.is_some())
+        .collect();
+    if !limited.is_empty() {
+        output.push_str(
+            "\n/// Limits declared with `#[server(rate_limit = \"...\")]`.\nfn rate_limit(function: &str) -> Option<lithe::rpc::RateLimit> {\n    match function {\n",
+        );
+        for func in limited {
+            let limit = func.rate_limit.unwrap();
+            output.push_str(&format!(
+                "        {:?} => Some(lithe::rpc::RateLimit {{\n            max: {},\n            window: std::time::Duration::from_secs({}),\n            key: lithe::rpc::RateLimitKey::{:?},\n        }}),\n",
+                func.id,
+                limit.max,
+                limit.window.as_secs(),
+                limit.key
+            ));
+        }
+        output.push_str("        _ => None,\n    }\n}\n");
+    }
+
+    output.push_str(&format!(
+        "\n/// Origins from `[package.metadata.lithe.csrf]` allowed to call server functions.\nconst TRUSTED_ORIGINS: &[&str] = &{:?};\n",
+        csrf.trusted_origins
+    ));
+    output.push_str("\n/// Functions exempt from CSRF checks, see `[package.metadata.lithe.csrf]`.\nfn csrf_exempt(function: &str) -> bool {\n");
+    if exempt.is_empty() {
+        output.push_str("    let _ = function;\n    false\n");
+    } else {
+        let ids: Vec<String> = exempt.iter().map(|id| format!("{:?}", id)).collect();
+        output.push_str(&format!("    matches!(function, {})\n", ids.join(" | ")));
+    }
     output.push_str("}\n");
 
+    // GET functions are also callable with POST, but only they may be called with GET.
+    let get: Vec<&ServerFunction> = plain.iter().copied().filter(|f| f.get).collect();
+    output.push_str(
+        "\nasync fn dispatch_get(function: &str, encodings: Encodings, args: &[u8]) -> RpcReply {\n",
+    );
+    output.push_str(&observed(dispatch_body(&get, default_guard, false)));
+    output.push_str("}\n");
+
+    output.push_str(
+        "\n/// `Cache-Control` declared with `#[server(cache = \"...\")]`, and whether a guard decides\n/// who gets the reply.\nfn cache_control(function: &str) -> Option<(&'static str, bool)> {\n",
+    );
+    let cached: Vec<&ServerFunction> = get.iter().copied().filter(|f| f.cache.is_some()).collect();
+    if cached.is_empty() {
+        output.push_str("    let _ = function;\n    None\n");
+    } else {
+        output.push_str("    match function {\n");
+        for func in cached {
+            let mut cache = func.cache.clone().unwrap_or_default();
+            let guarded = func.guards || (!func.public && default_guard.is_some());
+            if guarded {
+                let private = private_cache(&cache);
+                if private != cache {
+                    warn!(
+                        "{} is guarded, so its replies are cached as \"{}\" rather than \"{}\"",
+                        func.full_path, private, cache
+                    );
+                }
+                cache = private;
+            }
+            output.push_str(&format!(
+                "        {:?} => Some(({:?}, {})),\n",
+                func.id, cache, guarded
+            ));
+        }
+        output.push_str("        _ => None,\n    }\n");
+    }
+    output.push_str("}\n");
+
+    if let Some(guard) = default_guard {
+        output.push_str(&format!(
+            r#"
+/// Runs the project's default guard for functions that declare neither `guard` nor `public`.
+async fn default_guard(encodings: Encodings, guarded: bool) -> Result<(), RpcReply> {{
+    if guarded {{
+        return Ok(());
+    }}
+    {}()
+        .await
+        .map_err(|e| RpcReply::error(encodings.response, e))
+}}
+"#,
+            guard
+        ));
+    }
+
     fs::write(lithe_dir.join("rpc.rs"), output).context("Failed to write .lithe/rpc.rs")?;
     Ok(())
 }
 
+/// Resolves `[package.metadata.lithe.csrf] exempt` entries, given by path or by `name`, to
+/// function ids.
+fn csrf_exempt<'a>(
+    functions: &'a [ServerFunction],
+    crate_name: &str,
+    exempt: &[String],
+) -> Result<Vec<&'a str>> {
+    let crate_ident = crate_name.replace('-', "_");
+    exempt
+        .iter()
+        .map(|entry| {
+            let path = match entry.strip_prefix("crate::") {
+                Some(rest) => format!("{}::{}", crate_ident, rest),
+                None => entry.clone(),
+            };
+            functions
+                .iter()
+                .find(|f| f.id == *entry || f.full_path == path)
+                .map(|f| f.id.as_str())
+                .with_context(|| {
+                    format!(
+                        "[package.metadata.lithe.csrf] exempt entry {:?} matches no server function",
+                        entry
+                    )
+                })
+        })
+        .collect()
+}
+
+/// Wraps a dispatcher's body so each call gets a span and metrics.
+fn observed(body: String) -> String {
+    let body: String = body.lines().map(|line| format!("    {}\n", line)).collect();
+    format!(
+        "    lithe::telemetry::observe_call(function, async move {{\n{}    }})\n    .await\n",
+        body
+    )
+}
+
+/// The `match` routing a function id to its wrapper. Streaming dispatchers return
+/// `Result<RpcStream, RpcReply>`, so their rejections are wrapped in `Err`.
+fn dispatch_body(
+    functions: &[&ServerFunction],
+    default_guard: Option<&str>,
+    streaming: bool,
+) -> String {
+    let reject = |reply: &str| {
+        if streaming {
+            format!("Err({})", reply)
+        } else {
+            reply.to_string()
+        }
+    };
+    let not_found = reject(
+        "RpcReply::error(\n            encodings.response,\n            ServerFnError::<()>::NotFound(function.to_string()),\n        )",
+    );
+    if functions.is_empty() {
+        return format!(
+            "    let _ = args;\n    {}\n",
+            not_found.replace("\n    ", "\n")
+        );
+    }
+
+    let mut output = String::new();
+    // Counted before any guard runs, so rejected calls count too.
+    if functions.iter().any(|f| f.rate_limit.is_some()) {
+        output.push_str(&format!(
+            "    if let Some(limit) = rate_limit(function)\n        && let Err(e) = lithe::server::check_rate_limit::<()>(function, limit).await\n    {{\n        return {};\n    }}\n",
+            reject("RpcReply::error(encodings.response, e)")
+        ));
+    }
+    output.push_str("    match function {\n");
+    for func in functions {
+        let module = func
+            .full_path
+            .strip_suffix(&func.fn_name)
+            .unwrap()
+            .trim_end_matches("::");
+        let call = format!(
+            "{}::__lithe_rpc_wrapper_{}(encodings, args).await",
+            module, func.fn_name
+        );
+        if default_guard.is_some() {
+            output.push_str(&format!(
+                "        \"{}\" => match default_guard(encodings, {}::__LITHE_RPC_GUARDED_{}).await {{\n            Ok(()) => {},\n            Err(rejected) => {},\n        }},\n",
+                func.id,
+                module,
+                func.fn_name,
+                call,
+                reject("rejected")
+            ));
+        } else {
+            output.push_str(&format!("        \"{}\" => {},\n", func.id, call));
+        }
+    }
+    output.push_str(&format!("        _ => {},\n", not_found));
+    output.push_str("    }\n");
+    output
+}
+
 pub fn generate_wasm_exports(
     project_dir: &Path,
     crate_name: &str,
@@ -226,7 +1808,7 @@ pub fn __wasm_export_{}() {{
     crate::{}();
 }}
 "#,
-            func.hashed_id, func.hashed_id, relative_path
+            func.id, func.id, relative_path
         ));
     }
 
@@ -234,3 +1816,6 @@ pub fn __wasm_export_{}() {{
         .context("Failed to write .lithe/wasm_exports.rs")?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,163 @@
+use super::*;
+
+#[test]
+fn test_private_cache() {
+    assert_eq!(private_cache("public, max-age=60"), "private, max-age=60");
+    assert_eq!(
+        private_cache("max-age=60, s-maxage=600"),
+        "private, max-age=60"
+    );
+    assert_eq!(private_cache("private, max-age=60"), "private, max-age=60");
+    assert_eq!(private_cache("no-store"), "no-store");
+}
+
+/// Writes `files` into a fresh project under `src/` and scans it as the crate `app`.
+fn scan_sources(name: &str, files: &[(&str, &str)]) -> Result<ProjectItems> {
+    let dir = std::env::temp_dir().join(format!("lithe-scan-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, content) in files {
+        let path = dir.join("src").join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    let items = discover_items(&dir, "app");
+    fs::remove_dir_all(&dir).unwrap();
+    items
+}
+
+fn server_paths(items: &ProjectItems) -> Vec<&str> {
+    items
+        .server_fns
+        .iter()
+        .map(|f| f.full_path.as_str())
+        .collect()
+}
+
+#[test]
+fn test_scan_finds_functions_through_modules() {
+    let items = scan_sources(
+        "modules",
+        &[
+            (
+                "api.rs",
+                r#"
+use lithe::server;
+use std::collections::HashMap;
+
+// #[server]
+// pub async fn commented_out() -> ServerFnResult<()> { Ok(()) }
+
+/* #[server]
+pub async fn also_commented_out() -> ServerFnResult<()> { Ok(()) } */
+
+/// Totals the counts.
+#[server(name = "totals")]
+#[allow(clippy::implicit_hasher)]
+pub async fn totals(
+    counts: HashMap<String, Vec<u32>>,
+    pair: (i32, Option<String>),
+    step: fn(u32) -> u32,
+) -> ServerFnResult<(u32, HashMap<String, u32>)> {
+    todo!()
+}
+
+pub mod admin {
+    #[lithe::server]
+    pub async fn reset() -> ServerFnResult<()> { Ok(()) }
+
+    #[cfg(test)]
+    #[server]
+    pub async fn only_in_tests() -> ServerFnResult<()> { Ok(()) }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub mod browser {
+    #[server]
+    pub async fn never_on_the_server() -> ServerFnResult<()> { Ok(()) }
+
+    #[client]
+    pub fn greet() {}
+}
+
+#[path = "impls/extra.rs"]
+pub mod extra;
+"#,
+            ),
+            (
+                "impls/extra.rs",
+                "#[server]\npub async fn extra_call() -> ServerFnResult<()> { Ok(()) }\n",
+            ),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(
+        server_paths(&items),
+        [
+            "app::api::admin::reset",
+            "app::api::extra::extra_call",
+            "app::api::totals"
+        ]
+    );
+    let totals = items.server_fns.iter().find(|f| f.id == "totals").unwrap();
+    assert_eq!(totals.doc, "Totals the counts.");
+    let arg_types: Vec<&syn::Type> = totals.args.iter().map(|(_, ty)| ty).collect();
+    let expected: Vec<syn::Type> = [
+        "HashMap<String, Vec<u32>>",
+        "(i32, Option<String>)",
+        "fn(u32) -> u32",
+    ]
+    .iter()
+    .map(|ty| syn::parse_str(ty).unwrap())
+    .collect();
+    assert_eq!(arg_types, expected.iter().collect::<Vec<_>>());
+    assert_eq!(
+        items
+            .client_fns
+            .iter()
+            .map(|f| f.full_path.as_str())
+            .collect::<Vec<_>>(),
+        ["app::api::browser::greet"]
+    );
+}
+
+#[test]
+fn test_scan_reports_unsupported_functions() {
+    let Err(err) = scan_sources(
+        "unsupported",
+        &[(
+            "api.rs",
+            r#"
+#[server]
+pub async fn generic<T: Serialize>(value: T) -> ServerFnResult<T> { Ok(value) }
+
+#[server]
+pub async fn bounded(value: u32) -> ServerFnResult<u32> where u32: Copy { Ok(value) }
+
+#[server]
+pub fn blocking() -> ServerFnResult<()> { Ok(()) }
+
+#[server]
+pub async fn callback(f: impl Fn(u32)) -> ServerFnResult<()> { Ok(()) }
+
+mod hidden {
+    #[server]
+    pub async fn unreachable() -> ServerFnResult<()> { Ok(()) }
+}
+"#,
+        )],
+    ) else {
+        panic!("unsupported functions were accepted");
+    };
+    let err = err.to_string();
+
+    for expected in [
+        "src/api.rs:3:21: `generic`: #[server] functions cannot be generic",
+        "src/api.rs:6:57: `bounded`: #[server] functions cannot be generic",
+        "src/api.rs:9:5: `blocking`: #[server] functions must be async",
+        "`callback`: #[server] arguments cannot use `impl Trait`",
+        "src/api.rs:16:18: `unreachable`: #[server] functions must be reachable from the generated code, but module `app::api::hidden` is private",
+    ] {
+        assert!(err.contains(expected), "{:?} not in {}", expected, err);
+    }
+}
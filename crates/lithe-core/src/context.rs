@@ -0,0 +1,142 @@
+use crate::encoding::Encoding;
+use crate::page::PageMeta;
+use std::cell::RefCell;
+
+/// The parts of an incoming page request that rendering can depend on.
+#[derive(Debug, Clone, Default)]
+pub struct PageRequest {
+    pub path: String,
+    pub host: Option<String>,
+    pub accept_language: Option<String>,
+    pub cookie: Option<String>,
+    /// The session's CSRF token, see [`crate::csrf`]. Absent for static builds.
+    pub csrf_token: Option<String>,
+}
+
+impl PageRequest {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Maps the request onto a host's page tree, e.g. `/guide` on `docs.example.com` with
+    /// `("docs.example.com", "/docs")` becomes `/docs/guide`.
+    pub fn host_path(&self, path: &str, hosts: &[(&str, &str)]) -> String {
+        let host = self
+            .host
+            .as_deref()
+            .map(|h| h.split(':').next().unwrap_or(h));
+        match hosts.iter().find(|(name, _)| Some(*name) == host) {
+            Some((_, prefix)) if path == "/" => prefix.to_string(),
+            Some((_, prefix)) => format!("{}{}", prefix, path),
+            None => path.to_string(),
+        }
+    }
+
+    /// Returns the value of the named cookie, if the request carried it.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        parse_cookie(self.cookie.as_deref()?, name)
+    }
+}
+
+/// Finds the named cookie in a `Cookie` header value.
+pub fn parse_cookie<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+    header.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        (key == name).then_some(value)
+    })
+}
+
+/// State available to components while a page is being rendered.
+#[derive(Debug, Clone)]
+pub struct RenderContext {
+    pub path: String,
+    /// Prefix the app is mounted under, e.g. `/app`, or empty when served from the root.
+    pub base_path: String,
+    pub locale: String,
+    pub default_locale: String,
+    /// `(hreflang, href)` pairs for every localised variant of the page.
+    pub alternates: Vec<(String, String)>,
+    /// Metadata of the page being rendered.
+    pub meta: PageMeta,
+    /// Whether the request belongs to a preview session, see [`crate::preview`].
+    pub preview: bool,
+    /// Id of the app build serving the page, sent back with RPC calls.
+    pub build_id: String,
+    /// Encoding the browser uses for RPC calls that do not pick their own.
+    pub encoding: Encoding,
+    /// Token the browser sends back with RPC calls, or empty when the page can't carry one.
+    pub csrf_token: String,
+}
+
+impl Default for RenderContext {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            base_path: String::new(),
+            locale: "en".to_string(),
+            default_locale: "en".to_string(),
+            alternates: Vec::new(),
+            meta: PageMeta::DEFAULT,
+            preview: false,
+            build_id: String::new(),
+            encoding: Encoding::Json,
+            csrf_token: String::new(),
+        }
+    }
+}
+
+thread_local! {
+    static RENDER_CONTEXT: RefCell<Option<RenderContext>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `ctx` installed as the current render context.
+pub fn with_render_context<R>(ctx: RenderContext, f: impl FnOnce() -> R) -> R {
+    let previous = RENDER_CONTEXT.with(|c| c.replace(Some(ctx)));
+    let result = f();
+    RENDER_CONTEXT.with(|c| *c.borrow_mut() = previous);
+    result
+}
+
+/// Returns the current render context, or the default one outside of a page render.
+pub fn use_render_context() -> RenderContext {
+    RENDER_CONTEXT.with(|c| c.borrow().clone().unwrap_or_default())
+}
+
+/// Returns the locale the current page is being rendered in.
+pub fn current_locale() -> String {
+    RENDER_CONTEXT.with(|c| {
+        c.borrow()
+            .as_ref()
+            .map(|ctx| ctx.locale.clone())
+            .unwrap_or_else(|| "en".to_string())
+    })
+}
+
+/// Returns whether the current page is being rendered for a preview session.
+pub fn is_preview() -> bool {
+    RENDER_CONTEXT.with(|c| c.borrow().as_ref().is_some_and(|ctx| ctx.preview))
+}
+
+/// Prefixes an absolute app path (a page, asset or endpoint) with the base path.
+pub fn url(path: &str) -> String {
+    let base_path = RENDER_CONTEXT.with(|c| {
+        c.borrow()
+            .as_ref()
+            .map(|ctx| ctx.base_path.clone())
+            .unwrap_or_default()
+    });
+    join_base_path(&base_path, path)
+}
+
+pub fn join_base_path(base_path: &str, path: &str) -> String {
+    if base_path.is_empty() {
+        path.to_string()
+    } else if path == "/" {
+        base_path.to_string()
+    } else {
+        format!("{}{}", base_path, path)
+    }
+}
@@ -0,0 +1,168 @@
+//! Cron expressions for `#[scheduled("0 3 * * *")]` functions.
+//!
+//! Expressions have the five usual fields, `minute hour day-of-month month day-of-week`, each
+//! a `*`, a value, a range `a-b` or a list of them, optionally stepped with `/n`. Months and
+//! weekdays may be named (`jan`, `mon`), Sunday is `0` or `7`, and `@hourly`, `@daily`,
+//! `@weekly`, `@monthly` and `@yearly` stand for their usual expressions. Times are in UTC.
+//!
+//! As in classic cron, when both the day of the month and the day of the week are restricted
+//! a day matching either runs the task.
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// How far ahead [`Schedule::next_after`] looks, in days: enough for a 29 February in a
+/// century year that isn't leap.
+const HORIZON_DAYS: i64 = 366 * 8;
+
+/// A parsed cron expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// Whether the day-of-month field starts with `*`, making the days an AND rather than an OR.
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    pub fn parse(expr: &str) -> Result<Schedule, String> {
+        let expr = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day month weekday), found {}",
+                fields.len()
+            ));
+        };
+        // Sunday is both 0 and 7.
+        let weekdays = parse_field(weekday, "weekday", 0, 7, &WEEKDAYS)?;
+        Ok(Schedule {
+            minutes: parse_field(minute, "minute", 0, 59, &[])?,
+            hours: parse_field(hour, "hour", 0, 23, &[])? as u32,
+            days: parse_field(day, "day", 1, 31, &[])? as u32,
+            months: parse_field(month, "month", 1, 12, &MONTHS)? as u16,
+            weekdays: ((weekdays | (weekdays >> 7)) & 0x7f) as u8,
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+
+    /// The first time the schedule fires strictly after `unix_secs`, in seconds since the Unix
+    /// epoch, or `None` if it never does (e.g. `0 0 31 2 *`).
+    pub fn next_after(&self, unix_secs: u64) -> Option<u64> {
+        let start = (unix_secs / 60 + 1) as i64;
+        let first_day = start / 1440;
+        for day in first_day..first_day + HORIZON_DAYS {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let from = if day == first_day { start % 1440 } else { 0 };
+            if let Some(minute) = self.first_minute_from(from as u32) {
+                return Some(((day * 1440 + minute as i64) * 60) as u64);
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: i64) -> bool {
+        let (_, month, day) = civil_from_days(days_since_epoch);
+        if self.months & (1 << month) == 0 {
+            return false;
+        }
+        // 1970-01-01 was a Thursday.
+        let weekday = (days_since_epoch + 4).rem_euclid(7) as u32;
+        let day_ok = self.days & (1 << day) != 0;
+        let weekday_ok = self.weekdays & (1 << weekday) != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day_ok || weekday_ok,
+            _ => day_ok && weekday_ok,
+        }
+    }
+
+    /// The first matching minute of a day at or after minute `from` of that day.
+    fn first_minute_from(&self, from: u32) -> Option<u32> {
+        let (hour, minute) = (from / 60, from % 60);
+        (hour..24)
+            .filter(|h| self.hours & (1 << h) != 0)
+            .find_map(|h| {
+                let first = if h == hour { minute } else { 0 };
+                (first..60)
+                    .find(|m| self.minutes & (1 << m) != 0)
+                    .map(|m| h * 60 + m)
+            })
+    }
+}
+
+/// One field as a bit set of its allowed values.
+fn parse_field(field: &str, name: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let invalid = || format!("invalid {} field `{}`", name, field);
+    let value = |s: &str| -> Result<u32, String> {
+        let value = match names.iter().position(|n| n.eq_ignore_ascii_case(s)) {
+            // Named months start at 1, weekdays at 0.
+            Some(i) => i as u32 + if names.len() == 12 { 1 } else { 0 },
+            None => s.parse().map_err(|_| invalid())?,
+        };
+        if (min..=max).contains(&value) {
+            Ok(value)
+        } else {
+            Err(format!(
+                "{} {} is out of range {}-{}",
+                name, value, min, max
+            ))
+        }
+    };
+
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (value(a)?, value(b)?),
+                // `5/15` means from 5 to the end, every 15.
+                None if part.contains('/') => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if first > last {
+            return Err(invalid());
+        }
+        for v in (first..=last).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+/// `(year, month, day)` of a day counted from 1970-01-01, in the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,163 @@
+//! Defence against cross-site request forgery on the RPC endpoint.
+//!
+//! Every session gets a random token, stored in the [`CSRF_COOKIE`] cookie and written into the
+//! pages it renders. The browser sends it back in [`CSRF_HEADER`] with each call that may change
+//! state. Another site can neither read the token nor set that header without a CORS preflight,
+//! which the RPC endpoint never grants. Browsers also label such requests with `Origin` and
+//! `Sec-Fetch-Site`, which [`check`] rejects unless the origin is trusted. WebSocket handshakes
+//! can't carry the header, so [`check_handshake`] relies on `Origin` alone.
+
+#[cfg(not(target_arch = "wasm32"))]
+use hmac::{Hmac, Mac};
+#[cfg(not(target_arch = "wasm32"))]
+use sha2::Sha256;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+
+/// Cookie holding the session's token. Scripts can read it, so pages that can't carry the
+/// token, e.g. static or shared-cached ones, still find it.
+pub const CSRF_COOKIE: &str = "lithe_csrf";
+/// Header RPC calls carry the token in.
+pub const CSRF_HEADER: &str = "x-lithe-csrf";
+
+/// Whether `token` looks like one [`new_token`] issues: 32 lowercase hex digits.
+pub fn is_token(token: &str) -> bool {
+    token.len() == 32
+        && token
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// A fresh random token, signed so [`was_issued`] can tell it from one a client made up.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn new_token() -> String {
+    let mut nonce = [0u8; 8];
+    getrandom::getrandom(&mut nonce).expect("the OS random number generator failed");
+    let nonce = hex(&nonce);
+    let signature = token_mac(&nonce).finalize().into_bytes();
+    format!("{}{}", nonce, hex(&signature[..8]))
+}
+
+/// Whether this process issued `token` with [`new_token`]. Tokens from before a restart, from
+/// another instance, or that the browser made up on a static page were not.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn was_issued(token: &str) -> bool {
+    if !is_token(token) {
+        return false;
+    }
+    let (nonce, signature) = token.split_at(16);
+    let signature: Option<Vec<u8>> = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&signature[i..i + 2], 16).ok())
+        .collect();
+    signature.is_some_and(|signature| token_mac(nonce).verify_truncated_left(&signature).is_ok())
+}
+
+/// MAC over a token's nonce, keyed by a secret the process picks at random when it starts.
+#[cfg(not(target_arch = "wasm32"))]
+fn token_mac(nonce: &str) -> Hmac<Sha256> {
+    static KEY: OnceLock<[u8; 32]> = OnceLock::new();
+    let key = KEY.get_or_init(|| {
+        let mut key = [0u8; 32];
+        getrandom::getrandom(&mut key).expect("the OS random number generator failed");
+        key
+    });
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `Set-Cookie` value that stores `token` for the session.
+pub fn token_cookie(token: &str) -> String {
+    format!("{}={}; Path=/; SameSite=Lax", CSRF_COOKIE, token)
+}
+
+/// Whether a page sent with `cache_control` may carry the session's token. Pages kept in
+/// shared caches are served to everyone, so they leave it to the cookie.
+pub fn page_may_carry_token(cache_control: Option<&str>) -> bool {
+    cache_control.is_none_or(|cache| cache.contains("private") || cache.contains("no-store"))
+}
+
+/// What [`check`] and [`check_handshake`] look at in a call.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallOrigin<'a> {
+    /// `Host`, or `X-Forwarded-Host` behind a proxy.
+    pub host: Option<&'a str>,
+    pub origin: Option<&'a str>,
+    pub fetch_site: Option<&'a str>,
+    pub cookie_token: Option<&'a str>,
+    pub header_token: Option<&'a str>,
+}
+
+/// Checks that a call that may change state comes from the app's own pages.
+///
+/// Calls from `trusted_origins`, e.g. `https://admin.example.com`, pass without a token. Calls
+/// without `Origin` and `Sec-Fetch-Site` come from outside a browser, e.g. the native client
+/// or `curl`; browsers always send one of them with a forged request, so these pass too.
+pub fn check(call: &CallOrigin, trusted_origins: &[&str]) -> Result<(), String> {
+    if is_trusted(call, trusted_origins) {
+        return Ok(());
+    }
+    check_site(call)?;
+    if call.origin.is_none() && call.fetch_site.is_none() {
+        return Ok(());
+    }
+    match (call.header_token, call.cookie_token) {
+        (Some(header), Some(cookie)) if is_token(cookie) && tokens_match(header, cookie) => Ok(()),
+        (None, _) => Err("missing CSRF token".to_string()),
+        _ => Err("invalid CSRF token".to_string()),
+    }
+}
+
+/// Checks that a WebSocket handshake comes from the app's own pages or `trusted_origins`.
+///
+/// Browsers can't add headers to a handshake, so there is no token to check, but they always
+/// send `Origin` with it and another site can't forge that.
+pub fn check_handshake(call: &CallOrigin, trusted_origins: &[&str]) -> Result<(), String> {
+    if is_trusted(call, trusted_origins) {
+        return Ok(());
+    }
+    check_site(call)
+}
+
+fn is_trusted(call: &CallOrigin, trusted_origins: &[&str]) -> bool {
+    call.origin.is_some_and(|origin| {
+        trusted_origins
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(origin))
+    })
+}
+
+/// Rejects requests the browser labels as cross-site or sends from another origin.
+fn check_site(call: &CallOrigin) -> Result<(), String> {
+    if let Some(site) = call.fetch_site
+        && site != "same-origin"
+        && site != "none"
+    {
+        return Err(format!("{} request", site));
+    }
+    if let Some(origin) = call.origin {
+        let origin_host = origin.split_once("://").map(|(_, host)| host);
+        let same = origin_host
+            .zip(call.host)
+            .is_some_and(|(origin, host)| origin.eq_ignore_ascii_case(host));
+        if !same {
+            return Err(format!("origin {} is not allowed", origin));
+        }
+    }
+    Ok(())
+}
+
+/// Compares tokens in constant time.
+fn tokens_match(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
@@ -0,0 +1,151 @@
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Wire format of RPC arguments and results.
+///
+/// JSON is the default. The binary formats produce smaller payloads and are faster to encode,
+/// which matters for calls that move bytes or large collections. Postcard is the most
+/// compact but not self-describing, so both sides must agree on the exact types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MsgPack,
+    Cbor,
+    Postcard,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 4] = [
+        Encoding::Json,
+        Encoding::MsgPack,
+        Encoding::Cbor,
+        Encoding::Postcard,
+    ];
+
+    /// Name used in `#[server(encoding = "...")]` and the project config.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MsgPack => "msgpack",
+            Encoding::Cbor => "cbor",
+            Encoding::Postcard => "postcard",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.name() == name)
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MsgPack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+            Encoding::Postcard => "application/x-postcard",
+        }
+    }
+
+    /// Parses a `Content-Type` value, ignoring parameters such as `charset`.
+    pub fn from_content_type(value: &str) -> Option<Self> {
+        let media_type = value.split(';').next()?.trim().to_ascii_lowercase();
+        match media_type.as_str() {
+            "application/json" => Some(Encoding::Json),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+                Some(Encoding::MsgPack)
+            }
+            "application/cbor" => Some(Encoding::Cbor),
+            "application/x-postcard" => Some(Encoding::Postcard),
+            _ => None,
+        }
+    }
+
+    /// Returns the first supported encoding listed in an `Accept` header, if any.
+    pub fn from_accept(value: &str) -> Option<Self> {
+        value.split(',').find_map(Self::from_content_type)
+    }
+
+    pub fn encode<T: Serialize + ?Sized>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Encoding::MsgPack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Encoding::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::into_writer(value, &mut buf).map_err(|e| e.to_string())?;
+                Ok(buf)
+            }
+            Encoding::Postcard => postcard::to_allocvec(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> Result<T, String> {
+        match self {
+            Encoding::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Encoding::MsgPack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            Encoding::Cbor => ciborium::from_reader(bytes).map_err(|e| e.to_string()),
+            Encoding::Postcard => postcard::from_bytes(bytes).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// The encodings of one RPC call: the one its arguments arrived in and the one to reply in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Encodings {
+    pub request: Encoding,
+    pub response: Encoding,
+}
+
+impl Encodings {
+    /// Negotiates from the call's `Content-Type` and `Accept` headers. A missing content type
+    /// means JSON; the reply uses the request's encoding unless `Accept` names another one.
+    /// Returns `None` when the content type is not supported.
+    pub fn negotiate(content_type: Option<&str>, accept: Option<&str>) -> Option<Self> {
+        let request = match content_type {
+            Some(value) => Encoding::from_content_type(value)?,
+            None => Encoding::Json,
+        };
+        let response = accept.and_then(Encoding::from_accept).unwrap_or(request);
+        Some(Self { request, response })
+    }
+}
+
+/// Framing of the items a streaming server function sends. Each frame holds one JSON
+/// [`RpcResponse`](crate::rpc::RpcResponse) envelope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StreamFormat {
+    /// Newline-delimited JSON, read by the browser runtime.
+    #[default]
+    NdJson,
+    /// Server-sent events, for `EventSource` and other SSE clients.
+    Sse,
+}
+
+impl StreamFormat {
+    /// Picks SSE when `Accept` asks for `text/event-stream`, NDJSON otherwise.
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        let sse = accept.is_some_and(|value| {
+            value
+                .split(',')
+                .any(|t| t.split(';').next().unwrap_or("").trim() == "text/event-stream")
+        });
+        if sse {
+            StreamFormat::Sse
+        } else {
+            StreamFormat::NdJson
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            StreamFormat::NdJson => "application/x-ndjson",
+            StreamFormat::Sse => "text/event-stream",
+        }
+    }
+
+    /// Wraps one encoded envelope, which must not contain a newline, into a frame.
+    pub fn frame(self, json: &[u8]) -> Vec<u8> {
+        match self {
+            StreamFormat::NdJson => [json, b"\n"].concat(),
+            StreamFormat::Sse => [b"data: ", json, b"\n\n"].concat(),
+        }
+    }
+}
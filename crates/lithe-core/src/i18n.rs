@@ -0,0 +1,174 @@
+use crate::context::{join_base_path, use_render_context};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Cookie that overrides `Accept-Language` when picking a locale.
+pub const LOCALE_COOKIE: &str = "lithe_locale";
+
+type Catalogs = HashMap<String, HashMap<String, String>>;
+
+static CATALOGS: OnceLock<Catalogs> = OnceLock::new();
+
+/// Loads the JSON message catalogs for every locale. Only the first call has any effect.
+pub fn install_catalogs(sources: &[(&str, &str)]) {
+    CATALOGS.get_or_init(|| {
+        sources
+            .iter()
+            .map(|(locale, json)| {
+                let value: serde_json::Value = serde_json::from_str(json)
+                    .unwrap_or_else(|e| panic!("Invalid catalog for locale {}: {}", locale, e));
+                let mut messages = HashMap::new();
+                flatten_catalog("", &value, &mut messages);
+                (locale.to_string(), messages)
+            })
+            .collect()
+    });
+}
+
+/// Flattens nested catalog objects into dotted keys (`{"nav": {"home": ..}}` -> `nav.home`).
+pub fn flatten_catalog(prefix: &str, value: &serde_json::Value, out: &mut HashMap<String, String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten_catalog(&key, value, out);
+            }
+        }
+        serde_json::Value::String(s) => {
+            out.insert(prefix.to_string(), s.clone());
+        }
+        other => {
+            out.insert(prefix.to_string(), other.to_string());
+        }
+    }
+}
+
+/// Looks up `key` in the current locale's catalog and fills `{name}` placeholders from `args`.
+///
+/// Falls back to the default locale, then to the key itself.
+pub fn translate(key: &str, args: &[(&str, String)]) -> String {
+    let ctx = use_render_context();
+    let message = CATALOGS.get().and_then(|catalogs| {
+        catalogs
+            .get(&ctx.locale)
+            .and_then(|c| c.get(key))
+            .or_else(|| catalogs.get(&ctx.default_locale).and_then(|c| c.get(key)))
+    });
+
+    let mut text = message.cloned().unwrap_or_else(|| key.to_string());
+    for (name, value) in args {
+        text = text.replace(&format!("{{{}}}", name), value);
+    }
+    text
+}
+
+/// Splits a leading locale segment off `path`, e.g. `/fr/about` -> `("fr", "/about")`.
+pub fn split_locale_prefix<'a>(path: &'a str, locales: &[&'a str]) -> Option<(&'a str, &'a str)> {
+    let rest = path.strip_prefix('/')?;
+    let (segment, tail) = match rest.find('/') {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, "/"),
+    };
+    locales
+        .iter()
+        .find(|l| **l == segment)
+        .map(|l| (*l, if tail.is_empty() { "/" } else { tail }))
+}
+
+/// Picks a locale from the locale cookie, then `Accept-Language`, then the default.
+pub fn negotiate_locale<'a>(
+    locales: &[&'a str],
+    default_locale: &'a str,
+    cookie_locale: Option<&str>,
+    accept_language: Option<&str>,
+) -> &'a str {
+    if let Some(locale) = cookie_locale.and_then(|c| locales.iter().find(|l| **l == c)) {
+        return locale;
+    }
+
+    let mut ranges: Vec<(&str, f32)> = accept_language
+        .unwrap_or("")
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.trim().split(';');
+            let tag = pieces.next()?.trim();
+            if tag.is_empty() {
+                return None;
+            }
+            let quality = pieces
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            // `q=0` marks a language as not acceptable.
+            (quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    for (tag, _) in ranges {
+        let primary = tag.split('-').next().unwrap_or(tag);
+        if let Some(locale) = locales
+            .iter()
+            .find(|l| l.eq_ignore_ascii_case(tag) || l.eq_ignore_ascii_case(primary))
+        {
+            return locale;
+        }
+    }
+    default_locale
+}
+
+/// Returns `(hreflang, href)` pairs for every locale variant of `path`, plus `x-default`.
+pub fn alternates(base_path: &str, path: &str, locales: &[&str]) -> Vec<(String, String)> {
+    if locales.len() < 2 {
+        return Vec::new();
+    }
+    let mut links: Vec<(String, String)> = locales
+        .iter()
+        .map(|l| {
+            (
+                l.to_string(),
+                join_base_path(base_path, &prefixed_path(l, path)),
+            )
+        })
+        .collect();
+    links.push(("x-default".to_string(), join_base_path(base_path, path)));
+    links
+}
+
+/// Prefixes `path` with the base path and the current locale, unless it is the default one.
+pub fn localized_path(path: &str) -> String {
+    let ctx = use_render_context();
+    if ctx.locale == ctx.default_locale {
+        join_base_path(&ctx.base_path, path)
+    } else {
+        join_base_path(&ctx.base_path, &prefixed_path(&ctx.locale, path))
+    }
+}
+
+fn prefixed_path(locale: &str, path: &str) -> String {
+    if path == "/" {
+        format!("/{}", locale)
+    } else {
+        format!("/{}{}", locale, path)
+    }
+}
+
+/// Translates a message key from the `src/locales` catalogs for the current locale.
+///
+/// ```ignore
+/// t!("home.title");
+/// t!("home.greeting", name = user.name);
+/// ```
+#[macro_export]
+macro_rules! t {
+    ($key:literal) => {
+        $crate::i18n::translate($key, &[])
+    };
+    ($key:literal, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::translate($key, &[$((stringify!($name), ($value).to_string())),+])
+    };
+}
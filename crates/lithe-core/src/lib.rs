@@ -1,7 +1,21 @@
+pub mod context;
+pub mod cron;
+pub mod csrf;
 pub mod element;
+pub mod encoding;
+pub mod i18n;
+pub mod page;
+pub mod preview;
 pub mod rpc;
+pub mod validate;
 
+pub use context::{
+    PageRequest, RenderContext, current_locale, is_preview, url, use_render_context,
+    with_render_context,
+};
 pub use element::*;
+pub use page::{PageMeta, PageResponse, RenderMode};
+pub use rpc::{ServerFnError, ServerFnResult};
 pub use serde;
 pub use serde_json;
 pub trait Component {
@@ -94,6 +108,7 @@ impl<T: Component> Component for Option<T> {
 
 pub struct HtmlPage {
     pub title: String,
+    pub description: Option<String>,
     pub body: Box<dyn Component>,
     pub styles: Vec<String>,
     pub scripts: Vec<String>,
@@ -104,6 +119,7 @@ impl HtmlPage {
     pub fn new(title: &str, body: impl Component + 'static) -> Self {
         Self {
             title: title.to_string(),
+            description: None,
             body: Box::new(body),
             styles: Vec::new(),
             scripts: Vec::new(),
@@ -111,6 +127,11 @@ impl HtmlPage {
         }
     }
 
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
     pub fn add_style(mut self, css: &str) -> Self {
         self.styles.push(css.to_string());
         self
@@ -134,15 +155,35 @@ impl HtmlPage {
 
 impl Component for HtmlPage {
     fn render(&self, buf: &mut String) {
-        buf.push_str("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n");
+        let ctx = use_render_context();
+        buf.push_str("<!DOCTYPE html>\n<html lang=\"");
+        buf.push_str(&ctx.locale);
+        buf.push_str("\">\n<head>\n");
         buf.push_str("    <meta charset=\"UTF-8\">\n");
         buf.push_str(
             "    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\n",
         );
+        // An empty title or missing description falls back to the page's `#[page(...)]` metadata.
         buf.push_str("    <title>");
-        buf.push_str(&self.title);
+        if self.title.is_empty() {
+            push_escaped(buf, ctx.meta.title.unwrap_or_default());
+        } else {
+            push_escaped(buf, &self.title);
+        }
         buf.push_str("</title>\n");
+        if let Some(description) = self.description.as_deref().or(ctx.meta.description) {
+            buf.push_str("    <meta name=\"description\" content=\"");
+            push_escaped(buf, description);
+            buf.push_str("\">\n");
+        }
 
+        for (hreflang, href) in &ctx.alternates {
+            buf.push_str("    <link rel=\"alternate\" hreflang=\"");
+            buf.push_str(hreflang);
+            buf.push_str("\" href=\"");
+            buf.push_str(href);
+            buf.push_str("\">\n");
+        }
         for style in &self.styles {
             buf.push_str("    <style>");
             buf.push_str(style);
@@ -160,5 +201,20 @@ impl Component for HtmlPage {
     }
 }
 
+/// Appends `text` with the characters HTML treats specially in text and quoted attributes
+/// escaped.
+fn push_escaped(buf: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            '\'' => buf.push_str("&#39;"),
+            c => buf.push(c),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,72 @@
+/// How a page route is matched and served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// Rendered on the server for exactly its own route.
+    #[default]
+    Ssr,
+    /// Also served for every path below its route, leaving sub-routing to the client.
+    Spa,
+}
+
+/// Metadata declared with `#[page(...)]`, emitted by the macro as `PAGE_META`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageMeta {
+    pub title: Option<&'static str>,
+    pub description: Option<&'static str>,
+    /// `Cache-Control` value sent with the page.
+    pub cache: Option<&'static str>,
+    /// Whether a page that renders a fragment is wrapped in the default `HtmlPage` document.
+    pub layout: bool,
+    pub mode: RenderMode,
+    /// Drafts are only served to preview sessions and are left out of static builds.
+    pub draft: bool,
+}
+
+impl PageMeta {
+    pub const DEFAULT: PageMeta = PageMeta {
+        title: None,
+        description: None,
+        cache: None,
+        layout: true,
+        mode: RenderMode::Ssr,
+        draft: false,
+    };
+}
+
+impl Default for PageMeta {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A rendered page along with the response metadata the server should send.
+#[derive(Debug, Clone)]
+pub struct PageResponse {
+    pub status: u16,
+    pub cache_control: Option<String>,
+    pub body: String,
+}
+
+impl PageResponse {
+    pub fn ok(body: String, meta: &PageMeta) -> Self {
+        Self {
+            status: 200,
+            cache_control: meta.cache.map(str::to_string),
+            body,
+        }
+    }
+
+    /// Marks the response as belonging to a preview session so it is never cached.
+    pub fn preview(mut self) -> Self {
+        self.cache_control = Some("private, no-store".to_string());
+        self
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: 404,
+            cache_control: None,
+            body: "404 Not Found".to_string(),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::context::PageRequest;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Cookie that carries a signed preview token.
+pub const PREVIEW_COOKIE: &str = "lithe_preview";
+/// Environment variable holding the secret editors use to enable preview mode.
+pub const PREVIEW_SECRET_ENV: &str = "LITHE_PREVIEW_SECRET";
+/// How long a preview session lasts, in seconds.
+pub const PREVIEW_TTL_SECS: u64 = 60 * 60;
+
+fn secret() -> Option<String> {
+    std::env::var(PREVIEW_SECRET_ENV)
+        .ok()
+        .filter(|s| !s.is_empty())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn mac(secret: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn sign(secret: &str, payload: &str) -> String {
+    mac(secret, payload)
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Checks a candidate secret against `LITHE_PREVIEW_SECRET`. Always false when it is unset.
+pub fn check_secret(candidate: &str) -> bool {
+    let Some(secret) = secret() else {
+        return false;
+    };
+    // Verifying a MAC keyed by the candidate keeps the comparison constant-time.
+    let expected = mac(&secret, "lithe-preview").finalize().into_bytes();
+    mac(candidate, "lithe-preview")
+        .verify_slice(&expected)
+        .is_ok()
+        && candidate.len() == secret.len()
+}
+
+/// Issues a token of the form `<expiry>.<signature>`, valid for `PREVIEW_TTL_SECS`.
+pub fn issue_token() -> Option<String> {
+    let secret = secret()?;
+    let expiry = (now() + PREVIEW_TTL_SECS).to_string();
+    Some(format!("{}.{}", expiry, sign(&secret, &expiry)))
+}
+
+/// Returns whether `token` was signed with the current secret and has not expired.
+pub fn verify_token(token: &str) -> bool {
+    let Some(secret) = secret() else {
+        return false;
+    };
+    let Some((expiry, signature)) = token.split_once('.') else {
+        return false;
+    };
+    let Ok(expires_at) = expiry.parse::<u64>() else {
+        return false;
+    };
+    let Some(signature) = decode_hex(signature) else {
+        return false;
+    };
+    expires_at > now() && mac(&secret, expiry).verify_slice(&signature).is_ok()
+}
+
+/// Returns whether the request carries a valid preview cookie.
+pub fn is_preview_request(req: &PageRequest) -> bool {
+    req.cookie(PREVIEW_COOKIE).is_some_and(verify_token)
+}
+
+/// `Set-Cookie` value that starts a preview session.
+pub fn enable_cookie(token: &str) -> String {
+    format!(
+        "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+        PREVIEW_COOKIE, token, PREVIEW_TTL_SECS
+    )
+}
+
+/// `Set-Cookie` value that ends a preview session.
+pub fn disable_cookie() -> String {
+    format!(
+        "{}=; Path=/; HttpOnly; SameSite=Lax; Max-Age=0",
+        PREVIEW_COOKIE
+    )
+}
@@ -1,12 +1,546 @@
-use serde::{Deserialize, Serialize};
+use crate::validate::ValidationErrors;
+use serde::de::{DeserializeOwned, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::time::Duration;
 
+pub use crate::encoding::{Encoding, Encodings, StreamFormat};
+
+/// Header naming the server function an RPC call targets.
+pub const FUNCTION_HEADER: &str = "x-lithe-function";
+/// Header carrying the build id the calling page was rendered with.
+pub const BUILD_HEADER: &str = "x-lithe-build";
+/// Query parameter naming the function a `method = "GET"` call targets.
+pub const FUNCTION_PARAM: &str = "fn";
+/// Query parameter carrying the JSON arguments of a `method = "GET"` call.
+pub const ARGS_PARAM: &str = "args";
+
+/// Stable 64-bit hash behind every generated id. The macros, the CLI and the browser runtime
+/// must all derive ids through this function so they never drift apart.
+pub fn stable_hash(input: &str) -> String {
+    let mut hash: u64 = 5381;
+    for c in input.bytes() {
+        hash = ((hash << 5).wrapping_add(hash)).wrapping_add(c as u64);
+    }
+    format!("{:x}", hash)
+}
+
+/// Id of the server or client function at `full_path`, e.g. `app::utils::save`.
+pub fn function_id(full_path: &str) -> String {
+    format!("f_{}", stable_hash(full_path))
+}
+
+/// Id the `on_click` rewrite gives a handler referenced by `path`.
+pub fn handler_id(path: &str) -> String {
+    format!("h_{}", stable_hash(path))
+}
+
+/// Whether `name` may be used as an explicit `#[server(name = "...")]` id.
+pub fn is_valid_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/'))
+}
+
+/// Legacy JSON body of an RPC call, accepted when the call carries no [`FUNCTION_HEADER`].
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RpcRequest {
     pub function: String,
     pub args: serde_json::Value,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RpcResponse {
-    pub result: serde_json::Value,
+/// Envelope sent back for every RPC call: `{"ok": <value>}` or `{"error": <ServerFnError>}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcResponse<T, E> {
+    Ok(T),
+    Error(ServerFnError<E>),
+}
+
+impl<T, E> From<Result<T, ServerFnError<E>>> for RpcResponse<T, E> {
+    fn from(result: Result<T, ServerFnError<E>>) -> Self {
+        match result {
+            Ok(value) => RpcResponse::Ok(value),
+            Err(err) => RpcResponse::Error(err),
+        }
+    }
+}
+
+impl<T, E> RpcResponse<T, E> {
+    pub fn into_result(self) -> Result<T, ServerFnError<E>> {
+        match self {
+            RpcResponse::Ok(value) => Ok(value),
+            RpcResponse::Error(err) => Err(err),
+        }
+    }
+}
+
+/// An [`RpcResponse`] encoded for the wire, along with the HTTP status to send it with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcReply {
+    pub status: u16,
+    pub encoding: Encoding,
+    pub body: Vec<u8>,
+}
+
+impl RpcReply {
+    /// Encodes a server function result straight into `encoding`.
+    pub fn from_result<T, E>(encoding: Encoding, result: Result<T, ServerFnError<E>>) -> Self
+    where
+        T: Serialize,
+        E: Serialize,
+    {
+        let status = match &result {
+            Ok(_) => 200,
+            Err(err) => err.status_code(),
+        };
+        match encoding.encode(&RpcResponse::from(result)) {
+            Ok(body) => RpcReply {
+                status,
+                encoding,
+                body,
+            },
+            Err(e) => {
+                let err =
+                    ServerFnError::<()>::Server(format!("failed to serialize response: {}", e));
+                let body = encoding
+                    .encode(&RpcResponse::<(), ()>::Error(err))
+                    .expect("string errors always serialize");
+                RpcReply {
+                    status: 500,
+                    encoding,
+                    body,
+                }
+            }
+        }
+    }
+
+    pub fn error<E: Serialize>(encoding: Encoding, err: ServerFnError<E>) -> Self {
+        Self::from_result::<(), E>(encoding, Err(err))
+    }
+
+    /// Strong `ETag` of the reply body, sent with replies to `method = "GET"` calls.
+    pub fn etag(&self) -> String {
+        let digest = Sha256::digest(&self.body);
+        let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("\"{}\"", hex)
+    }
+
+    /// Decodes the reply back into the result the server function returned.
+    pub fn into_result<T, E>(self) -> Result<T, ServerFnError<E>>
+    where
+        T: DeserializeOwned,
+        E: DeserializeOwned,
+    {
+        self.encoding
+            .decode::<RpcResponse<T, E>>(&self.body)
+            .map_err(ServerFnError::Decode)?
+            .into_result()
+    }
+}
+
+/// Whether an `If-None-Match` header value matches `etag`. Uses the weak comparison required for
+/// `If-None-Match`, so `W/"x"` matches `"x"`.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// `Content-Disposition` asking to save a reply as `file_name`. Names that aren't plain ASCII,
+/// or that contain quotes or `;`, also travel percent-encoded in `filename*` (RFC 6266).
+pub fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' | ';' => '_',
+            c if c == ' ' || c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    if fallback == file_name {
+        return format!("attachment; filename=\"{}\"", fallback);
+    }
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => (b as char).to_string(),
+            b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+/// The file name in a `Content-Disposition` header, preferring `filename*` over `filename`.
+pub fn disposition_file_name(header: &str) -> Option<String> {
+    let params: Vec<(String, &str)> = header
+        .split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            Some((key.trim().to_ascii_lowercase(), value.trim()))
+        })
+        .collect();
+    let extended = params
+        .iter()
+        .find(|(key, _)| key == "filename*")
+        .and_then(|(_, value)| {
+            let (charset, rest) = value.split_once('\'')?;
+            let (_, encoded) = rest.split_once('\'')?;
+            if !charset.eq_ignore_ascii_case("utf-8") {
+                return None;
+            }
+            let mut bytes = Vec::new();
+            let mut iter = encoded.bytes();
+            while let Some(b) = iter.next() {
+                if b == b'%' {
+                    let hex = [iter.next()?, iter.next()?];
+                    bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+                } else {
+                    bytes.push(b);
+                }
+            }
+            String::from_utf8(bytes).ok()
+        });
+    extended.or_else(|| {
+        let (_, value) = params.iter().find(|(key, _)| key == "filename")?;
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+        Some(value.replace("\\\"", "\"").replace("\\\\", "\\"))
+    })
+}
+
+/// One call in a batch sent to the batch endpoint.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchCall {
+    pub function: String,
+    pub args: Payload,
+}
+
+/// Reply to one [`BatchCall`], in the same position as the call.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchReply {
+    pub status: u16,
+    pub body: Payload,
+}
+
+/// Already encoded arguments or reply inside a batch. Batches use one encoding throughout, so
+/// this is a JSON document embedded as a string in JSON and raw bytes in the binary encodings.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Payload(pub Vec<u8>);
+
+impl Serialize for Payload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            let text = std::str::from_utf8(&self.0).map_err(serde::ser::Error::custom)?;
+            serializer.serialize_str(text)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Payload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Payload;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an encoded payload")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Payload, E> {
+                Ok(Payload(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Payload, E> {
+                Ok(Payload(v))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Payload, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(b) = seq.next_element()? {
+                    bytes.push(b);
+                }
+                Ok(Payload(bytes))
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            String::deserialize(deserializer).map(|text| Payload(text.into_bytes()))
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+}
+
+/// Error returned by a `#[server]` function, either raised by the framework or by the
+/// function itself through `App`.
+///
+/// Serializes as `{"kind": "app", "detail": ...}` in JSON and as a plain enum in the binary
+/// encodings, since postcard cannot read the named `kind` tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerFnError<E = String> {
+    /// The request did not complete, e.g. the network failed or a proxy answered instead.
+    Transport(String),
+    /// The client could not decode the server's response.
+    Decode(String),
+    /// The server could not decode the function arguments.
+    Args(String),
+    /// No server function is registered under the requested id.
+    NotFound(String),
+    /// A guard rejected the call because the caller is not authenticated.
+    Unauthorized(String),
+    /// A guard rejected the call because the caller lacks permission.
+    Forbidden(String),
+    /// The caller was built against a different deployment and should reload.
+    Outdated(String),
+    /// The server failed outside of the function itself.
+    Server(String),
+    /// No reply arrived within the call's timeout.
+    Timeout(String),
+    /// The caller gave up on the call, e.g. it was aborted or superseded by a newer one.
+    Cancelled(String),
+    /// The caller exceeded the function's `rate_limit` and should wait before calling again.
+    RateLimited(String),
+    /// The arguments broke the rules declared with `#[derive(Validate)]`.
+    Invalid(ValidationErrors),
+    /// An error returned by the server function.
+    App(E),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(
+    remote = "ServerFnError",
+    tag = "kind",
+    content = "detail",
+    rename_all = "snake_case"
+)]
+enum TaggedServerFnError<E> {
+    Transport(String),
+    Decode(String),
+    Args(String),
+    NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Outdated(String),
+    Server(String),
+    Timeout(String),
+    Cancelled(String),
+    RateLimited(String),
+    Invalid(ValidationErrors),
+    App(E),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "ServerFnError")]
+enum CompactServerFnError<E> {
+    Transport(String),
+    Decode(String),
+    Args(String),
+    NotFound(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Outdated(String),
+    Server(String),
+    App(E),
+    // Variants are identified by index here, so new ones go last.
+    Timeout(String),
+    Cancelled(String),
+    RateLimited(String),
+    Invalid(ValidationErrors),
+}
+
+impl<E: Serialize> Serialize for ServerFnError<E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            TaggedServerFnError::serialize(self, serializer)
+        } else {
+            CompactServerFnError::serialize(self, serializer)
+        }
+    }
+}
+
+impl<'de, E: Deserialize<'de>> Deserialize<'de> for ServerFnError<E> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            TaggedServerFnError::deserialize(deserializer)
+        } else {
+            CompactServerFnError::deserialize(deserializer)
+        }
+    }
+}
+
+impl<E> ServerFnError<E> {
+    /// HTTP status the server responds with for this error.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ServerFnError::Args(_) => 400,
+            ServerFnError::Unauthorized(_) => 401,
+            ServerFnError::Forbidden(_) => 403,
+            ServerFnError::Outdated(_) => 409,
+            ServerFnError::RateLimited(_) => 429,
+            ServerFnError::Invalid(_) => 422,
+            ServerFnError::NotFound(_) => 404,
+            ServerFnError::Transport(_) => 502,
+            ServerFnError::Timeout(_) => 504,
+            // Not a standard status; only used if a server ever reports a cancelled call.
+            ServerFnError::Cancelled(_) => 499,
+            ServerFnError::Decode(_) | ServerFnError::Server(_) | ServerFnError::App(_) => 500,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for ServerFnError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerFnError::Transport(msg) => write!(f, "request failed: {}", msg),
+            ServerFnError::Decode(msg) => write!(f, "failed to decode response: {}", msg),
+            ServerFnError::Args(msg) => write!(f, "invalid arguments: {}", msg),
+            ServerFnError::NotFound(id) => write!(f, "server function not found: {}", id),
+            ServerFnError::Unauthorized(msg) => write!(f, "unauthorized: {}", msg),
+            ServerFnError::Forbidden(msg) => write!(f, "forbidden: {}", msg),
+            ServerFnError::Outdated(msg) => write!(f, "please reload the page: {}", msg),
+            ServerFnError::Server(msg) => write!(f, "server error: {}", msg),
+            ServerFnError::Timeout(msg) => write!(f, "timed out: {}", msg),
+            ServerFnError::Cancelled(msg) => write!(f, "cancelled: {}", msg),
+            ServerFnError::RateLimited(msg) => write!(f, "rate limited: {}", msg),
+            ServerFnError::Invalid(errors) => write!(f, "invalid arguments: {}", errors),
+            ServerFnError::App(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for ServerFnError<E> {}
+
+/// Shorthand for the return type of a `#[server]` function.
+pub type ServerFnResult<T, E = String> = Result<T, ServerFnError<E>>;
+
+/// How the browser waits for a server function: set per function with
+/// `#[server(timeout = "5s", retry = 3)]` and adjusted per call with `CallOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CallPolicy {
+    /// How long to wait for a reply before failing with [`ServerFnError::Timeout`].
+    pub timeout: Option<Duration>,
+    /// How many times to retry a call that failed with `Transport` or `Timeout`. Only
+    /// idempotent functions may be retried.
+    pub retries: u32,
+}
+
+impl CallPolicy {
+    /// Delay before retry number `attempt` (starting at 0): 200ms, doubling up to 5s.
+    pub fn backoff(attempt: u32) -> Duration {
+        Duration::from_millis(200u64.saturating_mul(1 << attempt.min(5)))
+            .min(Duration::from_secs(5))
+    }
+}
+
+/// Parses a duration such as `"250ms"`, `"5s"` or `"2m"`, as used by `#[server(timeout = ...)]`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit())?;
+    let amount: u64 = value[..split].parse().ok()?;
+    match value[split..].trim() {
+        "ms" => Some(Duration::from_millis(amount)),
+        "s" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_secs(amount.checked_mul(60)?)),
+        _ => None,
+    }
+}
+
+/// How often one client may call a server function, declared with
+/// `#[server(rate_limit = "10/min")]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max: u32,
+    pub window: Duration,
+    pub key: RateLimitKey,
+}
+
+/// What tells clients apart for a [`RateLimit`], set with `rate_limit_by`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RateLimitKey {
+    /// The client's IP address.
+    #[default]
+    Ip,
+    /// The browser session, i.e. its `lithe_csrf` cookie if the server issued it, else the IP
+    /// address.
+    Session,
+}
+
+impl RateLimitKey {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ip" => Some(RateLimitKey::Ip),
+            "session" => Some(RateLimitKey::Session),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a rate such as `"10/min"` into the number of calls and the window they are counted
+/// in. The window is `s`, `min`, `h` or `day`, optionally with a count, e.g. `"100/15min"`.
+pub fn parse_rate(value: &str) -> Option<(u32, Duration)> {
+    let (max, window) = value.split_once('/')?;
+    let max: u32 = max.trim().parse().ok().filter(|&max| max > 0)?;
+    let window = window.trim();
+    let split = window
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(window.len());
+    let count: u64 = match &window[..split] {
+        "" => 1,
+        count => count.parse().ok().filter(|&count| count > 0)?,
+    };
+    let unit = match &window[split..] {
+        "s" | "sec" | "second" => 1,
+        "m" | "min" | "minute" => 60,
+        "h" | "hour" => 60 * 60,
+        "d" | "day" => 24 * 60 * 60,
+        _ => return None,
+    };
+    Some((max, Duration::from_secs(count.checked_mul(unit)?)))
+}
+
+/// Implemented by the types a `#[server]` function may return, i.e.
+/// `Result<T, ServerFnError<E>>` and aliases of it.
+#[diagnostic::on_unimplemented(
+    message = "`#[server]` functions must return `Result<T, ServerFnError<E>>`, found `{Self}`"
+)]
+pub trait RpcResult: Sized {
+    type Ok: Serialize + DeserializeOwned;
+    type Err: Serialize + DeserializeOwned;
+
+    fn from_result(result: Result<Self::Ok, ServerFnError<Self::Err>>) -> Self;
+    fn into_result(self) -> Result<Self::Ok, ServerFnError<Self::Err>>;
+}
+
+impl<T, E> RpcResult for Result<T, ServerFnError<E>>
+where
+    T: Serialize + DeserializeOwned,
+    E: Serialize + DeserializeOwned,
+{
+    type Ok = T;
+    type Err = E;
+
+    fn from_result(result: Result<T, ServerFnError<E>>) -> Self {
+        result
+    }
+
+    fn into_result(self) -> Result<T, ServerFnError<E>> {
+        self
+    }
 }
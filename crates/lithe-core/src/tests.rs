@@ -1,4 +1,8 @@
-use crate::{br, div, img, p, render_to_string, span, Component};
+use crate::i18n::{alternates, negotiate_locale, split_locale_prefix};
+use crate::{
+    HtmlPage, PageMeta, PageRequest, RenderContext, br, div, img, p, render_to_string, span, url,
+    with_render_context,
+};
 
 #[test]
 fn test_basic_element_rendering() {
@@ -72,3 +76,515 @@ fn test_complex_tree() {
     let expected = "<div id=\"app\"><div class=\"header\"><span>Logo</span></div><div class=\"body\"><p>Welcome to the monorepo.</p><br /><img src=\"avatar.jpg\" /></div></div>";
     assert_eq!(render_to_string(&root), expected);
 }
+
+#[test]
+fn test_locale_prefix_and_negotiation() {
+    let locales = ["en", "fr", "de"];
+    assert_eq!(
+        split_locale_prefix("/fr/about", &locales),
+        Some(("fr", "/about"))
+    );
+    assert_eq!(split_locale_prefix("/de", &locales), Some(("de", "/")));
+    assert_eq!(split_locale_prefix("/about", &locales), None);
+
+    assert_eq!(
+        negotiate_locale(&locales, "en", None, Some("fr-CH, de;q=0.9")),
+        "fr"
+    );
+    assert_eq!(
+        negotiate_locale(&locales, "en", None, Some("es, de;q=0.5")),
+        "de"
+    );
+    assert_eq!(
+        negotiate_locale(&locales, "en", Some("de"), Some("fr")),
+        "de"
+    );
+    assert_eq!(negotiate_locale(&locales, "en", None, None), "en");
+    assert_eq!(negotiate_locale(&locales, "en", None, Some("de;q=0")), "en");
+    assert_eq!(
+        negotiate_locale(&locales, "en", None, Some("de;q=0, fr;q=0.1")),
+        "fr"
+    );
+
+    let req = PageRequest {
+        cookie: Some("theme=dark; lithe_locale=fr".to_string()),
+        ..PageRequest::new("/")
+    };
+    assert_eq!(req.cookie("lithe_locale"), Some("fr"));
+}
+
+#[test]
+fn test_html_page_uses_render_locale() {
+    let ctx = RenderContext {
+        path: "/about".to_string(),
+        base_path: String::new(),
+        locale: "fr".to_string(),
+        default_locale: "en".to_string(),
+        alternates: alternates("", "/about", &["en", "fr"]),
+        ..RenderContext::default()
+    };
+    let html = with_render_context(ctx, || HtmlPage::new("About", p()).render_to_string());
+
+    assert!(html.contains("<html lang=\"fr\">"));
+    assert!(html.contains("<link rel=\"alternate\" hreflang=\"fr\" href=\"/fr/about\">"));
+    assert!(html.contains("<link rel=\"alternate\" hreflang=\"x-default\" href=\"/about\">"));
+}
+
+#[test]
+fn test_base_path_and_host_routing() {
+    let ctx = RenderContext {
+        base_path: "/app".to_string(),
+        ..RenderContext::default()
+    };
+    with_render_context(ctx, || {
+        assert_eq!(url("/"), "/app");
+        assert_eq!(url("/public/style.css"), "/app/public/style.css");
+    });
+    assert_eq!(url("/about"), "/about");
+
+    let hosts = [("docs.example.com", "/docs")];
+    let req = PageRequest {
+        host: Some("docs.example.com:8080".to_string()),
+        ..PageRequest::new("/guide")
+    };
+    assert_eq!(req.host_path("/guide", &hosts), "/docs/guide");
+    assert_eq!(req.host_path("/", &hosts), "/docs");
+    assert_eq!(
+        PageRequest::new("/guide").host_path("/guide", &hosts),
+        "/guide"
+    );
+}
+
+#[test]
+fn test_html_page_falls_back_to_page_meta() {
+    let ctx = RenderContext {
+        meta: PageMeta {
+            title: Some("About"),
+            description: Some("About the project"),
+            ..PageMeta::DEFAULT
+        },
+        ..RenderContext::default()
+    };
+    let html = with_render_context(ctx, || HtmlPage::new("", p()).render_to_string());
+
+    assert!(html.contains("<title>About</title>"));
+    assert!(html.contains("<meta name=\"description\" content=\"About the project\">"));
+
+    let ctx = RenderContext {
+        meta: PageMeta {
+            title: Some("Q&A <beta>"),
+            description: Some("The \"fast\" framework"),
+            ..PageMeta::DEFAULT
+        },
+        ..RenderContext::default()
+    };
+    let html = with_render_context(ctx, || HtmlPage::new("", p()).render_to_string());
+
+    assert!(html.contains("<title>Q&amp;A &lt;beta&gt;</title>"));
+    assert!(html.contains("content=\"The &quot;fast&quot; framework\">"));
+}
+
+#[test]
+fn test_preview_tokens() {
+    use crate::preview::{PREVIEW_SECRET_ENV, check_secret, is_preview_request, issue_token};
+
+    // SAFETY: no other test reads or writes the preview secret.
+    unsafe { std::env::set_var(PREVIEW_SECRET_ENV, "s3cret") };
+    assert!(check_secret("s3cret"));
+    assert!(!check_secret("s3cre"));
+
+    let token = issue_token().unwrap();
+    let with_cookie = |cookie: String| PageRequest {
+        cookie: Some(cookie),
+        ..PageRequest::new("/")
+    };
+    assert!(is_preview_request(&with_cookie(format!(
+        "lithe_preview={}",
+        token
+    ))));
+    assert!(!is_preview_request(&with_cookie(format!(
+        "lithe_preview={}0",
+        token
+    ))));
+    assert!(!is_preview_request(&with_cookie(
+        "lithe_preview=1.abcd".to_string()
+    )));
+    assert!(!is_preview_request(&PageRequest::new("/")));
+}
+
+#[test]
+fn test_rpc_error_envelope() {
+    use crate::rpc::{Encoding, RpcReply, ServerFnError};
+
+    let reply = RpcReply::from_result::<i32, String>(Encoding::Json, Ok(7));
+    assert_eq!(reply.status, 200);
+    assert_eq!(reply.into_result::<i32, String>(), Ok(7));
+
+    let reply = RpcReply::from_result::<i32, String>(
+        Encoding::Json,
+        Err(ServerFnError::App("no stock".to_string())),
+    );
+    assert_eq!(reply.status, 500);
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&reply.body).unwrap(),
+        serde_json::json!({"error": {"kind": "app", "detail": "no stock"}})
+    );
+    assert_eq!(
+        reply.into_result::<i32, String>(),
+        Err(ServerFnError::App("no stock".to_string()))
+    );
+
+    let reply = RpcReply::error(Encoding::Json, ServerFnError::<()>::Args("bad".to_string()));
+    assert_eq!(reply.status, 400);
+    assert_eq!(
+        reply.into_result::<i32, String>(),
+        Err(ServerFnError::Args("bad".to_string()))
+    );
+    assert!(matches!(
+        RpcReply::from_result::<&str, ()>(Encoding::Json, Ok("x")).into_result::<i32, String>(),
+        Err(ServerFnError::Decode(_))
+    ));
+}
+
+#[test]
+fn test_rpc_encodings() {
+    use crate::rpc::{
+        BatchCall, Encoding, Encodings, Payload, RpcReply, ServerFnError, StreamFormat,
+    };
+
+    for encoding in Encoding::ALL {
+        assert_eq!(Encoding::from_name(encoding.name()), Some(encoding));
+        assert_eq!(
+            Encoding::from_content_type(encoding.content_type()),
+            Some(encoding)
+        );
+
+        let args = (3u32, "three".to_string(), vec![1u8, 2, 3]);
+        let bytes = encoding.encode(&args).unwrap();
+        assert_eq!(encoding.decode::<(u32, String, Vec<u8>)>(&bytes), Ok(args));
+
+        let batch = vec![BatchCall {
+            function: "f_1".to_string(),
+            args: Payload(encoding.encode(&(1, 2)).unwrap()),
+        }];
+        let decoded = encoding
+            .decode::<Vec<BatchCall>>(&encoding.encode(&batch).unwrap())
+            .unwrap();
+        assert_eq!(decoded, batch);
+        assert_eq!(
+            encoding.decode::<(i32, i32)>(&decoded[0].args.0),
+            Ok((1, 2))
+        );
+
+        let reply = RpcReply::from_result::<Vec<i64>, String>(encoding, Ok(vec![-1, 2]));
+        assert_eq!(reply.into_result::<Vec<i64>, String>(), Ok(vec![-1, 2]));
+        // Framework errors are raised as `ServerFnError<()>` but read with the caller's error type.
+        let reply = RpcReply::error(encoding, ServerFnError::<()>::NotFound("f_1".to_string()));
+        assert_eq!(reply.status, 404);
+        assert_eq!(
+            reply.into_result::<i32, String>(),
+            Err(ServerFnError::NotFound("f_1".to_string()))
+        );
+    }
+
+    assert_eq!(Encodings::negotiate(None, None), Some(Encodings::default()));
+    assert_eq!(
+        Encodings::negotiate(Some("application/cbor"), Some("*/*")),
+        Some(Encodings {
+            request: Encoding::Cbor,
+            response: Encoding::Cbor,
+        })
+    );
+    assert_eq!(
+        Encodings::negotiate(
+            Some("application/json; charset=utf-8"),
+            Some("text/html, application/x-msgpack;q=0.9")
+        ),
+        Some(Encodings {
+            request: Encoding::Json,
+            response: Encoding::MsgPack,
+        })
+    );
+    assert_eq!(Encodings::negotiate(Some("text/plain"), None), None);
+
+    assert_eq!(StreamFormat::from_accept(None), StreamFormat::NdJson);
+    let sse = StreamFormat::from_accept(Some("text/event-stream"));
+    assert_eq!(sse.frame(br#"{"ok":1}"#), b"data: {\"ok\":1}\n\n");
+    assert_eq!(StreamFormat::NdJson.frame(b"{}"), b"{}\n");
+}
+
+#[test]
+fn test_function_ids() {
+    use crate::rpc::{function_id, handler_id, is_valid_function_name};
+
+    // Ids are baked into deployed clients, so the hash must never change.
+    assert_eq!(
+        function_id("rust::utils::get_server_data"),
+        "f_b72c3ddf4f1e0d3b"
+    );
+    assert!(handler_id("rust_utils_get_server_data").starts_with("h_"));
+    assert!(is_valid_function_name("v1/save-post.draft"));
+    assert!(!is_valid_function_name(""));
+    assert!(!is_valid_function_name("v1 save"));
+}
+
+#[test]
+fn test_rpc_etag() {
+    use crate::rpc::{Encoding, RpcReply, etag_matches};
+
+    let reply = RpcReply::from_result::<_, ()>(Encoding::Json, Ok(42));
+    let etag = reply.etag();
+    assert!(etag.starts_with('"') && etag.ends_with('"'));
+    assert_eq!(etag, reply.clone().etag());
+    assert_ne!(
+        etag,
+        RpcReply::from_result::<_, ()>(Encoding::Json, Ok(43)).etag()
+    );
+
+    assert!(etag_matches(&etag, &etag));
+    assert!(etag_matches(&format!("\"other\", W/{}", etag), &etag));
+    assert!(etag_matches("*", &etag));
+    assert!(!etag_matches("\"other\"", &etag));
+}
+
+#[test]
+fn test_content_disposition() {
+    use crate::rpc::{content_disposition, disposition_file_name};
+
+    assert_eq!(
+        content_disposition("report.csv"),
+        "attachment; filename=\"report.csv\""
+    );
+    assert_eq!(
+        content_disposition("Übersicht 2024.pdf"),
+        "attachment; filename=\"_bersicht 2024.pdf\"; filename*=UTF-8''%C3%9Cbersicht%202024.pdf"
+    );
+    for name in ["report.csv", "Übersicht 2024.pdf", "a \"quoted\"; name.txt"] {
+        assert_eq!(
+            disposition_file_name(&content_disposition(name)).as_deref(),
+            Some(name)
+        );
+    }
+    assert_eq!(
+        disposition_file_name("attachment; filename=\"a \\\"b\\\".txt\"").as_deref(),
+        Some("a \"b\".txt")
+    );
+    assert_eq!(disposition_file_name("inline"), None);
+}
+
+#[test]
+fn test_call_policy() {
+    use crate::rpc::{CallPolicy, Encoding, ServerFnError, parse_duration};
+    use std::time::Duration;
+
+    assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+    assert_eq!(parse_duration("5s"), Some(Duration::from_secs(5)));
+    assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+    assert_eq!(parse_duration("5"), None);
+    assert_eq!(parse_duration("s"), None);
+    assert_eq!(parse_duration("1h"), None);
+
+    assert_eq!(CallPolicy::backoff(0), Duration::from_millis(200));
+    assert_eq!(CallPolicy::backoff(1), Duration::from_millis(400));
+    assert_eq!(CallPolicy::backoff(30), Duration::from_secs(5));
+
+    // Client-side errors roundtrip like the others, including in the binary encodings.
+    for encoding in Encoding::ALL {
+        let err = ServerFnError::<String>::Timeout("5s".to_string());
+        let bytes = encoding.encode(&err).unwrap();
+        assert_eq!(encoding.decode::<ServerFnError>(&bytes).unwrap(), err);
+    }
+}
+
+#[test]
+fn test_csrf_check() {
+    use crate::csrf::{
+        CallOrigin, check, check_handshake, is_token, new_token, page_may_carry_token, was_issued,
+    };
+
+    let token = new_token();
+    assert!(is_token(&token));
+    assert_ne!(token, new_token());
+    assert!(was_issued(&token));
+    assert!(!was_issued("0123456789abcdef0123456789abcdef"));
+    let last = if token.ends_with('0') { '1' } else { '0' };
+    assert!(!was_issued(&format!("{}{}", &token[..31], last)));
+
+    let page = CallOrigin {
+        host: Some("example.com"),
+        origin: Some("https://example.com"),
+        fetch_site: Some("same-origin"),
+        cookie_token: Some(&token),
+        header_token: Some(&token),
+    };
+    assert!(check(&page, &[]).is_ok());
+    assert!(
+        check(
+            &CallOrigin {
+                header_token: None,
+                ..page
+            },
+            &[]
+        )
+        .is_err()
+    );
+    assert!(
+        check(
+            &CallOrigin {
+                cookie_token: Some("other"),
+                ..page
+            },
+            &[]
+        )
+        .is_err()
+    );
+
+    let forged = CallOrigin {
+        origin: Some("https://evil.example"),
+        fetch_site: Some("cross-site"),
+        ..page
+    };
+    assert!(check(&forged, &[]).is_err());
+    assert!(
+        check(
+            &CallOrigin {
+                fetch_site: None,
+                ..forged
+            },
+            &[]
+        )
+        .is_err()
+    );
+    // Trusted origins skip the token, which they can't read.
+    let trusted = CallOrigin {
+        header_token: None,
+        ..forged
+    };
+    assert!(check(&trusted, &["https://evil.example"]).is_ok());
+
+    // Calls from outside a browser carry neither `Origin` nor `Sec-Fetch-Site`.
+    assert!(
+        check(
+            &CallOrigin {
+                host: Some("example.com"),
+                ..CallOrigin::default()
+            },
+            &[]
+        )
+        .is_ok()
+    );
+
+    // WebSocket handshakes carry no token, only the origin.
+    let handshake = CallOrigin {
+        header_token: None,
+        cookie_token: None,
+        ..page
+    };
+    assert!(check_handshake(&handshake, &[]).is_ok());
+    assert!(check_handshake(&forged, &[]).is_err());
+    assert!(check_handshake(&forged, &["https://evil.example"]).is_ok());
+
+    assert!(page_may_carry_token(None));
+    assert!(page_may_carry_token(Some("private, max-age=60")));
+    assert!(!page_may_carry_token(Some("public, max-age=60")));
+}
+
+#[test]
+fn test_parse_rate() {
+    use crate::rpc::{RateLimitKey, parse_rate};
+    use std::time::Duration;
+
+    assert_eq!(parse_rate("10/min"), Some((10, Duration::from_secs(60))));
+    assert_eq!(
+        parse_rate("100 / 15min"),
+        Some((100, Duration::from_secs(900)))
+    );
+    assert_eq!(parse_rate("5/s"), Some((5, Duration::from_secs(1))));
+    assert_eq!(
+        parse_rate("1000/day"),
+        Some((1000, Duration::from_secs(86400)))
+    );
+    assert_eq!(parse_rate("0/min"), None);
+    assert_eq!(parse_rate("10/0min"), None);
+    assert_eq!(parse_rate("10/week"), None);
+    assert_eq!(parse_rate("10"), None);
+    assert_eq!(
+        RateLimitKey::from_name("session"),
+        Some(RateLimitKey::Session)
+    );
+    assert_eq!(RateLimitKey::from_name("user"), None);
+}
+
+#[test]
+fn test_validation_rules() {
+    use crate::validate::{ValidationErrors, email, length, range};
+
+    assert!(length("héllo", Some(5), Some(5)).is_ok());
+    assert_eq!(
+        length("ab", Some(3), None),
+        Err("must have at least 3 characters".to_string())
+    );
+    assert!(length(&vec![1, 2, 3], None, Some(2)).is_err());
+    assert!(range(&18, Some(13), None).is_ok());
+    assert_eq!(
+        range(&2.5, Some(0.0), Some(1.0)),
+        Err("must be between 0 and 1".to_string())
+    );
+    assert!(email("ada@example.com").is_ok());
+    for bad in [
+        "ada",
+        "ada@",
+        "@example.com",
+        "ada@localhost",
+        "a da@example.com",
+        "a@b@c.com",
+    ] {
+        assert!(email(bad).is_err(), "{}", bad);
+    }
+
+    let mut inner = ValidationErrors::new();
+    inner.add("city", "length", "must have at least 1 characters");
+    let mut items = ValidationErrors::new();
+    items.nest("[1]", Err(inner));
+    let mut errors = ValidationErrors::new();
+    errors.nest("addresses", Err(items));
+    errors.nest("ignored", Ok(()));
+    assert_eq!(errors.0.len(), 1);
+    assert_eq!(errors.0[0].field, "addresses[1].city");
+    assert_eq!(errors.field("addresses[1].city").count(), 1);
+}
+
+#[test]
+fn test_cron_schedule() {
+    use crate::cron::Schedule;
+
+    // 2024-01-15 10:20:30 UTC, a Monday.
+    let now = 1_705_314_030;
+    let next = |expr: &str| Schedule::parse(expr).unwrap().next_after(now);
+
+    assert_eq!(next("0 3 * * *"), Some(1_705_374_000)); // 2024-01-16 03:00
+    assert_eq!(next("*/15 * * * *"), Some(1_705_314_600)); // 10:30
+    assert_eq!(next("@hourly"), Some(1_705_316_400)); // 11:00
+    assert_eq!(next("0 9 * * sat,sun"), Some(1_705_741_200)); // Saturday the 20th, 09:00
+    assert_eq!(next("0 0 29 feb *"), Some(1_709_164_800)); // 2024-02-29
+    // Day of month or day of week, when both are restricted.
+    assert_eq!(next("0 0 1 * 7"), Some(1_705_795_200)); // Sunday the 21st
+    assert_eq!(next("0 0 31 2 *"), None);
+    // Strictly after: a time that matches is not returned again.
+    assert_eq!(
+        Schedule::parse("20 10 * * *").unwrap().next_after(now - 30),
+        Some(now - 30 + 86_400)
+    );
+
+    for bad in [
+        "",
+        "* * * *",
+        "60 * * * *",
+        "* * 0 * *",
+        "*/0 * * * *",
+        "5-1 * * * *",
+        "* * * foo *",
+    ] {
+        assert!(Schedule::parse(bad).is_err(), "{}", bad);
+    }
+}
@@ -0,0 +1,249 @@
+//! Rules for the arguments of server functions, declared with `#[derive(Validate)]`:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize, Validate)]
+//! pub struct SignUp {
+//!     #[validate(length(min = 3, max = 32), regex = "^[a-z0-9_]+$")]
+//!     pub username: String,
+//!     #[validate(email)]
+//!     pub email: String,
+//!     #[validate(range(min = 13))]
+//!     pub age: u32,
+//!     #[validate(custom = not_reserved)]
+//!     pub display_name: Option<String>,
+//! }
+//! ```
+//!
+//! The RPC wrapper of a `#[server]` function checks every argument implementing [`Validate`]
+//! before the function runs, and rejects the call with [`ServerFnError::Invalid`]. The client
+//! stub runs the same checks before sending, and forms can call [`Validate::validate`] directly
+//! to show errors as the user types.
+//!
+//! [`ServerFnError::Invalid`]: crate::rpc::ServerFnError::Invalid
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::OnceLock;
+
+pub use regex_lite::Regex;
+
+/// A type whose values can be checked against the rules declared on its fields.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationErrors>;
+}
+
+impl<T: Validate> Validate for Option<T> {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        self.as_ref().map_or(Ok(()), T::validate)
+    }
+}
+
+impl<T: Validate> Validate for Vec<T> {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        for (i, item) in self.iter().enumerate() {
+            errors.nest(&format!("[{}]", i), item.validate());
+        }
+        errors.into_result()
+    }
+}
+
+/// A rule a field broke.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldError {
+    /// Path of the field, e.g. `email`, `address.city` or `items[2].name`.
+    pub field: String,
+    /// The rule that failed: `length`, `range`, `email`, `regex`, or `custom`.
+    pub code: String,
+    /// A message describing the failure, in English.
+    pub message: String,
+}
+
+/// Every rule the fields of a value broke, in declaration order.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, field: &str, code: &str, message: impl Into<String>) {
+        self.0.push(FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message: message.into(),
+        });
+    }
+
+    /// Adds the errors of a nested value, with their fields prefixed by `prefix`.
+    pub fn nest(&mut self, prefix: &str, result: Result<(), ValidationErrors>) {
+        let Err(errors) = result else {
+            return;
+        };
+        for mut error in errors.0 {
+            error.field =
+                if prefix.is_empty() || error.field.is_empty() || error.field.starts_with('[') {
+                    format!("{}{}", prefix, error.field)
+                } else {
+                    format!("{}.{}", prefix, error.field)
+                };
+            self.0.push(error);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The errors of one field, e.g. to show next to its input.
+    pub fn field<'a>(&'a self, field: &'a str) -> impl Iterator<Item = &'a FieldError> + 'a {
+        self.0.iter().filter(move |e| e.field == field)
+    }
+
+    pub fn into_result(self) -> Result<(), ValidationErrors> {
+        if self.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}: {}", error.field, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+/// Values the `length` rule applies to. Strings are measured in characters.
+pub trait HasLength {
+    /// What the length counts, for messages.
+    const UNIT: &'static str;
+
+    fn length(&self) -> usize;
+}
+
+impl HasLength for str {
+    const UNIT: &'static str = "characters";
+
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl HasLength for String {
+    const UNIT: &'static str = "characters";
+
+    fn length(&self) -> usize {
+        self.as_str().length()
+    }
+}
+
+impl<T> HasLength for [T] {
+    const UNIT: &'static str = "items";
+
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T> HasLength for Vec<T> {
+    const UNIT: &'static str = "items";
+
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+/// The `length(min = .., max = ..)` rule.
+pub fn length<T: HasLength + ?Sized>(
+    value: &T,
+    min: Option<usize>,
+    max: Option<usize>,
+) -> Result<(), String> {
+    let len = value.length();
+    match (min, max) {
+        (Some(min), Some(max)) if len < min || len > max => {
+            Err(format!("must have between {} and {} {}", min, max, T::UNIT))
+        }
+        (Some(1), _) if len < 1 => Err("must not be empty".to_string()),
+        (Some(min), _) if len < min => Err(format!("must have at least {} {}", min, T::UNIT)),
+        (_, Some(max)) if len > max => Err(format!("must have at most {} {}", max, T::UNIT)),
+        _ => Ok(()),
+    }
+}
+
+/// The `range(min = .., max = ..)` rule; both bounds are inclusive.
+pub fn range<T: PartialOrd + fmt::Display>(
+    value: &T,
+    min: Option<T>,
+    max: Option<T>,
+) -> Result<(), String> {
+    match (min, max) {
+        (Some(min), Some(max)) if *value < min || *value > max => {
+            Err(format!("must be between {} and {}", min, max))
+        }
+        (Some(min), _) if *value < min => Err(format!("must be at least {}", min)),
+        (_, Some(max)) if *value > max => Err(format!("must be at most {}", max)),
+        _ => Ok(()),
+    }
+}
+
+/// The `email` rule: one `@` between a local part and a dotted domain, without spaces. It
+/// catches typos, not undeliverable addresses.
+pub fn email(value: &str) -> Result<(), String> {
+    let valid = value.len() <= 254
+        && !value.chars().any(char::is_whitespace)
+        && value.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && domain.split('.').all(|label| {
+                    !label.is_empty() && !label.starts_with('-') && !label.ends_with('-')
+                })
+        });
+    if valid {
+        Ok(())
+    } else {
+        Err("must be an email address".to_string())
+    }
+}
+
+/// The `regex = ".."` rule. `cache` holds the compiled pattern, one per declaring field.
+pub fn regex(value: &str, cache: &OnceLock<Regex>, pattern: &str) -> Result<(), String> {
+    // The derive checked the pattern when it compiled.
+    let regex = cache.get_or_init(|| Regex::new(pattern).expect("invalid validation pattern"));
+    if regex.is_match(value) {
+        Ok(())
+    } else {
+        Err(format!("must match `{}`", pattern))
+    }
+}
+
+/// Wraps a server function argument so the generated code can check it only when its type
+/// implements [`Validate`]: [`ValidateArg`] applies then, and [`SkipArg`] otherwise.
+pub struct Check<'a, T>(pub &'a T);
+
+pub trait ValidateArg {
+    fn validate_arg(&self) -> Result<(), ValidationErrors>;
+}
+
+impl<T: Validate> ValidateArg for Check<'_, T> {
+    fn validate_arg(&self) -> Result<(), ValidationErrors> {
+        self.0.validate()
+    }
+}
+
+pub trait SkipArg {
+    fn validate_arg(&self) -> Result<(), ValidationErrors> {
+        Ok(())
+    }
+}
+
+impl<T> SkipArg for &Check<'_, T> {}
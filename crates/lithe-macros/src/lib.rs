@@ -1,18 +1,16 @@
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::{
+    meta::ParseNestedMeta,
     parse_macro_input, parse_quote,
     visit_mut::{self, VisitMut},
-    Block, Expr, ExprMethodCall, ExprPath, FnArg, ItemFn, Pat, ReturnType,
+    Block, Expr, ExprMethodCall, ExprPath, FnArg, GenericArgument, ItemFn, LitBool, LitStr, Pat,
+    PathArguments, ReturnType, Type, TypeParamBound,
 };
 
-fn stable_hash(s: &str) -> String {
-    let mut hash: u64 = 5381;
-    for c in s.bytes() {
-        hash = ((hash << 5).wrapping_add(hash)).wrapping_add(c as u64);
-    }
-    format!("{:x}", hash)
-}
+use lithe_core::rpc::{
+    handler_id, is_valid_function_name, parse_duration, parse_rate, stable_hash, Encoding, RateLimitKey,
+};
 
 #[proc_macro_attribute]
 pub fn client(_attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -24,22 +22,189 @@ pub fn client(_attr: TokenStream, item: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
+#[derive(Default)]
+struct ServerArgs {
+    guards: Vec<syn::Path>,
+    public: bool,
+    name: Option<LitStr>,
+    encoding: Option<Encoding>,
+    get: bool,
+    cache: Option<LitStr>,
+    idempotent: bool,
+    timeout: Option<std::time::Duration>,
+    retry: Option<syn::LitInt>,
+    rate_limit: bool,
+    rate_limit_by: Option<LitStr>,
+}
+
+impl ServerArgs {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("guard") {
+            parse_guards(&meta, &mut self.guards)?;
+        } else if meta.path.is_ident("public") {
+            self.public = true;
+        } else if meta.path.is_ident("idempotent") {
+            self.idempotent = true;
+        } else if meta.path.is_ident("timeout") {
+            let timeout: LitStr = meta.value()?.parse()?;
+            self.timeout = Some(parse_duration(&timeout.value()).ok_or_else(|| {
+                syn::Error::new(timeout.span(), "expected a duration such as \"500ms\", \"5s\" or \"1m\"")
+            })?);
+        } else if meta.path.is_ident("retry") {
+            let retry: syn::LitInt = meta.value()?.parse()?;
+            retry.base10_parse::<u32>()?;
+            self.retry = Some(retry);
+        } else if meta.path.is_ident("rate_limit") {
+            // Enforced by the generated router; checked here so typos fail at the attribute.
+            let rate: LitStr = meta.value()?.parse()?;
+            parse_rate(&rate.value()).ok_or_else(|| {
+                syn::Error::new(rate.span(), "expected a rate such as \"10/min\", \"5/s\" or \"100/15min\"")
+            })?;
+            self.rate_limit = true;
+        } else if meta.path.is_ident("rate_limit_by") {
+            let key: LitStr = meta.value()?.parse()?;
+            if RateLimitKey::from_name(&key.value()).is_none() {
+                return Err(syn::Error::new(key.span(), "expected rate_limit_by = \"ip\" or \"session\""));
+            }
+            self.rate_limit_by = Some(key);
+        } else if meta.path.is_ident("name") {
+            let name: LitStr = meta.value()?.parse()?;
+            if !is_valid_function_name(&name.value()) {
+                return Err(syn::Error::new(
+                    name.span(),
+                    "server function names may only contain letters, digits, `_`, `-`, `.` and `/`",
+                ));
+            }
+            self.name = Some(name);
+        } else if meta.path.is_ident("encoding") {
+            self.encoding = Some(parse_encoding(&meta)?);
+        } else if meta.path.is_ident("method") {
+            let method: LitStr = meta.value()?.parse()?;
+            self.get = match method.value().to_ascii_uppercase().as_str() {
+                "GET" => true,
+                "POST" => false,
+                _ => {
+                    return Err(syn::Error::new(
+                        method.span(),
+                        "expected method = \"GET\" or method = \"POST\"",
+                    ));
+                }
+            };
+        } else if meta.path.is_ident("cache") {
+            let cache: LitStr = meta.value()?.parse()?;
+            // The value is sent verbatim as the `Cache-Control` header.
+            if cache.value().is_empty() || !cache.value().bytes().all(|b| (0x20..0x7f).contains(&b)) {
+                return Err(syn::Error::new(
+                    cache.span(),
+                    "expected a Cache-Control value such as \"public, max-age=60\"",
+                ));
+            }
+            self.cache = Some(cache);
+        } else {
+            return Err(meta.error(
+                "unsupported server attribute, expected one of: guard, public, name, encoding, method, cache, timeout, retry, idempotent, rate_limit, rate_limit_by",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Accepts `guard = require_user` (repeatable) and `guard = [require_user, require_admin]`.
+fn parse_guards(meta: &ParseNestedMeta, guards: &mut Vec<syn::Path>) -> syn::Result<()> {
+    match meta.value()?.parse::<Expr>()? {
+        Expr::Path(p) => guards.push(p.path),
+        Expr::Array(list) => {
+            for elem in list.elems {
+                match elem {
+                    Expr::Path(p) => guards.push(p.path),
+                    other => {
+                        return Err(syn::Error::new_spanned(
+                            other,
+                            "expected a guard function path",
+                        ));
+                    }
+                }
+            }
+        }
+        other => {
+            return Err(syn::Error::new_spanned(
+                other,
+                "expected a guard function path",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn parse_encoding(meta: &ParseNestedMeta) -> syn::Result<Encoding> {
+    let encoding: LitStr = meta.value()?.parse()?;
+    Encoding::from_name(&encoding.value()).ok_or_else(|| {
+        syn::Error::new(
+            encoding.span(),
+            "expected encoding = \"json\", \"msgpack\", \"cbor\" or \"postcard\"",
+        )
+    })
+}
+
+/// The encoding a client stub calls with: the one it names, else the page's.
+fn encoding_tokens(encoding: Option<Encoding>) -> proc_macro2::TokenStream {
+    match encoding {
+        Some(encoding) => {
+            let variant = match encoding {
+                Encoding::Json => quote! { Json },
+                Encoding::MsgPack => quote! { MsgPack },
+                Encoding::Cbor => quote! { Cbor },
+                Encoding::Postcard => quote! { Postcard },
+            };
+            quote! { ::lithe::rpc::Encoding::#variant }
+        }
+        None => quote! { ::lithe::browser::default_encoding() },
+    }
+}
+
 #[proc_macro_attribute]
-pub fn server(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn server(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut server_args = ServerArgs::default();
+    let parser = syn::meta::parser(|meta| server_args.parse(meta));
+    parse_macro_input!(attr with parser);
+
     let input_fn = parse_macro_input!(item as ItemFn);
     let fn_name = &input_fn.sig.ident;
     let visibility = &input_fn.vis;
     let inputs = &input_fn.sig.inputs;
     let output = &input_fn.sig.output;
 
+    if input_fn.sig.asyncness.is_none() {
+        return syn::Error::new_spanned(input_fn.sig.fn_token, "#[server] functions must be async")
+            .to_compile_error()
+            .into();
+    }
+    if !input_fn.sig.generics.params.is_empty() || input_fn.sig.generics.where_clause.is_some() {
+        return syn::Error::new_spanned(&input_fn.sig.generics, "#[server] functions cannot be generic")
+            .to_compile_error()
+            .into();
+    }
+
     let mut arg_names = Vec::new();
     let mut arg_types = Vec::new();
 
     for arg in inputs {
-        if let FnArg::Typed(pat_type) = arg {
-            if let Pat::Ident(pat_ident) = &*pat_type.pat {
-                arg_names.push(&pat_ident.ident);
-                arg_types.push(&pat_type.ty);
+        match arg {
+            FnArg::Typed(pat_type) => match &*pat_type.pat {
+                Pat::Ident(pat_ident) => {
+                    arg_names.push(&pat_ident.ident);
+                    arg_types.push(&pat_type.ty);
+                }
+                other => {
+                    return syn::Error::new_spanned(other, "#[server] arguments must be plain identifiers")
+                        .to_compile_error()
+                        .into();
+                }
+            },
+            FnArg::Receiver(receiver) => {
+                return syn::Error::new_spanned(receiver, "#[server] functions cannot take `self`")
+                    .to_compile_error()
+                    .into();
             }
         }
     }
@@ -58,43 +223,752 @@ pub fn server(_attr: TokenStream, item: TokenStream) -> TokenStream {
         quote! { let (#(#arg_names),*) = args; }
     };
 
-    let output_type = match output {
-        ReturnType::Default => quote! { () },
-        ReturnType::Type(_, ty) => quote! { #ty },
-    };
-
     let internal_rpc_wrapper = syn::Ident::new(
         &format!("__lithe_rpc_wrapper_{}", fn_name),
         proc_macro2::Span::call_site(),
     );
 
-    let fn_name_str = fn_name.to_string();
+    // Named functions keep their id across refactors; others are identified by their path.
+    let rpc_id = match &server_args.name {
+        Some(name) => quote! { #name },
+        None => quote! {
+            &::lithe::rpc::function_id(concat!(module_path!(), "::", stringify!(#fn_name)))
+        },
+    };
+    let stream_item = stream_item(output);
+    let download = returns_download(output);
+    if let Some(cache) = &server_args.cache
+        && !server_args.get
+    {
+        return syn::Error::new(cache.span(), "`cache` requires method = \"GET\"")
+            .to_compile_error()
+            .into();
+    }
+    if let Some(retry) = &server_args.retry
+        && !server_args.get
+        && !server_args.idempotent
+    {
+        return syn::Error::new(
+            retry.span(),
+            "only idempotent functions can be retried; use method = \"GET\" or mark the function `idempotent`",
+        )
+        .to_compile_error()
+        .into();
+    }
+    if let Some(key) = &server_args.rate_limit_by
+        && !server_args.rate_limit
+    {
+        return syn::Error::new(key.span(), "`rate_limit_by` requires `rate_limit`")
+            .to_compile_error()
+            .into();
+    }
+    if stream_item.is_some() && (server_args.timeout.is_some() || server_args.retry.is_some()) {
+        return syn::Error::new_spanned(output, "streaming server functions do not support `timeout` or `retry`")
+            .to_compile_error()
+            .into();
+    }
+    if server_args.get && stream_item.is_some() {
+        return syn::Error::new_spanned(output, "streaming server functions cannot use method = \"GET\"")
+            .to_compile_error()
+            .into();
+    }
+    // Without an explicit encoding the call uses the one the page was rendered with.
+    let encoding = encoding_tokens(server_args.encoding);
+    let call_fn = if stream_item.is_some() {
+        quote! { stream_server_with }
+    } else if download && server_args.get {
+        quote! { download_server_get }
+    } else if download {
+        quote! { download_server_with }
+    } else if server_args.get {
+        quote! { call_server_get }
+    } else {
+        quote! { call_server_with }
+    };
+    let timeout = match server_args.timeout {
+        Some(timeout) => {
+            let millis = timeout.as_millis() as u64;
+            quote! { ::core::option::Option::Some(::core::time::Duration::from_millis(#millis)) }
+        }
+        None => quote! { ::core::option::Option::None },
+    };
+    let retries = match &server_args.retry {
+        Some(retry) => {
+            let retries: u32 = retry.base10_parse().expect("validated when parsed");
+            quote! { #retries }
+        }
+        None => quote! { 0 },
+    };
+    let call = if stream_item.is_some() {
+        quote! { ::lithe::browser::#call_fn(#encoding, #rpc_id, (#(#arg_names),*)) }
+    } else {
+        quote! {
+            ::lithe::browser::#call_fn(
+                #encoding,
+                ::lithe::rpc::CallPolicy { timeout: #timeout, retries: #retries },
+                #rpc_id,
+                (#(#arg_names),*),
+            )
+        }
+    };
+    // Streaming functions hand their stream to the server; the client reads it as a `ServerStream`.
+    let (wrapper_output, respond, client_output) = match stream_item {
+        Some(item) => (
+            quote! { ::core::result::Result<::lithe::server::RpcStream, ::lithe::rpc::RpcReply> },
+            quote! { ::core::result::Result::Ok(::lithe::server::RpcStream::new(#fn_name(#(#arg_names),*).await)) },
+            quote! { -> ::lithe::browser::ServerStream<#item> },
+        ),
+        // A `Download` replies with the file itself rather than an encoded value.
+        None if download => (
+            quote! { ::lithe::rpc::RpcReply },
+            quote! { ::lithe::server::download_reply(encodings.response, #fn_name(#(#arg_names),*).await) },
+            quote! { #output },
+        ),
+        None => (
+            quote! { ::lithe::rpc::RpcReply },
+            quote! {
+                let res = ::lithe::rpc::RpcResult::into_result(#fn_name(#(#arg_names),*).await);
+                ::lithe::rpc::RpcReply::from_result(encodings.response, res)
+            },
+            quote! { #output },
+        ),
+    };
+    let reject = |reply: proc_macro2::TokenStream| match stream_item {
+        Some(_) => quote! { ::core::result::Result::Err(#reply) },
+        None => reply,
+    };
+    let guard_rejected = reject(quote! { ::lithe::rpc::RpcReply::error(encodings.response, e) });
+    let args_rejected = reject(quote! {
+        ::lithe::rpc::RpcReply::error(
+            encodings.response,
+            ::lithe::rpc::ServerFnError::<()>::Args(e),
+        )
+    });
+    // Arguments deriving `Validate` are checked on both sides; others pass as they are. Errors
+    // name the fields of the argument, prefixed by its name when there are several.
+    let validate_args = (!arg_names.is_empty()).then(|| {
+        let prefixes = arg_names.iter().map(|name| {
+            if arg_names.len() == 1 { String::new() } else { name.to_string() }
+        });
+        quote! {
+            {
+                #[allow(unused_imports)]
+                use ::lithe::validate::{SkipArg as _, ValidateArg as _};
+                let mut errors = ::lithe::validate::ValidationErrors::new();
+                #(
+                    errors.nest(#prefixes, (&::lithe::validate::Check(&#arg_names)).validate_arg());
+                )*
+                errors.into_result()
+            }
+        }
+    });
+    let server_validate = validate_args.as_ref().map(|validate| {
+        let rejected = reject(quote! {
+            ::lithe::rpc::RpcReply::error(
+                encodings.response,
+                ::lithe::rpc::ServerFnError::<()>::Invalid(errors),
+            )
+        });
+        quote! {
+            if let ::core::result::Result::Err(errors) = #validate {
+                return #rejected;
+            }
+        }
+    });
+    // Streaming stubs leave the checks to the server, as a `ServerStream` cannot start failed.
+    let client_validate = validate_args.as_ref().filter(|_| stream_item.is_none()).map(|validate| {
+        let invalid = quote! {
+            ::core::result::Result::Err(::lithe::rpc::ServerFnError::Invalid(errors))
+        };
+        let rejected = if download {
+            invalid
+        } else {
+            quote! { ::lithe::rpc::RpcResult::from_result(#invalid) }
+        };
+        quote! {
+            if let ::core::result::Result::Err(errors) = #validate {
+                return #rejected;
+            }
+        }
+    });
+    let guards = &server_args.guards;
+    // Functions with their own guards, or explicitly public ones, skip the project's default guard.
+    let guarded = server_args.public || !guards.is_empty();
+    let guarded_const = syn::Ident::new(
+        &format!("__LITHE_RPC_GUARDED_{}", fn_name),
+        proc_macro2::Span::call_site(),
+    );
 
+    let server_cfg = server_cfg();
+    let client_cfg = client_cfg();
     let expanded = quote! {
-        #[cfg(not(target_arch = "wasm32"))]
+        #server_cfg
         #input_fn
 
-        #[cfg(not(target_arch = "wasm32"))]
+        #server_cfg
+        #[allow(dead_code, non_upper_case_globals)]
+        pub const #guarded_const: bool = #guarded;
+
+        #server_cfg
         #[allow(dead_code)]
-        pub async fn #internal_rpc_wrapper(args: #args_tuple_type) -> ::lithe::serde_json::Value {
-            fn _check_serialize<T: ::lithe::serde::Serialize>() {}
-            _check_serialize::<#output_type>();
+        pub async fn #internal_rpc_wrapper(
+            encodings: ::lithe::rpc::Encodings,
+            args: &[u8],
+        ) -> #wrapper_output {
+            #(
+                if let Err(e) = #guards().await {
+                    return #guard_rejected;
+                }
+            )*
+            // Arguments are decoded straight into their types, without a `Value` in between.
+            let args: #args_tuple_type = match encodings.request.decode(args) {
+                Ok(args) => args,
+                Err(e) => {
+                    return #args_rejected;
+                }
+            };
             #args_unpack
-            let res = #fn_name(#(#arg_names),*).await;
-            ::lithe::serde_json::to_value(res).expect("Failed to serialize RPC result")
+            #server_validate
+            #respond
         }
 
-        #[cfg(target_arch = "wasm32")]
+        #client_cfg
+        #[allow(dead_code)]
+        #visibility async fn #fn_name(#inputs) #client_output {
+            #client_validate
+            #call.await
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+/// Returns `T` when a server function returns `impl Stream<Item = T>`.
+fn stream_item(output: &ReturnType) -> Option<&Type> {
+    let ReturnType::Type(_, ty) = output else {
+        return None;
+    };
+    let Type::ImplTrait(impl_trait) = &**ty else {
+        return None;
+    };
+    impl_trait.bounds.iter().find_map(|bound| {
+        let TypeParamBound::Trait(bound) = bound else {
+            return None;
+        };
+        let segment = bound.path.segments.last()?;
+        let PathArguments::AngleBracketed(args) = &segment.arguments else {
+            return None;
+        };
+        if segment.ident != "Stream" {
+            return None;
+        }
+        args.args.iter().find_map(|arg| match arg {
+            GenericArgument::AssocType(assoc) if assoc.ident == "Item" => Some(&assoc.ty),
+            _ => None,
+        })
+    })
+}
+
+/// Whether a server function returns `Result<Download, _>` or `ServerFnResult<Download>`.
+fn returns_download(output: &ReturnType) -> bool {
+    let ReturnType::Type(_, ty) = output else {
+        return false;
+    };
+    let Type::Path(path) = &**ty else {
+        return false;
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return false;
+    };
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return false;
+    };
+    matches!(
+        args.args.first(),
+        Some(GenericArgument::Type(Type::Path(ok)))
+            if ok.path.segments.last().is_some_and(|s| s.ident == "Download")
+    )
+}
+
+/// The `#[cfg]` of code that only the server compiles. A crate built with
+/// `--cfg lithe_native_client` calls server functions over HTTP like the browser does, so it has no
+/// server code at all. The cfg is checked in the crate using the macro, so other crates in the same
+/// build, such as the server itself, are unaffected.
+fn server_cfg() -> proc_macro2::TokenStream {
+    quote! { #[cfg(not(any(target_arch = "wasm32", lithe_native_client)))] }
+}
+
+/// The `#[cfg]` of the client stubs that call a server function, the counterpart of [`server_cfg`].
+fn client_cfg() -> proc_macro2::TokenStream {
+    quote! { #[cfg(any(target_arch = "wasm32", lithe_native_client))] }
+}
+
+/// Checks that a `#[job]` or `#[scheduled]` function is async, not generic and takes plain
+/// arguments, returning their names and types.
+fn background_fn_args<'a>(
+    input_fn: &'a ItemFn,
+    kind: &str,
+) -> syn::Result<(Vec<&'a syn::Ident>, Vec<&'a Type>)> {
+    let sig = &input_fn.sig;
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(sig.fn_token, format!("{} functions must be async", kind)));
+    }
+    if !sig.generics.params.is_empty() || sig.generics.where_clause.is_some() {
+        return Err(syn::Error::new_spanned(&sig.generics, format!("{} functions cannot be generic", kind)));
+    }
+    let mut names = Vec::new();
+    let mut types = Vec::new();
+    for arg in &sig.inputs {
+        match arg {
+            FnArg::Typed(pat_type) => match &*pat_type.pat {
+                Pat::Ident(pat_ident) => {
+                    names.push(&pat_ident.ident);
+                    types.push(&*pat_type.ty);
+                }
+                other => {
+                    return Err(syn::Error::new_spanned(other, format!("{} arguments must be plain identifiers", kind)));
+                }
+            },
+            FnArg::Receiver(receiver) => {
+                return Err(syn::Error::new_spanned(receiver, format!("{} functions cannot take `self`", kind)));
+            }
+        }
+    }
+    Ok((names, types))
+}
+
+/// The name a job or scheduled task is stored under: `name = ".."`, else the function's path.
+fn background_name(name: &Option<LitStr>, fn_name: &syn::Ident) -> proc_macro2::TokenStream {
+    match name {
+        Some(name) => quote! { #name },
+        None => quote! { concat!(module_path!(), "::", stringify!(#fn_name)) },
+    }
+}
+
+fn parse_background_name(meta: &ParseNestedMeta, kind: &str) -> syn::Result<LitStr> {
+    let name: LitStr = meta.value()?.parse()?;
+    if !is_valid_function_name(&name.value()) {
+        return Err(syn::Error::new(
+            name.span(),
+            format!("{} names may only contain letters, digits, `_`, `-`, `.` and `/`", kind),
+        ));
+    }
+    Ok(name)
+}
+
+#[derive(Default)]
+struct JobArgs {
+    name: Option<LitStr>,
+    retries: Option<u32>,
+    backoff: Option<std::time::Duration>,
+}
+
+impl JobArgs {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("name") {
+            self.name = Some(parse_background_name(&meta, "job")?);
+        } else if meta.path.is_ident("retries") {
+            self.retries = Some(meta.value()?.parse::<syn::LitInt>()?.base10_parse()?);
+        } else if meta.path.is_ident("backoff") {
+            let backoff: LitStr = meta.value()?.parse()?;
+            self.backoff = Some(parse_duration(&backoff.value()).ok_or_else(|| {
+                syn::Error::new(backoff.span(), "expected a duration such as \"500ms\", \"30s\" or \"5m\"")
+            })?);
+        } else {
+            return Err(meta.error("unsupported job attribute, expected one of: name, retries, backoff"));
+        }
+        Ok(())
+    }
+}
+
+/// Declares a background job. Server code runs it by calling the generated
+/// `enqueue_{name}(args..)`, which stores the arguments as JSON and returns the job's id; the
+/// server then runs it outside of the request, retrying it on errors. Options: `retries = 3`,
+/// `backoff = "10s"` (doubled on each retry) and `name = ".."`, which keeps stored jobs
+/// runnable when the function moves.
+#[proc_macro_attribute]
+pub fn job(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut job_args = JobArgs::default();
+    let parser = syn::meta::parser(|meta| job_args.parse(meta));
+    parse_macro_input!(attr with parser);
+
+    let input_fn = parse_macro_input!(item as ItemFn);
+    let (arg_names, arg_types) = match background_fn_args(&input_fn, "#[job]") {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let fn_name = &input_fn.sig.ident;
+    let visibility = &input_fn.vis;
+    let name = background_name(&job_args.name, fn_name);
+    let retries = job_args.retries.unwrap_or(3);
+    let backoff = job_args.backoff.unwrap_or(std::time::Duration::from_secs(10)).as_millis() as u64;
+    // One argument is stored as itself, several as a tuple, like server function arguments.
+    let (args_type, args) = if arg_types.len() == 1 {
+        let (ty, name) = (arg_types[0], arg_names[0]);
+        (quote! { #ty }, quote! { #name })
+    } else {
+        (quote! { (#(#arg_types),*) }, quote! { (#(#arg_names),*) })
+    };
+    let job_const = syn::Ident::new(&format!("__LITHE_JOB_{}", fn_name), proc_macro2::Span::call_site());
+    let runner = syn::Ident::new(&format!("__lithe_job_runner_{}", fn_name), proc_macro2::Span::call_site());
+    let enqueue = syn::Ident::new(&format!("enqueue_{}", fn_name), fn_name.span());
+    let enqueue_doc = format!("Enqueues the [`{}`] job to run in the background.", fn_name);
+    let server_cfg = server_cfg();
+
+    let expanded = quote! {
+        #server_cfg
+        #input_fn
+
+        #server_cfg
+        #[allow(dead_code, non_upper_case_globals)]
+        pub const #job_const: ::lithe::jobs::Job = ::lithe::jobs::Job {
+            name: #name,
+            run: #runner,
+            retries: #retries,
+            backoff: ::core::time::Duration::from_millis(#backoff),
+        };
+
+        #server_cfg
+        #[allow(dead_code)]
+        pub fn #runner(payload: &[u8]) -> ::lithe::jobs::JobFuture {
+            let args: #args_type = match ::lithe::serde_json::from_slice(payload) {
+                ::core::result::Result::Ok(args) => args,
+                ::core::result::Result::Err(e) => {
+                    let error = ::std::format!("invalid job arguments: {}", e);
+                    return ::std::boxed::Box::pin(async move { ::core::result::Result::Err(error) });
+                }
+            };
+            let #args = args;
+            ::std::boxed::Box::pin(async move {
+                ::lithe::jobs::JobResult::into_job_result(#fn_name(#(#arg_names),*).await)
+            })
+        }
+
+        #server_cfg
+        #[doc = #enqueue_doc]
+        #[allow(dead_code)]
+        #visibility async fn #enqueue(#(#arg_names: #arg_types),*) -> ::core::result::Result<u64, ::std::string::String> {
+            ::lithe::jobs::enqueue(#name, &#args).await
+        }
+    };
+    TokenStream::from(expanded)
+}
+
+/// Declares a task the server runs on a cron schedule, in UTC: `#[scheduled("0 3 * * *")]`,
+/// optionally followed by `name = ".."`. The function takes no arguments.
+#[proc_macro_attribute]
+pub fn scheduled(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut name = None;
+    let parser = |input: syn::parse::ParseStream| -> syn::Result<LitStr> {
+        let cron: LitStr = input.parse().map_err(|e| {
+            syn::Error::new(e.span(), "expected a cron expression such as #[scheduled(\"0 3 * * *\")]")
+        })?;
+        if input.parse::<Option<syn::Token![,]>>()?.is_some() && !input.is_empty() {
+            let options = syn::meta::parser(|meta| {
+                if meta.path.is_ident("name") {
+                    name = Some(parse_background_name(&meta, "scheduled task")?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported scheduled attribute, expected: name"))
+                }
+            });
+            syn::parse::Parser::parse2(options, input.parse()?)?;
+        }
+        Ok(cron)
+    };
+    let cron = parse_macro_input!(attr with parser);
+    if let Err(e) = lithe_core::cron::Schedule::parse(&cron.value()) {
+        return syn::Error::new(cron.span(), e).to_compile_error().into();
+    }
+
+    let input_fn = parse_macro_input!(item as ItemFn);
+    match background_fn_args(&input_fn, "#[scheduled]") {
+        Ok((names, _)) if !names.is_empty() => {
+            return syn::Error::new_spanned(&input_fn.sig.inputs, "#[scheduled] functions cannot take arguments")
+                .to_compile_error()
+                .into();
+        }
+        Ok(_) => {}
+        Err(e) => return e.to_compile_error().into(),
+    }
+    let fn_name = &input_fn.sig.ident;
+    let name = background_name(&name, fn_name);
+    let task_const = syn::Ident::new(&format!("__LITHE_SCHEDULED_{}", fn_name), proc_macro2::Span::call_site());
+    let runner = syn::Ident::new(&format!("__lithe_scheduled_runner_{}", fn_name), proc_macro2::Span::call_site());
+    let server_cfg = server_cfg();
+
+    let expanded = quote! {
+        #server_cfg
+        #input_fn
+
+        #server_cfg
+        #[allow(dead_code, non_upper_case_globals)]
+        pub const #task_const: ::lithe::jobs::Scheduled = ::lithe::jobs::Scheduled {
+            name: #name,
+            cron: #cron,
+            run: #runner,
+        };
+
+        #server_cfg
         #[allow(dead_code)]
-        #visibility async fn #fn_name(#inputs) #output {
-            let full_path = concat!(module_path!(), "::", stringify!(#fn_name));
-            ::lithe::browser::call_server(full_path, (#(#arg_names),*)).await
+        pub fn #runner() -> ::lithe::jobs::JobFuture {
+            ::std::boxed::Box::pin(async { ::lithe::jobs::JobResult::into_job_result(#fn_name().await) })
+        }
+    };
+    TokenStream::from(expanded)
+}
+
+#[derive(Default)]
+struct SocketArgs {
+    guards: Vec<syn::Path>,
+    public: bool,
+    name: Option<LitStr>,
+    encoding: Option<Encoding>,
+}
+
+impl SocketArgs {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("guard") {
+            parse_guards(&meta, &mut self.guards)?;
+        } else if meta.path.is_ident("public") {
+            self.public = true;
+        } else if meta.path.is_ident("name") {
+            self.name = Some(parse_background_name(&meta, "socket")?);
+        } else if meta.path.is_ident("encoding") {
+            self.encoding = Some(parse_encoding(&meta)?);
+        } else {
+            return Err(meta.error("unsupported socket attribute, expected one of: guard, public, name, encoding"));
+        }
+        Ok(())
+    }
+}
+
+/// Returns `(In, Out)` of a `#[socket]` function's `Socket<In, Out>` argument.
+fn socket_message_types(input_fn: &ItemFn) -> syn::Result<(&Type, &Type)> {
+    let expected = || {
+        syn::Error::new_spanned(
+            &input_fn.sig.inputs,
+            "#[socket] functions take a single `lithe::socket::Socket<In, Out>` argument",
+        )
+    };
+    let (_, types) = background_fn_args(input_fn, "#[socket]")?;
+    let [Type::Path(ty)] = types[..] else {
+        return Err(expected());
+    };
+    let segment = ty.path.segments.last().ok_or_else(expected)?;
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return Err(expected());
+    };
+    match args.args.iter().collect::<Vec<_>>()[..] {
+        [GenericArgument::Type(incoming), GenericArgument::Type(outgoing)] if segment.ident == "Socket" => {
+            Ok((incoming, outgoing))
         }
+        _ => Err(expected()),
+    }
+}
+
+/// Declares a WebSocket channel. The server runs the function for each connection, passing a
+/// `lithe::socket::Socket<In, Out>` to receive `In` from the client and send `Out` to it. In the
+/// browser the function takes no arguments and returns a `lithe::browser::Channel<In, Out>`,
+/// which connects and reconnects on its own. Messages use the page's encoding unless
+/// `encoding = ".."` is given. Options `guard`, `public` and `name` work as on `#[server]`;
+/// guards run before the connection is accepted.
+#[proc_macro_attribute]
+pub fn socket(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut socket_args = SocketArgs::default();
+    let parser = syn::meta::parser(|meta| socket_args.parse(meta));
+    parse_macro_input!(attr with parser);
+
+    let input_fn = parse_macro_input!(item as ItemFn);
+    let (incoming, outgoing) = match socket_message_types(&input_fn) {
+        Ok(types) => types,
+        Err(e) => return e.to_compile_error().into(),
     };
+    let fn_name = &input_fn.sig.ident;
+    let visibility = &input_fn.vis;
+    let socket_id = match &socket_args.name {
+        Some(name) => quote! { #name },
+        None => quote! {
+            &::lithe::rpc::function_id(concat!(module_path!(), "::", stringify!(#fn_name)))
+        },
+    };
+    let encoding = encoding_tokens(socket_args.encoding);
+    let guards = &socket_args.guards;
+    let guarded = socket_args.public || !guards.is_empty();
+    let guarded_const = syn::Ident::new(&format!("__LITHE_SOCKET_GUARDED_{}", fn_name), proc_macro2::Span::call_site());
+    let guard_fn = syn::Ident::new(&format!("__lithe_socket_guard_{}", fn_name), proc_macro2::Span::call_site());
+    let wrapper = syn::Ident::new(&format!("__lithe_socket_wrapper_{}", fn_name), proc_macro2::Span::call_site());
+
+    let server_cfg = server_cfg();
+    let expanded = quote! {
+        #server_cfg
+        #input_fn
+
+        #server_cfg
+        #[allow(dead_code, non_upper_case_globals)]
+        pub const #guarded_const: bool = #guarded;
+
+        #server_cfg
+        #[allow(dead_code)]
+        pub async fn #guard_fn(
+            encoding: ::lithe::rpc::Encoding,
+        ) -> ::core::result::Result<(), ::lithe::rpc::RpcReply> {
+            #(
+                if let Err(e) = #guards().await {
+                    return ::core::result::Result::Err(::lithe::rpc::RpcReply::error(encoding, e));
+                }
+            )*
+            ::core::result::Result::Ok(())
+        }
 
+        #server_cfg
+        #[allow(dead_code)]
+        pub async fn #wrapper(
+            encoding: ::lithe::rpc::Encoding,
+            io: ::lithe::socket::SocketIo,
+        ) -> ::core::result::Result<(), ::std::string::String> {
+            ::lithe::jobs::JobResult::into_job_result(#fn_name(::lithe::socket::Socket::new(encoding, io)).await)
+        }
+
+        // Channels only connect from the browser, so native clients get no stub.
+        #[cfg(target_arch = "wasm32")]
+        #[allow(dead_code)]
+        #visibility fn #fn_name() -> ::lithe::browser::Channel<#incoming, #outgoing> {
+            ::lithe::browser::Channel::connect(#socket_id, #encoding)
+        }
+    };
     TokenStream::from(expanded)
 }
 
+/// Implements `lithe::validate::Validate` from `#[validate(...)]` rules on named fields:
+/// `length(min = .., max = ..)`, `range(min = .., max = ..)`, `email`, `regex = ".."`,
+/// `custom = path` and `nested`. Rules on `Option` fields apply to the value when present.
+#[proc_macro_derive(Validate, attributes(validate))]
+pub fn derive_validate(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as syn::DeriveInput);
+    match validate_impl(&input) {
+        Ok(expanded) => TokenStream::from(expanded),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn validate_impl(input: &syn::DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let syn::Data::Struct(syn::DataStruct { fields: syn::Fields::Named(fields), .. }) = &input.data else {
+        return Err(syn::Error::new_spanned(&input.ident, "Validate can only be derived for structs with named fields"));
+    };
+
+    let mut checks = Vec::new();
+    for field in &fields.named {
+        let ident = field.ident.as_ref().expect("named field");
+        let name = ident.to_string();
+        let mut rules = Vec::new();
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("validate")) {
+            attr.parse_nested_meta(|meta| {
+                rules.push(validation_rule(&name, meta)?);
+                Ok(())
+            })?;
+        }
+        if rules.is_empty() {
+            continue;
+        }
+        checks.push(if is_option(&field.ty) {
+            quote! {
+                if let ::core::option::Option::Some(value) = &self.#ident {
+                    #(#rules)*
+                }
+            }
+        } else {
+            quote! {
+                {
+                    let value = &self.#ident;
+                    #(#rules)*
+                }
+            }
+        });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::lithe::validate::Validate for #ident #ty_generics #where_clause {
+            fn validate(&self) -> ::core::result::Result<(), ::lithe::validate::ValidationErrors> {
+                let mut errors = ::lithe::validate::ValidationErrors::new();
+                #(#checks)*
+                errors.into_result()
+            }
+        }
+    })
+}
+
+/// The check for one `#[validate(...)]` rule, run with the field's value bound to `value`.
+fn validation_rule(field: &str, meta: ParseNestedMeta) -> syn::Result<proc_macro2::TokenStream> {
+    let report = |code: &str, check: proc_macro2::TokenStream| {
+        quote! {
+            if let ::core::result::Result::Err(message) = #check {
+                errors.add(#field, #code, message);
+            }
+        }
+    };
+    if meta.path.is_ident("length") || meta.path.is_ident("range") {
+        let (mut min, mut max) = (None, None);
+        meta.parse_nested_meta(|bound| {
+            let value: Expr = bound.value()?.parse()?;
+            if bound.path.is_ident("min") {
+                min = Some(value);
+            } else if bound.path.is_ident("max") {
+                max = Some(value);
+            } else {
+                return Err(bound.error("expected `min` or `max`"));
+            }
+            Ok(())
+        })?;
+        if min.is_none() && max.is_none() {
+            return Err(meta.error("expected `min`, `max` or both"));
+        }
+        let bound = |b: Option<Expr>| match b {
+            Some(b) => quote! { ::core::option::Option::Some(#b) },
+            None => quote! { ::core::option::Option::None },
+        };
+        let (min, max) = (bound(min), bound(max));
+        Ok(if meta.path.is_ident("length") {
+            report("length", quote! { ::lithe::validate::length(value, #min, #max) })
+        } else {
+            report("range", quote! { ::lithe::validate::range(value, #min, #max) })
+        })
+    } else if meta.path.is_ident("email") {
+        Ok(report("email", quote! { ::lithe::validate::email(value) }))
+    } else if meta.path.is_ident("regex") {
+        let pattern: LitStr = meta.value()?.parse()?;
+        if let Err(e) = lithe_core::validate::Regex::new(&pattern.value()) {
+            return Err(syn::Error::new(pattern.span(), format!("invalid pattern: {}", e)));
+        }
+        Ok(report(
+            "regex",
+            quote! {
+                {
+                    static REGEX: ::std::sync::OnceLock<::lithe::validate::Regex> = ::std::sync::OnceLock::new();
+                    ::lithe::validate::regex(value, &REGEX, #pattern)
+                }
+            },
+        ))
+    } else if meta.path.is_ident("custom") {
+        // `fn(&T) -> Result<(), String>`, given the field's value.
+        let check: syn::Path = meta.value()?.parse()?;
+        Ok(report("custom", quote! { #check(value) }))
+    } else if meta.path.is_ident("nested") {
+        Ok(quote! {
+            errors.nest(#field, ::lithe::validate::Validate::validate(value));
+        })
+    } else {
+        Err(meta.error("unsupported validate rule, expected one of: length, range, email, regex, custom, nested"))
+    }
+}
+
+/// Whether `ty` is written as `Option<T>`.
+fn is_option(ty: &Type) -> bool {
+    matches!(ty, Type::Path(path) if path.path.segments.last().is_some_and(|s| s.ident == "Option"))
+}
+
 struct OnClickVisitor {
     anon_handlers: Vec<ItemFn>,
     base_name: String,
@@ -111,8 +985,7 @@ impl VisitMut for OnClickVisitor {
             match arg {
                 Expr::Path(ExprPath { path, .. }) => {
                     let path_str = quote!(#path).to_string().replace(" ", "");
-                    let symbolic_hash = stable_hash(&path_str);
-                    let dispatch_str = format!("Lithe.dispatch('h_{}')", symbolic_hash);
+                    let dispatch_str = format!("Lithe.dispatch('{}')", handler_id(&path_str));
                     let new_arg: Expr = parse_quote!(#dispatch_str);
                     node.args[0] = new_arg;
                 }
@@ -154,8 +1027,88 @@ impl VisitMut for OnClickVisitor {
     }
 }
 
+#[derive(Default)]
+struct PageArgs {
+    title: Option<LitStr>,
+    description: Option<LitStr>,
+    cache: Option<LitStr>,
+    layout: Option<LitBool>,
+    mode: Option<LitStr>,
+    draft: Option<LitBool>,
+}
+
+impl PageArgs {
+    fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("title") {
+            self.title = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("description") {
+            self.description = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("cache") {
+            let cache: LitStr = meta.value()?.parse()?;
+            // The value is sent verbatim as the `Cache-Control` header.
+            if cache.value().is_empty() || !cache.value().bytes().all(|b| (0x20..0x7f).contains(&b)) {
+                return Err(syn::Error::new(
+                    cache.span(),
+                    "expected a Cache-Control value such as \"public, max-age=60\"",
+                ));
+            }
+            self.cache = Some(cache);
+        } else if meta.path.is_ident("layout") {
+            self.layout = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("mode") {
+            let mode: LitStr = meta.value()?.parse()?;
+            if !matches!(mode.value().as_str(), "ssr" | "spa") {
+                return Err(syn::Error::new(
+                    mode.span(),
+                    "expected mode = \"ssr\" or mode = \"spa\"",
+                ));
+            }
+            self.mode = Some(mode);
+        } else if meta.path.is_ident("draft") {
+            self.draft = Some(meta.value()?.parse()?);
+        } else {
+            return Err(meta.error(
+                "unsupported page attribute, expected one of: title, description, cache, layout, mode, draft",
+            ));
+        }
+        Ok(())
+    }
+
+    fn to_meta_tokens(&self) -> proc_macro2::TokenStream {
+        let opt = |v: &Option<LitStr>| match v {
+            Some(lit) => quote! { ::core::option::Option::Some(#lit) },
+            None => quote! { ::core::option::Option::None },
+        };
+        let title = opt(&self.title);
+        let description = opt(&self.description);
+        let cache = opt(&self.cache);
+        let layout = self.layout.as_ref().is_none_or(|b| b.value);
+        let draft = self.draft.as_ref().is_some_and(|b| b.value);
+        let mode = match self.mode.as_ref().map(|m| m.value()).as_deref() {
+            Some("spa") => quote! { ::lithe::RenderMode::Spa },
+            _ => quote! { ::lithe::RenderMode::Ssr },
+        };
+        quote! {
+            #[allow(dead_code)]
+            pub const PAGE_META: ::lithe::PageMeta = ::lithe::PageMeta {
+                title: #title,
+                description: #description,
+                cache: #cache,
+                layout: #layout,
+                mode: #mode,
+                draft: #draft,
+            };
+        }
+    }
+}
+
 #[proc_macro_attribute]
-pub fn page(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn page(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let mut args = PageArgs::default();
+    let parser = syn::meta::parser(|meta| args.parse(meta));
+    parse_macro_input!(attr with parser);
+    let page_meta = args.to_meta_tokens();
+
     let mut input_fn = parse_macro_input!(item as ItemFn);
     let base_name = input_fn.sig.ident.to_string();
 
@@ -170,6 +1123,7 @@ pub fn page(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let anon_fns = visitor.anon_handlers;
 
     let expanded = quote! {
+        #page_meta
         #input_fn
         #(#anon_fns)*
     };
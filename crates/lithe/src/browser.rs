@@ -1,14 +1,49 @@
-//! Browser API wrappers that work on WASM and are no-ops on the server.
+//! Browser API wrappers that work on WASM and are no-ops on the server. Server function calls
+//! also work natively with the `native-client` feature, see [`crate::client`].
 
-use serde::{de::DeserializeOwned, Serialize};
+use crate::files::Download;
+use crate::rpc::{CallPolicy, Encoding, RpcResult, ServerFnError};
+use futures_util::Stream;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+use {crate::files::UploadedFile, futures_util::StreamExt};
 
-/// Deterministic stable hashing for IDs
-pub fn hash_id(path: &str) -> String {
-    let mut hash: u64 = 5381;
-    for c in path.bytes() {
-        hash = ((hash << 5).wrapping_add(hash)).wrapping_add(c as u64);
+mod policy;
+mod socket;
+#[cfg(target_arch = "wasm32")]
+mod transport;
+
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+pub(crate) use policy::ProgressFn;
+pub use policy::{AbortHandle, CallOptions, with_call_options};
+pub use socket::{Channel, ChannelSender};
+
+/// Reads a string setting from the `window.Lithe` object the page was rendered with.
+#[cfg(target_arch = "wasm32")]
+fn page_setting(key: &str) -> Option<String> {
+    let lithe = web_sys::window()
+        .and_then(|w| js_sys::Reflect::get(&w, &"Lithe".into()).ok())
+        .filter(|v| v.is_object())?;
+    js_sys::Reflect::get(&lithe, &key.into())
+        .ok()
+        .and_then(|v| v.as_string())
+}
+
+/// Returns the RPC endpoint advertised by the page (`window.Lithe.rpc_endpoint`), which
+/// includes the app's base path.
+pub fn rpc_endpoint() -> String {
+    #[cfg(target_arch = "wasm32")]
+    {
+        if let Some(endpoint) = page_setting("rpc_endpoint") {
+            return endpoint;
+        }
     }
-    format!("f_{:x}", hash)
+    crate::RPC_ENDPOINT.to_string()
 }
 
 /// Shows an alert dialog with the given message.
@@ -39,77 +74,545 @@ pub fn console_log(message: &str) {
     }
 }
 
-/// Calls a server function from the client.
-pub async fn call_server<Args, Ret>(full_path: &str, args: Args) -> Ret
+/// Returns the encoding the page was rendered with (`window.Lithe.encoding`), used by server
+/// functions that do not pick their own.
+pub fn default_encoding() -> Encoding {
+    #[cfg(target_arch = "wasm32")]
+    {
+        if let Some(encoding) = page_setting("encoding").and_then(|e| Encoding::from_name(&e)) {
+            return encoding;
+        }
+    }
+    Encoding::default()
+}
+
+/// Calls a server function from the client using the page's [`default_encoding`].
+///
+/// Calls issued in the same tick, e.g. futures joined together, are sent as one batch request.
+/// Identical calls already in flight share a single request and its reply.
+///
+/// Failures surface as `ServerFnError` variants: `Transport` when the request did not
+/// complete, `Decode` when the response could not be read, `Timeout` and `Cancelled` when the
+/// caller gave up, and the server's own error otherwise.
+pub async fn call_server<Args, R>(id: &str, args: Args) -> R
 where
     Args: Serialize,
-    Ret: DeserializeOwned + Default,
+    R: RpcResult,
 {
-    #[cfg(target_arch = "wasm32")]
+    call_server_with(default_encoding(), CallPolicy::default(), id, args).await
+}
+
+/// Like [`call_server`], but sends the call in `encoding`, asks for the reply in it and waits
+/// as `policy` says. [`with_call_options`] overrides the policy for single calls.
+pub async fn call_server_with<Args, R>(
+    encoding: Encoding,
+    policy: CallPolicy,
+    id: &str,
+    args: Args,
+) -> R
+where
+    Args: Serialize,
+    R: RpcResult,
+{
+    #[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+    {
+        let progress = policy::upload_progress();
+        let result = match crate::files::collect(|| encoding.encode(&args)) {
+            (Ok(args), files) if files.is_empty() => {
+                policy::run(id, policy, || fetch_rpc(encoding, id, &args)).await
+            }
+            // Calls with files are sent on their own, as multipart.
+            (Ok(args), files) => {
+                let call = || fetch_rpc_alone(encoding, id, &args, &files, progress.clone());
+                policy::run(id, policy, call).await
+            }
+            (Err(e), _) => Err(ServerFnError::Args(e)),
+        };
+        R::from_result(result)
+    }
+    #[cfg(not(any(target_arch = "wasm32", feature = "native-client")))]
     {
-        use crate::rpc::{RpcRequest, RpcResponse};
-        use wasm_bindgen::JsCast;
-        use web_sys::{Request, RequestInit, RequestMode, Response};
+        let _ = (encoding, policy, id, args);
+        panic!("call_server needs WASM or the `native-client` feature")
+    }
+}
 
-        let hashed_id = hash_id(full_path);
-        web_sys::console::log_1(&format!("RPC Call: {} -> {}", full_path, hashed_id).into());
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+async fn fetch_rpc<T, E>(encoding: Encoding, id: &str, args: &[u8]) -> Result<T, ServerFnError<E>>
+where
+    T: DeserializeOwned,
+    E: DeserializeOwned,
+{
+    #[cfg(target_arch = "wasm32")]
+    let reply = {
+        // Dropping the ticket, e.g. on timeout, gives the call up.
+        let ticket = transport::send(encoding, id, args.to_vec());
+        ticket.reply().await
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let reply = crate::client::post(encoding, id, args).await;
+    decode_reply(id, reply.map_err(ServerFnError::Transport)?)
+}
 
-        let args_value = serde_json::to_value(args).unwrap();
-        let rpc_req = RpcRequest {
-            function: hashed_id,
-            args: args_value,
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+async fn fetch_rpc_alone<T, E>(
+    encoding: Encoding,
+    id: &str,
+    args: &[u8],
+    files: &[(String, UploadedFile)],
+    progress: Option<ProgressFn>,
+) -> Result<T, ServerFnError<E>>
+where
+    T: DeserializeOwned,
+    E: DeserializeOwned,
+{
+    let reply = fetch_alone(encoding, id, args, files, progress).await;
+    decode_reply(id, reply.map_err(ServerFnError::Transport)?)
+}
+
+/// Sends a call on its own rather than in a batch, as `multipart/form-data` when it carries
+/// files.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+async fn fetch_alone(
+    encoding: Encoding,
+    id: &str,
+    args: &[u8],
+    files: &[(String, UploadedFile)],
+    progress: Option<ProgressFn>,
+) -> Result<RawReply, String> {
+    #[cfg(target_arch = "wasm32")]
+    if files.is_empty() {
+        transport::send_alone(encoding, id, args).await
+    } else {
+        transport::upload(encoding, id, args, files, progress).await
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if files.is_empty() {
+        crate::client::post(encoding, id, args).await
+    } else {
+        crate::client::upload(encoding, id, args, files, progress).await
+    }
+}
+
+/// Calls a `#[server(method = "GET")]` function. Its arguments travel as JSON in the query
+/// string, so the reply can be cached by the browser and by proxies; `encoding` only applies
+/// to the reply. GET calls are never batched.
+pub async fn call_server_get<Args, R>(
+    encoding: Encoding,
+    policy: CallPolicy,
+    id: &str,
+    args: Args,
+) -> R
+where
+    Args: Serialize,
+    R: RpcResult,
+{
+    #[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+    {
+        let result = match get_args(&args) {
+            Ok(args) => policy::run(id, policy, || fetch_rpc_get(encoding, id, &args)).await,
+            Err(e) => Err(e),
         };
-        let body = serde_json::to_string(&rpc_req).unwrap();
-
-        let opts = RequestInit::new();
-        opts.set_method("POST");
-        opts.set_mode(RequestMode::Cors);
-        opts.set_body(&js_sys::JsString::from(body));
-
-        let request = Request::new_with_str_and_init("/api/lithe-rpc", &opts).unwrap();
-        request
-            .headers()
-            .set("Content-Type", "application/json")
-            .unwrap();
-
-        let window = web_sys::window().unwrap();
-        let resp_value = match wasm_bindgen_futures::JsFuture::from(window.fetch_with_request(&request)).await {
-            Ok(v) => v,
-            Err(e) => {
-                web_sys::console::error_1(&format!("Fetch error: {:?}", e).into());
-                return Ret::default();
+        R::from_result(result)
+    }
+    #[cfg(not(any(target_arch = "wasm32", feature = "native-client")))]
+    {
+        let _ = (encoding, policy, id, args);
+        panic!("call_server_get needs WASM or the `native-client` feature")
+    }
+}
+
+/// The JSON arguments of a GET call, which has no room for files.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+fn get_args<Args: Serialize, E>(args: &Args) -> Result<String, ServerFnError<E>> {
+    match crate::files::collect(|| serde_json::to_string(args)) {
+        (Ok(_), files) if !files.is_empty() => Err(ServerFnError::Args(
+            "files cannot be sent with method = \"GET\"".to_string(),
+        )),
+        (Ok(args), _) => Ok(args),
+        (Err(e), _) => Err(ServerFnError::Args(e.to_string())),
+    }
+}
+
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+async fn fetch_rpc_get<T, E>(
+    encoding: Encoding,
+    id: &str,
+    args: &str,
+) -> Result<T, ServerFnError<E>>
+where
+    T: DeserializeOwned,
+    E: DeserializeOwned,
+{
+    let reply = fetch_get(encoding, id, args).await;
+    decode_reply(id, reply.map_err(ServerFnError::Transport)?)
+}
+
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+async fn fetch_get(encoding: Encoding, id: &str, args: &str) -> Result<RawReply, String> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        use crate::rpc::{ARGS_PARAM, FUNCTION_PARAM};
+
+        let url = format!(
+            "{}?{}={}&{}={}",
+            rpc_endpoint(),
+            FUNCTION_PARAM,
+            js_sys::encode_uri_component(id),
+            ARGS_PARAM,
+            js_sys::encode_uri_component(args)
+        );
+        transport::get(&url, encoding).await
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        crate::client::get(encoding, id, args).await
+    }
+}
+
+/// Calls a server function returning a [`Download`]. Its reply is the file itself, so the call
+/// is never batched.
+pub async fn download_server_with<Args, E>(
+    encoding: Encoding,
+    policy: CallPolicy,
+    id: &str,
+    args: Args,
+) -> Result<Download, ServerFnError<E>>
+where
+    Args: Serialize,
+    E: DeserializeOwned,
+{
+    #[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+    {
+        let progress = policy::upload_progress();
+        match crate::files::collect(|| encoding.encode(&args)) {
+            (Ok(args), files) => {
+                let call = || fetch_download(encoding, id, &args, &files, progress.clone());
+                policy::run(id, policy, call).await
             }
-        };
-        
-        let resp: Response = resp_value.dyn_into().unwrap();
-        if !resp.ok() {
-             web_sys::console::error_1(&format!("RPC failed with status: {}. Path: {}", resp.status(), full_path).into());
-             return Ret::default();
+            (Err(e), _) => Err(ServerFnError::Args(e)),
         }
+    }
+    #[cfg(not(any(target_arch = "wasm32", feature = "native-client")))]
+    {
+        let _ = (encoding, policy, id, args);
+        panic!("download_server_with needs WASM or the `native-client` feature")
+    }
+}
+
+/// Like [`download_server_with`], for `#[server(method = "GET")]` functions.
+pub async fn download_server_get<Args, E>(
+    encoding: Encoding,
+    policy: CallPolicy,
+    id: &str,
+    args: Args,
+) -> Result<Download, ServerFnError<E>>
+where
+    Args: Serialize,
+    E: DeserializeOwned,
+{
+    #[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+    {
+        let args = get_args(&args)?;
+        policy::run(id, policy, || async {
+            let reply = fetch_get(encoding, id, &args).await;
+            decode_download(id, reply.map_err(ServerFnError::Transport)?)
+        })
+        .await
+    }
+    #[cfg(not(any(target_arch = "wasm32", feature = "native-client")))]
+    {
+        let _ = (encoding, policy, id, args);
+        panic!("download_server_get needs WASM or the `native-client` feature")
+    }
+}
+
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+async fn fetch_download<E: DeserializeOwned>(
+    encoding: Encoding,
+    id: &str,
+    args: &[u8],
+    files: &[(String, UploadedFile)],
+    progress: Option<ProgressFn>,
+) -> Result<Download, ServerFnError<E>> {
+    let reply = fetch_alone(encoding, id, args, files, progress).await;
+    decode_download(id, reply.map_err(ServerFnError::Transport)?)
+}
+
+/// Turns a successful reply into the file it carries. Failed calls reply with an envelope.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+fn decode_download<E: DeserializeOwned>(
+    id: &str,
+    reply: RawReply,
+) -> Result<Download, ServerFnError<E>> {
+    if !reply.ok() {
+        decode_reply::<serde::de::IgnoredAny, E>(id, reply)?;
+        return Err(ServerFnError::Decode(
+            "failed reply without an error".to_string(),
+        ));
+    }
+    let content_type = reply
+        .content_type
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let download = Download::new(content_type, reply.body);
+    match reply
+        .disposition
+        .as_deref()
+        .and_then(crate::rpc::disposition_file_name)
+    {
+        Some(file_name) => Ok(download.attachment(file_name)),
+        None => Ok(download),
+    }
+}
+
+/// A reply as received, before it is decoded into the caller's types.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+#[derive(Debug, Clone)]
+pub(crate) struct RawReply {
+    pub status: u16,
+    /// Encoding named by the reply's `Content-Type`, if Lithe supports it.
+    pub encoding: Option<Encoding>,
+    pub body: Vec<u8>,
+    /// `Content-Type` and `Content-Disposition` as sent, which describe a [`Download`].
+    pub content_type: Option<String>,
+    pub disposition: Option<String>,
+    /// `x-request-id` of the reply, which names the call in the server's logs.
+    pub request_id: Option<String>,
+}
+
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+impl RawReply {
+    pub fn ok(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Body of a streaming reply, chunk by chunk; errors are transport failures.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+pub(crate) type BodyChunks = futures_util::stream::LocalBoxStream<'static, Result<Vec<u8>, String>>;
+
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+fn decode_reply<T, E>(id: &str, reply: RawReply) -> Result<T, ServerFnError<E>>
+where
+    T: DeserializeOwned,
+    E: DeserializeOwned,
+{
+    use crate::rpc::RpcResponse;
+
+    // The server may pick another encoding, e.g. for errors raised before negotiation.
+    match reply
+        .encoding
+        .map(|e| e.decode::<RpcResponse<T, E>>(&reply.body))
+    {
+        // Server failures are looked up in the server's logs by the call's request id.
+        Some(Ok(envelope)) => envelope.into_result().map_err(|e| match e {
+            ServerFnError::Server(msg) => {
+                ServerFnError::Server(with_request_id(msg, reply.request_id.as_deref()))
+            }
+            e => e,
+        }),
+        // Limits enforced in front of the function reply without an envelope.
+        _ if reply.status == 429 => {
+            Err(ServerFnError::RateLimited(format!("HTTP 429 from {}", id)))
+        }
+        _ if reply.status == 413 => Err(ServerFnError::Args(format!(
+            "the call to {} is larger than the server accepts",
+            id
+        ))),
+        // Anything but an envelope on an error status came from something other than Lithe.
+        _ if !reply.ok() => Err(ServerFnError::Transport(with_request_id(
+            format!("HTTP {} from {}", reply.status, id),
+            reply.request_id.as_deref(),
+        ))),
+        Some(Err(e)) => Err(ServerFnError::Decode(e)),
+        None => Err(ServerFnError::Decode(
+            "response has no supported content type".to_string(),
+        )),
+    }
+}
+
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+fn with_request_id(message: String, request_id: Option<&str>) -> String {
+    match request_id {
+        Some(request_id) => format!("{} (request id {})", message, request_id),
+        None => message,
+    }
+}
+
+/// Items sent by a streaming `#[server]` function, returned by its client stub.
+///
+/// The stream ends when the function's stream does or when the call fails; [`error`] then
+/// tells the two apart. Dropping it aborts the request, which stops the function on the server.
+///
+/// [`error`]: ServerStream::error
+pub struct ServerStream<T> {
+    items: Pin<Box<dyn Stream<Item = T>>>,
+    error: Rc<RefCell<Option<ServerFnError>>>,
+    #[cfg(target_arch = "wasm32")]
+    abort: Option<web_sys::AbortController>,
+}
+
+impl<T> ServerStream<T> {
+    /// The error that ended the stream early, if any.
+    pub fn error(&self) -> Option<ServerFnError> {
+        self.error.borrow().clone()
+    }
+}
+
+impl<T> Stream for ServerStream<T> {
+    type Item = T;
 
-        let text_value = wasm_bindgen_futures::JsFuture::from(resp.text().unwrap()).await.unwrap();
-        let text = text_value.as_string().unwrap();
-        
-        let rpc_res: RpcResponse = match serde_json::from_str(&text) {
-            Ok(r) => r,
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.items.as_mut().poll_next(cx)
+    }
+}
+
+impl<T> Drop for ServerStream<T> {
+    fn drop(&mut self) {
+        #[cfg(target_arch = "wasm32")]
+        if let Some(abort) = &self.abort {
+            abort.abort();
+        }
+    }
+}
+
+/// Calls a streaming server function using the page's [`default_encoding`] for its arguments.
+/// Items always arrive as newline-delimited JSON.
+pub async fn stream_server<Args, T>(id: &str, args: Args) -> ServerStream<T>
+where
+    Args: Serialize,
+    T: DeserializeOwned + 'static,
+{
+    stream_server_with(default_encoding(), id, args).await
+}
+
+/// Like [`stream_server`], but sends the arguments in `encoding`.
+pub async fn stream_server_with<Args, T>(
+    encoding: Encoding,
+    id: &str,
+    args: Args,
+) -> ServerStream<T>
+where
+    Args: Serialize,
+    T: DeserializeOwned + 'static,
+{
+    let error: Rc<RefCell<Option<ServerFnError>>> = Rc::default();
+    #[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+    {
+        #[cfg(target_arch = "wasm32")]
+        let abort = web_sys::AbortController::new().ok();
+        #[cfg(target_arch = "wasm32")]
+        let opened = open_stream(encoding, id, args, abort.as_ref().map(|a| a.signal())).await;
+        // Native requests are aborted by dropping their body.
+        #[cfg(not(target_arch = "wasm32"))]
+        let opened = open_stream(encoding, id, args).await;
+        let items = match opened {
+            Ok(chunks) => read_stream(chunks, error.clone()).boxed_local(),
             Err(e) => {
-                web_sys::console::error_1(&format!("Failed to parse RPC response: {:?}. Text: {}", e, text).into());
-                return Ret::default();
+                *error.borrow_mut() = Some(e);
+                futures_util::stream::empty().boxed_local()
             }
         };
-
-        serde_json::from_value(rpc_res.result).unwrap_or_else(|e| {
-            web_sys::console::error_1(&format!("Failed to deserialize RPC result: {:?}", e).into());
-            Ret::default()
-        })
+        ServerStream {
+            items,
+            error,
+            #[cfg(target_arch = "wasm32")]
+            abort,
+        }
     }
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(not(any(target_arch = "wasm32", feature = "native-client")))]
     {
-        let _ = (full_path, args);
-        panic!("call_server should only be called from WASM")
+        let _ = (encoding, id, args, error);
+        panic!("stream_server needs WASM or the `native-client` feature")
     }
 }
 
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+async fn open_stream<Args: Serialize>(
+    encoding: Encoding,
+    id: &str,
+    args: Args,
+    #[cfg(target_arch = "wasm32")] signal: Option<web_sys::AbortSignal>,
+) -> Result<BodyChunks, ServerFnError> {
+    use crate::rpc::RpcResponse;
+
+    let body = match crate::files::collect(|| encoding.encode(&args)) {
+        (Ok(_), files) if !files.is_empty() => {
+            let e = "streaming server functions cannot take files";
+            return Err(ServerFnError::Args(e.to_string()));
+        }
+        (body, _) => body.map_err(ServerFnError::Args)?,
+    };
+    #[cfg(target_arch = "wasm32")]
+    let opened = transport::open_stream(encoding, id, &body, signal.as_ref()).await;
+    #[cfg(not(target_arch = "wasm32"))]
+    let opened = crate::client::open_stream(encoding, id, &body).await;
+
+    match opened.map_err(ServerFnError::Transport)? {
+        Ok(chunks) => Ok(chunks),
+        // Calls rejected before streaming starts answer with a regular envelope.
+        Err(reply) => Err(
+            match reply
+                .encoding
+                .and_then(|e| e.decode::<RpcResponse<(), String>>(&reply.body).ok())
+            {
+                Some(RpcResponse::Error(err)) => err,
+                _ => ServerFnError::Transport(format!("HTTP {} from {}", reply.status, id)),
+            },
+        ),
+    }
+}
+
+/// Decodes the newline-delimited envelopes in `chunks`, recording the error that ends the
+/// stream early in `error`.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+fn read_stream<T: DeserializeOwned + 'static>(
+    chunks: BodyChunks,
+    error: Rc<RefCell<Option<ServerFnError>>>,
+) -> impl Stream<Item = T> {
+    use crate::rpc::RpcResponse;
+
+    let state = (chunks, Vec::<u8>::new(), false);
+    futures_util::stream::unfold(state, move |(mut chunks, mut buffer, mut done)| {
+        let error = error.clone();
+        async move {
+            loop {
+                if let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=end).collect();
+                    if line.iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    let envelope = serde_json::from_slice::<RpcResponse<T, String>>(&line)
+                        .map_err(|e| ServerFnError::Decode(e.to_string()))
+                        .and_then(RpcResponse::into_result);
+                    return match envelope {
+                        Ok(item) => Some((item, (chunks, buffer, done))),
+                        Err(e) => {
+                            *error.borrow_mut() = Some(e);
+                            None
+                        }
+                    };
+                }
+                if done {
+                    if !buffer.is_empty() {
+                        buffer.push(b'\n');
+                        continue;
+                    }
+                    return None;
+                }
+                match chunks.next().await {
+                    Some(Ok(chunk)) => buffer.extend(chunk),
+                    Some(Err(e)) => {
+                        *error.borrow_mut() = Some(ServerFnError::Transport(e));
+                        return None;
+                    }
+                    None => done = true,
+                }
+            }
+        }
+    })
+}
+
 #[inline]
 pub fn location_href() -> Option<String> {
     #[cfg(target_arch = "wasm32")]
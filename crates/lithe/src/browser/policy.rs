@@ -0,0 +1,260 @@
+//! Timeouts, retries and cancellation of server function calls.
+
+use crate::files::UploadProgress;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Waker};
+use std::time::Duration;
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+use {
+    crate::rpc::{CallPolicy, ServerFnError},
+    futures_util::future::{Either, pending, select},
+    std::collections::HashMap,
+    std::pin::pin,
+    std::sync::LazyLock,
+    std::task::Poll,
+};
+
+/// Cancels the calls it is passed to with [`CallOptions::abort_handle`]. Clones share the
+/// handle, so a part of the page can abort all of its pending calls when it goes away.
+#[derive(Clone, Default)]
+pub struct AbortHandle(Arc<AbortState>);
+
+#[derive(Default)]
+struct AbortState {
+    aborted: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl AbortHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Aborts every call made with this handle, now and later. They fail with
+    /// `ServerFnError::Cancelled` and their requests are aborted.
+    pub fn abort(&self) {
+        self.0.aborted.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.0.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.0.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the handle is aborted.
+    #[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+    async fn aborted(&self) {
+        futures_util::future::poll_fn(|cx| {
+            let mut wakers = self.0.wakers.lock().unwrap();
+            // Checked under the lock, so an `abort` racing with this poll still wakes it.
+            if self.is_aborted() {
+                return Poll::Ready(());
+            }
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Receives the progress of a call's upload.
+pub(crate) type ProgressFn = Arc<dyn Fn(UploadProgress) + Send + Sync>;
+
+/// Per-call overrides of a server function's [`CallPolicy`](crate::rpc::CallPolicy), applied
+/// with [`with_call_options`].
+#[derive(Clone, Default)]
+pub struct CallOptions {
+    timeout: Option<Duration>,
+    retries: Option<u32>,
+    abort: Option<AbortHandle>,
+    supersede: Option<String>,
+    upload_progress: Option<ProgressFn>,
+}
+
+impl CallOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails calls that get no reply within `timeout` with `ServerFnError::Timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retries calls that fail with `Transport` or `Timeout` up to `retries` times, with
+    /// [`CallPolicy::backoff`](crate::rpc::CallPolicy::backoff) between attempts. Only use
+    /// this for idempotent functions.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Lets `handle` cancel the calls.
+    pub fn abort_handle(mut self, handle: &AbortHandle) -> Self {
+        self.abort = Some(handle.clone());
+        self
+    }
+
+    /// Cancels the previous call made with the same `key` that is still pending, so only the
+    /// latest one completes, e.g. for search-as-you-type.
+    pub fn supersede(mut self, key: impl Into<String>) -> Self {
+        self.supersede = Some(key.into());
+        self
+    }
+
+    /// Calls `f` as the [`UploadedFile`](crate::files::UploadedFile)s of the calls are sent,
+    /// e.g. to drive a progress bar.
+    pub fn on_upload_progress(
+        mut self,
+        f: impl Fn(UploadProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.upload_progress = Some(Arc::new(f));
+        self
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<CallOptions>> = const { RefCell::new(None) };
+}
+
+/// The pending call of each `supersede` key.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+static LATEST: LazyLock<Mutex<HashMap<String, AbortHandle>>> = LazyLock::new(Mutex::default);
+
+/// Applies `options` to the server function calls `future` starts.
+///
+/// ```ignore
+/// let options = CallOptions::new().timeout(Duration::from_secs(2)).supersede("search");
+/// let results = with_call_options(options, search(query)).await;
+/// ```
+pub fn with_call_options<F: Future>(
+    options: CallOptions,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    let mut future = Box::pin(future);
+    futures_util::future::poll_fn(move |cx: &mut Context<'_>| {
+        // Calls read their options when first polled, which happens inside this poll.
+        let outer = CURRENT.with(|current| current.replace(Some(options.clone())));
+        let poll = Pin::as_mut(&mut future).poll(cx);
+        CURRENT.with(|current| *current.borrow_mut() = outer);
+        poll
+    })
+}
+
+/// The upload progress callback of the caller's [`CallOptions`]. Like the other options, it is
+/// read when the call is first polled.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+pub(crate) fn upload_progress() -> Option<ProgressFn> {
+    CURRENT.with(|current| current.borrow().as_ref()?.upload_progress.clone())
+}
+
+/// Runs `call` until it succeeds, fails for good, times out or is cancelled, retrying as
+/// `policy` and the caller's [`CallOptions`] allow.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+pub(crate) async fn run<T, E, F, Fut>(
+    id: &str,
+    policy: CallPolicy,
+    call: F,
+) -> Result<T, ServerFnError<E>>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, ServerFnError<E>>>,
+{
+    let options = CURRENT.with(|current| current.borrow().clone().unwrap_or_default());
+    let timeout = options.timeout.or(policy.timeout);
+    let retries = options.retries.unwrap_or(policy.retries);
+
+    let superseded = AbortHandle::new();
+    if let Some(key) = &options.supersede
+        && let Some(previous) = LATEST
+            .lock()
+            .unwrap()
+            .insert(key.clone(), superseded.clone())
+    {
+        previous.abort();
+    }
+    let cancelled = || cancelled(&superseded, options.abort.as_ref());
+
+    let mut attempt = 0;
+    let result = loop {
+        let stop = async {
+            let timed_out = async {
+                match timeout {
+                    Some(timeout) => {
+                        sleep(timeout).await;
+                        ServerFnError::Timeout(format!("{} did not reply within {:?}", id, timeout))
+                    }
+                    None => pending().await,
+                }
+            };
+            match select(pin!(timed_out), pin!(cancelled())).await {
+                Either::Left((err, _)) | Either::Right((err, _)) => err,
+            }
+        };
+        let result = match select(pin!(call()), pin!(stop)).await {
+            Either::Left((result, _)) => result,
+            Either::Right((err, _)) => Err(err),
+        };
+        match result {
+            Err(ServerFnError::Transport(_) | ServerFnError::Timeout(_)) if attempt < retries => {
+                let backoff = sleep(CallPolicy::backoff(attempt));
+                if let Either::Right((err, _)) = select(pin!(backoff), pin!(cancelled())).await {
+                    break Err(err);
+                }
+                attempt += 1;
+            }
+            result => break result,
+        }
+    };
+
+    if let Some(key) = &options.supersede {
+        let mut latest = LATEST.lock().unwrap();
+        if latest
+            .get(key)
+            .is_some_and(|h| Arc::ptr_eq(&h.0, &superseded.0))
+        {
+            latest.remove(key);
+        }
+    }
+    result
+}
+
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+async fn cancelled<E>(superseded: &AbortHandle, abort: Option<&AbortHandle>) -> ServerFnError<E> {
+    let aborted = async {
+        match abort {
+            Some(abort) => abort.aborted().await,
+            None => pending().await,
+        }
+    };
+    match select(pin!(superseded.aborted()), pin!(aborted)).await {
+        Either::Left(_) => ServerFnError::Cancelled("superseded by a newer call".to_string()),
+        Either::Right(_) => ServerFnError::Cancelled("the call was aborted".to_string()),
+    }
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-client"))]
+async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await;
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(super) async fn sleep(duration: Duration) {
+    let millis = duration.as_millis().min(i32::MAX as u128) as i32;
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        if let Some(window) = web_sys::window() {
+            let _ = window.set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, millis);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
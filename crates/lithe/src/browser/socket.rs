@@ -0,0 +1,379 @@
+//! WebSocket client behind `#[socket]` stubs. A channel keeps one connection open, reconnecting
+//! with a growing, randomised delay whenever it drops, and queues what is sent in between.
+
+use crate::rpc::{Encoding, ServerFnError};
+use futures_util::Stream;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+#[cfg(target_arch = "wasm32")]
+use {
+    super::{page_setting, policy::sleep},
+    futures_channel::mpsc,
+    futures_util::StreamExt,
+    std::cell::Cell,
+    std::collections::VecDeque,
+    std::rc::Rc,
+    std::time::Duration,
+    wasm_bindgen::{JsCast, JsValue, closure::Closure},
+    web_sys::{BinaryType, MessageEvent, WebSocket},
+};
+
+/// Delay before the first reconnect; it doubles with each failed attempt.
+#[cfg(target_arch = "wasm32")]
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+/// Longest delay between reconnects.
+#[cfg(target_arch = "wasm32")]
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The client end of a `#[socket]` function, sending `In` to the server and receiving `Out`
+/// from it, returned by the function's client stub.
+///
+/// Messages sent while the connection is down are queued and go out once it is back; messages
+/// in flight when it drops may be lost. Dropping the channel closes the connection.
+pub struct Channel<In, Out> {
+    sender: ChannelSender<In>,
+    #[cfg(target_arch = "wasm32")]
+    incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+    _out: PhantomData<fn() -> Out>,
+}
+
+impl<In: Serialize, Out: DeserializeOwned> Channel<In, Out> {
+    /// Connects to the `#[socket]` function registered under `id`, exchanging messages in
+    /// `encoding`. Outside the browser there is nothing to connect from, so the channel starts
+    /// out closed: `send` fails and `recv` returns `None`.
+    pub fn connect(id: &str, encoding: Encoding) -> Self {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let url = socket_url(id, encoding);
+            let (outgoing_tx, outgoing_rx) = mpsc::unbounded();
+            let (incoming_tx, incoming_rx) = mpsc::unbounded();
+            let connected = Rc::new(Cell::new(false));
+            wasm_bindgen_futures::spawn_local(drive(
+                url,
+                encoding,
+                outgoing_rx,
+                incoming_tx,
+                connected.clone(),
+            ));
+            Channel {
+                sender: ChannelSender {
+                    encoding,
+                    outgoing: outgoing_tx,
+                    connected,
+                    _in: PhantomData,
+                },
+                incoming: incoming_rx,
+                _out: PhantomData,
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let _ = id;
+            Channel {
+                sender: ChannelSender {
+                    encoding,
+                    _in: PhantomData,
+                },
+                _out: PhantomData,
+            }
+        }
+    }
+
+    /// Sends a message, queueing it while the connection is down. Fails once the channel is
+    /// closed.
+    pub fn send(&self, message: &In) -> Result<(), ServerFnError> {
+        self.sender.send(message)
+    }
+
+    /// The next message from the server, or `None` once the channel is closed. Messages that
+    /// don't decode as `Out` are logged and skipped.
+    pub async fn recv(&mut self) -> Option<Out> {
+        futures_util::StreamExt::next(self).await
+    }
+
+    /// A handle for sending from elsewhere, e.g. event handlers while a task reads.
+    pub fn sender(&self) -> ChannelSender<In> {
+        self.sender.clone()
+    }
+
+    /// Whether the connection is currently open.
+    pub fn is_connected(&self) -> bool {
+        self.sender.is_connected()
+    }
+
+    /// Closes the connection for good. Messages already received can still be read.
+    pub fn close(&self) {
+        #[cfg(target_arch = "wasm32")]
+        self.sender.outgoing.close_channel();
+    }
+}
+
+impl<In, Out: DeserializeOwned> Stream for Channel<In, Out> {
+    type Item = Out;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Out>> {
+        #[cfg(target_arch = "wasm32")]
+        {
+            let this = self.get_mut();
+            loop {
+                let Some(bytes) = futures_util::ready!(this.incoming.poll_next_unpin(cx)) else {
+                    return Poll::Ready(None);
+                };
+                match this.sender.encoding.decode(&bytes) {
+                    Ok(message) => return Poll::Ready(Some(message)),
+                    Err(e) => web_sys::console::warn_1(
+                        &format!("dropped a socket message that did not decode: {}", e).into(),
+                    ),
+                }
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let _ = (self, cx);
+            Poll::Ready(None)
+        }
+    }
+}
+
+impl<In, Out> Drop for Channel<In, Out> {
+    fn drop(&mut self) {
+        // Senders handed out fail from now on rather than keep the connection open.
+        #[cfg(target_arch = "wasm32")]
+        self.sender.outgoing.close_channel();
+    }
+}
+
+/// Sends messages on a [`Channel`], see [`Channel::sender`].
+pub struct ChannelSender<In> {
+    #[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+    encoding: Encoding,
+    #[cfg(target_arch = "wasm32")]
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+    #[cfg(target_arch = "wasm32")]
+    connected: Rc<Cell<bool>>,
+    _in: PhantomData<fn(In)>,
+}
+
+impl<In> Clone for ChannelSender<In> {
+    fn clone(&self) -> Self {
+        ChannelSender {
+            encoding: self.encoding,
+            #[cfg(target_arch = "wasm32")]
+            outgoing: self.outgoing.clone(),
+            #[cfg(target_arch = "wasm32")]
+            connected: self.connected.clone(),
+            _in: PhantomData,
+        }
+    }
+}
+
+impl<In: Serialize> ChannelSender<In> {
+    /// See [`Channel::send`].
+    pub fn send(&self, message: &In) -> Result<(), ServerFnError> {
+        let bytes = self.encoding.encode(message).map_err(ServerFnError::Args)?;
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.outgoing
+                .unbounded_send(bytes)
+                .map_err(|_| ServerFnError::Cancelled("the channel is closed".to_string()))
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let _ = bytes;
+            Err(ServerFnError::Transport(
+                "#[socket] channels only connect in the browser".to_string(),
+            ))
+        }
+    }
+
+    /// See [`Channel::is_connected`].
+    pub fn is_connected(&self) -> bool {
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.connected.get()
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            false
+        }
+    }
+}
+
+/// `ws(s)://host/{base_path}{SOCKET_ENDPOINT}/{id}?encoding=..` on the page's own host.
+#[cfg(target_arch = "wasm32")]
+fn socket_url(id: &str, encoding: Encoding) -> String {
+    let location = web_sys::window().expect("no window").location();
+    let scheme = match location.protocol().as_deref() {
+        Ok("https:") => "wss",
+        _ => "ws",
+    };
+    format!(
+        "{}://{}{}{}/{}?encoding={}",
+        scheme,
+        location.host().unwrap_or_default(),
+        page_setting("base_path").unwrap_or_default(),
+        crate::SOCKET_ENDPOINT,
+        id,
+        encoding.name()
+    )
+}
+
+/// What the task driving a channel reacts to. Socket events carry the number of the
+/// connection they belong to, so late events of a replaced one are ignored.
+#[cfg(target_arch = "wasm32")]
+enum Input {
+    Open(u32),
+    Message(u32, Vec<u8>),
+    Closed(u32),
+    Reconnect,
+    Send(Vec<u8>),
+    Stop,
+}
+
+/// Keeps a channel's connection open until the channel is closed, forwarding messages both
+/// ways.
+#[cfg(target_arch = "wasm32")]
+async fn drive(
+    url: String,
+    encoding: Encoding,
+    outgoing: mpsc::UnboundedReceiver<Vec<u8>>,
+    incoming: mpsc::UnboundedSender<Vec<u8>>,
+    connected: Rc<Cell<bool>>,
+) {
+    let (events, events_rx) = mpsc::unbounded();
+    let sends =
+        outgoing
+            .map(Input::Send)
+            .chain(futures_util::stream::once(futures_util::future::ready(
+                Input::Stop,
+            )));
+    let mut inputs = futures_util::stream::select(events_rx, sends);
+
+    let mut queue = VecDeque::<Vec<u8>>::new();
+    let mut attempt = 0;
+    let mut generation = 0;
+    let mut connection = Connection::open(&url, generation, &events);
+    if connection.is_none() {
+        reconnect_later(&events, attempt);
+    }
+    while let Some(input) = inputs.next().await {
+        match input {
+            Input::Open(g) if g == generation => {
+                connected.set(true);
+                attempt = 0;
+                if let Some(connection) = &connection {
+                    for bytes in queue.drain(..) {
+                        connection.send(encoding, &bytes);
+                    }
+                }
+            }
+            Input::Message(g, bytes) if g == generation => {
+                let _ = incoming.unbounded_send(bytes);
+            }
+            Input::Closed(g) if g == generation => {
+                connected.set(false);
+                connection = None;
+                reconnect_later(&events, attempt);
+                attempt += 1;
+            }
+            Input::Reconnect => {
+                generation += 1;
+                connection = Connection::open(&url, generation, &events);
+                if connection.is_none() {
+                    reconnect_later(&events, attempt);
+                    attempt += 1;
+                }
+            }
+            Input::Send(bytes) => match &connection {
+                Some(connection) if connected.get() => connection.send(encoding, &bytes),
+                _ => queue.push_back(bytes),
+            },
+            Input::Stop => break,
+            Input::Open(_) | Input::Message(..) | Input::Closed(_) => {}
+        }
+    }
+    connected.set(false);
+}
+
+/// Asks for a reconnect after the delay for the `attempt`th failure in a row: doubling from
+/// [`MIN_BACKOFF`] up to [`MAX_BACKOFF`], then randomly cut by up to half so clients dropped
+/// together don't all come back at once.
+#[cfg(target_arch = "wasm32")]
+fn reconnect_later(events: &mpsc::UnboundedSender<Input>, attempt: u32) {
+    let delay = MIN_BACKOFF
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_BACKOFF)
+        .mul_f64(0.5 + js_sys::Math::random() / 2.0);
+    let events = events.clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        sleep(delay).await;
+        let _ = events.unbounded_send(Input::Reconnect);
+    });
+}
+
+/// An open WebSocket and the handlers reporting its events. Dropping it closes the socket.
+#[cfg(target_arch = "wasm32")]
+struct Connection {
+    socket: WebSocket,
+    _handlers: [Closure<dyn FnMut(JsValue)>; 3],
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Connection {
+    fn open(url: &str, generation: u32, events: &mpsc::UnboundedSender<Input>) -> Option<Self> {
+        let socket = WebSocket::new(url).ok()?;
+        socket.set_binary_type(BinaryType::Arraybuffer);
+        let handler = |input: fn(u32, JsValue) -> Option<Input>| {
+            let events = events.clone();
+            Closure::<dyn FnMut(JsValue)>::new(move |event| {
+                if let Some(input) = input(generation, event) {
+                    let _ = events.unbounded_send(input);
+                }
+            })
+        };
+        let handlers = [
+            handler(|g, _| Some(Input::Open(g))),
+            handler(|g, event| Some(Input::Message(g, message_bytes(event)?))),
+            // An `error` event is always followed by `close`.
+            handler(|g, _| Some(Input::Closed(g))),
+        ];
+        socket.set_onopen(Some(handlers[0].as_ref().unchecked_ref()));
+        socket.set_onmessage(Some(handlers[1].as_ref().unchecked_ref()));
+        socket.set_onclose(Some(handlers[2].as_ref().unchecked_ref()));
+        Some(Connection {
+            socket,
+            _handlers: handlers,
+        })
+    }
+
+    /// Sends JSON as text frames and the binary encodings as binary ones.
+    fn send(&self, encoding: Encoding, bytes: &[u8]) {
+        let _ = match (encoding, std::str::from_utf8(bytes)) {
+            (Encoding::Json, Ok(text)) => self.socket.send_with_str(text),
+            _ => self.socket.send_with_u8_array(bytes),
+        };
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.socket.set_onopen(None);
+        self.socket.set_onmessage(None);
+        self.socket.set_onclose(None);
+        let _ = self.socket.close();
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn message_bytes(event: JsValue) -> Option<Vec<u8>> {
+    let data = event.dyn_into::<MessageEvent>().ok()?.data();
+    if let Some(text) = data.as_string() {
+        return Some(text.into_bytes());
+    }
+    let buffer = data.dyn_into::<js_sys::ArrayBuffer>().ok()?;
+    Some(js_sys::Uint8Array::new(&buffer).to_vec())
+}
@@ -0,0 +1,558 @@
+//! HTTP transport behind `call_server`. Calls issued in the same tick are sent together as one
+//! batch, and identical calls already in flight share a single request. A request is aborted
+//! once none of its calls is awaited anymore.
+
+use super::{BodyChunks, ProgressFn, RawReply, page_setting, rpc_endpoint};
+use crate::csrf::{CSRF_COOKIE, CSRF_HEADER};
+use crate::files::{ARGS_PART, UploadProgress, UploadedFile};
+use crate::rpc::{
+    BUILD_HEADER, BatchCall, BatchReply, Encoding, FUNCTION_HEADER, Payload, StreamFormat,
+};
+use futures_channel::oneshot;
+use futures_util::StreamExt;
+use futures_util::future::{FutureExt, LocalBoxFuture, Shared, join_all};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    AbortController, AbortSignal, Blob, BlobPropertyBag, FormData, ProgressEvent,
+    ReadableStreamDefaultReader, Request, RequestInit, RequestMode, Response, XmlHttpRequest,
+    XmlHttpRequestResponseType,
+};
+
+/// Calls with the same encoding, function and arguments are identical and share a reply.
+type CallKey = (Encoding, String, Vec<u8>);
+/// A pending reply; errors are transport failures.
+type SharedReply = Shared<LocalBoxFuture<'static, Result<RawReply, String>>>;
+
+struct QueuedCall {
+    key: CallKey,
+    id: u64,
+    reply: oneshot::Sender<Result<RawReply, String>>,
+}
+
+/// A call that was queued or sent and whose reply has not arrived yet.
+struct InFlight {
+    id: u64,
+    reply: SharedReply,
+    /// Tickets still waiting for the reply.
+    waiters: usize,
+    /// The request carrying the call, once it was sent.
+    request: Option<Rc<SentRequest>>,
+}
+
+/// One HTTP request and the number of its calls that are still awaited.
+struct SentRequest {
+    abort: Option<AbortController>,
+    live: Cell<usize>,
+}
+
+thread_local! {
+    static QUEUE: RefCell<Vec<QueuedCall>> = const { RefCell::new(Vec::new()) };
+    static IN_FLIGHT: RefCell<HashMap<CallKey, InFlight>> = RefCell::default();
+    static NEXT_ID: Cell<u64> = const { Cell::new(0) };
+    static CSRF_TOKEN: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// A caller's claim on the reply to a call. Dropping it before the reply arrives gives the call
+/// up; the call is then left out of the next batch, or its request is aborted when no other
+/// call shares it.
+pub(super) struct Ticket {
+    key: CallKey,
+    id: u64,
+    reply: SharedReply,
+}
+
+impl Ticket {
+    pub async fn reply(&self) -> Result<RawReply, String> {
+        self.reply.clone().await
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let request = IN_FLIGHT.with(|calls| {
+            let mut calls = calls.borrow_mut();
+            let call = calls.get_mut(&self.key).filter(|call| call.id == self.id)?;
+            call.waiters -= 1;
+            if call.waiters > 0 {
+                return None;
+            }
+            calls.remove(&self.key).and_then(|call| call.request)
+        });
+        if let Some(request) = request {
+            request.live.set(request.live.get() - 1);
+            if request.live.get() == 0
+                && let Some(abort) = &request.abort
+            {
+                abort.abort();
+            }
+        }
+    }
+}
+
+/// Queues a call for the next batch, or joins an identical call that is already in flight.
+pub(super) fn send(encoding: Encoding, function: &str, args: Vec<u8>) -> Ticket {
+    let key = (encoding, function.to_string(), args);
+    let joined = IN_FLIGHT.with(|calls| {
+        calls.borrow_mut().get_mut(&key).map(|call| {
+            call.waiters += 1;
+            (call.id, call.reply.clone())
+        })
+    });
+    if let Some((id, reply)) = joined {
+        return Ticket { key, id, reply };
+    }
+
+    let id = NEXT_ID.with(|next| next.replace(next.get() + 1));
+    let (tx, rx) = oneshot::channel();
+    let first = QUEUE.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        queue.push(QueuedCall {
+            key: key.clone(),
+            id,
+            reply: tx,
+        });
+        queue.len() == 1
+    });
+    if first {
+        // Runs once the current task yields, after every call of this tick was queued.
+        wasm_bindgen_futures::spawn_local(flush());
+    }
+
+    let reply = rx
+        .map(|reply| reply.unwrap_or_else(|_| Err("the call was cancelled".to_string())))
+        .boxed_local()
+        .shared();
+    IN_FLIGHT.with(|calls| {
+        calls.borrow_mut().insert(
+            key.clone(),
+            InFlight {
+                id,
+                reply: reply.clone(),
+                waiters: 1,
+                request: None,
+            },
+        )
+    });
+    Ticket { key, id, reply }
+}
+
+async fn flush() {
+    let calls = QUEUE.with(|queue| std::mem::take(&mut *queue.borrow_mut()));
+    // A batch is encoded as a whole, so only calls in the same encoding can share one.
+    let mut groups: Vec<Vec<QueuedCall>> = Vec::new();
+    // Calls given up before they were sent are dropped.
+    for call in calls.into_iter().filter(|call| !call.reply.is_canceled()) {
+        match groups.iter_mut().find(|group| group[0].key.0 == call.key.0) {
+            Some(group) => group.push(call),
+            None => groups.push(vec![call]),
+        }
+    }
+    join_all(groups.into_iter().map(send_group)).await;
+}
+
+async fn send_group(calls: Vec<QueuedCall>) {
+    let request = Rc::new(SentRequest {
+        abort: AbortController::new().ok(),
+        live: Cell::new(calls.len()),
+    });
+    IN_FLIGHT.with(|in_flight| {
+        let mut in_flight = in_flight.borrow_mut();
+        for call in &calls {
+            if let Some(entry) = in_flight.get_mut(&call.key).filter(|e| e.id == call.id) {
+                entry.request = Some(request.clone());
+            }
+        }
+    });
+    let signal = request.abort.as_ref().map(|abort| abort.signal());
+
+    let replies = match calls.as_slice() {
+        // A lone call goes to the regular endpoint.
+        [call] => {
+            let (encoding, function, args) = &call.key;
+            let endpoint = rpc_endpoint();
+            vec![fetch(&endpoint, *encoding, Some(function), args, signal.as_ref()).await]
+        }
+        _ => send_batch(&calls, signal.as_ref()).await,
+    };
+    for (call, reply) in calls.into_iter().zip(replies) {
+        IN_FLIGHT.with(|in_flight| {
+            let mut in_flight = in_flight.borrow_mut();
+            if in_flight.get(&call.key).is_some_and(|e| e.id == call.id) {
+                in_flight.remove(&call.key);
+            }
+        });
+        let _ = call.reply.send(reply);
+    }
+}
+
+async fn send_batch(
+    calls: &[QueuedCall],
+    signal: Option<&AbortSignal>,
+) -> Vec<Result<RawReply, String>> {
+    let encoding = calls[0].key.0;
+    let batch: Vec<BatchCall> = calls
+        .iter()
+        .map(|call| BatchCall {
+            function: call.key.1.clone(),
+            args: Payload(call.key.2.clone()),
+        })
+        .collect();
+
+    let replies = async {
+        let body = encoding.encode(&batch)?;
+        // The batch endpoint sits below the RPC endpoint, see `RPC_BATCH_ENDPOINT`.
+        let endpoint = format!("{}/batch", rpc_endpoint());
+        let reply = fetch(&endpoint, encoding, None, &body, signal).await?;
+        if !reply.ok() {
+            // The batch as a whole was rejected, e.g. because the page is outdated.
+            return Ok(vec![reply; calls.len()]);
+        }
+        let replies = reply
+            .encoding
+            .ok_or("batch reply has no supported content type")?
+            .decode::<Vec<BatchReply>>(&reply.body)?;
+        if replies.len() != calls.len() {
+            return Err("batch reply does not match the calls sent".to_string());
+        }
+        Ok(replies
+            .into_iter()
+            .map(|r| RawReply {
+                status: r.status,
+                encoding: reply.encoding,
+                body: r.body.0,
+                content_type: reply.content_type.clone(),
+                disposition: None,
+                request_id: reply.request_id.clone(),
+            })
+            .collect())
+    };
+    match replies.await {
+        Ok(replies) => replies.into_iter().map(Ok).collect(),
+        Err(e) => vec![Err(e); calls.len()],
+    }
+}
+
+async fn fetch(
+    endpoint: &str,
+    encoding: Encoding,
+    function: Option<&str>,
+    body: &[u8],
+    signal: Option<&AbortSignal>,
+) -> Result<RawReply, String> {
+    let resp = post(
+        endpoint,
+        encoding,
+        encoding.content_type(),
+        function,
+        body,
+        signal,
+    )
+    .await?;
+    read_reply(resp).await
+}
+
+/// Sends a call on its own instead of queueing it for a batch, e.g. one returning a
+/// [`Download`](crate::files::Download). Dropping the future aborts the request.
+pub(super) async fn send_alone(
+    encoding: Encoding,
+    function: &str,
+    args: &[u8],
+) -> Result<RawReply, String> {
+    let abort = AbortOnDrop(AbortController::new().ok());
+    let signal = abort.0.as_ref().map(|a| a.signal());
+    fetch(
+        &rpc_endpoint(),
+        encoding,
+        Some(function),
+        args,
+        signal.as_ref(),
+    )
+    .await
+}
+
+/// Posts a call with files as `multipart/form-data`: the encoded arguments, then one part per
+/// file. Uses `XMLHttpRequest`, as `fetch` cannot report upload progress. Dropping the future
+/// aborts the request.
+pub(super) async fn upload(
+    encoding: Encoding,
+    function: &str,
+    args: &[u8],
+    files: &[(String, UploadedFile)],
+    progress: Option<ProgressFn>,
+) -> Result<RawReply, String> {
+    let js = |e: JsValue| format!("{:?}", e);
+
+    let form = FormData::new().map_err(js)?;
+    let options = BlobPropertyBag::new();
+    options.set_type(encoding.content_type());
+    let args = js_sys::Array::of1(&js_sys::Uint8Array::from(args));
+    let args = Blob::new_with_u8_array_sequence_and_options(&args, &options).map_err(js)?;
+    form.append_with_blob(ARGS_PART, &args).map_err(js)?;
+    for (part, file) in files {
+        form.append_with_blob_and_filename(part, file.blob(), file.name())
+            .map_err(js)?;
+    }
+
+    let xhr = XmlHttpRequest::new().map_err(js)?;
+    xhr.open("POST", &rpc_endpoint()).map_err(js)?;
+    xhr.set_response_type(XmlHttpRequestResponseType::Arraybuffer);
+    // The browser sets `Content-Type` itself, with the multipart boundary.
+    xhr.set_request_header("Accept", encoding.content_type())
+        .map_err(js)?;
+    xhr.set_request_header(FUNCTION_HEADER, function)
+        .map_err(js)?;
+    if let Some(token) = csrf_token() {
+        xhr.set_request_header(CSRF_HEADER, &token).map_err(js)?;
+    }
+    if let Some(build_id) = page_setting("build_id").filter(|b| !b.is_empty()) {
+        xhr.set_request_header(BUILD_HEADER, &build_id)
+            .map_err(js)?;
+    }
+
+    let (tx, rx) = oneshot::channel();
+    let tx = Rc::new(RefCell::new(Some(tx)));
+    let settle = |loaded: bool| {
+        let tx = tx.clone();
+        Closure::<dyn FnMut()>::new(move || {
+            if let Some(tx) = tx.borrow_mut().take() {
+                let _ = tx.send(loaded);
+            }
+        })
+    };
+    let (onload, onfail) = (settle(true), settle(false));
+    xhr.set_onload(Some(onload.as_ref().unchecked_ref()));
+    xhr.set_onerror(Some(onfail.as_ref().unchecked_ref()));
+    xhr.set_onabort(Some(onfail.as_ref().unchecked_ref()));
+    let onprogress = progress.map(|progress| {
+        Closure::<dyn FnMut(ProgressEvent)>::new(move |e: ProgressEvent| {
+            progress(UploadProgress {
+                sent: e.loaded() as u64,
+                total: e.total() as u64,
+            })
+        })
+    });
+    if let Some(onprogress) = &onprogress {
+        xhr.upload()
+            .map_err(js)?
+            .set_onprogress(Some(onprogress.as_ref().unchecked_ref()));
+    }
+
+    // Dropped before the closures, so an abort still finds its handler.
+    let _abort = AbortXhrOnDrop(xhr.clone());
+    xhr.send_with_opt_form_data(Some(&form)).map_err(js)?;
+    if !rx.await.unwrap_or(false) {
+        return Err("the upload failed".to_string());
+    }
+
+    let header = |name| xhr.get_response_header(name).ok().flatten();
+    let content_type = header("Content-Type");
+    let body = xhr.response().map_err(js)?;
+    Ok(RawReply {
+        status: xhr.status().map_err(js)?,
+        encoding: content_type
+            .as_deref()
+            .and_then(Encoding::from_content_type),
+        body: js_sys::Uint8Array::new(&body).to_vec(),
+        content_type,
+        disposition: header("Content-Disposition"),
+        request_id: header("X-Request-Id"),
+    })
+}
+
+struct AbortXhrOnDrop(XmlHttpRequest);
+
+impl Drop for AbortXhrOnDrop {
+    fn drop(&mut self) {
+        // Aborting a completed request would discard its response.
+        if self.0.ready_state() != XmlHttpRequest::DONE {
+            let _ = self.0.abort();
+        }
+    }
+}
+
+/// Sends a `method = "GET"` call to `url`, which already carries the function and arguments.
+/// The browser's HTTP cache revalidates it with `If-None-Match` on its own. Dropping the future
+/// aborts the request.
+pub(super) async fn get(url: &str, encoding: Encoding) -> Result<RawReply, String> {
+    let js = |e: JsValue| format!("{:?}", e);
+    // Aborting a request that already completed has no effect.
+    let abort = AbortOnDrop(AbortController::new().ok());
+
+    let opts = RequestInit::new();
+    opts.set_method("GET");
+    opts.set_mode(RequestMode::SameOrigin);
+    opts.set_signal(abort.0.as_ref().map(|a| a.signal()).as_ref());
+
+    let request = Request::new_with_str_and_init(url, &opts).map_err(js)?;
+    let headers = request.headers();
+    headers.set("Accept", encoding.content_type()).map_err(js)?;
+    if let Some(build_id) = page_setting("build_id").filter(|b| !b.is_empty()) {
+        headers.set(BUILD_HEADER, &build_id).map_err(js)?;
+    }
+
+    let window = web_sys::window().ok_or("no window available")?;
+    let resp: Response = JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(js)?
+        .dyn_into()
+        .map_err(js)?;
+    read_reply(resp).await
+}
+
+struct AbortOnDrop(Option<AbortController>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        if let Some(abort) = &self.0 {
+            abort.abort();
+        }
+    }
+}
+
+/// The session's CSRF token, see [`crate::csrf`]: the page's, else the one in the cookie. Pages
+/// that carry neither, e.g. static ones, store a fresh token in the cookie.
+fn csrf_token() -> Option<String> {
+    if let Some(token) = CSRF_TOKEN.with(|t| t.borrow().clone()) {
+        return Some(token);
+    }
+    let document: web_sys::HtmlDocument = web_sys::window()?.document()?.dyn_into().ok()?;
+    let cookie = || {
+        let cookies = document.cookie().ok()?;
+        crate::context::parse_cookie(&cookies, CSRF_COOKIE).map(str::to_string)
+    };
+    let is_token = |t: &String| crate::csrf::is_token(t);
+    let token = match page_setting("csrf_token")
+        .filter(is_token)
+        .or_else(|| cookie().filter(is_token))
+    {
+        Some(token) => token,
+        None => {
+            let mut bytes = [0u8; 16];
+            web_sys::window()?
+                .crypto()
+                .ok()?
+                .get_random_values_with_u8_array(&mut bytes)
+                .ok()?;
+            let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            document
+                .set_cookie(&crate::csrf::token_cookie(&token))
+                .ok()?;
+            token
+        }
+    };
+    CSRF_TOKEN.with(|t| *t.borrow_mut() = Some(token.clone()));
+    Some(token)
+}
+
+/// Posts `body` to `endpoint` with the headers every RPC call carries.
+async fn post(
+    endpoint: &str,
+    encoding: Encoding,
+    accept: &str,
+    function: Option<&str>,
+    body: &[u8],
+    signal: Option<&AbortSignal>,
+) -> Result<Response, String> {
+    let js = |e: JsValue| format!("{:?}", e);
+
+    let opts = RequestInit::new();
+    opts.set_method("POST");
+    opts.set_mode(RequestMode::SameOrigin);
+    opts.set_body(&js_sys::Uint8Array::from(body));
+    opts.set_signal(signal);
+
+    let request = Request::new_with_str_and_init(endpoint, &opts).map_err(js)?;
+    let headers = request.headers();
+    headers
+        .set("Content-Type", encoding.content_type())
+        .map_err(js)?;
+    headers.set("Accept", accept).map_err(js)?;
+    if let Some(function) = function {
+        headers.set(FUNCTION_HEADER, function).map_err(js)?;
+    }
+    if let Some(token) = csrf_token() {
+        headers.set(CSRF_HEADER, &token).map_err(js)?;
+    }
+    if let Some(build_id) = page_setting("build_id").filter(|b| !b.is_empty()) {
+        headers.set(BUILD_HEADER, &build_id).map_err(js)?;
+    }
+
+    let window = web_sys::window().ok_or("no window available")?;
+    JsFuture::from(window.fetch_with_request(&request))
+        .await
+        .map_err(js)?
+        .dyn_into()
+        .map_err(js)
+}
+
+/// Posts the arguments of a streaming call. Returns the body's chunks, or the whole reply when
+/// the call was rejected before streaming started.
+pub(super) async fn open_stream(
+    encoding: Encoding,
+    function: &str,
+    body: &[u8],
+    signal: Option<&AbortSignal>,
+) -> Result<Result<BodyChunks, RawReply>, String> {
+    // The stream endpoint sits below the RPC endpoint, see `RPC_STREAM_ENDPOINT`.
+    let endpoint = format!("{}/stream", rpc_endpoint());
+    let accept = StreamFormat::NdJson.content_type();
+    let resp = post(&endpoint, encoding, accept, Some(function), body, signal).await?;
+    if !resp.ok() {
+        return Ok(Err(read_reply(resp).await?));
+    }
+
+    let reader: ReadableStreamDefaultReader = resp
+        .body()
+        .ok_or("streaming response has no body")?
+        .get_reader()
+        .dyn_into()
+        .map_err(|e| format!("{:?}", e))?;
+    let chunks = futures_util::stream::unfold(Some(reader), |reader| async move {
+        let reader = reader?;
+        let chunk = match JsFuture::from(reader.read()).await {
+            Ok(chunk) => chunk,
+            Err(e) => return Some((Err(format!("{:?}", e)), None)),
+        };
+        let done = js_sys::Reflect::get(&chunk, &"done".into())
+            .ok()
+            .and_then(|d| d.as_bool())
+            .unwrap_or(true);
+        if done {
+            return None;
+        }
+        let bytes = js_sys::Reflect::get(&chunk, &"value".into())
+            .ok()
+            .filter(|value| !value.is_undefined())
+            .map(|value| js_sys::Uint8Array::new(&value).to_vec())
+            .unwrap_or_default();
+        Some((Ok(bytes), Some(reader)))
+    });
+    Ok(Ok(chunks.boxed_local()))
+}
+
+/// Reads the whole body of `resp`.
+async fn read_reply(resp: Response) -> Result<RawReply, String> {
+    let js = |e: JsValue| format!("{:?}", e);
+    let header = |name| resp.headers().get(name).ok().flatten();
+    let content_type = header("Content-Type");
+    let disposition = header("Content-Disposition");
+    let request_id = header("X-Request-Id");
+    let bytes = JsFuture::from(resp.array_buffer().map_err(js)?)
+        .await
+        .map_err(js)?;
+    Ok(RawReply {
+        status: resp.status(),
+        encoding: content_type
+            .as_deref()
+            .and_then(Encoding::from_content_type),
+        body: js_sys::Uint8Array::new(&bytes).to_vec(),
+        content_type,
+        disposition,
+        request_id,
+    })
+}
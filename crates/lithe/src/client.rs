@@ -0,0 +1,187 @@
+//! Calls `#[server]` functions over HTTP from native code, e.g. CLIs, integration tests and other
+//! services. In a crate built with `--cfg lithe_native_client`, `#[server]` functions compile to
+//! the same client stubs as in the browser, so their typed signatures work unchanged:
+//!
+//! ```ignore
+//! lithe::client::set_base_url("https://example.com");
+//! let total = app::orders::get_total(7).await?;
+//! ```
+//!
+//! Calls need a Tokio runtime. They are not batched, and timeouts, retries and
+//! [`with_call_options`](crate::browser::with_call_options) work as in the browser.
+
+use crate::browser::{BodyChunks, ProgressFn, RawReply};
+use crate::files::{ARGS_PART, UploadProgress, UploadedFile};
+use crate::rpc::{ARGS_PARAM, Encoding, FUNCTION_HEADER, FUNCTION_PARAM, StreamFormat};
+use futures_util::StreamExt;
+use reqwest::header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE};
+use reqwest::multipart::{Form, Part};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub use reqwest;
+
+/// Environment variable read when no base URL was set with [`set_base_url`].
+pub const BASE_URL_ENV: &str = "LITHE_BASE_URL";
+
+static BASE_URL: RwLock<Option<String>> = RwLock::new(None);
+static HTTP_CLIENT: RwLock<Option<reqwest::Client>> = RwLock::new(None);
+
+/// Sets the URL of the app calls go to, including its base path, e.g. `https://example.com/app`.
+pub fn set_base_url(url: impl Into<String>) {
+    *BASE_URL.write().unwrap() = Some(url.into());
+}
+
+/// The URL calls go to: the one set with [`set_base_url`], else `$LITHE_BASE_URL`, else
+/// `http://localhost:3000`.
+pub fn base_url() -> String {
+    if let Some(url) = BASE_URL.read().unwrap().clone() {
+        return url;
+    }
+    std::env::var(BASE_URL_ENV).unwrap_or_else(|_| "http://localhost:3000".to_string())
+}
+
+/// Replaces the HTTP client calls are made with, e.g. to send a session cookie or an
+/// `Authorization` header with every call.
+pub fn set_http_client(client: reqwest::Client) {
+    *HTTP_CLIENT.write().unwrap() = Some(client);
+}
+
+fn http_client() -> reqwest::Client {
+    HTTP_CLIENT.read().unwrap().clone().unwrap_or_default()
+}
+
+fn endpoint(path: &str) -> String {
+    format!("{}{}", base_url().trim_end_matches('/'), path)
+}
+
+pub(crate) async fn post(
+    encoding: Encoding,
+    function: &str,
+    args: &[u8],
+) -> Result<RawReply, String> {
+    let resp = http_client()
+        .post(endpoint(crate::RPC_ENDPOINT))
+        .header(CONTENT_TYPE, encoding.content_type())
+        .header(ACCEPT, encoding.content_type())
+        .header(FUNCTION_HEADER, function)
+        .body(args.to_vec())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    read_reply(resp).await
+}
+
+/// Bytes of a file handed to the connection at a time, and so between progress reports.
+const UPLOAD_CHUNK: usize = 64 * 1024;
+
+/// Posts a call with files as `multipart/form-data`: the encoded arguments, then one part per
+/// file.
+pub(crate) async fn upload(
+    encoding: Encoding,
+    function: &str,
+    args: &[u8],
+    files: &[(String, UploadedFile)],
+    progress: Option<ProgressFn>,
+) -> Result<RawReply, String> {
+    let args = Part::bytes(args.to_vec())
+        .mime_str(encoding.content_type())
+        .map_err(|e| e.to_string())?;
+    let total = files.iter().map(|(_, file)| file.len()).sum();
+    let sent = Arc::new(AtomicU64::new(0));
+    let mut form = Form::new().part(ARGS_PART, args);
+    for (part, file) in files {
+        let (sent, progress) = (sent.clone(), progress.clone());
+        let chunks: Vec<Vec<u8>> = file
+            .bytes()
+            .chunks(UPLOAD_CHUNK)
+            .map(<[u8]>::to_vec)
+            .collect();
+        let body = futures_util::stream::iter(chunks).map(move |chunk| {
+            let sent = sent.fetch_add(chunk.len() as u64, Ordering::Relaxed) + chunk.len() as u64;
+            if let Some(progress) = &progress {
+                progress(UploadProgress { sent, total });
+            }
+            Ok::<_, std::io::Error>(chunk)
+        });
+        let file_part = Part::stream_with_length(reqwest::Body::wrap_stream(body), file.len())
+            .file_name(file.name().to_string())
+            .mime_str(file.content_type())
+            .map_err(|e| e.to_string())?;
+        form = form.part(part.clone(), file_part);
+    }
+    let resp = http_client()
+        .post(endpoint(crate::RPC_ENDPOINT))
+        .header(ACCEPT, encoding.content_type())
+        .header(FUNCTION_HEADER, function)
+        .multipart(form)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    read_reply(resp).await
+}
+
+pub(crate) async fn get(
+    encoding: Encoding,
+    function: &str,
+    args: &str,
+) -> Result<RawReply, String> {
+    let resp = http_client()
+        .get(endpoint(crate::RPC_ENDPOINT))
+        .query(&[(FUNCTION_PARAM, function), (ARGS_PARAM, args)])
+        .header(ACCEPT, encoding.content_type())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    read_reply(resp).await
+}
+
+/// Posts the arguments of a streaming call. Returns the body's chunks, or the whole reply when
+/// the call was rejected before streaming started.
+pub(crate) async fn open_stream(
+    encoding: Encoding,
+    function: &str,
+    args: &[u8],
+) -> Result<Result<BodyChunks, RawReply>, String> {
+    let resp = http_client()
+        .post(endpoint(crate::RPC_STREAM_ENDPOINT))
+        .header(CONTENT_TYPE, encoding.content_type())
+        .header(ACCEPT, StreamFormat::NdJson.content_type())
+        .header(FUNCTION_HEADER, function)
+        .body(args.to_vec())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Ok(Err(read_reply(resp).await?));
+    }
+    let chunks = resp
+        .bytes_stream()
+        .map(|chunk| chunk.map(|bytes| bytes.to_vec()).map_err(|e| e.to_string()));
+    Ok(Ok(chunks.boxed_local()))
+}
+
+async fn read_reply(resp: reqwest::Response) -> Result<RawReply, String> {
+    let status = resp.status().as_u16();
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let content_type = header(CONTENT_TYPE);
+    let disposition = header(CONTENT_DISPOSITION);
+    let request_id = header(reqwest::header::HeaderName::from_static("x-request-id"));
+    let encoding = content_type
+        .as_deref()
+        .and_then(Encoding::from_content_type);
+    let body = resp.bytes().await.map_err(|e| e.to_string())?;
+    Ok(RawReply {
+        status,
+        encoding,
+        body: body.to_vec(),
+        content_type,
+        disposition,
+        request_id,
+    })
+}
@@ -0,0 +1,316 @@
+//! Files sent to and returned from `#[server]` functions.
+//!
+//! [`UploadedFile`] arguments travel as `multipart/form-data` parts next to the encoded
+//! arguments, so they are never inflated into JSON arrays. Functions returning [`Download`]
+//! reply with the file's bytes and `Content-Type` instead of an encoded value.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cell::RefCell;
+use std::fmt;
+
+/// Name of the multipart part carrying the encoded arguments of a call with files.
+pub const ARGS_PART: &str = "args";
+
+/// A file passed to a server function, e.g. picked in an `<input type="file">`:
+///
+/// ```ignore
+/// #[server]
+/// pub async fn set_avatar(file: UploadedFile) -> ServerFnResult<()> {
+///     store(file.name(), file.bytes()).await
+/// }
+///
+/// // In the browser:
+/// if let Some(file) = UploadedFile::from_input("avatar").pop() {
+///     set_avatar(file).await?;
+/// }
+/// ```
+///
+/// In the browser it wraps the picked file without reading it; on the server it holds the
+/// uploaded bytes. Files may appear anywhere in the arguments, e.g. as `Vec<UploadedFile>` or
+/// in a struct field. Calls with files are sent on their own rather than batched, and cannot
+/// use `method = "GET"`; [`CallOptions::on_upload_progress`](crate::browser::CallOptions::on_upload_progress)
+/// reports how much was sent.
+#[derive(Clone)]
+pub struct UploadedFile {
+    name: String,
+    content_type: String,
+    #[cfg(target_arch = "wasm32")]
+    blob: web_sys::Blob,
+    #[cfg(not(target_arch = "wasm32"))]
+    bytes: Vec<u8>,
+}
+
+impl UploadedFile {
+    /// The file's name on the sender's side, without any directory.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// Size in bytes.
+    pub fn len(&self) -> u64 {
+        #[cfg(target_arch = "wasm32")]
+        {
+            self.blob.size() as u64
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            self.bytes.len() as u64
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl UploadedFile {
+    pub fn new(
+        name: impl Into<String>,
+        content_type: impl Into<String>,
+        bytes: impl Into<Vec<u8>>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            content_type: content_type.into(),
+            bytes: bytes.into(),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl UploadedFile {
+    pub fn from_file(file: web_sys::File) -> Self {
+        let content_type = file.type_();
+        Self::from_blob(file.name(), content_type, file.into())
+    }
+
+    pub fn from_blob(
+        name: impl Into<String>,
+        content_type: impl Into<String>,
+        blob: web_sys::Blob,
+    ) -> Self {
+        let content_type = content_type.into();
+        Self {
+            name: name.into(),
+            content_type: if content_type.is_empty() {
+                "application/octet-stream".to_string()
+            } else {
+                content_type
+            },
+            blob,
+        }
+    }
+
+    /// The files picked in the `<input type="file">` with the given id.
+    pub fn from_input(id: &str) -> Vec<UploadedFile> {
+        use wasm_bindgen::JsCast;
+
+        let Some(files) = crate::browser::get_element_by_id(id)
+            .and_then(|el| el.dyn_into::<web_sys::HtmlInputElement>().ok())
+            .and_then(|input| input.files())
+        else {
+            return Vec::new();
+        };
+        (0..files.length())
+            .filter_map(|i| files.get(i))
+            .map(UploadedFile::from_file)
+            .collect()
+    }
+
+    pub fn blob(&self) -> &web_sys::Blob {
+        &self.blob
+    }
+}
+
+impl fmt::Debug for UploadedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploadedFile")
+            .field("name", &self.name)
+            .field("content_type", &self.content_type)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// How an [`UploadedFile`] appears among the encoded arguments: a reference to the multipart
+/// part holding its bytes.
+#[derive(Serialize, Deserialize)]
+struct FilePart {
+    part: String,
+    name: String,
+    content_type: String,
+    size: u64,
+}
+
+thread_local! {
+    static COLLECTED: RefCell<Option<Vec<(String, UploadedFile)>>> = const { RefCell::new(None) };
+}
+
+/// Runs `encode`, returning its output and the files it serialized, each with the name of the
+/// part it is sent in.
+#[cfg(any(target_arch = "wasm32", feature = "native-client"))]
+pub(crate) fn collect<T>(encode: impl FnOnce() -> T) -> (T, Vec<(String, UploadedFile)>) {
+    let outer = COLLECTED.with(|c| c.replace(Some(Vec::new())));
+    let output = encode();
+    let files = COLLECTED.with(|c| c.replace(outer)).unwrap_or_default();
+    (output, files)
+}
+
+impl Serialize for UploadedFile {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let part = COLLECTED
+            .with(|c| {
+                c.borrow_mut().as_mut().map(|files| {
+                    let part = format!("file-{}", files.len());
+                    files.push((part.clone(), self.clone()));
+                    part
+                })
+            })
+            .unwrap_or_default();
+        FilePart {
+            part,
+            name: self.name.clone(),
+            content_type: self.content_type.clone(),
+            size: self.len(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for UploadedFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let part = FilePart::deserialize(deserializer)?;
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let bytes = crate::server::take_upload(&part.part).ok_or_else(|| {
+                D::Error::custom(format!("no file was uploaded for `{}`", part.name))
+            })?;
+            Ok(UploadedFile {
+                name: part.name,
+                content_type: part.content_type,
+                bytes,
+            })
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            let _ = part;
+            Err(D::Error::custom(
+                "uploaded files can only be received by the server",
+            ))
+        }
+    }
+}
+
+/// How much of a call's files has been sent, reported to
+/// [`CallOptions::on_upload_progress`](crate::browser::CallOptions::on_upload_progress).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadProgress {
+    pub sent: u64,
+    pub total: u64,
+}
+
+/// A file returned by a server function. The reply carries its bytes as they are, with its
+/// content type, so the function can also be linked to directly when it uses `method = "GET"`:
+///
+/// ```ignore
+/// #[server(method = "GET")]
+/// pub async fn export_orders(month: u32) -> ServerFnResult<Download> {
+///     let csv = orders_csv(month).await?;
+///     Ok(Download::new("text/csv", csv).attachment(format!("orders-{}.csv", month)))
+/// }
+/// ```
+///
+/// The file is held in memory on both sides. Errors still arrive as `ServerFnError`s.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Download {
+    content_type: String,
+    file_name: Option<String>,
+    bytes: Vec<u8>,
+}
+
+impl Download {
+    pub fn new(content_type: impl Into<String>, bytes: impl Into<Vec<u8>>) -> Self {
+        Self {
+            content_type: content_type.into(),
+            file_name: None,
+            bytes: bytes.into(),
+        }
+    }
+
+    /// Asks browsers to save the file as `file_name` rather than show it.
+    pub fn attachment(mut self, file_name: impl Into<String>) -> Self {
+        self.file_name = Some(file_name.into());
+        self
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Download {
+    pub fn to_blob(&self) -> Result<web_sys::Blob, wasm_bindgen::JsValue> {
+        let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(self.bytes.as_slice()));
+        let options = web_sys::BlobPropertyBag::new();
+        options.set_type(&self.content_type);
+        web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)
+    }
+
+    /// Offers the file to the user as if they had followed a download link.
+    pub fn save(&self) -> Result<(), wasm_bindgen::JsValue> {
+        use wasm_bindgen::JsCast;
+
+        let url = web_sys::Url::create_object_url_with_blob(&self.to_blob()?)?;
+        let document = web_sys::window()
+            .and_then(|w| w.document())
+            .ok_or("no document available")?;
+        let link: web_sys::HtmlAnchorElement = document.create_element("a")?.unchecked_into();
+        link.set_href(&url);
+        link.set_download(self.file_name.as_deref().unwrap_or("download"));
+        link.click();
+        // Revoked once the click was handled; revoking right away can cancel the download.
+        let revoke = js_sys::Function::new_with_args("url", "URL.revokeObjectURL(url)");
+        web_sys::window()
+            .ok_or("no window available")?
+            .set_timeout_with_callback_and_timeout_and_arguments_1(&revoke, 0, &url.into())?;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Download {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Download")
+            .field("content_type", &self.content_type)
+            .field("file_name", &self.file_name)
+            .field("len", &self.bytes.len())
+            .finish()
+    }
+}
@@ -0,0 +1,628 @@
+//! Background jobs and scheduled tasks.
+//!
+//! `#[job]` functions run on the server outside of any request, once server code enqueued
+//! them through the `enqueue_{name}` function the macro generates next to them:
+//!
+//! ```ignore
+//! #[job(retries = 5, backoff = "30s")]
+//! pub async fn send_welcome(user_id: u64) -> Result<(), MailError> {
+//!     mailer().send(welcome_email(user_id).await?).await
+//! }
+//!
+//! #[server]
+//! pub async fn sign_up(form: SignUp) -> ServerFnResult<()> {
+//!     let user = create_user(form).await?;
+//!     enqueue_send_welcome(user.id).await.map_err(ServerFnError::Server)?;
+//!     Ok(())
+//! }
+//!
+//! #[scheduled("0 3 * * *")]
+//! pub async fn purge_sessions() -> Result<(), sqlx::Error> {
+//!     sessions().purge_expired().await
+//! }
+//! ```
+//!
+//! `#[scheduled]` functions take no arguments and run on a [cron schedule](crate::cron), in
+//! UTC. The CLI finds both kinds like server functions, and the generated server hands them to
+//! [`start`].
+//!
+//! Jobs wait in a [`JobStore`]: in memory by default, so they are lost when the server stops.
+//! [`SqliteStore`] (feature `sqlite`) keeps them in a database file, and [`set_job_store`]
+//! installs it or any other. Jobs run at least once: a failing job is retried after a backoff
+//! that doubles each time, and one whose server stopped while running it is run again after
+//! [`LEASE`].
+
+use futures_util::FutureExt;
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Notify, Semaphore};
+use tracing::Instrument;
+
+use crate::cron::Schedule;
+
+/// What running a job or scheduled task produces.
+pub type JobFuture = BoxFuture<'static, Result<(), String>>;
+
+/// A `#[job]` function, as the macro declares it for the generated server.
+#[derive(Clone, Copy)]
+pub struct Job {
+    /// The job's name in the store: `name = "..."` if given, else the function's path.
+    pub name: &'static str,
+    /// Decodes the arguments the job was enqueued with and runs the function.
+    pub run: fn(&[u8]) -> JobFuture,
+    /// How many times a failed run is retried.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub backoff: Duration,
+}
+
+/// A `#[scheduled("...")]` function.
+#[derive(Clone, Copy)]
+pub struct Scheduled {
+    pub name: &'static str,
+    /// The cron expression, checked by the macro.
+    pub cron: &'static str,
+    pub run: fn() -> JobFuture,
+}
+
+/// Return types of job, scheduled and socket functions: `()`, or a `Result` whose error is
+/// logged.
+pub trait JobResult {
+    fn into_job_result(self) -> Result<(), String>;
+}
+
+impl JobResult for () {
+    fn into_job_result(self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl<E: fmt::Display> JobResult for Result<(), E> {
+    fn into_job_result(self) -> Result<(), String> {
+        self.map_err(|e| e.to_string())
+    }
+}
+
+/// A job waiting in a [`JobStore`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredJob {
+    pub id: u64,
+    pub name: String,
+    /// The arguments, as JSON.
+    pub payload: Vec<u8>,
+    /// Runs started so far, including the current one once claimed.
+    pub attempts: u32,
+}
+
+/// How long a claimed job stays hidden from other runners. Jobs still running after it may
+/// be started a second time, by this server or another.
+pub const LEASE: Duration = Duration::from_secs(60 * 60);
+
+/// Keeps enqueued jobs until they ran, and the last run of each scheduled task, so several
+/// servers sharing a store run each job and each scheduled run once.
+pub trait JobStore: Send + Sync {
+    /// Saves a job to run at `run_at`, returning its id.
+    fn push<'a>(
+        &'a self,
+        name: &'a str,
+        payload: &'a [u8],
+        run_at: SystemTime,
+    ) -> BoxFuture<'a, Result<u64, String>>;
+
+    /// Takes up to `limit` jobs due at `now`, oldest first, counting an attempt for each and
+    /// hiding them from other claims until `now + LEASE`.
+    fn claim(&self, now: SystemTime, limit: usize)
+    -> BoxFuture<'_, Result<Vec<StoredJob>, String>>;
+
+    /// Removes a job that succeeded.
+    fn complete(&self, id: u64) -> BoxFuture<'_, Result<(), String>>;
+
+    /// Makes a failed job due again at `run_at`.
+    fn retry<'a>(
+        &'a self,
+        id: u64,
+        run_at: SystemTime,
+        error: &'a str,
+    ) -> BoxFuture<'a, Result<(), String>>;
+
+    /// Sets aside a job that failed for good. Stores may keep it for inspection, but never
+    /// return it from [`claim`](JobStore::claim) again.
+    fn fail<'a>(&'a self, id: u64, error: &'a str) -> BoxFuture<'a, Result<(), String>>;
+
+    /// Records the run of scheduled task `name` due at `at`, returning `false` when it was
+    /// already recorded, i.e. another server runs it.
+    fn claim_run<'a>(
+        &'a self,
+        name: &'a str,
+        at: SystemTime,
+    ) -> BoxFuture<'a, Result<bool, String>>;
+}
+
+/// A [`JobStore`] in memory, for a single server. Jobs are lost when it stops, and failed
+/// ones are dropped once logged.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    state: Mutex<MemoryJobs>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryJobs {
+    next_id: u64,
+    /// Jobs by id, with the time they are due.
+    queued: BTreeMap<u64, (StoredJob, SystemTime)>,
+    runs: HashMap<String, SystemTime>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with<T>(&self, f: impl FnOnce(&mut MemoryJobs) -> T) -> BoxFuture<'static, Result<T, String>>
+    where
+        T: Send + 'static,
+    {
+        let out = f(&mut self.state.lock().unwrap_or_else(|e| e.into_inner()));
+        Box::pin(async move { Ok(out) })
+    }
+}
+
+impl JobStore for MemoryStore {
+    fn push<'a>(
+        &'a self,
+        name: &'a str,
+        payload: &'a [u8],
+        run_at: SystemTime,
+    ) -> BoxFuture<'a, Result<u64, String>> {
+        self.with(|jobs| {
+            jobs.next_id += 1;
+            let job = StoredJob {
+                id: jobs.next_id,
+                name: name.to_string(),
+                payload: payload.to_vec(),
+                attempts: 0,
+            };
+            jobs.queued.insert(job.id, (job, run_at));
+            jobs.next_id
+        })
+    }
+
+    fn claim(
+        &self,
+        now: SystemTime,
+        limit: usize,
+    ) -> BoxFuture<'_, Result<Vec<StoredJob>, String>> {
+        self.with(|jobs| {
+            let mut due: Vec<(SystemTime, u64)> = jobs
+                .queued
+                .iter()
+                .filter(|(_, (_, run_at))| *run_at <= now)
+                .map(|(id, (_, run_at))| (*run_at, *id))
+                .collect();
+            due.sort();
+            due.into_iter()
+                .take(limit)
+                .filter_map(|(_, id)| {
+                    let (job, run_at) = jobs.queued.get_mut(&id)?;
+                    job.attempts += 1;
+                    *run_at = now + LEASE;
+                    Some(job.clone())
+                })
+                .collect()
+        })
+    }
+
+    fn complete(&self, id: u64) -> BoxFuture<'_, Result<(), String>> {
+        self.with(|jobs| {
+            jobs.queued.remove(&id);
+        })
+    }
+
+    fn retry<'a>(
+        &'a self,
+        id: u64,
+        run_at: SystemTime,
+        _error: &'a str,
+    ) -> BoxFuture<'a, Result<(), String>> {
+        self.with(|jobs| {
+            if let Some((_, due)) = jobs.queued.get_mut(&id) {
+                *due = run_at;
+            }
+        })
+    }
+
+    fn fail<'a>(&'a self, id: u64, _error: &'a str) -> BoxFuture<'a, Result<(), String>> {
+        self.complete(id)
+    }
+
+    fn claim_run<'a>(
+        &'a self,
+        name: &'a str,
+        at: SystemTime,
+    ) -> BoxFuture<'a, Result<bool, String>> {
+        self.with(|jobs| match jobs.runs.get(name) {
+            Some(last) if *last >= at => false,
+            _ => {
+                jobs.runs.insert(name.to_string(), at);
+                true
+            }
+        })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{JobStore, LEASE, StoredJob};
+    use futures_util::future::BoxFuture;
+    use rusqlite::{Connection, OptionalExtension, params};
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    /// A [`JobStore`] in a SQLite database, which servers on the same machine can share.
+    /// Failed jobs stay in the `lithe_jobs` table with their `failed_at` and `last_error`.
+    #[derive(Clone)]
+    pub struct SqliteStore {
+        conn: Arc<Mutex<Connection>>,
+    }
+
+    impl SqliteStore {
+        /// Opens or creates the database at `path`, with the tables the store needs.
+        pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+            let conn = Connection::open(path).map_err(|e| e.to_string())?;
+            conn.busy_timeout(Duration::from_secs(5))
+                .map_err(|e| e.to_string())?;
+            conn.pragma_update(None, "journal_mode", "WAL")
+                .map_err(|e| e.to_string())?;
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS lithe_jobs (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    payload BLOB NOT NULL,
+                    attempts INTEGER NOT NULL DEFAULT 0,
+                    run_at INTEGER NOT NULL,
+                    failed_at INTEGER,
+                    last_error TEXT
+                );
+                CREATE INDEX IF NOT EXISTS lithe_jobs_due ON lithe_jobs (run_at)
+                    WHERE failed_at IS NULL;
+                CREATE TABLE IF NOT EXISTS lithe_schedule_runs (
+                    name TEXT PRIMARY KEY,
+                    last_run INTEGER NOT NULL
+                );",
+            )
+            .map_err(|e| e.to_string())?;
+            Ok(Self {
+                conn: Arc::new(Mutex::new(conn)),
+            })
+        }
+
+        /// Runs `f` on the connection, off the async runtime's threads.
+        fn with<T, F>(&self, f: F) -> BoxFuture<'static, Result<T, String>>
+        where
+            T: Send + 'static,
+            F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+        {
+            let conn = self.conn.clone();
+            Box::pin(async move {
+                tokio::task::spawn_blocking(move || {
+                    let mut conn = conn.lock().unwrap_or_else(|e| e.into_inner());
+                    f(&mut conn).map_err(|e| e.to_string())
+                })
+                .await
+                .map_err(|e| e.to_string())?
+            })
+        }
+    }
+
+    /// Milliseconds since the Unix epoch, as stored in the tables.
+    fn millis(time: SystemTime) -> i64 {
+        time.duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as i64)
+    }
+
+    impl JobStore for SqliteStore {
+        fn push<'a>(
+            &'a self,
+            name: &'a str,
+            payload: &'a [u8],
+            run_at: SystemTime,
+        ) -> BoxFuture<'a, Result<u64, String>> {
+            let (name, payload) = (name.to_string(), payload.to_vec());
+            self.with(move |conn| {
+                conn.execute(
+                    "INSERT INTO lithe_jobs (name, payload, run_at) VALUES (?1, ?2, ?3)",
+                    params![name, payload, millis(run_at)],
+                )?;
+                Ok(conn.last_insert_rowid() as u64)
+            })
+        }
+
+        fn claim(
+            &self,
+            now: SystemTime,
+            limit: usize,
+        ) -> BoxFuture<'_, Result<Vec<StoredJob>, String>> {
+            self.with(move |conn| {
+                // Immediate, so two servers can't read the same due jobs before either leases them.
+                let tx =
+                    conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+                let jobs = tx
+                    .prepare(
+                        "SELECT id, name, payload, attempts FROM lithe_jobs
+                         WHERE failed_at IS NULL AND run_at <= ?1
+                         ORDER BY run_at, id LIMIT ?2",
+                    )?
+                    .query_map(params![millis(now), limit as i64], |row| {
+                        Ok(StoredJob {
+                            id: row.get::<_, i64>(0)? as u64,
+                            name: row.get(1)?,
+                            payload: row.get(2)?,
+                            attempts: row.get::<_, u32>(3)? + 1,
+                        })
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                for job in &jobs {
+                    tx.execute(
+                        "UPDATE lithe_jobs SET attempts = ?2, run_at = ?3 WHERE id = ?1",
+                        params![job.id as i64, job.attempts, millis(now + LEASE)],
+                    )?;
+                }
+                tx.commit()?;
+                Ok(jobs)
+            })
+        }
+
+        fn complete(&self, id: u64) -> BoxFuture<'_, Result<(), String>> {
+            self.with(move |conn| {
+                conn.execute("DELETE FROM lithe_jobs WHERE id = ?1", [id as i64])?;
+                Ok(())
+            })
+        }
+
+        fn retry<'a>(
+            &'a self,
+            id: u64,
+            run_at: SystemTime,
+            error: &'a str,
+        ) -> BoxFuture<'a, Result<(), String>> {
+            let error = error.to_string();
+            self.with(move |conn| {
+                conn.execute(
+                    "UPDATE lithe_jobs SET run_at = ?2, last_error = ?3 WHERE id = ?1",
+                    params![id as i64, millis(run_at), error],
+                )?;
+                Ok(())
+            })
+        }
+
+        fn fail<'a>(&'a self, id: u64, error: &'a str) -> BoxFuture<'a, Result<(), String>> {
+            let error = error.to_string();
+            self.with(move |conn| {
+                conn.execute(
+                    "UPDATE lithe_jobs SET failed_at = ?2, last_error = ?3 WHERE id = ?1",
+                    params![id as i64, millis(SystemTime::now()), error],
+                )?;
+                Ok(())
+            })
+        }
+
+        fn claim_run<'a>(
+            &'a self,
+            name: &'a str,
+            at: SystemTime,
+        ) -> BoxFuture<'a, Result<bool, String>> {
+            let name = name.to_string();
+            self.with(move |conn| {
+                let claimed = conn
+                    .query_row(
+                        "INSERT INTO lithe_schedule_runs (name, last_run) VALUES (?1, ?2)
+                         ON CONFLICT (name) DO UPDATE SET last_run = excluded.last_run
+                         WHERE last_run < excluded.last_run
+                         RETURNING name",
+                        params![name, millis(at)],
+                        |_| Ok(()),
+                    )
+                    .optional()?;
+                Ok(claimed.is_some())
+            })
+        }
+    }
+}
+
+static STORE: OnceLock<RwLock<Arc<dyn JobStore>>> = OnceLock::new();
+
+fn job_store() -> Arc<dyn JobStore> {
+    STORE
+        .get_or_init(|| RwLock::new(Arc::new(MemoryStore::new())))
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// Replaces the store jobs wait in, e.g. in `AppState::new`, which runs before jobs start.
+pub fn set_job_store(store: impl JobStore + 'static) {
+    let lock = STORE.get_or_init(|| RwLock::new(Arc::new(MemoryStore::new())));
+    *lock.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(store);
+}
+
+/// Wakes the runner when a job is enqueued or finishes, rather than at its next poll.
+fn wake() -> &'static Notify {
+    static WAKE: OnceLock<Notify> = OnceLock::new();
+    WAKE.get_or_init(Notify::new)
+}
+
+/// Enqueues job `name` to run as soon as possible with `args`, its arguments as a tuple (or
+/// the one argument itself). `enqueue_{name}` functions call this.
+pub async fn enqueue<T: Serialize>(name: &str, args: &T) -> Result<u64, String> {
+    enqueue_at(name, args, SystemTime::now()).await
+}
+
+/// Enqueues job `name` to run at `run_at`, e.g. `SystemTime::now() + Duration::from_secs(3600)`.
+pub async fn enqueue_at<T: Serialize>(
+    name: &str,
+    args: &T,
+    run_at: SystemTime,
+) -> Result<u64, String> {
+    let payload = serde_json::to_vec(args).map_err(|e| e.to_string())?;
+    let id = job_store().push(name, &payload, run_at).await?;
+    wake().notify_one();
+    Ok(id)
+}
+
+/// How many jobs run at once on each server.
+const CONCURRENCY: usize = 8;
+
+/// How often the store is checked for jobs that became due, e.g. retries and jobs enqueued
+/// by other servers.
+const POLL: Duration = Duration::from_secs(1);
+
+/// Starts running `jobs` as they are enqueued, and each of `scheduled` on its schedule. The
+/// generated server calls it once its state is set up.
+pub fn start(jobs: &'static [Job], scheduled: &'static [Scheduled]) {
+    if !jobs.is_empty() {
+        tokio::spawn(run_jobs(jobs));
+    }
+    for task in scheduled {
+        tokio::spawn(run_schedule(*task));
+    }
+}
+
+async fn run_jobs(jobs: &'static [Job]) {
+    let slots = Arc::new(Semaphore::new(CONCURRENCY));
+    loop {
+        let free = slots.available_permits();
+        if free > 0 {
+            match job_store().claim(SystemTime::now(), free).await {
+                Ok(claimed) => {
+                    for stored in claimed {
+                        let Ok(slot) = slots.clone().try_acquire_owned() else {
+                            break;
+                        };
+                        tokio::spawn(async move {
+                            run_job(jobs, stored).await;
+                            drop(slot);
+                            wake().notify_one();
+                        });
+                    }
+                }
+                Err(e) => tracing::error!(error = %e, "claiming jobs failed"),
+            }
+        }
+        let _ = tokio::time::timeout(POLL, wake().notified()).await;
+    }
+}
+
+async fn run_job(jobs: &[Job], stored: StoredJob) {
+    let span = tracing::info_span!(
+        "job",
+        name = %stored.name,
+        id = stored.id,
+        attempt = stored.attempts
+    );
+    let store = job_store();
+    let Some(job) = jobs.iter().find(|job| job.name == stored.name) else {
+        // Enqueued by another version of the app, or for a job since renamed.
+        let error = format!("no job is named `{}`", stored.name);
+        span.in_scope(|| tracing::error!(%error, "job failed"));
+        if let Err(e) = store.fail(stored.id, &error).await {
+            span.in_scope(|| tracing::error!(error = %e, "recording the failure failed"));
+        }
+        return;
+    };
+
+    let start = Instant::now();
+    let outcome = run_caught((job.run)(&stored.payload))
+        .instrument(span.clone())
+        .await;
+    crate::telemetry::record_job(job.name, &outcome, start.elapsed());
+    let recorded = match outcome {
+        Ok(()) => {
+            span.in_scope(|| tracing::info!("job finished"));
+            store.complete(stored.id).await
+        }
+        Err(error) if stored.attempts <= job.retries => {
+            let delay = job
+                .backoff
+                .saturating_mul(1 << (stored.attempts - 1).min(16));
+            span.in_scope(|| {
+                tracing::warn!(error = %error, retry_in_ms = delay.as_millis() as u64, "job failed")
+            });
+            store
+                .retry(stored.id, SystemTime::now() + delay, &error)
+                .await
+        }
+        Err(error) => {
+            span.in_scope(|| tracing::error!(error = %error, "job failed for good"));
+            store.fail(stored.id, &error).await
+        }
+    };
+    if let Err(e) = recorded {
+        span.in_scope(|| tracing::error!(error = %e, "recording the outcome failed"));
+    }
+}
+
+/// Runs a job or task, turning a panic into an error.
+async fn run_caught(run: JobFuture) -> Result<(), String> {
+    AssertUnwindSafe(run)
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(format!("panicked: {}", message))
+        })
+}
+
+async fn run_schedule(task: Scheduled) {
+    let span = tracing::info_span!("scheduled", name = task.name, cron = task.cron);
+    let schedule = match Schedule::parse(task.cron) {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            span.in_scope(|| tracing::error!(error = %e, "invalid schedule"));
+            return;
+        }
+    };
+    let mut last = 0;
+    loop {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let Some(next) = schedule.next_after(now.as_secs().max(last)) else {
+            span.in_scope(|| tracing::warn!("schedule never fires again"));
+            return;
+        };
+        last = next;
+        tokio::time::sleep(Duration::from_secs(next).saturating_sub(now)).await;
+        let at = UNIX_EPOCH + Duration::from_secs(next);
+
+        // Every server with the task wakes up; the store lets one of them run it.
+        match job_store().claim_run(task.name, at).await {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                span.in_scope(|| tracing::error!(error = %e, "claiming the run failed"));
+                continue;
+            }
+        }
+        // Runs don't overlap: one still going when the next is due makes it skip.
+        let start = Instant::now();
+        let outcome = run_caught((task.run)()).instrument(span.clone()).await;
+        crate::telemetry::record_job(task.name, &outcome, start.elapsed());
+        match outcome {
+            Ok(()) => span.in_scope(|| tracing::info!("scheduled run finished")),
+            Err(error) => span.in_scope(|| tracing::error!(error = %error, "scheduled run failed")),
+        }
+    }
+}
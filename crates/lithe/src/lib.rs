@@ -2,23 +2,82 @@ pub mod core {
     pub use lithe_core::*;
 }
 pub mod browser;
+#[cfg(all(not(target_arch = "wasm32"), feature = "native-client"))]
+pub mod client;
+pub mod files;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod jobs;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod socket;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod telemetry;
 
+pub use futures_util::{Stream, StreamExt, future, stream};
 pub use lithe_core::*;
-pub use lithe_macros::{client, page, server};
+pub use lithe_macros::{client, job, page, scheduled, server, socket};
 
+/// See [`lithe_core::validate`]; also exports the `Validate` derive.
+pub mod validate {
+    pub use lithe_core::validate::*;
+    pub use lithe_macros::Validate;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use tracing;
 #[cfg(target_arch = "wasm32")]
 pub use wasm_bindgen;
 #[cfg(target_arch = "wasm32")]
 pub use wasm_bindgen_futures;
 
+/// Path of the RPC endpoint relative to the app's base path.
+pub const RPC_ENDPOINT: &str = "/api/lithe-rpc";
+/// Path of the endpoint streaming server functions are called on, relative to the app's base path.
+pub const RPC_STREAM_ENDPOINT: &str = "/api/lithe-rpc/stream";
+/// Path of the endpoint batched server function calls are sent to, relative to the app's base path.
+pub const RPC_BATCH_ENDPOINT: &str = "/api/lithe-rpc/batch";
+/// Prefix of the per-function RPC paths, `{RPC_FUNCTION_ENDPOINT}/{id}`, relative to the app's
+/// base path. These are what `.lithe/openapi.json` documents.
+pub const RPC_FUNCTION_ENDPOINT: &str = "/api/lithe-rpc/fn";
+/// Prefix of the WebSocket paths of `#[socket]` functions, `{SOCKET_ENDPOINT}/{id}`, relative
+/// to the app's base path.
+pub const SOCKET_ENDPOINT: &str = "/api/lithe-socket";
+/// Path of the endpoint that starts a preview session, relative to the app's base path.
+pub const PREVIEW_ENDPOINT: &str = "/api/lithe-preview";
+/// Path of the endpoint that ends a preview session, relative to the app's base path.
+pub const PREVIEW_EXIT_ENDPOINT: &str = "/api/lithe-preview/exit";
+
 pub fn render_page<C: Component + 'static>(comp: C, app_name: &str, id_map_json: &str) -> String {
     let mut s = render_to_string(&comp);
 
+    // Pages that render a fragment get the default document shell unless `layout = false`.
+    if use_render_context().meta.layout && !s.contains("<html") {
+        s = HtmlPage::new("", s).render_to_string();
+    }
+
     if s.contains("</head>") {
+        let RenderContext {
+            base_path,
+            build_id,
+            encoding,
+            csrf_token,
+            ..
+        } = use_render_context();
+        #[cfg(not(target_arch = "wasm32"))]
+        let request_id = telemetry::current_request_id().unwrap_or_default();
+        #[cfg(target_arch = "wasm32")]
+        let request_id = String::new();
         let script = format!(
             r#"    <script type="module">
                 window.Lithe = {{
                     id_map: {id_map_json},
+                    base_path: "{base_path}",
+                    build_id: "{build_id}",
+                    encoding: "{encoding}",
+                    csrf_token: "{csrf_token}",
+                    request_id: "{request_id}",
+                    rpc_endpoint: "{base_path}{rpc_endpoint}",
                     dispatch: (name) => {{
                         if (!window.wasm_module) {{
                             console.warn('WASM module not initialized');
@@ -40,15 +99,21 @@ pub fn render_page<C: Component + 'static>(comp: C, app_name: &str, id_map_json:
                         console.warn('WASM function not found:', name, ' (mapped to ' + mapped + ')');
                     }}
                 }};
-                import init, * as exports from '/public/pkg/{app_name}.js';
+                import init, * as exports from '{base_path}/public/pkg/{app_name}.js';
                 init().then(() => {{
                     window.wasm_module = exports;
                 }});
             </script>"#,
             id_map_json = id_map_json,
-            app_name = app_name
+            app_name = app_name,
+            base_path = base_path,
+            encoding = encoding.name(),
+            rpc_endpoint = RPC_ENDPOINT
         );
         s = s.replace("</head>", &format!("{}\n</head>", script));
     }
     s
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests;
@@ -0,0 +1,563 @@
+//! Server-side context for `#[server]` functions: the incoming request, the response
+//! headers, cookies and status the function wants to send back, and shared app state.
+
+use futures_util::future::{BoxFuture, join_all};
+use futures_util::stream::{BoxStream, Stream, StreamExt};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri, header};
+use lithe_core::PageRequest;
+use lithe_core::csrf;
+use lithe_core::rpc::{
+    BatchCall, BatchReply, Encodings, Payload, RateLimit, RateLimitKey, RpcReply, RpcResponse,
+    ServerFnError, StreamFormat,
+};
+use serde::Serialize;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+/// The HTTP request that invoked the current server function.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    /// Address of the connecting client; behind a proxy this is the proxy's address.
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl RequestContext {
+    /// Returns the named header if it is present and valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Returns the value of the named cookie, if the request carried it.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find_map(|h| lithe_core::context::parse_cookie(h, name))
+    }
+}
+
+/// Response changes requested by a server function while it ran.
+#[derive(Debug, Default)]
+pub struct ResponseParts {
+    pub status: Option<StatusCode>,
+    pub headers: HeaderMap,
+}
+
+struct Scope {
+    request: Arc<RequestContext>,
+    response: RefCell<ResponseParts>,
+}
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+/// Runs `f` with `request` as the current request and returns what it produced, along with
+/// the response parts it set.
+pub async fn scope<F: Future>(request: RequestContext, f: F) -> (F::Output, ResponseParts) {
+    let scope = Scope {
+        request: Arc::new(request),
+        response: RefCell::default(),
+    };
+    SCOPE
+        .scope(scope, async move {
+            let output = f.await;
+            let parts = SCOPE.with(|s| s.response.take());
+            (output, parts)
+        })
+        .await
+}
+
+/// Returns the request being handled.
+///
+/// # Panics
+///
+/// Panics when called outside of a server function invoked over RPC.
+pub fn use_request() -> Arc<RequestContext> {
+    try_use_request().expect("use_request() called outside of a server function")
+}
+
+/// Like [`use_request`], but returns `None` outside of a server function.
+pub fn try_use_request() -> Option<Arc<RequestContext>> {
+    SCOPE.try_with(|s| s.request.clone()).ok()
+}
+
+/// Sets the HTTP status of the RPC response, overriding the one Lithe would send. Fails on an
+/// invalid status, or outside of a server function, with an error of the function's own type
+/// so `?` works whatever its `E`.
+pub fn set_status<E>(status: u16) -> Result<(), ServerFnError<E>> {
+    let status = StatusCode::from_u16(status)
+        .map_err(|_| ServerFnError::Server(format!("invalid HTTP status code {}", status)))?;
+    with_response(|r| r.status = Some(status))
+}
+
+/// Appends a response header. Fails on an invalid name or value, e.g. one with a line break,
+/// or outside of a server function.
+pub fn set_header<E>(name: &str, value: &str) -> Result<(), ServerFnError<E>> {
+    let name = HeaderName::try_from(name)
+        .map_err(|_| ServerFnError::Server(format!("invalid header name {:?}", name)))?;
+    let value = HeaderValue::try_from(value)
+        .map_err(|_| ServerFnError::Server(format!("invalid value for header {}", name)))?;
+    with_response(|r| {
+        r.headers.append(name, value);
+    })
+}
+
+/// Adds a `Set-Cookie` header, e.g. `set_cookie("session=abc; Path=/; HttpOnly")`. Fails like
+/// [`set_header`].
+pub fn set_cookie<E>(cookie: &str) -> Result<(), ServerFnError<E>> {
+    set_header(header::SET_COOKIE.as_str(), cookie)
+}
+
+fn with_response<E>(f: impl FnOnce(&mut ResponseParts)) -> Result<(), ServerFnError<E>> {
+    SCOPE
+        .try_with(|s| f(&mut s.response.borrow_mut()))
+        .map_err(|_| {
+            ServerFnError::Server(
+                "the response can only be modified inside a server function".to_string(),
+            )
+        })
+}
+
+/// Rejects calls a browser made on behalf of another site, see [`lithe_core::csrf`].
+pub fn check_csrf(request: &RequestContext, trusted_origins: &[&str]) -> Result<(), String> {
+    csrf::check(&call_origin(request), trusted_origins)
+}
+
+/// Rejects WebSocket handshakes another site's pages started, see
+/// [`lithe_core::csrf::check_handshake`].
+pub fn check_socket_origin(
+    request: &RequestContext,
+    trusted_origins: &[&str],
+) -> Result<(), String> {
+    csrf::check_handshake(&call_origin(request), trusted_origins)
+}
+
+fn call_origin(request: &RequestContext) -> csrf::CallOrigin<'_> {
+    csrf::CallOrigin {
+        host: request
+            .header("x-forwarded-host")
+            .or_else(|| request.header(header::HOST.as_str()))
+            .or_else(|| request.uri.authority().map(|a| a.as_str())),
+        origin: request.header(header::ORIGIN.as_str()),
+        fetch_site: request.header("sec-fetch-site"),
+        cookie_token: request.cookie(csrf::CSRF_COOKIE),
+        header_token: request.header(csrf::CSRF_HEADER),
+    }
+}
+
+/// Where the preview endpoints send the browser afterwards: `redirect` under `base_path` if it
+/// is a path on this site, else the app's root. Anything with a scheme, a host, `\` or control
+/// characters is refused: browsers read `//evil.com` and `/\evil.com` as another site, which
+/// would make the endpoints an open redirect.
+pub fn preview_redirect(redirect: Option<&str>, base_path: &str) -> String {
+    let path = redirect.filter(|r| {
+        r.starts_with('/')
+            && !r.starts_with("//")
+            && !r.contains('\\')
+            && !r.chars().any(char::is_control)
+            && r.parse::<Uri>()
+                .is_ok_and(|uri| uri.scheme().is_none() && uri.authority().is_none())
+    });
+    match path {
+        Some(path) => format!("{}{}", base_path, path),
+        None if base_path.is_empty() => "/".to_string(),
+        None => base_path.to_string(),
+    }
+}
+
+/// Gives a page request its session's CSRF token: the one in its cookie, or a fresh one. Returns
+/// the `Set-Cookie` value storing a fresh token.
+pub fn start_csrf_session(req: &mut PageRequest) -> Option<String> {
+    if let Some(token) = req.cookie(csrf::CSRF_COOKIE).filter(|t| csrf::is_token(t)) {
+        req.csrf_token = Some(token.to_string());
+        return None;
+    }
+    let token = csrf::new_token();
+    let cookie = csrf::token_cookie(&token);
+    req.csrf_token = Some(token);
+    Some(cookie)
+}
+
+/// How many calls a client made in the current rate limit window, as counted by a
+/// [`RateLimitStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hit {
+    /// Calls in the window so far, including this one.
+    pub count: u32,
+    /// Time left until the window ends and the count starts over.
+    pub reset: Duration,
+}
+
+/// Counts calls for `#[server(rate_limit = "...")]`. The default [`MemoryStore`] counts per
+/// process; servers running several instances can share counts through e.g. Redis with
+/// [`set_rate_limit_store`].
+pub trait RateLimitStore: Send + Sync {
+    /// Counts a call for `key` in a window of `window`, starting the window if there is none.
+    fn hit<'a>(&'a self, key: &'a str, window: Duration) -> BoxFuture<'a, Result<Hit, String>>;
+}
+
+/// A [`RateLimitStore`] keeping fixed windows in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    windows: Mutex<MemoryWindows>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryWindows {
+    counts: HashMap<String, (u32, Instant)>,
+    pruned: Option<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn count(&self, key: &str, window: Duration) -> Hit {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        // Ended windows are dropped now and then, so clients that went away don't pile up.
+        if windows
+            .pruned
+            .is_none_or(|at| now - at > Duration::from_secs(60))
+        {
+            windows.counts.retain(|_, (_, end)| *end > now);
+            windows.pruned = Some(now);
+        }
+        let (count, end) = match windows.counts.get_mut(key) {
+            Some((count, end)) if *end > now => {
+                *count = count.saturating_add(1);
+                (*count, *end)
+            }
+            _ => {
+                windows.counts.insert(key.to_string(), (1, now + window));
+                (1, now + window)
+            }
+        };
+        Hit {
+            count,
+            reset: end - now,
+        }
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn hit<'a>(&'a self, key: &'a str, window: Duration) -> BoxFuture<'a, Result<Hit, String>> {
+        let hit = self.count(key, window);
+        Box::pin(async move { Ok(hit) })
+    }
+}
+
+static RATE_LIMIT_STORE: OnceLock<RwLock<Arc<dyn RateLimitStore>>> = OnceLock::new();
+
+fn rate_limit_store() -> &'static RwLock<Arc<dyn RateLimitStore>> {
+    RATE_LIMIT_STORE.get_or_init(|| RwLock::new(Arc::new(MemoryStore::new())))
+}
+
+/// Replaces the store counting calls for rate limits, e.g. in `AppState::new`.
+pub fn set_rate_limit_store(store: impl RateLimitStore + 'static) {
+    *rate_limit_store()
+        .write()
+        .unwrap_or_else(|e| e.into_inner()) = Arc::new(store);
+}
+
+/// Counts a call to `function` against `limit` and rejects it with
+/// [`ServerFnError::RateLimited`] and a `Retry-After` header once the client is over it.
+///
+/// Calls are let through when the store fails, so an outage of a shared store doesn't take
+/// the app down with it.
+pub async fn check_rate_limit<E>(function: &str, limit: RateLimit) -> Result<(), ServerFnError<E>> {
+    let request = use_request();
+    let ip = request.remote_addr.map(|addr| addr.ip().to_string());
+    let client = match limit.key {
+        RateLimitKey::Session => request
+            .cookie(csrf::CSRF_COOKIE)
+            .filter(|t| csrf::was_issued(t))
+            .map(|token| format!("session:{}", token))
+            .or_else(|| ip.map(|ip| format!("ip:{}", ip))),
+        RateLimitKey::Ip => ip.map(|ip| format!("ip:{}", ip)),
+    };
+    let key = format!("{}|{}", function, client.as_deref().unwrap_or("unknown"));
+    let store = rate_limit_store()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+    let hit = match store.hit(&key, limit.window).await {
+        Ok(hit) => hit,
+        Err(e) => {
+            tracing::error!(function, error = %e, "rate limit store failed");
+            return Ok(());
+        }
+    };
+    if hit.count <= limit.max {
+        return Ok(());
+    }
+    let retry_after = retry_after_secs(hit.reset);
+    // Can't fail: the header is valid and `use_request` already found the scope.
+    let _ = set_header::<E>(header::RETRY_AFTER.as_str(), &retry_after.to_string());
+    Err(ServerFnError::RateLimited(format!(
+        "at most {} calls to {} per {}s, retry in {}s",
+        limit.max,
+        function,
+        limit.window.as_secs(),
+        retry_after
+    )))
+}
+
+/// Whole seconds until `reset`, rounded up so clients don't retry before the window ended.
+pub(crate) fn retry_after_secs(reset: Duration) -> u64 {
+    reset.as_secs() + u64::from(reset.subsec_nanos() > 0)
+}
+
+/// Files uploaded with a call, by the name of their multipart part.
+#[derive(Debug, Default)]
+pub struct Uploads(HashMap<String, Vec<u8>>);
+
+tokio::task_local! {
+    static UPLOADS: RefCell<Uploads>;
+}
+
+/// Whether `content_type` is `multipart/form-data`, i.e. the call carries files and its
+/// arguments' encoding is only known once [`read_uploads`] read them.
+pub fn is_multipart(content_type: &str) -> bool {
+    content_type
+        .trim_start()
+        .get(..19)
+        .is_some_and(|t| t.eq_ignore_ascii_case("multipart/form-data"))
+}
+
+/// Splits a `multipart/form-data` call into its encoded arguments and the files uploaded with
+/// them, and negotiates the arguments' encoding from their part's `Content-Type`. Other calls
+/// are returned as they are, with `encodings`. Only call it once the call passed its checks, as
+/// it reads the whole body.
+pub async fn read_uploads(
+    request: &RequestContext,
+    encodings: Encodings,
+    body: Vec<u8>,
+) -> Result<(Vec<u8>, Encodings, Uploads), String> {
+    let Some(boundary) = request
+        .header(header::CONTENT_TYPE.as_str())
+        .filter(|ct| is_multipart(ct))
+        .and_then(|ct| multer::parse_boundary(ct).ok())
+    else {
+        return Ok((body, encodings, Uploads::default()));
+    };
+    let body = futures_util::stream::once(async move { Ok::<_, Infallible>(body) });
+    let mut multipart = multer::Multipart::new(body, boundary);
+    let mut args = None;
+    let mut files = HashMap::new();
+    while let Some(field) = multipart.next_field().await.map_err(|e| e.to_string())? {
+        let name = field.name().unwrap_or_default().to_string();
+        let content_type = field.content_type().map(|m| m.to_string());
+        let bytes = field.bytes().await.map_err(|e| e.to_string())?.to_vec();
+        if name == crate::files::ARGS_PART {
+            args = Some((bytes, content_type));
+        } else {
+            files.insert(name, bytes);
+        }
+    }
+    let (args, content_type) =
+        args.ok_or_else(|| format!("multipart call has no `{}` part", crate::files::ARGS_PART))?;
+    let encodings = Encodings::negotiate(
+        content_type.as_deref(),
+        request.header(header::ACCEPT.as_str()),
+    )
+    .ok_or_else(|| {
+        format!(
+            "unsupported content type of the `{}` part",
+            crate::files::ARGS_PART
+        )
+    })?;
+    Ok((args, encodings, Uploads(files)))
+}
+
+/// Runs `f` with `uploads` available to the [`UploadedFile`](crate::files::UploadedFile)s
+/// decoded in it.
+pub async fn with_uploads<F: Future>(uploads: Uploads, f: F) -> F::Output {
+    UPLOADS.scope(RefCell::new(uploads), f).await
+}
+
+/// Takes the bytes uploaded in `part`; each part can be taken once.
+pub(crate) fn take_upload(part: &str) -> Option<Vec<u8>> {
+    UPLOADS
+        .try_with(|uploads| uploads.borrow_mut().0.remove(part))
+        .ok()
+        .flatten()
+}
+
+/// Encodes the result of a function returning a [`Download`](crate::files::Download): the
+/// file's bytes under its own `Content-Type`, or the error as usual.
+pub fn download_reply<E: Serialize>(
+    encoding: lithe_core::rpc::Encoding,
+    result: Result<crate::files::Download, ServerFnError<E>>,
+) -> RpcReply {
+    let download = match result {
+        Ok(download) => download,
+        Err(e) => return RpcReply::error(encoding, e),
+    };
+    let content_type = HeaderValue::try_from(download.content_type())
+        .unwrap_or(HeaderValue::from_static("application/octet-stream"));
+    let disposition = download
+        .file_name()
+        .and_then(|name| HeaderValue::try_from(lithe_core::rpc::content_disposition(name)).ok());
+    let headers = with_response::<E>(|r| {
+        r.headers.insert(header::CONTENT_TYPE, content_type);
+        if let Some(disposition) = disposition {
+            r.headers.insert(header::CONTENT_DISPOSITION, disposition);
+        }
+    });
+    if let Err(e) = headers {
+        return RpcReply::error(encoding, e);
+    }
+    RpcReply {
+        status: 200,
+        encoding,
+        body: download.into_bytes(),
+    }
+}
+
+/// Runs the calls of a batch concurrently, each in its own [`scope`], and encodes their replies
+/// in `encodings.response`.
+///
+/// Headers set by any call are added to the returned parts. A status set by a call only
+/// applies to that call's reply.
+pub async fn batch<F, Fut>(
+    request: RequestContext,
+    encodings: Encodings,
+    body: &[u8],
+    dispatch: F,
+) -> (RpcReply, ResponseParts)
+where
+    F: Fn(BatchCall) -> Fut,
+    Fut: Future<Output = RpcReply>,
+{
+    let calls = match encodings.request.decode::<Vec<BatchCall>>(body) {
+        Ok(calls) => calls,
+        Err(e) => {
+            let reply = RpcReply::error(encodings.response, ServerFnError::<()>::Args(e));
+            return (reply, ResponseParts::default());
+        }
+    };
+    let results = join_all(
+        calls
+            .into_iter()
+            .map(|call| scope(request.clone(), dispatch(call))),
+    )
+    .await;
+
+    let mut parts = ResponseParts::default();
+    let mut replies = Vec::with_capacity(results.len());
+    for (reply, call_parts) in results {
+        for (name, value) in &call_parts.headers {
+            parts.headers.append(name, value.clone());
+        }
+        replies.push(BatchReply {
+            status: call_parts.status.map_or(reply.status, |s| s.as_u16()),
+            body: Payload(reply.body),
+        });
+    }
+    let reply = match encodings.response.encode(&replies) {
+        Ok(body) => RpcReply {
+            status: 200,
+            encoding: encodings.response,
+            body,
+        },
+        Err(e) => RpcReply::error(
+            encodings.response,
+            ServerFnError::<()>::Server(format!("failed to serialize batch: {}", e)),
+        ),
+    };
+    (reply, parts)
+}
+
+/// Items of a streaming server function, each encoded as a JSON [`RpcResponse`] envelope.
+///
+/// The function runs inside [`scope`], but the stream is polled after it returns, so anything
+/// the stream needs from [`use_request`] must be read up front.
+pub struct RpcStream(BoxStream<'static, Vec<u8>>);
+
+impl RpcStream {
+    pub fn new<S>(items: S) -> Self
+    where
+        S: Stream + Send + 'static,
+        S::Item: Serialize,
+    {
+        RpcStream(
+            items
+                .map(|item| {
+                    serde_json::to_vec(&RpcResponse::<_, ()>::Ok(item)).unwrap_or_else(|e| {
+                        let err = ServerFnError::<()>::Server(format!(
+                            "failed to serialize stream item: {}",
+                            e
+                        ));
+                        serde_json::to_vec(&RpcResponse::<(), ()>::Error(err))
+                            .expect("string errors always serialize")
+                    })
+                })
+                .boxed(),
+        )
+    }
+
+    /// The response body: one frame per item. Dropping it, e.g. when the client disconnects,
+    /// drops the function's stream.
+    pub fn into_frames(
+        self,
+        format: StreamFormat,
+    ) -> impl Stream<Item = Result<Vec<u8>, Infallible>> + Send {
+        self.0.map(move |json| Ok(format.frame(&json)))
+    }
+}
+
+type StateMap = HashMap<TypeId, Arc<dyn Any + Send + Sync>>;
+
+static STATE: OnceLock<RwLock<StateMap>> = OnceLock::new();
+
+/// Registers `state` so that server functions and pages can reach it with [`use_state`].
+///
+/// The generated `main.rs` calls this with the project's `AppState` at startup and passes the
+/// returned handle to the router's `with_state`.
+pub fn provide_state<T: Send + Sync + 'static>(state: T) -> Arc<T> {
+    let state = Arc::new(state);
+    STATE
+        .get_or_init(RwLock::default)
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(TypeId::of::<T>(), state.clone());
+    state
+}
+
+/// Returns the state of type `T` registered with [`provide_state`].
+///
+/// # Panics
+///
+/// Panics when no state of type `T` was provided.
+pub fn use_state<T: Send + Sync + 'static>() -> Arc<T> {
+    try_use_state().unwrap_or_else(|| {
+        panic!(
+            "use_state::<{}>() called but no such state was provided",
+            std::any::type_name::<T>()
+        )
+    })
+}
+
+/// Like [`use_state`], but returns `None` when no state of type `T` was provided.
+pub fn try_use_state<T: Send + Sync + 'static>() -> Option<Arc<T>> {
+    let state = STATE
+        .get()?
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&TypeId::of::<T>())?
+        .clone();
+    state.downcast().ok()
+}
@@ -0,0 +1,134 @@
+//! Server side of `#[socket]` functions: typed messages over a WebSocket.
+//!
+//! The generated route accepts the connection and hands the function a [`Socket`]. Messages are
+//! encoded like RPC calls, in the encoding the client connected with.
+
+use crate::rpc::{Encoding, ServerFnError};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+use tokio::sync::mpsc;
+
+/// Messages buffered each way before the slower side holds the other back.
+const BUFFER: usize = 32;
+
+/// One end of a WebSocket connection, receiving `In` from the client and sending `Out` to it.
+pub struct Socket<In, Out> {
+    incoming: mpsc::Receiver<Vec<u8>>,
+    sender: SocketSender<Out>,
+    _in: PhantomData<fn() -> In>,
+}
+
+impl<In: DeserializeOwned, Out: Serialize> Socket<In, Out> {
+    #[doc(hidden)]
+    pub fn new(encoding: Encoding, io: SocketIo) -> Self {
+        Socket {
+            incoming: io.incoming,
+            sender: SocketSender {
+                encoding,
+                outgoing: io.outgoing,
+                _out: PhantomData,
+            },
+            _in: PhantomData,
+        }
+    }
+
+    /// The next message from the client, or `None` once it disconnected. Messages that don't
+    /// decode as `In` are logged and skipped.
+    pub async fn recv(&mut self) -> Option<In> {
+        loop {
+            let bytes = self.incoming.recv().await?;
+            match self.sender.encoding.decode(&bytes) {
+                Ok(message) => return Some(message),
+                Err(e) => {
+                    tracing::warn!(error = %e, "dropped a socket message that did not decode")
+                }
+            }
+        }
+    }
+
+    /// Sends a message to the client. Fails once the client disconnected.
+    pub async fn send(&self, message: &Out) -> Result<(), ServerFnError> {
+        self.sender.send(message).await
+    }
+
+    /// A handle for sending from other tasks, e.g. one forwarding broadcasts while this one
+    /// reads.
+    pub fn sender(&self) -> SocketSender<Out> {
+        self.sender.clone()
+    }
+
+    /// The encoding the client connected with.
+    pub fn encoding(&self) -> Encoding {
+        self.sender.encoding
+    }
+}
+
+/// Sends messages on a [`Socket`], see [`Socket::sender`].
+pub struct SocketSender<Out> {
+    encoding: Encoding,
+    outgoing: mpsc::Sender<Vec<u8>>,
+    _out: PhantomData<fn(Out)>,
+}
+
+impl<Out> Clone for SocketSender<Out> {
+    fn clone(&self) -> Self {
+        SocketSender {
+            encoding: self.encoding,
+            outgoing: self.outgoing.clone(),
+            _out: PhantomData,
+        }
+    }
+}
+
+impl<Out: Serialize> SocketSender<Out> {
+    /// Sends a message to the client, waiting while too many are still unsent. Fails once the
+    /// client disconnected.
+    pub async fn send(&self, message: &Out) -> Result<(), ServerFnError> {
+        let bytes = self
+            .encoding
+            .encode(message)
+            .map_err(ServerFnError::Server)?;
+        self.outgoing
+            .send(bytes)
+            .await
+            .map_err(|_| ServerFnError::Transport("the socket is closed".to_string()))
+    }
+
+    /// Whether the client disconnected.
+    pub fn is_closed(&self) -> bool {
+        self.outgoing.is_closed()
+    }
+}
+
+/// The function's side of a connection's message channels.
+#[doc(hidden)]
+pub struct SocketIo {
+    incoming: mpsc::Receiver<Vec<u8>>,
+    outgoing: mpsc::Sender<Vec<u8>>,
+}
+
+/// The route's side of a connection's message channels: it forwards the client's messages into
+/// `incoming` and sends what arrives on `outgoing`. Dropping it disconnects the function.
+#[doc(hidden)]
+pub struct SocketRemote {
+    pub incoming: mpsc::Sender<Vec<u8>>,
+    pub outgoing: mpsc::Receiver<Vec<u8>>,
+}
+
+/// Message channels between a `#[socket]` function and the route serving its connection.
+#[doc(hidden)]
+pub fn channel() -> (SocketIo, SocketRemote) {
+    let (incoming_tx, incoming_rx) = mpsc::channel(BUFFER);
+    let (outgoing_tx, outgoing_rx) = mpsc::channel(BUFFER);
+    (
+        SocketIo {
+            incoming: incoming_rx,
+            outgoing: outgoing_tx,
+        },
+        SocketRemote {
+            incoming: incoming_tx,
+            outgoing: outgoing_rx,
+        },
+    )
+}
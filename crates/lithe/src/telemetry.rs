@@ -0,0 +1,397 @@
+//! Logs, spans and metrics for the generated server.
+//!
+//! Every request runs in a `request` span carrying its method, path, route and request id, and
+//! every server function call in an `rpc` span inside it. Durations and outcomes are counted
+//! for Prometheus, which scrapes them from [`render_metrics`].
+
+use http::{HeaderValue, Method};
+use lithe_core::rpc::RpcReply;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+
+use crate::server::RpcStream;
+
+/// Header carrying the id of a request, both ways. Calls arriving with one keep it, so ids
+/// from a proxy or another service connect their logs with Lithe's.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// `Content-Type` of [`render_metrics`].
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// How log lines are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the current spans.
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber, filtered by `RUST_LOG` (`info` by default).
+/// `LITHE_LOG_FORMAT=json` or `text` overrides `format`.
+pub fn init(format: LogFormat) {
+    use tracing_subscriber::EnvFilter;
+
+    let format = std::env::var("LITHE_LOG_FORMAT")
+        .ok()
+        .and_then(|name| LogFormat::from_name(&name))
+        .unwrap_or(format);
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    // Fails only when the app installed a subscriber of its own, which then wins.
+    let _ = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+    };
+}
+
+struct RequestInfo {
+    id: String,
+    route: RefCell<Option<String>>,
+}
+
+tokio::task_local! {
+    static REQUEST: RequestInfo;
+}
+
+/// Runs `respond` as the handling of one request: in a span, timed and counted under its
+/// route, with its id added to the response. `request_id` is the [`REQUEST_ID_HEADER`] it
+/// arrived with, if any.
+///
+/// `matched` is the route pattern the router matched, e.g. `/api/lithe-rpc/batch`; handlers
+/// can name a more precise one with [`set_route`].
+pub async fn observe<B, F>(
+    method: &Method,
+    path: &str,
+    request_id: Option<&str>,
+    matched: Option<&str>,
+    respond: F,
+) -> http::Response<B>
+where
+    F: Future<Output = http::Response<B>>,
+{
+    let id = request_id
+        .filter(|id| is_request_id(id))
+        .map_or_else(new_request_id, str::to_string);
+    let span = tracing::info_span!(
+        "request",
+        method = %method,
+        path,
+        request_id = %id,
+        route = tracing::field::Empty,
+        status = tracing::field::Empty,
+    );
+    let info = RequestInfo {
+        id: id.clone(),
+        route: RefCell::new(None),
+    };
+    let start = Instant::now();
+    let (mut response, route) = REQUEST
+        .scope(info, async {
+            let response = respond.instrument(span.clone()).await;
+            (response, REQUEST.with(|r| r.route.take()))
+        })
+        .await;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let status = response.status().as_u16();
+    let route = route.or_else(|| matched.map(str::to_string));
+    let route = route.as_deref().unwrap_or("unmatched");
+    span.record("route", route);
+    span.record("status", status);
+    span.in_scope(|| {
+        if status >= 500 {
+            tracing::error!(elapsed_ms = elapsed * 1000.0, "request failed");
+        } else {
+            tracing::info!(elapsed_ms = elapsed * 1000.0, "request finished");
+        }
+    });
+    with_metrics(|m| {
+        m.count(
+            "lithe_http_requests_total",
+            &[("route", route), ("status", &status.to_string())],
+        );
+        m.observe(
+            "lithe_http_request_duration_seconds",
+            &[("route", route)],
+            elapsed,
+        );
+    });
+    if let Ok(id) = HeaderValue::try_from(id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, id);
+    }
+    response
+}
+
+/// Names the route of the current request in its span and metrics, e.g. the page that
+/// rendered. Does nothing outside of [`observe`].
+pub fn set_route(route: &str) {
+    let _ = REQUEST.try_with(|r| *r.route.borrow_mut() = Some(route.to_string()));
+}
+
+/// The id of the request being handled, also sent to the browser in `x-request-id` and in
+/// `window.Lithe.request_id`.
+pub fn current_request_id() -> Option<String> {
+    REQUEST.try_with(|r| r.id.clone()).ok()
+}
+
+fn is_request_id(id: &str) -> bool {
+    (1..=128).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+fn new_request_id() -> String {
+    let mut bytes = [0u8; 8];
+    getrandom::getrandom(&mut bytes).expect("the OS random number generator failed");
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// What a server function call produced, as far as its metrics are concerned.
+pub trait CallOutcome {
+    fn status(&self) -> u16;
+}
+
+impl CallOutcome for RpcReply {
+    fn status(&self) -> u16 {
+        self.status
+    }
+}
+
+impl CallOutcome for Result<RpcStream, RpcReply> {
+    fn status(&self) -> u16 {
+        self.as_ref().map_or_else(|reply| reply.status, |_| 200)
+    }
+}
+
+/// Runs the call of server function `function` in an `rpc` span, timed and counted. Streaming
+/// calls are timed until their stream starts.
+pub async fn observe_call<R: CallOutcome>(function: &str, call: impl Future<Output = R>) -> R {
+    let span = tracing::info_span!("rpc", function, status = tracing::field::Empty);
+    let start = Instant::now();
+    let outcome = call.instrument(span.clone()).await;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let status = outcome.status();
+    span.record("status", status);
+    if status >= 500 {
+        span.in_scope(|| tracing::error!("server function failed"));
+    } else if status >= 400 {
+        span.in_scope(|| tracing::warn!("server function rejected the call"));
+    }
+    // Replies are only 404 for ids no function has. Those come from the caller, so they share
+    // one label rather than adding one each.
+    let function = if status == 404 { "unknown" } else { function };
+    with_metrics(|m| {
+        m.count("lithe_rpc_calls_total", &[("function", function)]);
+        if status >= 400 {
+            m.count(
+                "lithe_rpc_errors_total",
+                &[("function", function), ("status", &status.to_string())],
+            );
+        }
+        m.observe(
+            "lithe_rpc_call_duration_seconds",
+            &[("function", function)],
+            elapsed,
+        );
+    });
+    outcome
+}
+
+/// Counts a run of a job or scheduled task.
+pub(crate) fn record_job(name: &str, outcome: &Result<(), String>, elapsed: Duration) {
+    let outcome = if outcome.is_ok() { "ok" } else { "error" };
+    with_metrics(|m| {
+        m.count("lithe_jobs_total", &[("job", name), ("outcome", outcome)]);
+        m.observe(
+            "lithe_job_duration_seconds",
+            &[("job", name)],
+            elapsed.as_secs_f64(),
+        );
+    });
+}
+
+/// Runs a connection to `#[socket]` function `socket` in a `socket` span, counting it and
+/// logging how it ended.
+pub async fn observe_socket(socket: &str, connection: impl Future<Output = Result<(), String>>) {
+    let span = tracing::info_span!("socket", socket);
+    span.in_scope(|| tracing::info!("socket opened"));
+    let outcome = connection.instrument(span.clone()).await;
+    span.in_scope(|| match &outcome {
+        Ok(()) => tracing::info!("socket closed"),
+        Err(e) => tracing::error!(error = %e, "socket function failed"),
+    });
+    let outcome = if outcome.is_ok() { "ok" } else { "error" };
+    with_metrics(|m| {
+        m.count(
+            "lithe_socket_connections_total",
+            &[("socket", socket), ("outcome", outcome)],
+        )
+    });
+}
+
+/// Upper bounds of the duration histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Name, type and help text of each metric, in the order they are rendered.
+const METRICS: &[(&str, &str, &str)] = &[
+    (
+        "lithe_http_requests_total",
+        "counter",
+        "HTTP requests handled, by route and status.",
+    ),
+    (
+        "lithe_http_request_duration_seconds",
+        "histogram",
+        "Time spent handling HTTP requests, by route.",
+    ),
+    (
+        "lithe_rpc_calls_total",
+        "counter",
+        "Server function calls, by function.",
+    ),
+    (
+        "lithe_rpc_errors_total",
+        "counter",
+        "Server function calls that failed, by function and status.",
+    ),
+    (
+        "lithe_rpc_call_duration_seconds",
+        "histogram",
+        "Time spent in server function calls, by function.",
+    ),
+    (
+        "lithe_jobs_total",
+        "counter",
+        "Runs of jobs and scheduled tasks, by job and outcome.",
+    ),
+    (
+        "lithe_job_duration_seconds",
+        "histogram",
+        "Time spent running jobs and scheduled tasks, by job.",
+    ),
+    (
+        "lithe_socket_connections_total",
+        "counter",
+        "Connections to socket functions, by socket and outcome.",
+    ),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Metrics {
+    counters: BTreeMap<(&'static str, Labels), u64>,
+    histograms: BTreeMap<(&'static str, Labels), Histogram>,
+}
+
+impl Metrics {
+    fn count(&mut self, name: &'static str, labels: &[(&'static str, &str)]) {
+        *self.counters.entry((name, owned(labels))).or_default() += 1;
+    }
+
+    fn observe(&mut self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        let histogram = self.histograms.entry((name, owned(labels))).or_default();
+        for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+}
+
+fn owned(labels: &[(&'static str, &str)]) -> Labels {
+    labels.iter().map(|&(k, v)| (k, v.to_string())).collect()
+}
+
+static REGISTRY: Mutex<Option<Metrics>> = Mutex::new(None);
+
+fn with_metrics<T>(f: impl FnOnce(&mut Metrics) -> T) -> T {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    f(registry.get_or_insert_with(Metrics::default))
+}
+
+/// The metrics collected so far, in the Prometheus text format, served on `metrics_path`.
+pub fn render_metrics() -> String {
+    with_metrics(|m| {
+        let mut out = String::new();
+        for &(name, kind, help) in METRICS {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            for ((_, labels), value) in m.counters.iter().filter(|((n, _), _)| *n == name) {
+                let _ = writeln!(out, "{}{} {}", name, label_set(labels, None), value);
+            }
+            for ((_, labels), h) in m.histograms.iter().filter(|((n, _), _)| *n == name) {
+                for (bound, count) in BUCKETS.iter().zip(h.buckets) {
+                    let le = bound.to_string();
+                    let _ = writeln!(
+                        out,
+                        "{}_bucket{} {}",
+                        name,
+                        label_set(labels, Some(&le)),
+                        count
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{} {}",
+                    name,
+                    label_set(labels, Some("+Inf")),
+                    h.count
+                );
+                let _ = writeln!(out, "{}_sum{} {}", name, label_set(labels, None), h.sum);
+                let _ = writeln!(out, "{}_count{} {}", name, label_set(labels, None), h.count);
+            }
+        }
+        out
+    })
+}
+
+/// `{a="1",b="2"}`, with `le` last for histogram buckets.
+fn label_set(labels: &Labels, le: Option<&str>) -> String {
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| (*k, v.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
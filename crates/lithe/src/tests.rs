@@ -0,0 +1,77 @@
+use crate::server::{MemoryStore, retry_after_secs};
+use std::time::Duration;
+
+#[test]
+fn test_memory_store_counts_per_key() {
+    let store = MemoryStore::new();
+    let window = Duration::from_secs(60);
+    assert_eq!(store.count("a", window).count, 1);
+    assert_eq!(store.count("a", window).count, 2);
+    assert_eq!(store.count("b", window).count, 1);
+
+    let hit = store.count("a", window);
+    assert_eq!(hit.count, 3);
+    assert!(hit.reset <= window && hit.reset > Duration::from_secs(59));
+}
+
+#[test]
+fn test_memory_store_resets_after_window() {
+    let store = MemoryStore::new();
+    let window = Duration::from_millis(20);
+    store.count("a", window);
+    assert_eq!(store.count("a", window).count, 2);
+    std::thread::sleep(Duration::from_millis(30));
+    assert_eq!(store.count("a", window).count, 1);
+}
+
+#[test]
+fn test_retry_after_rounds_up() {
+    assert_eq!(retry_after_secs(Duration::from_secs(3)), 3);
+    assert_eq!(retry_after_secs(Duration::from_millis(2001)), 3);
+    assert_eq!(retry_after_secs(Duration::from_millis(1)), 1);
+    assert_eq!(retry_after_secs(Duration::ZERO), 0);
+}
+
+#[test]
+fn test_response_setters_fail_outside_a_function() {
+    #[derive(Debug)]
+    struct AppError;
+
+    fn handler() -> Result<(), lithe_core::ServerFnError<AppError>> {
+        crate::server::set_status(201)?;
+        crate::server::set_cookie("visits=1; Path=/")?;
+        Ok(())
+    }
+    assert!(matches!(
+        handler(),
+        Err(lithe_core::ServerFnError::Server(_))
+    ));
+    assert!(matches!(
+        crate::server::set_header::<()>("x-ok", "a\nb"),
+        Err(lithe_core::ServerFnError::Server(_))
+    ));
+}
+
+#[test]
+fn test_preview_redirect_stays_on_site() {
+    use crate::server::preview_redirect;
+
+    assert_eq!(
+        preview_redirect(Some("/blog/draft?x=1"), ""),
+        "/blog/draft?x=1"
+    );
+    assert_eq!(preview_redirect(Some("/blog"), "/docs"), "/docs/blog");
+    for redirect in [
+        "/\\evil.com",
+        "//evil.com",
+        "https://evil.com",
+        // `?redirect=/%0A` arrives decoded.
+        "/\n",
+        "/\r\nSet-Cookie: a=b",
+        "blog",
+    ] {
+        assert_eq!(preview_redirect(Some(redirect), ""), "/", "{:?}", redirect);
+        assert_eq!(preview_redirect(Some(redirect), "/docs"), "/docs");
+    }
+    assert_eq!(preview_redirect(None, "/docs"), "/docs");
+}
@@ -0,0 +1,227 @@
+// Auto-generated by lithe-cli - do not edit manually
+// Typed client for the server functions of `rust`, described by `openapi.json` too.
+
+/** Prefix the app is served under. */
+const BASE_PATH = "";
+const FUNCTION_ENDPOINT = "/api/lithe-rpc/fn";
+
+export type ErrorKind = "transport" | "decode" | "args" | "not_found" | "unauthorized" | "forbidden" | "outdated" | "server" | "timeout" | "cancelled" | "rate_limited";
+
+export interface ClientOptions {
+  /** URL of the app including its base path, e.g. `https://example.com/app`. */
+  baseUrl?: string;
+  /** Headers sent with every call, e.g. `Authorization`. */
+  headers?: Record<string, string>;
+  /** The `fetch` calls are made with. */
+  fetch?: typeof fetch;
+}
+
+const options: ClientOptions = {};
+
+/** Changes how calls are made. Defaults to the page's own origin. */
+export function configure(next: ClientOptions): void {
+  Object.assign(options, next);
+}
+
+/** A rule an argument field broke, see `#[derive(Validate)]`. */
+export type FieldError = { field: string; code: string; message: string };
+
+export type ServerFnError<E = string> =
+  | { kind: ErrorKind; detail: string }
+  | { kind: "invalid"; detail: FieldError[] }
+  | { kind: "app"; detail: E };
+
+export type RpcResponse<T, E = string> = { ok: T } | { error: ServerFnError<E> };
+
+/** Thrown by calls that fail, with the server's error. */
+export class LitheError<E = string> extends Error {
+  constructor(
+    readonly error: ServerFnError<E>,
+    readonly status: number,
+  ) {
+    super(`${error.kind}: ${typeof error.detail === "string" ? error.detail : JSON.stringify(error.detail)}`);
+  }
+}
+
+/**
+ * The session's CSRF token on a page of the app, which the server expects from browsers:
+ * the page's, else the cookie's, else a fresh one stored in the cookie.
+ */
+function csrfToken(): string | undefined {
+  if (typeof document === "undefined" || options.baseUrl) {
+    return undefined;
+  }
+  const isToken = (t?: string) => t !== undefined && /^[0-9a-f]{32}$/.test(t);
+  const page = (globalThis as { Lithe?: { csrf_token?: string } }).Lithe?.csrf_token;
+  if (isToken(page)) {
+    return page;
+  }
+  const cookie = document.cookie.match(/(?:^|;\s*)lithe_csrf=([^;]*)/)?.[1];
+  if (isToken(cookie)) {
+    return cookie;
+  }
+  const token = Array.from(crypto.getRandomValues(new Uint8Array(16)), (b) => b.toString(16).padStart(2, "0")).join("");
+  document.cookie = `lithe_csrf=${token}; Path=/; SameSite=Lax`;
+  return token;
+}
+
+function endpoint(id: string): string {
+  return `${options.baseUrl ?? BASE_PATH}${FUNCTION_ENDPOINT}/${id}`;
+}
+
+/** Arguments as JSON, with each `Blob` replaced by a reference to the part it is sent in. */
+function encodeArgs(args: unknown): { json: string; files: [string, Blob, string][] } {
+  const files: [string, Blob, string][] = [];
+  const json = JSON.stringify(args, (_key, value) => {
+    if (typeof Blob === "undefined" || !(value instanceof Blob)) {
+      return value;
+    }
+    const part = `file-${files.length}`;
+    const name = typeof File !== "undefined" && value instanceof File ? value.name : part;
+    files.push([part, value, name]);
+    return { part, name, content_type: value.type || "application/octet-stream", size: value.size };
+  });
+  return { json, files };
+}
+
+async function send(id: string, args: unknown, method: "GET" | "POST", accept: string): Promise<Response> {
+  const doFetch = options.fetch ?? fetch;
+  const headers: Record<string, string> = { accept, ...options.headers };
+  const { json, files } = encodeArgs(args);
+  if (files.length > 0 && method === "GET") {
+    throw new LitheError({ kind: "args", detail: "files cannot be sent with GET" }, 0);
+  }
+  try {
+    if (method === "GET") {
+      return await doFetch(`${endpoint(id)}?args=${encodeURIComponent(json)}`, { headers });
+    }
+    const token = csrfToken();
+    if (token) {
+      headers["x-lithe-csrf"] = token;
+    }
+    if (files.length === 0) {
+      headers["content-type"] = "application/json";
+      return await doFetch(endpoint(id), { method, headers, body: json });
+    }
+    // `fetch` sets the multipart content type itself.
+    const form = new FormData();
+    form.append("args", new Blob([json], { type: "application/json" }));
+    for (const [part, blob, name] of files) {
+      form.append(part, blob, name);
+    }
+    return await doFetch(endpoint(id), { method, headers, body: form });
+  } catch (e) {
+    throw new LitheError({ kind: "transport", detail: String(e) }, 0);
+  }
+}
+
+async function failure<E>(response: Response): Promise<LitheError<E>> {
+  try {
+    const envelope = (await response.json()) as RpcResponse<unknown, E>;
+    if ("error" in envelope) {
+      return new LitheError(envelope.error, response.status);
+    }
+  } catch {
+    // Not a Lithe reply, e.g. from a proxy.
+  }
+  return new LitheError({ kind: "transport", detail: `HTTP ${response.status}` }, response.status);
+}
+
+async function call<T, E>(id: string, args: unknown, method: "GET" | "POST"): Promise<T> {
+  const response = await send(id, args, method, "application/json");
+  if (!response.ok) {
+    throw await failure<E>(response);
+  }
+  let envelope: RpcResponse<T, E>;
+  try {
+    envelope = await response.json();
+  } catch (e) {
+    throw new LitheError({ kind: "decode", detail: String(e) }, response.status);
+  }
+  if ("error" in envelope) {
+    throw new LitheError(envelope.error, response.status);
+  }
+  return envelope.ok;
+}
+
+async function download<E>(id: string, args: unknown, method: "GET" | "POST"): Promise<Blob> {
+  const response = await send(id, args, method, "application/json");
+  if (!response.ok) {
+    throw await failure<E>(response);
+  }
+  return await response.blob();
+}
+
+async function* stream<T, E>(id: string, args: unknown): AsyncGenerator<T> {
+  const response = await send(id, args, "POST", "application/x-ndjson");
+  if (!response.ok || !response.body) {
+    throw await failure<E>(response);
+  }
+  const reader = response.body.pipeThrough(new TextDecoderStream()).getReader();
+  let buffer = "";
+  for (;;) {
+    const { done, value } = await reader.read();
+    buffer += value ?? "";
+    let newline: number;
+    while ((newline = buffer.indexOf("\n")) >= 0) {
+      const line = buffer.slice(0, newline).trim();
+      buffer = buffer.slice(newline + 1);
+      if (!line) {
+        continue;
+      }
+      const envelope = JSON.parse(line) as RpcResponse<T, E>;
+      if ("error" in envelope) {
+        throw new LitheError(envelope.error, response.status);
+      }
+      yield envelope.ok;
+    }
+    if (done) {
+      return;
+    }
+  }
+}
+
+// Server functions
+
+/**
+ * Counts down from `from`, one number every 200ms. The client dropping the stream stops it.
+ *
+ * Calls `rust::pages_test::countdown`.
+ */
+export function countdown(from: number): AsyncGenerator<number> {
+  return stream<number, string>("f_5971bffc66859f8b", from);
+}
+
+/**
+ * Read-only, so browsers and proxies may cache its replies for a minute and failed calls are
+ * safe to retry.
+ *
+ * Calls `rust::pages_test::get_data_final`.
+ */
+export function getDataFinal(val: number): Promise<string> {
+  return call<string, string>("f_c4ff90b18223fb0c", val, "GET");
+}
+
+/** Calls `rust::pages_test::get_data_step_1`. */
+export function getDataStep1(val: number): Promise<number> {
+  return call<number, string>("f_64f1a6e1e5b8c92e", val, "POST");
+}
+
+/**
+ * Guarded by the project's `default_guard`, so only signed-in users can call it.
+ *
+ * Calls `rust::utils::get_account`.
+ */
+export function getAccount(): Promise<string> {
+  return call<string, string>("f_af630eea4cf76978", null, "POST");
+}
+
+/** Calls `rust::utils::get_server_data`. */
+export function getServerData(id: number): Promise<string> {
+  return call<string, string>("v1/server-data", id, "POST");
+}
+
+/** Calls `rust::utils::reset_counters`. */
+export function resetCounters(): Promise<null> {
+  return call<null, string>("f_7259c9826d40b0c1", null, "POST");
+}
@@ -0,0 +1,12 @@
+// Auto-generated by lithe-cli - do not edit manually
+use lithe::jobs::{Job, Scheduled};
+
+/// The project's `#[job]` functions.
+pub static JOBS: &[Job] = &[
+    rust::utils::__LITHE_JOB_note_first_visit,
+];
+
+/// The project's `#[scheduled]` functions.
+pub static SCHEDULED: &[Scheduled] = &[
+    rust::utils::__LITHE_SCHEDULED_report_uptime,
+];
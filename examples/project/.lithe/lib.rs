@@ -1,17 +1,27 @@
 // Auto-generated by lithe-cli - do not edit manually
 
-#[path = "../src/pages/index.rs"]
-pub mod pages_index;
+#[path = "../src/auth.rs"]
+pub mod auth;
+
+#[path = "../src/pages/about.rs"]
+pub mod pages_about;
 
 #[path = "../src/pages/contact.rs"]
 pub mod pages_contact;
 
-#[path = "../src/pages/about.rs"]
-pub mod pages_about;
+#[path = "../src/pages/index.rs"]
+pub mod pages_index;
+
+#[path = "../src/pages/roadmap.rs"]
+pub mod pages_roadmap;
 
 #[path = "../src/pages/test.rs"]
 pub mod pages_test;
 
+#[cfg(not(target_arch = "wasm32"))]
+#[path = "../src/state.rs"]
+pub mod state;
+
 #[path = "../src/utils.rs"]
 pub mod utils;
 
@@ -19,6 +29,7 @@ pub mod pages {
     pub use super::pages_index as index;
     pub use super::pages_about as about;
     pub use super::pages_contact as contact;
+    pub use super::pages_roadmap as roadmap;
     pub use super::pages_test as test;
 }
 
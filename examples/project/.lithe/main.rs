@@ -1,21 +1,83 @@
 // Auto-generated by lithe-cli - do not edit manually
-use axum::{Router, extract::Path, response::{Html, IntoResponse, Response}, routing::{get, post}, body::Body, http::{header, StatusCode}};
+use axum::{Router, extract::{MatchedPath, Path, Query, Request}, middleware::{self, Next}, response::{Html, IntoResponse, Redirect, Response}, routing::{get, post}, body::Body, http::{header, HeaderMap, StatusCode}};
 use rust_embed::RustEmbed;
+use std::collections::HashMap;
 #[path = "routes.rs"]
 mod routes;
 #[path = "rpc.rs"]
 mod rpc;
+#[path = "jobs.rs"]
+mod jobs;
+#[path = "sockets.rs"]
+mod sockets;
 
 #[derive(RustEmbed)]
 #[folder = ".lithe/public/"]
 struct Assets;
 
-async fn handle_root() -> Html<String> {
-    Html(routes::dispatch("/"))
+fn render(path: String, headers: &HeaderMap) -> Response {
+    let header_value = |name| headers.get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let mut req = lithe::PageRequest {
+        path,
+        host: header_value(header::HOST),
+        accept_language: header_value(header::ACCEPT_LANGUAGE),
+        cookie: header_value(header::COOKIE),
+        csrf_token: None,
+    };
+    let csrf_cookie = lithe::server::start_csrf_session(&mut req);
+    let page = routes::dispatch_request(&req);
+    let mut response = (
+        StatusCode::from_u16(page.status).unwrap_or(StatusCode::OK),
+        [(header::VARY, "Accept-Language, Cookie")],
+        Html(page.body),
+    )
+        .into_response();
+    // Pages in shared caches carry no token, and must not set one for everyone.
+    if let Some(cookie) = csrf_cookie.filter(|_| lithe::csrf::page_may_carry_token(page.cache_control.as_deref()))
+        && let Ok(cookie) = cookie.parse()
+    {
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+    }
+    if let Some(cache) = page.cache_control.and_then(|c| c.parse().ok()) {
+        response.headers_mut().insert(header::CACHE_CONTROL, cache);
+    }
+    response
+}
+
+async fn handle_root(headers: HeaderMap) -> Response {
+    render("/".to_string(), &headers)
 }
 
-async fn handle_path(Path(path): Path<String>) -> Html<String> {
-    Html(routes::dispatch(&format!("/{}", path)))
+async fn handle_path(Path(path): Path<String>, headers: HeaderMap) -> Response {
+    render(format!("/{}", path), &headers)
+}
+
+/// Starts a preview session: `/api/lithe-preview?secret=...&redirect=/drafts/post`.
+async fn enter_preview(Query(params): Query<HashMap<String, String>>) -> Response {
+    let secret = params.get("secret").map(String::as_str).unwrap_or("");
+    if !lithe::preview::check_secret(secret) {
+        return (StatusCode::UNAUTHORIZED, "Invalid preview secret").into_response();
+    }
+    let Some(token) = lithe::preview::issue_token() else {
+        return (StatusCode::UNAUTHORIZED, "Preview mode is not configured").into_response();
+    };
+    (
+        [(header::SET_COOKIE, lithe::preview::enable_cookie(&token))],
+        Redirect::to(&preview_redirect(&params)),
+    )
+        .into_response()
+}
+
+async fn exit_preview(Query(params): Query<HashMap<String, String>>) -> Response {
+    (
+        [(header::SET_COOKIE, lithe::preview::disable_cookie())],
+        Redirect::to(&preview_redirect(&params)),
+    )
+        .into_response()
+}
+
+fn preview_redirect(params: &HashMap<String, String>) -> String {
+    lithe::server::preview_redirect(params.get("redirect").map(String::as_str), "")
 }
 
 async fn static_handler(Path(path): Path<String>) -> impl IntoResponse {
@@ -34,14 +96,54 @@ async fn static_handler(Path(path): Path<String>) -> impl IntoResponse {
     }
 }
 
+/// Runs every request in a span, and counts it for the metrics.
+async fn observe(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let request_id = request
+        .headers()
+        .get(lithe::telemetry::REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let matched = request.extensions().get::<MatchedPath>().map(|m| m.as_str().to_string());
+    lithe::telemetry::observe(&method, &path, request_id.as_deref(), matched.as_deref(), next.run(request)).await
+}
+
+/// Serves the metrics collected by `lithe::telemetry` to Prometheus.
+async fn metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, lithe::telemetry::METRICS_CONTENT_TYPE)],
+        lithe::telemetry::render_metrics(),
+    )
+}
+
 #[tokio::main]
 async fn main() {
+    lithe::telemetry::init(lithe::telemetry::LogFormat::Text);
+    let state = lithe::server::provide_state(rust::state::AppState::new().await);
+    lithe::jobs::start(jobs::JOBS, jobs::SCHEDULED);
     let app = Router::new()
-        .route("/api/lithe-rpc", post(rpc::handle_rpc))
+        .route(lithe::RPC_ENDPOINT, get(rpc::handle_rpc_get).post(rpc::handle_rpc))
+        .route(lithe::RPC_STREAM_ENDPOINT, post(rpc::handle_rpc_stream))
+        .route(lithe::RPC_BATCH_ENDPOINT, post(rpc::handle_rpc_batch))
+        .route(
+            &format!("{}/*function", lithe::RPC_FUNCTION_ENDPOINT),
+            get(rpc::handle_rpc_function_get).post(rpc::handle_rpc_function),
+        )
+        // Applies to the routes above, i.e. server function calls.
+        .layer(axum::extract::DefaultBodyLimit::max(2097152))
+        .route(&format!("{}/*id", lithe::SOCKET_ENDPOINT), get(sockets::handle_socket))
+        .route("/metrics", get(metrics))
+        .route(lithe::PREVIEW_ENDPOINT, get(enter_preview))
+        .route(lithe::PREVIEW_EXIT_ENDPOINT, get(exit_preview))
         .route("/public/*path", get(static_handler))
         .route("/", get(handle_root))
-        .route("/*path", get(handle_path));
+        .route("/*path", get(handle_path))
+        .layer(middleware::from_fn(observe))
+        .with_state(state);
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    println!("Listening on http://localhost:3000");
-    axum::serve(listener, app).await.unwrap();
+    lithe::tracing::info!("Listening on http://localhost:3000");
+    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .await
+        .unwrap();
 }
@@ -1,5 +1,5 @@
 // Auto-generated by lithe-cli - do not edit manually
-use lithe::render_page;
+use lithe::{PageMeta, PageRequest, PageResponse, RenderContext, i18n, preview, render_page, with_render_context};
 
 #[path = "../src/pages/index.rs"]
 mod pages_index;
@@ -7,19 +7,74 @@ mod pages_index;
 mod pages_about;
 #[path = "../src/pages/contact.rs"]
 mod pages_contact;
+#[path = "../src/pages/roadmap.rs"]
+mod pages_roadmap;
 #[path = "../src/pages/test.rs"]
 mod pages_test;
 
+const LOCALES: &[&str] = &["en", "de", "fr"];
+const DEFAULT_LOCALE: &str = "en";
+const CATALOGS: &[(&str, &str)] = &[("en", include_str!("../src/locales/en.json")), ("de", include_str!("../src/locales/de.json")), ("fr", include_str!("../src/locales/fr.json"))];
+const BASE_PATH: &str = "";
+const HOSTS: &[(&str, &str)] = &[];
+/// Id of this build; RPC calls from pages rendered by another build are rejected.
+pub const BUILD_ID: &str = "dc1c3dfbad7e8a64";
+const ENCODING: lithe::rpc::Encoding = lithe::rpc::Encoding::Json;
+
+#[allow(dead_code)]
 pub fn dispatch(path: &str) -> String {
-    match path {
-        "/" => render_page(pages_index::page(), "rust", "{\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\",\"h_d0a7df551ec396b1\":\"f_b72c3ddf4f1e0d3b\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_acb981a73002a620\":\"f_1090703e697e515d\",\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\"}"),
-        "/about" => render_page(pages_about::page(), "rust", "{\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_d0a7df551ec396b1\":\"f_b72c3ddf4f1e0d3b\",\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\"}"),
-        "/contact" => render_page(pages_contact::page(), "rust", "{\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\",\"h_d0a7df551ec396b1\":\"f_b72c3ddf4f1e0d3b\"}"),
-        "/test" => render_page(pages_test::page(), "rust", "{\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_1491e29eb461c5a7\":\"f_c4ff90b18223fb0c\",\"h_d0a7df551ec396b1\":\"f_b72c3ddf4f1e0d3b\",\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\",\"h_a6ce36755fafe729\":\"f_64f1a6e1e5b8c92e\"}"),
-        _ => "404 Not Found".to_string(),
+    dispatch_request(&PageRequest::new(path)).body
+}
+
+pub fn dispatch_request(req: &PageRequest) -> PageResponse {
+    let (locale, path) = match i18n::split_locale_prefix(&req.path, LOCALES) {
+        Some((locale, path)) => (locale, path),
+        None => (
+            i18n::negotiate_locale(
+                LOCALES,
+                DEFAULT_LOCALE,
+                req.cookie(i18n::LOCALE_COOKIE),
+                req.accept_language.as_deref(),
+            ),
+            req.path.as_str(),
+        ),
+    };
+    let page_path = req.host_path(path, HOSTS);
+    let preview = preview::is_preview_request(req);
+    i18n::install_catalogs(CATALOGS);
+    let mut ctx = RenderContext {
+        path: path.to_string(),
+        base_path: BASE_PATH.to_string(),
+        locale: locale.to_string(),
+        default_locale: DEFAULT_LOCALE.to_string(),
+        alternates: i18n::alternates(BASE_PATH, path, LOCALES),
+        preview,
+        build_id: BUILD_ID.to_string(),
+        encoding: ENCODING,
+        ..RenderContext::default()
+    };
+    // Metrics are labelled with the matched route, not the path, so SPA sub-paths share one.
+    let (meta, route, render): (PageMeta, &str, fn() -> String) = match page_path.as_str() {
+        "/" => (pages_index::PAGE_META, "/", || render_page(pages_index::page(), "rust", "{\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_5bd9a79d44ffe377\":\"f_7259c9826d40b0c1\",\"h_8118e999dad6a7ee\":\"f_af630eea4cf76978\",\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_acb981a73002a620\":\"f_1090703e697e515d\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\",\"h_bd09d148dceb23a1\":\"f_5971bffc66859f8b\",\"h_d0a7df551ec396b1\":\"v1/server-data\"}")),
+        "/about" => (pages_about::PAGE_META, "/about", || render_page(pages_about::page(), "rust", "{\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_5bd9a79d44ffe377\":\"f_7259c9826d40b0c1\",\"h_8118e999dad6a7ee\":\"f_af630eea4cf76978\",\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\",\"h_bd09d148dceb23a1\":\"f_5971bffc66859f8b\",\"h_d0a7df551ec396b1\":\"v1/server-data\"}")),
+        "/contact" => (pages_contact::PAGE_META, "/contact", || render_page(pages_contact::page(), "rust", "{\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_5bd9a79d44ffe377\":\"f_7259c9826d40b0c1\",\"h_8118e999dad6a7ee\":\"f_af630eea4cf76978\",\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\",\"h_bd09d148dceb23a1\":\"f_5971bffc66859f8b\",\"h_d0a7df551ec396b1\":\"v1/server-data\"}")),
+        "/roadmap" => (pages_roadmap::PAGE_META, "/roadmap", || render_page(pages_roadmap::page(), "rust", "{\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_5bd9a79d44ffe377\":\"f_7259c9826d40b0c1\",\"h_8118e999dad6a7ee\":\"f_af630eea4cf76978\",\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\",\"h_bd09d148dceb23a1\":\"f_5971bffc66859f8b\",\"h_d0a7df551ec396b1\":\"v1/server-data\"}")),
+        "/test" => (pages_test::PAGE_META, "/test", || render_page(pages_test::page(), "rust", "{\"h_1491e29eb461c5a7\":\"f_c4ff90b18223fb0c\",\"h_377c476a6289d46\":\"f_5971bffc66859f8b\",\"h_53460cb959796a1d\":\"f_a563c899522b1e7\",\"h_5bd9a79d44ffe377\":\"f_7259c9826d40b0c1\",\"h_8118e999dad6a7ee\":\"f_af630eea4cf76978\",\"h_81a67f04dbed7ce2\":\"f_c4ff90b18223fb0c\",\"h_a6ce36755fafe729\":\"f_64f1a6e1e5b8c92e\",\"h_a9aebf0743e28893\":\"f_1090703e697e515d\",\"h_b6765fa078b285c4\":\"f_64f1a6e1e5b8c92e\",\"h_bd09d148dceb23a1\":\"f_5971bffc66859f8b\",\"h_d0a7df551ec396b1\":\"v1/server-data\"}")),
+        _ => return PageResponse::not_found(),
+    };
+    if meta.draft && !preview {
+        return PageResponse::not_found();
+    }
+    ctx.meta = meta;
+    lithe::telemetry::set_route(route);
+    if lithe::csrf::page_may_carry_token(meta.cache) {
+        ctx.csrf_token = req.csrf_token.clone().unwrap_or_default();
     }
+    let page = PageResponse::ok(with_render_context(ctx, render), &meta);
+    if preview { page.preview() } else { page }
 }
 
+#[allow(dead_code)]
 pub fn routes() -> Vec<&'static str> {
-    vec!["/", "/about", "/contact", "/test"]
+    vec!["/", "/about", "/contact", "/test", "/en", "/en/about", "/en/contact", "/en/test", "/de", "/de/about", "/de/contact", "/de/test", "/fr", "/fr/about", "/fr/contact", "/fr/test"]
 }
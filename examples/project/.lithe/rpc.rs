@@ -1,23 +1,434 @@
 // Auto-generated by lithe-cli - do not edit manually
-use axum::{Json, response::IntoResponse, http::StatusCode};
-use lithe::rpc::{RpcRequest, RpcResponse};
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Path, Query},
+    http::{HeaderMap, HeaderValue, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use lithe::rpc::{
+    ARGS_PARAM, BUILD_HEADER, Encoding, Encodings, FUNCTION_HEADER, FUNCTION_PARAM, RpcReply,
+    RpcRequest, ServerFnError, StreamFormat,
+};
+use lithe::server::{RequestContext, ResponseParts, RpcStream};
+use std::collections::HashMap;
+use std::net::SocketAddr;
 
-pub async fn handle_rpc(Json(req): Json<RpcRequest>) -> impl IntoResponse {
-    let result = match req.function.as_str() {
-        "f_64f1a6e1e5b8c92e" => {
-            let args: i32 = serde_json::from_value(req.args).unwrap();
-            rust::pages_test::__lithe_rpc_wrapper_get_data_step_1(args).await
+pub async fn handle_rpc(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let function = request.header(FUNCTION_HEADER).map(str::to_string);
+    call(request, function, body).await
+}
+
+/// Calls a `method = "GET"` server function with its JSON arguments in the query string.
+/// Successful replies carry an `ETag`, and conditional requests that still match get
+/// `304 Not Modified` without a body.
+pub async fn handle_rpc_get(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let function = params.get(FUNCTION_PARAM).cloned().unwrap_or_default();
+    call_get(request, function, &params).await
+}
+
+/// Calls the server function named by the path, e.g. `POST /api/lithe-rpc/fn/{id}`, so each
+/// function has its own URL for clients generated from `.lithe/openapi.json`.
+pub async fn handle_rpc_function(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(function): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    if is_streaming(&function) {
+        call_stream(request, function, body).await
+    } else {
+        call(request, Some(function), body).await
+    }
+}
+
+/// The `GET` form of [`handle_rpc_function`], for `method = "GET"` functions.
+pub async fn handle_rpc_function_get(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(function): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    call_get(request, function, &params).await
+}
+
+/// Runs a batch of calls concurrently and replies with one result per call.
+pub async fn handle_rpc_batch(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let (reply, parts) = match preflight_post(&request, None) {
+        Err(reply) => (reply, ResponseParts::default()),
+        Ok(encodings) => {
+            lithe::server::batch(request, encodings, &body, |call| async move {
+                dispatch(&call.function, encodings, &call.args.0).await
+            })
+            .await
+        }
+    };
+    reply_response(reply, parts)
+}
+
+/// Calls a streaming server function and sends its items as NDJSON, or as SSE when asked.
+pub async fn handle_rpc_stream(
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let function = request
+        .header(FUNCTION_HEADER)
+        .unwrap_or_default()
+        .to_string();
+    call_stream(request, function, body).await
+}
+
+async fn call(request: RequestContext, function: Option<String>, body: Bytes) -> Response {
+    let encodings = match preflight_post(&request, function.as_deref()) {
+        Ok(encodings) => encodings,
+        Err(reply) => return reply_response(reply, ResponseParts::default()),
+    };
+    // Calls with files are `multipart/form-data`, with the encoded arguments in one part.
+    let (body, encodings, uploads) =
+        match lithe::server::read_uploads(&request, encodings, body.into()).await {
+            Ok(split) => split,
+            Err(e) => {
+                let reply = RpcReply::error(encodings.response, ServerFnError::<()>::Args(e));
+                return reply_response(reply, ResponseParts::default());
+            }
+        };
+    let (reply, parts) = lithe::server::scope(
+        request,
+        lithe::server::with_uploads(uploads, async move {
+            if let Some(function) = function {
+                return dispatch(&function, encodings, &body).await;
+            }
+            // Calls without the function header carry a JSON `{"function": ..., "args": ...}` body.
+            match lithe::serde_json::from_slice::<RpcRequest>(&body) {
+                Ok(req) => {
+                    let encodings = Encodings {
+                        request: Encoding::Json,
+                        ..encodings
+                    };
+                    dispatch(&req.function, encodings, req.args.to_string().as_bytes()).await
+                }
+                Err(e) => RpcReply::error(
+                    encodings.response,
+                    ServerFnError::<()>::Args(e.to_string()),
+                ),
+            }
+        }),
+    )
+    .await;
+    reply_response(reply, parts)
+}
+
+async fn call_get(
+    request: RequestContext,
+    function: String,
+    params: &HashMap<String, String>,
+) -> Response {
+    // Functions without arguments take `()`, which is `null` in JSON.
+    let args = params.get(ARGS_PARAM).map_or("null", String::as_str).to_string();
+    let if_none_match = request
+        .header(header::IF_NONE_MATCH.as_str())
+        .map(str::to_string);
+    let (reply, mut parts) = match preflight(&request) {
+        Err(reply) => (reply, ResponseParts::default()),
+        Ok(encodings) => {
+            let encodings = Encodings {
+                request: Encoding::Json,
+                ..encodings
+            };
+            let function = function.clone();
+            lithe::server::scope(request, async move {
+                dispatch_get(&function, encodings, args.as_bytes()).await
+            })
+            .await
         }
-        "f_c4ff90b18223fb0c" => {
-            let args: i32 = serde_json::from_value(req.args).unwrap();
-            rust::pages_test::__lithe_rpc_wrapper_get_data_final(args).await
+    };
+    if reply.status != 200 || parts.status.is_some() {
+        return reply_response(reply, parts);
+    }
+
+    let etag = reply.etag();
+    // The reply depends on the encoding the caller accepts.
+    parts
+        .headers
+        .append(header::VARY, HeaderValue::from_static("accept"));
+    if let Some((cache, guarded)) = cache_control(&function) {
+        parts
+            .headers
+            .entry(header::CACHE_CONTROL)
+            .or_insert(HeaderValue::from_static(cache));
+        if guarded {
+            // Who may see the reply depends on the caller's credentials.
+            parts
+                .headers
+                .append(header::VARY, HeaderValue::from_static("cookie, authorization"));
         }
-        "f_b72c3ddf4f1e0d3b" => {
-            let args: i32 = serde_json::from_value(req.args).unwrap();
-            rust::utils::__lithe_rpc_wrapper_get_server_data(args).await
+    }
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        parts.headers.insert(header::ETAG, value);
+    }
+    if if_none_match.is_some_and(|tags| lithe::rpc::etag_matches(&tags, &etag)) {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        response.headers_mut().extend(parts.headers);
+        return response;
+    }
+    reply_response(reply, parts)
+}
+
+async fn call_stream(request: RequestContext, function: String, body: Bytes) -> Response {
+    let format = StreamFormat::from_accept(request.header(header::ACCEPT.as_str()));
+    let (result, parts) = match preflight_post(&request, Some(&function)) {
+        Err(reply) => (Err(reply), ResponseParts::default()),
+        Ok(encodings) => {
+            lithe::server::scope(request, async move {
+                dispatch_stream(&function, encodings, &body).await
+            })
+            .await
         }
-        _ => return (StatusCode::NOT_FOUND, "Function not found").into_response(),
     };
+    match result {
+        Ok(stream) => {
+            let mut response = (
+                parts.status.unwrap_or(StatusCode::OK),
+                [(header::CONTENT_TYPE, format.content_type())],
+                Body::from_stream(stream.into_frames(format)),
+            )
+                .into_response();
+            response.headers_mut().extend(parts.headers);
+            response
+        }
+        Err(reply) => reply_response(reply, parts),
+    }
+}
+
+/// Negotiates the encodings of a call and rejects calls from outdated builds. Multipart calls
+/// declare their arguments' encoding in a part, see `lithe::server::read_uploads`.
+fn preflight(request: &RequestContext) -> Result<Encodings, RpcReply> {
+    let Some(encodings) = Encodings::negotiate(
+        request
+            .header(header::CONTENT_TYPE.as_str())
+            .filter(|ct| !lithe::server::is_multipart(ct)),
+        request.header(header::ACCEPT.as_str()),
+    ) else {
+        let mut reply = RpcReply::error(
+            Encoding::Json,
+            ServerFnError::<()>::Args("unsupported content type".to_string()),
+        );
+        reply.status = StatusCode::UNSUPPORTED_MEDIA_TYPE.as_u16();
+        return Err(reply);
+    };
+    if request
+        .header(BUILD_HEADER)
+        .is_some_and(|build| build != crate::routes::BUILD_ID)
+    {
+        return Err(RpcReply::error(
+            encodings.response,
+            ServerFnError::<()>::Outdated(
+                "the app was updated after this page was loaded".to_string(),
+            ),
+        ));
+    }
+    Ok(encodings)
+}
+
+/// [`preflight`] for calls that may change state, which also rejects calls made by other
+/// sites unless the function is exempt.
+fn preflight_post(request: &RequestContext, function: Option<&str>) -> Result<Encodings, RpcReply> {
+    let encodings = preflight(request)?;
+    if function.is_some_and(csrf_exempt) {
+        return Ok(encodings);
+    }
+    lithe::server::check_csrf(request, TRUSTED_ORIGINS)
+        .map(|()| encodings)
+        .map_err(|e| RpcReply::error(encodings.response, ServerFnError::<()>::Forbidden(e)))
+}
+
+fn reply_response(reply: RpcReply, parts: ResponseParts) -> Response {
+    let status = parts
+        .status
+        .unwrap_or(StatusCode::from_u16(reply.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+    let mut response = (
+        status,
+        [(header::CONTENT_TYPE, reply.encoding.content_type())],
+        reply.body,
+    )
+        .into_response();
+    response.headers_mut().extend(parts.headers);
+    response
+}
+
+async fn dispatch(function: &str, encodings: Encodings, args: &[u8]) -> RpcReply {
+    lithe::telemetry::observe_call(function, async move {
+        if let Some(limit) = rate_limit(function)
+            && let Err(e) = lithe::server::check_rate_limit::<()>(function, limit).await
+        {
+            return RpcReply::error(encodings.response, e);
+        }
+        match function {
+            "f_c4ff90b18223fb0c" => match default_guard(encodings, rust::pages_test::__LITHE_RPC_GUARDED_get_data_final).await {
+                Ok(()) => rust::pages_test::__lithe_rpc_wrapper_get_data_final(encodings, args).await,
+                Err(rejected) => rejected,
+            },
+            "f_64f1a6e1e5b8c92e" => match default_guard(encodings, rust::pages_test::__LITHE_RPC_GUARDED_get_data_step_1).await {
+                Ok(()) => rust::pages_test::__lithe_rpc_wrapper_get_data_step_1(encodings, args).await,
+                Err(rejected) => rejected,
+            },
+            "f_af630eea4cf76978" => match default_guard(encodings, rust::utils::__LITHE_RPC_GUARDED_get_account).await {
+                Ok(()) => rust::utils::__lithe_rpc_wrapper_get_account(encodings, args).await,
+                Err(rejected) => rejected,
+            },
+            "v1/server-data" => match default_guard(encodings, rust::utils::__LITHE_RPC_GUARDED_get_server_data).await {
+                Ok(()) => rust::utils::__lithe_rpc_wrapper_get_server_data(encodings, args).await,
+                Err(rejected) => rejected,
+            },
+            "f_7259c9826d40b0c1" => match default_guard(encodings, rust::utils::__LITHE_RPC_GUARDED_reset_counters).await {
+                Ok(()) => rust::utils::__lithe_rpc_wrapper_reset_counters(encodings, args).await,
+                Err(rejected) => rejected,
+            },
+            _ => RpcReply::error(
+                encodings.response,
+                ServerFnError::<()>::NotFound(function.to_string()),
+            ),
+        }
+    })
+    .await
+}
+
+async fn dispatch_stream(
+    function: &str,
+    encodings: Encodings,
+    args: &[u8],
+) -> Result<RpcStream, RpcReply> {
+    lithe::telemetry::observe_call(function, async move {
+        match function {
+            "f_5971bffc66859f8b" => match default_guard(encodings, rust::pages_test::__LITHE_RPC_GUARDED_countdown).await {
+                Ok(()) => rust::pages_test::__lithe_rpc_wrapper_countdown(encodings, args).await,
+                Err(rejected) => Err(rejected),
+            },
+            _ => Err(RpcReply::error(
+                encodings.response,
+                ServerFnError::<()>::NotFound(function.to_string()),
+            )),
+        }
+    })
+    .await
+}
+
+fn is_streaming(function: &str) -> bool {
+    matches!(function, "f_5971bffc66859f8b")
+}
+
+/// Limits declared with `#[server(rate_limit = "...")]`.
+fn rate_limit(function: &str) -> Option<lithe::rpc::RateLimit> {
+    match function {
+        "v1/server-data" => Some(lithe::rpc::RateLimit {
+            max: 20,
+            window: std::time::Duration::from_secs(60),
+            key: lithe::rpc::RateLimitKey::Ip,
+        }),
+        _ => None,
+    }
+}
+
+/// Origins from `[package.metadata.lithe.csrf]` allowed to call server functions.
+const TRUSTED_ORIGINS: &[&str] = &[];
+
+/// Functions exempt from CSRF checks, see `[package.metadata.lithe.csrf]`.
+fn csrf_exempt(function: &str) -> bool {
+    let _ = function;
+    false
+}
+
+async fn dispatch_get(function: &str, encodings: Encodings, args: &[u8]) -> RpcReply {
+    lithe::telemetry::observe_call(function, async move {
+        match function {
+            "f_c4ff90b18223fb0c" => match default_guard(encodings, rust::pages_test::__LITHE_RPC_GUARDED_get_data_final).await {
+                Ok(()) => rust::pages_test::__lithe_rpc_wrapper_get_data_final(encodings, args).await,
+                Err(rejected) => rejected,
+            },
+            _ => RpcReply::error(
+                encodings.response,
+                ServerFnError::<()>::NotFound(function.to_string()),
+            ),
+        }
+    })
+    .await
+}
+
+/// `Cache-Control` declared with `#[server(cache = "...")]`, and whether a guard decides
+/// who gets the reply.
+fn cache_control(function: &str) -> Option<(&'static str, bool)> {
+    match function {
+        "f_c4ff90b18223fb0c" => Some(("public, max-age=60", false)),
+        _ => None,
+    }
+}
 
-    Json(RpcResponse { result }).into_response()
+/// Runs the project's default guard for functions that declare neither `guard` nor `public`.
+async fn default_guard(encodings: Encodings, guarded: bool) -> Result<(), RpcReply> {
+    if guarded {
+        return Ok(());
+    }
+    rust::auth::require_user()
+        .await
+        .map_err(|e| RpcReply::error(encodings.response, e))
 }
@@ -0,0 +1,157 @@
+// Auto-generated by lithe-cli - do not edit manually
+// `#[socket]` functions need axum's `ws` feature: axum = { version = "0.7", features = ["ws"] }
+use axum::{
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    extract::{ConnectInfo, Path, Query},
+    http::{HeaderMap, Method, StatusCode, Uri, header},
+    response::{IntoResponse, Response},
+};
+use lithe::rpc::{Encoding, RpcReply, ServerFnError};
+use lithe::server::{RequestContext, ResponseParts};
+use lithe::socket::SocketRemote;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+
+/// Origins from `[package.metadata.lithe.csrf]` allowed to open sockets.
+const TRUSTED_ORIGINS: &[&str] = &[];
+
+/// Opens a WebSocket to the `#[socket]` function named by the path, e.g.
+/// `GET /api/lithe-socket/{id}?encoding=json`, once its guards accept the handshake.
+pub async fn handle_socket(
+    upgrade: WebSocketUpgrade,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let request = RequestContext {
+        method,
+        uri,
+        headers,
+        remote_addr: Some(remote_addr),
+    };
+    let encoding = params
+        .get("encoding")
+        .and_then(|name| Encoding::from_name(name))
+        .unwrap_or_default();
+    if let Err(e) = lithe::server::check_socket_origin(&request, TRUSTED_ORIGINS) {
+        let reply = RpcReply::error(encoding, ServerFnError::<()>::Forbidden(e));
+        return reply_response(reply, ResponseParts::default());
+    }
+    let (accepted, parts) = lithe::server::scope(request.clone(), guard(&id, encoding)).await;
+    match accepted {
+        Ok(true) => {}
+        Ok(false) => return StatusCode::NOT_FOUND.into_response(),
+        Err(reply) => return reply_response(reply, parts),
+    }
+    upgrade
+        .max_message_size(2097152)
+        .on_upgrade(move |socket| async move {
+            let (io, remote) = lithe::socket::channel();
+            let handler = async move {
+                lithe::telemetry::observe_socket(&id, async {
+                    match id.as_str() {
+                        "f_2ab95ba2dacf98fd" => rust::pages_test::__lithe_socket_wrapper_shout(encoding, io).await,
+                        _ => Ok(()),
+                    }
+                })
+                .await
+            };
+            lithe::server::scope(request, pump(socket, encoding, remote, handler)).await;
+        })
+}
+
+/// Runs the guards of socket `id`, returning whether there is such a socket.
+async fn guard(id: &str, encoding: Encoding) -> Result<bool, RpcReply> {
+    match id {
+        "f_2ab95ba2dacf98fd" => {
+            default_guard(encoding, rust::pages_test::__LITHE_SOCKET_GUARDED_shout).await?;
+            rust::pages_test::__lithe_socket_guard_shout(encoding).await?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Runs the project's default guard for sockets that declare neither `guard` nor `public`.
+async fn default_guard(encoding: Encoding, guarded: bool) -> Result<(), RpcReply> {
+    if guarded {
+        return Ok(());
+    }
+    rust::auth::require_user()
+        .await
+        .map_err(|e| RpcReply::error(encoding, e))
+}
+
+/// Carries messages between the WebSocket and the function until either side is done. Once
+/// the client is gone the function's `recv` returns `None` and its sends fail, so it can
+/// finish on its own.
+async fn pump(
+    mut socket: WebSocket,
+    encoding: Encoding,
+    remote: SocketRemote,
+    handler: impl Future<Output = ()>,
+) {
+    let SocketRemote { incoming, mut outgoing } = remote;
+    let mut handler = std::pin::pin!(handler);
+    // A message the function has no room for yet; reading waits until it has.
+    let mut pending: Option<Vec<u8>> = None;
+    loop {
+        tokio::select! {
+            () = &mut handler => {
+                while let Ok(bytes) = outgoing.try_recv() {
+                    if socket.send(frame(encoding, bytes)).await.is_err() {
+                        break;
+                    }
+                }
+                let _ = socket.close().await;
+                return;
+            }
+            permit = incoming.reserve(), if pending.is_some() => match permit {
+                Ok(permit) => permit.send(pending.take().unwrap_or_default()),
+                // The function dropped its socket; what the client sends goes nowhere.
+                Err(_) => pending = None,
+            },
+            message = socket.recv(), if pending.is_none() => match message {
+                Some(Ok(Message::Text(text))) => pending = Some(text.into_bytes()),
+                Some(Ok(Message::Binary(bytes))) => pending = Some(bytes),
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            Some(bytes) = outgoing.recv() => {
+                if socket.send(frame(encoding, bytes)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    // Reading on answers a client's close frame and finishes the closing handshake.
+    while let Some(Ok(_)) = socket.recv().await {}
+    drop((incoming, outgoing));
+    handler.await;
+}
+
+/// JSON goes out in text frames, the binary encodings in binary ones.
+fn frame(encoding: Encoding, bytes: Vec<u8>) -> Message {
+    match encoding {
+        Encoding::Json => Message::Text(String::from_utf8_lossy(&bytes).into_owned()),
+        _ => Message::Binary(bytes),
+    }
+}
+
+fn reply_response(reply: RpcReply, parts: ResponseParts) -> Response {
+    let status = parts
+        .status
+        .unwrap_or(StatusCode::from_u16(reply.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR));
+    let mut response = (
+        status,
+        [(header::CONTENT_TYPE, reply.encoding.content_type())],
+        reply.body,
+    )
+        .into_response();
+    response.headers_mut().extend(parts.headers);
+    response
+}
@@ -0,0 +1,23 @@
+//! Guards for server functions. They read the request, so they only exist on the server.
+#![cfg(not(target_arch = "wasm32"))]
+
+use lithe::ServerFnError;
+
+/// Cookie holding the signed-in user's name.
+pub const SESSION_COOKIE: &str = "session";
+
+/// Rejects callers without a session cookie.
+pub async fn require_user() -> Result<(), ServerFnError> {
+    match lithe::server::use_request().cookie(SESSION_COOKIE) {
+        Some(user) if !user.is_empty() => Ok(()),
+        _ => Err(ServerFnError::Unauthorized("sign in first".to_string())),
+    }
+}
+
+/// Only lets the `admin` user through.
+pub async fn require_admin() -> Result<(), ServerFnError> {
+    match lithe::server::use_request().cookie(SESSION_COOKIE) {
+        Some("admin") => Ok(()),
+        _ => Err(ServerFnError::Forbidden("admins only".to_string())),
+    }
+}
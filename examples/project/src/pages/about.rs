@@ -1,9 +1,17 @@
-#[lithe::page]
+use lithe::t;
+
+#[lithe::page(
+    title = "About Lithe.rs",
+    description = "What Lithe.rs is and who builds it.",
+    cache = "public, max-age=300"
+)]
 pub fn page() -> impl lithe::Component {
     lithe::div()
         .class("about-container")
-        .with_child(lithe::h1().with_child("About Lithe.rs"))
-        .with_child(lithe::p().with_child(
-            "Lithe.rs is a lightweight framework for building web applications in Rust.",
-        ))
+        .with_child(lithe::h1().with_child(t!("about.title")))
+        .with_child(lithe::p().with_child(t!("about.intro")))
+        .with_child(lithe::p().with_child(t!(
+            "about.locale",
+            locale = lithe::current_locale()
+        )))
 }
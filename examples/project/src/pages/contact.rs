@@ -1,6 +1,6 @@
 use lithe::{div, h1, p, Component};
 
-#[lithe::page]
+#[lithe::page(title = "Contact Lithe.rs")]
 pub fn page() -> impl Component {
     div()
         .class("container")
@@ -39,8 +39,10 @@ pub fn page() -> impl Component {
         .with_child(
             button()
                 .on_click(|| async move {
-                    let data = crate::utils::get_server_data(123).await;
-                    browser::alert(&data);
+                    match crate::utils::get_server_data(123).await {
+                        Ok(data) => browser::alert(&data),
+                        Err(e) => browser::alert(&format!("Error: {}", e)),
+                    }
                 })
                 .with_child("Fetch from Server"),
         )
@@ -48,7 +50,7 @@ pub fn page() -> impl Component {
         .with_child(
             div()
                 .style("margin-top: 20px;")
-                .with_child(a().href("/about").with_child("Go to About")),
+                .with_child(a().href(&lithe::url("/about")).with_child("Go to About")),
         );
     HtmlPage::new("Lithe.rs - Full Test", body)
 }
@@ -0,0 +1,9 @@
+use lithe::{div, h1, p, Component};
+
+#[lithe::page(title = "Roadmap", draft = true)]
+pub fn page() -> impl Component {
+    div()
+        .class("container")
+        .with_child(h1().with_child("Roadmap"))
+        .with_child(p().with_child("This page is a draft and is only visible in preview mode."))
+}
@@ -1,17 +1,43 @@
-use lithe::{Component, HtmlPage, browser, button, div, p, page, server};
+use lithe::{Component, HtmlPage, ServerFnResult, button, div, p, page, server, socket};
 
-#[server]
-pub async fn get_data_step_1(val: i32) -> i32 {
+#[server(public, encoding = "cbor")]
+pub async fn get_data_step_1(val: i32) -> ServerFnResult<i32> {
     println!("Step 1 received value: {}", val);
-    let memory_info = sys_info::mem_info().unwrap();
+    let memory_info = sys_info::mem_info().map_err(|e| lithe::ServerFnError::App(e.to_string()))?;
     println!("System memory info: {:?}", memory_info);
-    memory_info.total as i32
+    Ok(memory_info.total as i32)
 }
 
-#[server]
-pub async fn get_data_final(val: i32) -> String {
-    let res = get_data_step_1(val).await;
-    format!("Final server result: {}", res)
+/// Read-only, so browsers and proxies may cache its replies for a minute and failed calls are
+/// safe to retry.
+#[server(
+    public,
+    method = "GET",
+    cache = "public, max-age=60",
+    timeout = "5s",
+    retry = 2
+)]
+pub async fn get_data_final(val: i32) -> ServerFnResult<String> {
+    let res = get_data_step_1(val).await?;
+    Ok(format!("Final server result: {}", res))
+}
+
+/// Counts down from `from`, one number every 200ms. The client dropping the stream stops it.
+#[server(public)]
+pub async fn countdown(from: u32) -> impl lithe::Stream<Item = u32> {
+    lithe::stream::unfold(from, |n| async move {
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        n.checked_sub(1).map(|next| (n, next))
+    })
+}
+
+/// Answers each message with it in capitals, for as long as the page stays connected.
+#[socket(public)]
+pub async fn shout(mut socket: lithe::socket::Socket<String, String>) -> ServerFnResult<()> {
+    while let Some(text) = socket.recv().await {
+        socket.send(&text.to_uppercase()).await?;
+    }
+    Ok(())
 }
 
 #[page]
@@ -22,10 +48,53 @@ pub fn page() -> impl Component {
         .with_child(
             button()
                 .on_click(|| async move {
-                    let res = get_data_final(5).await;
-                    browser::alert(&res);
+                    match get_data_final(5).await {
+                        Ok(res) => lithe::browser::alert(&res),
+                        Err(e) => lithe::browser::alert(&format!("Error: {}", e)),
+                    }
                 })
                 .with_child("Call Multi-step Server Fn"),
-        );
+        )
+        .with_child(
+            button()
+                .on_click(|| async move {
+                    // Both calls go out in a single batch request.
+                    let (a, b) = lithe::future::join(get_data_final(1), get_data_final(2)).await;
+                    match (a, b) {
+                        (Ok(a), Ok(b)) => lithe::browser::alert(&format!("{}\n{}", a, b)),
+                        (Err(e), _) | (_, Err(e)) => {
+                            lithe::browser::alert(&format!("Error: {}", e))
+                        }
+                    }
+                })
+                .with_child("Call Two Server Fns at Once"),
+        )
+        .with_child(
+            button()
+                .on_click(|| async move {
+                    use lithe::StreamExt;
+                    let mut items = countdown(5).await;
+                    while let Some(n) = items.next().await {
+                        lithe::browser::set_inner_html("countdown", &n.to_string());
+                    }
+                    if let Some(e) = items.error() {
+                        lithe::browser::alert(&format!("Error: {}", e));
+                    }
+                })
+                .with_child("Stream a Countdown"),
+        )
+        .with_child(
+            button()
+                .on_click(|| async move {
+                    let mut channel = shout();
+                    if let Err(e) = channel.send(&"hello over a socket".to_string()) {
+                        lithe::browser::alert(&format!("Error: {}", e));
+                    } else if let Some(reply) = channel.recv().await {
+                        lithe::browser::alert(&reply);
+                    }
+                })
+                .with_child("Shout over a Socket"),
+        )
+        .with_child(p().id("countdown"));
     HtmlPage::new("Lithe.rs - Test", body)
 }
@@ -0,0 +1,21 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Shared server state, built once at startup and available through `lithe::server::use_state`.
+pub struct AppState {
+    pub started_at: std::time::Instant,
+    calls: AtomicU64,
+}
+
+impl AppState {
+    pub async fn new() -> AppState {
+        AppState {
+            started_at: std::time::Instant::now(),
+            calls: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a server function call and returns the running total.
+    pub fn record_call(&self) -> u64 {
+        self.calls.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
@@ -1,12 +1,63 @@
-use lithe::{browser, client, server};
+use lithe::{ServerFnResult, browser, client, job, scheduled, server};
 
 #[client]
 pub fn alert_from_utils() {
     browser::alert("Hello from src/utils.rs!");
 }
 
+#[server(public, name = "v1/server-data", rate_limit = "20/min")]
+pub async fn get_server_data(id: i32) -> ServerFnResult<String> {
+    let request = lithe::server::use_request();
+    let state = lithe::server::use_state::<crate::state::AppState>();
+    println!(
+        "Fetching data for ID {} from {:?} (call #{}, up {:?})",
+        id,
+        request.remote_addr,
+        state.record_call(),
+        state.started_at.elapsed()
+    );
+    let visits = request
+        .cookie("visits")
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(0)
+        + 1;
+    lithe::server::set_cookie(&format!("visits={}; Path=/; SameSite=Lax", visits))?;
+    if visits == 1 {
+        enqueue_note_first_visit(id)
+            .await
+            .map_err(lithe::ServerFnError::Server)?;
+    }
+    Ok(format!(
+        "Server data for ID {} (visit {}): Success!",
+        id, visits
+    ))
+}
+
+/// Guarded by the project's `default_guard`, so only signed-in users can call it.
 #[server]
-pub async fn get_server_data(id: i32) -> String {
-    println!("Fetching data for ID: {}", id);
-    format!("Server data for ID {}: Success!", id)
+pub async fn get_account() -> ServerFnResult<String> {
+    let request = lithe::server::use_request();
+    Ok(format!(
+        "Signed in as {}",
+        request
+            .cookie(crate::auth::SESSION_COOKIE)
+            .unwrap_or_default()
+    ))
+}
+
+#[server(guard = [crate::auth::require_user, crate::auth::require_admin])]
+pub async fn reset_counters() -> ServerFnResult<()> {
+    Ok(())
+}
+
+/// Runs in the background after a browser's first call, without holding up the reply.
+#[job]
+pub async fn note_first_visit(id: i32) {
+    println!("First visit, for ID {}", id);
+}
+
+#[scheduled("@hourly")]
+pub async fn report_uptime() {
+    let state = lithe::server::use_state::<crate::state::AppState>();
+    println!("Up for {:?}", state.started_at.elapsed());
 }